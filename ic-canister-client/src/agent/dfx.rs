@@ -0,0 +1,259 @@
+//! Resolution of the canister ids and network URLs of a dfx project.
+//!
+//! The canister ids are read from the `canister_ids.json` file at the root of the project for the
+//! remote networks, and from `.dfx/<network>/canister_ids.json` for the local ones, the same way
+//! dfx does, so integration scripts can build their clients by canister name:
+//!
+//! ```ignore
+//! let project = DfxProject::from_current_dir()?;
+//! let client = IcAgentClient::from_dfx(&project, "my_canister", "ic", identity_path, None).await?;
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use candid::Principal;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// The name of the dfx project file
+pub const DFX_JSON: &str = "dfx.json";
+
+/// The URL of the `ic` network
+pub const IC_NETWORK_URL: &str = "https://icp0.io";
+
+/// The address of the `local` network when it's not configured in `dfx.json`
+const DEFAULT_LOCAL_BIND: &str = "127.0.0.1:4943";
+
+const CANISTER_IDS_JSON: &str = "canister_ids.json";
+
+#[derive(Error, Debug)]
+pub enum DfxError {
+    #[error("{DFX_JSON} not found in {0} or its parents")]
+    ProjectNotFound(PathBuf),
+
+    #[error("failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("failed to parse {0}: {1}")]
+    Json(PathBuf, serde_json::Error),
+
+    #[error("network {0} is not configured")]
+    UnknownNetwork(String),
+
+    #[error("canister {canister} has no id on network {network}")]
+    UnknownCanister { canister: String, network: String },
+
+    #[error("invalid id of canister {canister}: {id}")]
+    InvalidCanisterId { canister: String, id: String },
+}
+
+pub type DfxResult<T> = std::result::Result<T, DfxError>;
+
+/// The parts of `dfx.json` used to resolve the networks
+#[derive(Debug, Default, Deserialize)]
+struct DfxJson {
+    #[serde(default)]
+    networks: HashMap<String, NetworkConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NetworkConfig {
+    /// Set for the local networks
+    bind: Option<String>,
+    /// Set for the remote networks
+    #[serde(default)]
+    providers: Vec<String>,
+}
+
+/// `canister_ids.json` content: the ids of the canisters, by canister name and network name
+type CanisterIds = HashMap<String, HashMap<String, String>>;
+
+/// A dfx project, identified by the directory containing its `dfx.json`.
+#[derive(Debug)]
+pub struct DfxProject {
+    root: PathBuf,
+    networks: HashMap<String, NetworkConfig>,
+}
+
+impl DfxProject {
+    /// Load the project whose `dfx.json` is in the given directory.
+    pub fn load(root: impl Into<PathBuf>) -> DfxResult<Self> {
+        let root = root.into();
+        let dfx_json: DfxJson = read_json(&root.join(DFX_JSON))?;
+
+        Ok(Self {
+            root,
+            networks: dfx_json.networks,
+        })
+    }
+
+    /// Load the project containing the current directory, looking for `dfx.json`
+    /// in the current directory and its parents.
+    pub fn from_current_dir() -> DfxResult<Self> {
+        let current_dir = std::env::current_dir().map_err(|e| DfxError::Io(".".into(), e))?;
+        let root = current_dir
+            .ancestors()
+            .find(|dir| dir.join(DFX_JSON).is_file())
+            .ok_or_else(|| DfxError::ProjectNotFound(current_dir.clone()))?;
+
+        Self::load(root)
+    }
+
+    /// Returns the root directory of the project.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the URL of the network.
+    ///
+    /// The `ic` and `local` networks don't need to be configured in `dfx.json`.
+    pub fn network_url(&self, network: &str) -> DfxResult<String> {
+        match (self.networks.get(network), network) {
+            (Some(config), _) => {
+                if let Some(provider) = config.providers.first() {
+                    Ok(provider.clone())
+                } else if let Some(bind) = &config.bind {
+                    Ok(format!("http://{bind}"))
+                } else {
+                    Err(DfxError::UnknownNetwork(network.to_string()))
+                }
+            }
+            (None, "ic") => Ok(IC_NETWORK_URL.to_string()),
+            (None, "local") => Ok(format!("http://{DEFAULT_LOCAL_BIND}")),
+            (None, _) => Err(DfxError::UnknownNetwork(network.to_string())),
+        }
+    }
+
+    /// Returns the id of the canister on the network.
+    pub fn canister_id(&self, canister: &str, network: &str) -> DfxResult<Principal> {
+        let path = self.canister_ids_path(network);
+        let canister_ids: CanisterIds = read_json(&path)?;
+
+        let unknown_canister = || DfxError::UnknownCanister {
+            canister: canister.to_string(),
+            network: network.to_string(),
+        };
+        let id = canister_ids
+            .get(canister)
+            .ok_or_else(unknown_canister)?
+            .get(network)
+            .ok_or_else(unknown_canister)?;
+
+        Principal::from_text(id).map_err(|_| DfxError::InvalidCanisterId {
+            canister: canister.to_string(),
+            id: id.clone(),
+        })
+    }
+
+    /// The ids of the canisters deployed on the local networks are not committed
+    /// with the project, dfx keeps them in the `.dfx` directory.
+    fn canister_ids_path(&self, network: &str) -> PathBuf {
+        if self.is_local(network) {
+            self.root.join(".dfx").join(network).join(CANISTER_IDS_JSON)
+        } else {
+            self.root.join(CANISTER_IDS_JSON)
+        }
+    }
+
+    fn is_local(&self, network: &str) -> bool {
+        match self.networks.get(network) {
+            Some(config) => config.providers.is_empty(),
+            None => network == "local",
+        }
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> DfxResult<T> {
+    let content = std::fs::read(path).map_err(|e| DfxError::Io(path.to_path_buf(), e))?;
+    serde_json::from_slice(&content).map_err(|e| DfxError::Json(path.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    const LEDGER_IC: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+    const LEDGER_LOCAL: &str = "bkyz2-fmaaa-aaaaa-qaaaq-cai";
+
+    fn project() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(DFX_JSON),
+            r#"{
+                "canisters": { "ledger": { "type": "custom" } },
+                "networks": {
+                    "staging": { "providers": ["https://staging.example.com"] },
+                    "local": { "bind": "127.0.0.1:8000" }
+                }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join(CANISTER_IDS_JSON),
+            format!(r#"{{ "ledger": {{ "ic": "{LEDGER_IC}", "staging": "invalid" }} }}"#),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.path().join(".dfx/local")).unwrap();
+        std::fs::write(
+            dir.path().join(".dfx/local").join(CANISTER_IDS_JSON),
+            format!(r#"{{ "ledger": {{ "local": "{LEDGER_LOCAL}" }} }}"#),
+        )
+        .unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn should_resolve_network_urls() {
+        let dir = project();
+        let project = DfxProject::load(dir.path()).unwrap();
+
+        assert_eq!(project.network_url("ic").unwrap(), IC_NETWORK_URL);
+        assert_eq!(
+            project.network_url("local").unwrap(),
+            "http://127.0.0.1:8000"
+        );
+        assert_eq!(
+            project.network_url("staging").unwrap(),
+            "https://staging.example.com"
+        );
+        assert!(matches!(
+            project.network_url("prod"),
+            Err(DfxError::UnknownNetwork(_))
+        ));
+    }
+
+    #[test]
+    fn should_resolve_canister_ids() {
+        let dir = project();
+        let project = DfxProject::load(dir.path()).unwrap();
+
+        assert_eq!(
+            project.canister_id("ledger", "ic").unwrap(),
+            Principal::from_text(LEDGER_IC).unwrap()
+        );
+        assert_eq!(
+            project.canister_id("ledger", "local").unwrap(),
+            Principal::from_text(LEDGER_LOCAL).unwrap()
+        );
+        assert!(matches!(
+            project.canister_id("ledger", "staging"),
+            Err(DfxError::InvalidCanisterId { .. })
+        ));
+        assert!(matches!(
+            project.canister_id("index", "ic"),
+            Err(DfxError::UnknownCanister { .. })
+        ));
+    }
+
+    #[test]
+    fn should_fail_without_dfx_json() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            DfxProject::load(dir.path()),
+            Err(DfxError::Io(..))
+        ));
+    }
+}
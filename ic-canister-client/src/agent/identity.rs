@@ -4,16 +4,28 @@ use std::time::Duration;
 use candid::Principal;
 use ic_agent::agent::http_transport::ReqwestTransport;
 use ic_agent::agent::EnvelopeContent;
-use ic_agent::identity::{BasicIdentity, Secp256k1Identity};
+use ic_agent::identity::{AnonymousIdentity, BasicIdentity, PemError, Secp256k1Identity};
 use ic_agent::{Agent, Identity};
 
 use super::AgentError;
 
+/// Hosts of the boundary nodes of the mainnet
+const MAINNET_HOSTS: [&str; 3] = ["icp-api.io", "ic0.app", "icp0.io"];
+
 pub enum GenericIdentity {
     Secp256k1Identity(Secp256k1Identity),
     BasicIdentity(BasicIdentity),
 }
 
+impl GenericIdentity {
+    /// Parse an identity from the content of a PEM file
+    pub fn from_pem(pem: &[u8]) -> std::result::Result<Self, PemError> {
+        Secp256k1Identity::from_pem(pem)
+            .map(GenericIdentity::from)
+            .or(BasicIdentity::from_pem(pem).map(GenericIdentity::from))
+    }
+}
+
 impl TryFrom<&Path> for GenericIdentity {
     type Error = AgentError;
 
@@ -67,7 +79,15 @@ pub async fn init_agent(
     timeout: Option<Duration>,
 ) -> super::Result<Agent> {
     let identity = GenericIdentity::try_from(identity_path.as_ref())?;
+    init_agent_with_identity(identity, url, timeout).await
+}
 
+/// Initialize an IC Agent with an identity, e.g. loaded from an [`IdentityStore`](super::keystore::IdentityStore)
+pub async fn init_agent_with_identity(
+    identity: impl Identity + 'static,
+    url: &str,
+    timeout: Option<Duration>,
+) -> super::Result<Agent> {
     let timeout = timeout.unwrap_or(Duration::from_secs(120));
 
     let client = ic_agent::agent::http_transport::reqwest_transport::reqwest::ClientBuilder::new()
@@ -88,11 +108,33 @@ pub async fn init_agent(
         .with_ingress_expiry(Some(timeout))
         .build()?;
 
-    agent.fetch_root_key().await?;
+    // The agent embeds the root key of the mainnet, which must not be fetched from the network
+    if !is_mainnet_url(url) {
+        agent.fetch_root_key().await?;
+    }
 
     Ok(agent)
 }
 
+/// Initialize an IC Agent with the anonymous identity, e.g. for an off-chain service which only
+/// reads public data
+pub async fn init_anonymous_agent(url: &str, timeout: Option<Duration>) -> super::Result<Agent> {
+    init_agent_with_identity(AnonymousIdentity, url, timeout).await
+}
+
+/// Returns `true` if the URL is a boundary node of the mainnet
+pub fn is_mainnet_url(url: &str) -> bool {
+    let host = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    MAINNET_HOSTS
+        .iter()
+        .any(|mainnet| host == *mainnet || host.ends_with(&format!(".{mainnet}")))
+}
+
 #[cfg(test)]
 mod test {
 
@@ -111,6 +153,17 @@ mod test {
         ));
     }
 
+    #[test]
+    fn should_get_identity_from_pem() {
+        let pem = std::fs::read("./tests/identity/identity.pem").unwrap();
+
+        assert!(matches!(
+            GenericIdentity::from_pem(&pem).unwrap(),
+            GenericIdentity::Secp256k1Identity(_)
+        ));
+        assert!(GenericIdentity::from_pem(b"not a pem").is_err());
+    }
+
     #[test]
     fn should_get_sender_from_identity() {
         let path = Path::new("./tests/identity/identity.pem");
@@ -124,6 +177,18 @@ mod test {
         assert_eq!(expected, principal);
     }
 
+    #[test]
+    fn should_detect_mainnet_urls() {
+        assert!(is_mainnet_url("https://icp-api.io"));
+        assert!(is_mainnet_url("https://ic0.app/api/v2"));
+        assert!(is_mainnet_url(
+            "https://a4gq6-oaaaa-aaaab-qaa4q-cai.raw.icp0.io"
+        ));
+        assert!(!is_mainnet_url("http://127.0.0.1:4943"));
+        assert!(!is_mainnet_url("http://localhost:8000"));
+        assert!(!is_mainnet_url("https://notic0.app"));
+    }
+
     #[test]
     fn identity_should_sign() {
         let path = Path::new("./tests/identity/identity.pem");
@@ -0,0 +1,281 @@
+//! Identities encrypted at rest for off-chain tools.
+//!
+//! The [`IdentityStore`] keeps named profiles in a directory, each one being a PEM file encrypted
+//! with a passphrase using [age](https://age-encryption.org) (scrypt), so the CLI tools built on this
+//! crate don't need to keep plaintext PEM files on disk:
+//!
+//! ```ignore
+//! let store = IdentityStore::new(config_dir.join("identities"));
+//! store.import("deployer", Path::new("./identity.pem"), &passphrase)?;
+//!
+//! let identity = store.load("deployer", &passphrase)?;
+//! let agent = init_agent_with_identity(identity, "https://icp0.io", None).await?;
+//! ```
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::secrecy::SecretString;
+use ic_agent::identity::PemError;
+use thiserror::Error;
+
+use super::identity::GenericIdentity;
+
+/// The extension of the encrypted profile files
+const PROFILE_EXTENSION: &str = "pem.age";
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("invalid profile name {0:?}: only ASCII letters, digits, '-' and '_' are allowed")]
+    InvalidProfileName(String),
+
+    #[error("profile {0} not found")]
+    ProfileNotFound(String),
+
+    #[error("profile {0} is not encrypted with a passphrase")]
+    NotPassphraseEncrypted(String),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("encryption error: {0}")]
+    Encrypt(#[from] age::EncryptError),
+
+    #[error("decryption error, the passphrase may be wrong: {0}")]
+    Decrypt(#[from] age::DecryptError),
+
+    #[error("invalid PEM: {0}")]
+    Pem(#[from] PemError),
+}
+
+pub type KeystoreResult<T> = std::result::Result<T, KeystoreError>;
+
+/// A directory of named identities, encrypted with a passphrase.
+#[derive(Debug, Clone)]
+pub struct IdentityStore {
+    dir: PathBuf,
+}
+
+impl IdentityStore {
+    /// Creates a store keeping the profiles in the given directory.
+    /// The directory is created when the first profile is stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the directory of the store.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Encrypt the PEM with the passphrase and store it as the given profile,
+    /// replacing the previous identity of the profile if any.
+    pub fn store(&self, profile: &str, pem: &[u8], passphrase: &str) -> KeystoreResult<()> {
+        let path = self.profile_path(profile)?;
+        // don't store something which can't be loaded back
+        GenericIdentity::from_pem(pem)?;
+
+        let encryptor = age::Encryptor::with_user_passphrase(secret(passphrase));
+        let mut encrypted = vec![];
+        let mut writer = encryptor.wrap_output(&mut encrypted)?;
+        writer.write_all(pem)?;
+        writer.finish()?;
+
+        fs::create_dir_all(&self.dir)?;
+        // write to a temporary file first, so an interrupted write doesn't corrupt the profile
+        let tmp_path = path.with_extension("tmp");
+        write_private(&tmp_path, &encrypted)?;
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+
+    /// Encrypt the plaintext PEM file and store it as the given profile.
+    /// The plaintext file is left untouched, it's up to the caller to remove it.
+    pub fn import(&self, profile: &str, pem_path: &Path, passphrase: &str) -> KeystoreResult<()> {
+        let pem = fs::read(pem_path)?;
+        self.store(profile, &pem, passphrase)
+    }
+
+    /// Returns the decrypted PEM of the profile.
+    pub fn load_pem(&self, profile: &str, passphrase: &str) -> KeystoreResult<Vec<u8>> {
+        let path = self.profile_path(profile)?;
+        let encrypted = match fs::read(&path) {
+            Ok(encrypted) => encrypted,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(KeystoreError::ProfileNotFound(profile.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let decryptor = match age::Decryptor::new(&encrypted[..])? {
+            age::Decryptor::Passphrase(decryptor) => decryptor,
+            _ => return Err(KeystoreError::NotPassphraseEncrypted(profile.to_string())),
+        };
+
+        let mut pem = vec![];
+        decryptor
+            .decrypt(&secret(passphrase), None)?
+            .read_to_end(&mut pem)?;
+
+        Ok(pem)
+    }
+
+    /// Returns the identity of the profile.
+    pub fn load(&self, profile: &str, passphrase: &str) -> KeystoreResult<GenericIdentity> {
+        let pem = self.load_pem(profile, passphrase)?;
+        Ok(GenericIdentity::from_pem(&pem)?)
+    }
+
+    /// Returns true if the profile exists.
+    pub fn contains(&self, profile: &str) -> KeystoreResult<bool> {
+        Ok(self.profile_path(profile)?.is_file())
+    }
+
+    /// Returns the names of the stored profiles, sorted.
+    pub fn profiles(&self) -> KeystoreResult<Vec<String>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let suffix = format!(".{PROFILE_EXTENSION}");
+        let mut profiles = vec![];
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(profile) = name.to_str().and_then(|name| name.strip_suffix(&suffix)) {
+                if is_valid_profile_name(profile) {
+                    profiles.push(profile.to_string());
+                }
+            }
+        }
+        profiles.sort();
+
+        Ok(profiles)
+    }
+
+    /// Remove the profile. Returns false if it doesn't exist.
+    pub fn remove(&self, profile: &str) -> KeystoreResult<bool> {
+        match fs::remove_file(self.profile_path(profile)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn profile_path(&self, profile: &str) -> KeystoreResult<PathBuf> {
+        if !is_valid_profile_name(profile) {
+            return Err(KeystoreError::InvalidProfileName(profile.to_string()));
+        }
+
+        Ok(self.dir.join(format!("{profile}.{PROFILE_EXTENSION}")))
+    }
+}
+
+/// The profile names are used as file names, so they are restricted to avoid path traversals
+fn is_valid_profile_name(profile: &str) -> bool {
+    !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn secret(passphrase: &str) -> SecretString {
+    SecretString::new(passphrase.to_string())
+}
+
+/// Write the file readable by the owner only
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(content)
+}
+
+#[cfg(test)]
+mod test {
+
+    use ic_agent::Identity;
+
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn pem() -> Vec<u8> {
+        fs::read("./tests/identity/identity.pem").unwrap()
+    }
+
+    #[test]
+    fn should_store_and_load_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = IdentityStore::new(dir.path().join("identities"));
+
+        store.store("deployer", &pem(), PASSPHRASE).unwrap();
+
+        let encrypted = fs::read(dir.path().join("identities/deployer.pem.age")).unwrap();
+        assert!(!encrypted.windows(10).any(|w| w == b"PRIVATE KE"));
+
+        assert_eq!(store.load_pem("deployer", PASSPHRASE).unwrap(), pem());
+        let identity = store.load("deployer", PASSPHRASE).unwrap();
+        let expected = GenericIdentity::from_pem(&pem()).unwrap();
+        assert_eq!(identity.sender(), expected.sender());
+    }
+
+    #[test]
+    fn should_reject_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = IdentityStore::new(dir.path());
+        store.store("deployer", &pem(), PASSPHRASE).unwrap();
+
+        assert!(matches!(
+            store.load("deployer", "wrong"),
+            Err(KeystoreError::Decrypt(_))
+        ));
+        assert!(matches!(
+            store.load("unknown", PASSPHRASE),
+            Err(KeystoreError::ProfileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn should_manage_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = IdentityStore::new(dir.path());
+        assert!(store.profiles().unwrap().is_empty());
+
+        let pem_path = dir.path().join("plain.pem");
+        fs::write(&pem_path, pem()).unwrap();
+        store.import("prod", &pem_path, PASSPHRASE).unwrap();
+        store.store("local", &pem(), "other").unwrap();
+
+        assert_eq!(store.profiles().unwrap(), vec!["local", "prod"]);
+        assert!(store.contains("prod").unwrap());
+
+        assert!(store.remove("prod").unwrap());
+        assert!(!store.remove("prod").unwrap());
+        assert_eq!(store.profiles().unwrap(), vec!["local"]);
+    }
+
+    #[test]
+    fn should_reject_invalid_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = IdentityStore::new(dir.path());
+
+        assert!(matches!(
+            store.store("../escape", &pem(), PASSPHRASE),
+            Err(KeystoreError::InvalidProfileName(_))
+        ));
+        assert!(matches!(
+            store.store("deployer", b"not a pem", PASSPHRASE),
+            Err(KeystoreError::Pem(_))
+        ));
+        assert!(store.profiles().unwrap().is_empty());
+    }
+}
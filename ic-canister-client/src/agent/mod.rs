@@ -1,4 +1,8 @@
+pub mod dfx;
 pub mod identity;
+pub mod keystore;
+pub mod polling;
+pub mod provisional;
 
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -23,6 +27,30 @@ pub enum AgentError {
 
     #[error("failed to read PEM file {0}: {1}")]
     PemError(PathBuf, PemError),
+
+    #[error("dfx project error: {0}")]
+    Dfx(#[from] dfx::DfxError),
+
+    #[error("keystore error: {0}")]
+    Keystore(#[from] keystore::KeystoreError),
+
+    #[error("candid error: {0}")]
+    Candid(#[from] candid::Error),
+
+    #[error("pending call error: {0}")]
+    PendingCall(String),
+
+    #[error("the call was rejected with code {code}: {message}")]
+    CallRejected { code: String, message: String },
+
+    #[error("the result of the call is not available anymore")]
+    CallResultExpired,
+
+    #[error("the call did not complete after {0:?}")]
+    PollingTimeout(Duration),
+
+    #[error("provisional APIs are not available on the mainnet")]
+    ProvisionalCallOnMainnet,
 }
 
 pub type Result<T> = std::result::Result<T, AgentError>;
@@ -48,6 +76,51 @@ impl IcAgentClient {
         })
     }
 
+    /// Initialize an IC Agent for a canister of a dfx project, resolving the canister id and the
+    /// URL of the network from the project files
+    pub async fn from_dfx(
+        project: &dfx::DfxProject,
+        canister: &str,
+        network: &str,
+        identity_path: impl AsRef<Path>,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let canister_id = project.canister_id(canister, network)?;
+        let url = project.network_url(network)?;
+        Self::with_identity(canister_id, identity_path, &url, timeout).await
+    }
+
+    /// Initialize an IC Agent with a profile of an encrypted identity store
+    pub async fn with_stored_identity(
+        canister: Principal,
+        store: &keystore::IdentityStore,
+        profile: &str,
+        passphrase: &str,
+        network: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let identity = store.load(profile, passphrase)?;
+        let agent = identity::init_agent_with_identity(identity, network, timeout).await?;
+        Ok(Self {
+            canister_id: canister,
+            agent,
+        })
+    }
+
+    /// Initialize an IC Agent with the anonymous identity, e.g. for an off-chain service which
+    /// only calls the public methods of the canister
+    pub async fn anonymous(
+        canister: Principal,
+        network: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let agent = identity::init_anonymous_agent(network, timeout).await?;
+        Ok(Self {
+            canister_id: canister,
+            agent,
+        })
+    }
+
     /// Initialize an IC Agent with an existing agent
     pub fn with_agent(canister: Principal, agent: ic_agent::Agent) -> Self {
         Self {
@@ -55,6 +128,16 @@ impl IcAgentClient {
             agent,
         }
     }
+
+    /// Returns a client for another canister, sharing the agent and its identity
+    pub fn with_canister(&self, canister: Principal) -> Self {
+        Self::with_agent(canister, self.agent.clone())
+    }
+
+    /// Returns the underlying agent, e.g. to call the management canister
+    pub fn agent(&self) -> &ic_agent::Agent {
+        &self.agent
+    }
 }
 
 #[async_trait::async_trait]
@@ -0,0 +1,284 @@
+//! Update calls polled with custom strategies, for long-running calls driven from CLIs.
+//!
+//! Instead of `call_and_wait`, the call is submitted with [`IcAgentClient::submit_update`], which
+//! returns a [`PendingCall`] holding the request id. The CLI saves it before polling, so it can
+//! resume polling after a restart instead of submitting the call again:
+//!
+//! ```ignore
+//! let pending = client.submit_update("migrate", (batch,)).await?;
+//! pending.save(&state_dir.join("migrate.json"))?;
+//!
+//! // After a restart: let pending = PendingCall::load(&state_dir.join("migrate.json"))?;
+//! let report: MigrationReport = client
+//!     .poll(&pending, ExponentialBackoff::default().with_max_wait(Duration::from_secs(600)), |progress| {
+//!         println!("{:?} after {:?}", progress.status, progress.elapsed)
+//!     })
+//!     .await?;
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use candid::utils::ArgumentEncoder;
+use candid::{encode_args, CandidType, Decode, Principal};
+use ic_agent::agent::{RejectResponse, ReplyResponse, RequestStatusResponse};
+use ic_agent::RequestId;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::{AgentError, IcAgentClient, Result};
+
+/// An update call submitted to the replica, whose result has not been read yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingCall {
+    pub canister_id: Principal,
+    pub method: String,
+    /// Request id of the call, hex-encoded
+    pub request_id: String,
+}
+
+impl PendingCall {
+    pub fn new(canister_id: Principal, method: &str, request_id: &RequestId) -> Self {
+        Self {
+            canister_id,
+            method: method.to_string(),
+            request_id: hex::encode(request_id.as_slice()),
+        }
+    }
+
+    /// Decodes the request id.
+    pub fn request_id(&self) -> Result<RequestId> {
+        let bytes: [u8; 32] = hex::decode(&self.request_id)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| {
+                AgentError::PendingCall(format!("invalid request id {}", self.request_id))
+            })?;
+        Ok(RequestId::new(&bytes))
+    }
+
+    /// Saves the call to a JSON file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| AgentError::PendingCall(e.to_string()))?;
+        fs::write(path, json).map_err(|e| AgentError::PendingCall(e.to_string()))
+    }
+
+    /// Loads a call saved with [`PendingCall::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read(path).map_err(|e| AgentError::PendingCall(e.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| AgentError::PendingCall(e.to_string()))
+    }
+}
+
+/// Status of a call which is not completed yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallStatus {
+    /// The replica doesn't know the call yet, or anymore
+    Unknown,
+    /// The call is received by the replica but not executed yet
+    Received,
+    /// The call is being executed
+    Processing,
+}
+
+/// Progress of the polling, passed to the progress callback after every poll
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollProgress {
+    /// Number of polls so far
+    pub attempt: u32,
+    /// Time elapsed since the start of the polling
+    pub elapsed: Duration,
+    pub status: CallStatus,
+}
+
+/// Decides when to poll the status of a call again.
+pub trait PollingStrategy {
+    /// Returns the delay before the next poll, or `None` to stop polling.
+    fn next_delay(&mut self, progress: &PollProgress) -> Option<Duration>;
+}
+
+/// Polls with a delay multiplied after every poll, up to a maximum delay, until the maximum
+/// wait time is elapsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExponentialBackoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub max_wait: Duration,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            multiplier: 1.4,
+            max_wait: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    pub fn with_delays(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self
+    }
+}
+
+impl PollingStrategy for ExponentialBackoff {
+    fn next_delay(&mut self, progress: &PollProgress) -> Option<Duration> {
+        if progress.elapsed >= self.max_wait {
+            return None;
+        }
+
+        let factor = self
+            .multiplier
+            .powi(progress.attempt.saturating_sub(1) as i32);
+        let delay = self.initial_delay.mul_f64(factor).min(self.max_delay);
+        Some(delay.min(self.max_wait - progress.elapsed))
+    }
+}
+
+impl<F: FnMut(&PollProgress) -> Option<Duration>> PollingStrategy for F {
+    fn next_delay(&mut self, progress: &PollProgress) -> Option<Duration> {
+        self(progress)
+    }
+}
+
+impl IcAgentClient {
+    /// Submits an update call without waiting for its result. The result is read with
+    /// [`IcAgentClient::poll`].
+    pub async fn submit_update<T>(&self, method: &str, args: T) -> Result<PendingCall>
+    where
+        T: ArgumentEncoder + Send + Sync,
+    {
+        let args = encode_args(args)?;
+        let request_id = self
+            .agent
+            .update(&self.canister_id, method)
+            .with_arg(args)
+            .call()
+            .await?;
+
+        Ok(PendingCall::new(self.canister_id, method, &request_id))
+    }
+
+    /// Polls the status of the call with the strategy until it completes, calling `on_progress`
+    /// after every poll, and decodes its result.
+    pub async fn poll<R, S, P>(
+        &self,
+        call: &PendingCall,
+        mut strategy: S,
+        mut on_progress: P,
+    ) -> Result<R>
+    where
+        R: DeserializeOwned + CandidType,
+        S: PollingStrategy,
+        P: FnMut(&PollProgress),
+    {
+        let request_id = call.request_id()?;
+        let started_at = Instant::now();
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let status = match self
+                .agent
+                .request_status_raw(&request_id, call.canister_id)
+                .await?
+            {
+                RequestStatusResponse::Replied(ReplyResponse { arg }) => {
+                    return Ok(Decode!(&arg, R)?);
+                }
+                RequestStatusResponse::Rejected(RejectResponse {
+                    reject_code,
+                    reject_message,
+                    ..
+                }) => {
+                    return Err(AgentError::CallRejected {
+                        code: format!("{reject_code:?}"),
+                        message: reject_message,
+                    });
+                }
+                RequestStatusResponse::Done => return Err(AgentError::CallResultExpired),
+                RequestStatusResponse::Unknown => CallStatus::Unknown,
+                RequestStatusResponse::Received => CallStatus::Received,
+                RequestStatusResponse::Processing => CallStatus::Processing,
+            };
+
+            let progress = PollProgress {
+                attempt,
+                elapsed: started_at.elapsed(),
+                status,
+            };
+            on_progress(&progress);
+
+            match strategy.next_delay(&progress) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(AgentError::PollingTimeout(progress.elapsed)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(attempt: u32, elapsed_secs: u64) -> PollProgress {
+        PollProgress {
+            attempt,
+            elapsed: Duration::from_secs(elapsed_secs),
+            status: CallStatus::Processing,
+        }
+    }
+
+    #[test]
+    fn should_back_off_until_max_wait() {
+        let mut strategy = ExponentialBackoff {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            max_wait: Duration::from_secs(60),
+        };
+
+        assert_eq!(
+            strategy.next_delay(&progress(1, 0)),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            strategy.next_delay(&progress(3, 3)),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(
+            strategy.next_delay(&progress(10, 20)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            strategy.next_delay(&progress(20, 58)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(strategy.next_delay(&progress(21, 60)), None);
+    }
+
+    #[test]
+    fn should_persist_pending_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.json");
+        let request_id = RequestId::new(&[7; 32]);
+        let call = PendingCall::new(Principal::management_canister(), "migrate", &request_id);
+
+        call.save(&path).unwrap();
+        let loaded = PendingCall::load(&path).unwrap();
+        assert_eq!(loaded, call);
+        assert_eq!(loaded.request_id().unwrap(), request_id);
+    }
+}
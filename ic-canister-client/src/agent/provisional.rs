@@ -0,0 +1,86 @@
+//! Provisional APIs of the management canister, to create and fund canisters on local replicas.
+//!
+//! The provisional APIs are only available on local replicas and test networks; the helpers
+//! refuse to call them on the mainnet, so a bootstrap script pointed at the wrong network fails
+//! before sending any call.
+
+use candid::{Encode, Principal};
+use ic_agent::Agent;
+use ic_exports::ic_cdk::api::management_canister::main::CanisterIdRecord;
+use ic_exports::ic_cdk::api::management_canister::provisional::{
+    ProvisionalCreateCanisterWithCyclesArgument, ProvisionalTopUpCanisterArgument,
+};
+
+use super::{AgentError, IcAgentClient, Result};
+
+const MAINNET_URL: &str = "https://icp-api.io";
+
+impl IcAgentClient {
+    /// Returns `true` if the agent is connected to the mainnet, i.e. if the root key of the
+    /// network is the root key of the mainnet.
+    pub fn is_mainnet(&self) -> Result<bool> {
+        let mainnet = Agent::builder().with_url(MAINNET_URL).build()?;
+        Ok(self.agent.read_root_key() == mainnet.read_root_key())
+    }
+
+    /// Creates a canister with the cycles, or with the default amount of the replica if `None`,
+    /// and returns its id.
+    ///
+    /// The canister is created on the subnet of the canister of the client.
+    pub async fn provisional_create_canister_with_cycles(
+        &self,
+        cycles: Option<u128>,
+    ) -> Result<Principal> {
+        self.ensure_not_mainnet()?;
+
+        let arg = ProvisionalCreateCanisterWithCyclesArgument {
+            amount: cycles.map(Into::into),
+            ..Default::default()
+        };
+        let reply = self
+            .agent
+            .update(
+                &Principal::management_canister(),
+                "provisional_create_canister_with_cycles",
+            )
+            .with_effective_canister_id(self.canister_id)
+            .with_arg(Encode!(&arg)?)
+            .call_and_wait()
+            .await?;
+
+        let record = candid::decode_one::<CanisterIdRecord>(&reply)?;
+        Ok(record.canister_id)
+    }
+
+    /// Adds the cycles to the balance of the canister.
+    pub async fn provisional_top_up_canister(
+        &self,
+        canister_id: Principal,
+        cycles: u128,
+    ) -> Result<()> {
+        self.ensure_not_mainnet()?;
+
+        let arg = ProvisionalTopUpCanisterArgument {
+            canister_id,
+            amount: cycles.into(),
+        };
+        self.agent
+            .update(
+                &Principal::management_canister(),
+                "provisional_top_up_canister",
+            )
+            .with_effective_canister_id(canister_id)
+            .with_arg(Encode!(&arg)?)
+            .call_and_wait()
+            .await?;
+
+        Ok(())
+    }
+
+    fn ensure_not_mainnet(&self) -> Result<()> {
+        if self.is_mainnet()? {
+            return Err(AgentError::ProvisionalCallOnMainnet);
+        }
+        Ok(())
+    }
+}
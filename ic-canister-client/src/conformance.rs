@@ -0,0 +1,547 @@
+//! Conformance suite of ICRC-1, ICRC-2 and ICRC-3 token canisters.
+//!
+//! The suite runs against any token canister through a [`CanisterClient`], e.g. a canister built
+//! with the payments or token modules of the SDK and customized by a project, to certify that it
+//! still meets the standards: metadata, fee handling, errors, deduplication of transactions,
+//! allowances and the transaction log.
+//!
+//! ```ignore
+//! let suite = IcrcConformanceSuite::new(owner_client, owner_account, recipient_account)
+//!     .with_spender(spender_client, spender_account)
+//!     .with_amount(1_000u64.into());
+//!
+//! let report = suite.run(ledger_time_nanos).await;
+//! assert!(report.is_success(), "{report}");
+//! ```
+//!
+//! The owner account must hold enough tokens to pay a few transfers and their fees. The checks
+//! modify the balances of the accounts, so the suite should run against a test deployment.
+
+use std::fmt;
+
+use candid::{CandidType, Nat};
+use ic_exports::icrc_types::icrc::generic_metadata_value::MetadataValue;
+use ic_exports::icrc_types::icrc::generic_value::Value;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc1::transfer::{Memo, TransferArg, TransferError};
+use ic_exports::icrc_types::icrc2::allowance::{Allowance, AllowanceArgs};
+use ic_exports::icrc_types::icrc2::approve::{ApproveArgs, ApproveError};
+use ic_exports::icrc_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+use serde::Deserialize;
+
+use crate::CanisterClient;
+
+/// Nanoseconds older than the transaction window and the permitted drift of any ledger
+const TOO_OLD_NANOS: u64 = 2 * 24 * 60 * 60 * 1_000_000_000;
+/// Nanoseconds in the future beyond the permitted drift of any ledger
+const IN_FUTURE_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+/// Result of a conformance check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    Failed(String),
+    /// The check doesn't apply to the canister, e.g. ICRC-2 checks if the standard isn't supported
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+/// Results of all the checks of the suite
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl ConformanceReport {
+    /// True if no check failed.
+    pub fn is_success(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Returns the failed checks.
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results
+            .iter()
+            .filter(|result| matches!(result.outcome, CheckOutcome::Failed(_)))
+    }
+
+    fn push(&mut self, name: &'static str, outcome: Result<CheckOutcome, String>) {
+        let outcome = outcome.unwrap_or_else(CheckOutcome::Failed);
+        self.results.push(CheckResult { name, outcome });
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                CheckOutcome::Passed => writeln!(f, "[PASS] {}", result.name)?,
+                CheckOutcome::Failed(reason) => writeln!(f, "[FAIL] {}: {reason}", result.name)?,
+                CheckOutcome::Skipped(reason) => writeln!(f, "[SKIP] {}: {reason}", result.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A standard supported by the canister, as returned by `icrc1_supported_standards`
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+/// Request of `icrc3_get_blocks`
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct GetBlocksArgs {
+    start: Nat,
+    length: Nat,
+}
+
+/// The fields of the `icrc3_get_blocks` response used by the suite
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct GetBlocksResult {
+    log_length: Nat,
+    blocks: Vec<BlockWithId>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct BlockWithId {
+    id: Nat,
+    block: Value,
+}
+
+/// Runs the conformance checks against a token canister.
+pub struct IcrcConformanceSuite<C: CanisterClient> {
+    owner_client: C,
+    owner: Account,
+    recipient: Account,
+    spender: Option<(C, Account)>,
+    amount: Nat,
+}
+
+impl<C: CanisterClient> IcrcConformanceSuite<C> {
+    /// Creates the suite. The client calls the token canister as the owner of the `owner` account,
+    /// which pays the transfers to the `recipient` account.
+    pub fn new(owner_client: C, owner: Account, recipient: Account) -> Self {
+        Self {
+            owner_client,
+            owner,
+            recipient,
+            spender: None,
+            amount: Nat::from(10_000u64),
+        }
+    }
+
+    /// Enables the ICRC-2 checks, with a client calling the token canister as the owner of the
+    /// `spender` account.
+    pub fn with_spender(mut self, spender_client: C, spender: Account) -> Self {
+        self.spender = Some((spender_client, spender));
+        self
+    }
+
+    /// Sets the amount of the transfers made by the checks.
+    pub fn with_amount(mut self, amount: Nat) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    /// Runs all the checks. `now` is the current time of the ledger, in nanoseconds, used to test
+    /// the deduplication of the transactions.
+    pub async fn run(&self, now: u64) -> ConformanceReport {
+        let mut report = ConformanceReport::default();
+        let standards = self.supported_standards().await.unwrap_or_default();
+
+        report.push("icrc1: metadata", self.check_metadata().await);
+        let icrc1_outcome = match self.check_standard(&standards, "ICRC-1") {
+            CheckOutcome::Skipped(reason) => CheckOutcome::Failed(reason),
+            outcome => outcome,
+        };
+        report.push("icrc1: supported standards", Ok(icrc1_outcome));
+        report.push("icrc1: transfer", self.check_transfer().await);
+        report.push("icrc1: bad fee", self.check_bad_fee().await);
+        report.push(
+            "icrc1: insufficient funds",
+            self.check_insufficient_funds().await,
+        );
+        report.push("icrc1: deduplication", self.check_deduplication(now).await);
+        report.push("icrc1: too old", self.check_too_old(now).await);
+        report.push(
+            "icrc1: created in future",
+            self.check_created_in_future(now).await,
+        );
+
+        match self.check_standard(&standards, "ICRC-2") {
+            CheckOutcome::Passed => {
+                report.push(
+                    "icrc2: approve and transfer_from",
+                    self.check_approve().await,
+                );
+                report.push(
+                    "icrc2: insufficient allowance",
+                    self.check_insufficient_allowance().await,
+                );
+                report.push(
+                    "icrc2: expected allowance",
+                    self.check_expected_allowance().await,
+                );
+            }
+            skipped => report.push("icrc2", Ok(skipped)),
+        }
+
+        match self.check_standard(&standards, "ICRC-3") {
+            CheckOutcome::Passed => report.push("icrc3: blocks log", self.check_blocks().await),
+            skipped => report.push("icrc3", Ok(skipped)),
+        }
+
+        report
+    }
+
+    fn check_standard(&self, standards: &[StandardRecord], name: &str) -> CheckOutcome {
+        match standards.iter().any(|standard| standard.name == name) {
+            true => CheckOutcome::Passed,
+            false => CheckOutcome::Skipped(format!("{name} is not in the supported standards")),
+        }
+    }
+
+    async fn check_metadata(&self) -> Result<CheckOutcome, String> {
+        let metadata: Vec<(String, MetadataValue)> = self.query("icrc1_metadata", ()).await?;
+        let find = |key: &str| {
+            metadata
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        let fee = self.fee().await?;
+        expect_metadata(find("icrc1:fee"), MetadataValue::Nat(fee), "icrc1:fee")?;
+        let decimals: u8 = self.query("icrc1_decimals", ()).await?;
+        expect_metadata(
+            find("icrc1:decimals"),
+            MetadataValue::Nat(Nat::from(decimals)),
+            "icrc1:decimals",
+        )?;
+        let symbol: String = self.query("icrc1_symbol", ()).await?;
+        expect_metadata(
+            find("icrc1:symbol"),
+            MetadataValue::Text(symbol),
+            "icrc1:symbol",
+        )?;
+        let name: String = self.query("icrc1_name", ()).await?;
+        expect_metadata(find("icrc1:name"), MetadataValue::Text(name), "icrc1:name")?;
+
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_transfer(&self) -> Result<CheckOutcome, String> {
+        let fee = self.fee().await?;
+        let owner_balance = self.balance(&self.owner).await?;
+        let recipient_balance = self.balance(&self.recipient).await?;
+
+        self.transfer(self.transfer_arg(None, None))
+            .await?
+            .map_err(|e| format!("transfer failed: {e:?}"))?;
+
+        expect_eq(
+            self.balance(&self.owner).await?,
+            owner_balance - self.amount.clone() - fee,
+            "owner balance",
+        )?;
+        expect_eq(
+            self.balance(&self.recipient).await?,
+            recipient_balance + self.amount.clone(),
+            "recipient balance",
+        )?;
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_bad_fee(&self) -> Result<CheckOutcome, String> {
+        let fee = self.fee().await?;
+        let mut arg = self.transfer_arg(None, None);
+        arg.fee = Some(fee.clone() + Nat::from(1u64));
+
+        match self.transfer(arg).await? {
+            Err(TransferError::BadFee { expected_fee }) => expect_eq(expected_fee, fee, "fee")?,
+            other => return Err(format!("expected BadFee, got {other:?}")),
+        }
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_insufficient_funds(&self) -> Result<CheckOutcome, String> {
+        let balance = self.balance(&self.owner).await?;
+        let mut arg = self.transfer_arg(None, None);
+        arg.amount = balance.clone() + Nat::from(1u64);
+
+        match self.transfer(arg).await? {
+            Err(TransferError::InsufficientFunds { balance: reported }) => {
+                expect_eq(reported, balance, "balance")?
+            }
+            other => return Err(format!("expected InsufficientFunds, got {other:?}")),
+        }
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_deduplication(&self, now: u64) -> Result<CheckOutcome, String> {
+        let memo = Memo::from(now.to_be_bytes().to_vec());
+        let arg = self.transfer_arg(Some(now), Some(memo));
+
+        let tx_id = self
+            .transfer(arg.clone())
+            .await?
+            .map_err(|e| format!("transfer failed: {e:?}"))?;
+        match self.transfer(arg).await? {
+            Err(TransferError::Duplicate { duplicate_of }) => {
+                expect_eq(duplicate_of, tx_id, "duplicate transaction")?
+            }
+            other => return Err(format!("expected Duplicate, got {other:?}")),
+        }
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_too_old(&self, now: u64) -> Result<CheckOutcome, String> {
+        let arg = self.transfer_arg(Some(now.saturating_sub(TOO_OLD_NANOS)), None);
+        match self.transfer(arg).await? {
+            Err(TransferError::TooOld) => Ok(CheckOutcome::Passed),
+            other => Err(format!("expected TooOld, got {other:?}")),
+        }
+    }
+
+    async fn check_created_in_future(&self, now: u64) -> Result<CheckOutcome, String> {
+        let arg = self.transfer_arg(Some(now + IN_FUTURE_NANOS), None);
+        match self.transfer(arg).await? {
+            Err(TransferError::CreatedInFuture { .. }) => Ok(CheckOutcome::Passed),
+            other => Err(format!("expected CreatedInFuture, got {other:?}")),
+        }
+    }
+
+    async fn check_approve(&self) -> Result<CheckOutcome, String> {
+        let (spender_client, spender) = self.spender()?;
+        let fee = self.fee().await?;
+        let allowance = self.amount.clone() + fee.clone();
+
+        self.approve(self.approve_args(spender, allowance.clone(), None))
+            .await?
+            .map_err(|e| format!("approve failed: {e:?}"))?;
+        expect_eq(self.allowance(spender).await?, allowance, "allowance")?;
+
+        let owner_balance = self.balance(&self.owner).await?;
+        let result: Result<Nat, TransferFromError> = call(
+            spender_client
+                .update("icrc2_transfer_from", (self.transfer_from_args(spender),))
+                .await,
+        )?;
+        result.map_err(|e| format!("transfer_from failed: {e:?}"))?;
+
+        expect_eq(self.allowance(spender).await?, Nat::from(0u64), "allowance")?;
+        expect_eq(
+            self.balance(&self.owner).await?,
+            owner_balance - self.amount.clone() - fee,
+            "owner balance",
+        )?;
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_insufficient_allowance(&self) -> Result<CheckOutcome, String> {
+        let (spender_client, spender) = self.spender()?;
+        self.approve(self.approve_args(spender, Nat::from(0u64), None))
+            .await?
+            .map_err(|e| format!("approve failed: {e:?}"))?;
+
+        let result: Result<Nat, TransferFromError> = call(
+            spender_client
+                .update("icrc2_transfer_from", (self.transfer_from_args(spender),))
+                .await,
+        )?;
+        match result {
+            Err(TransferFromError::InsufficientAllowance { .. }) => Ok(CheckOutcome::Passed),
+            other => Err(format!("expected InsufficientAllowance, got {other:?}")),
+        }
+    }
+
+    async fn check_expected_allowance(&self) -> Result<CheckOutcome, String> {
+        let (_, spender) = self.spender()?;
+        let current = self.allowance(spender).await?;
+        let args = self.approve_args(
+            spender,
+            self.amount.clone(),
+            Some(current.clone() + Nat::from(1u64)),
+        );
+
+        match self.approve(args).await? {
+            Err(ApproveError::AllowanceChanged { current_allowance }) => {
+                expect_eq(current_allowance, current, "current allowance")?
+            }
+            other => return Err(format!("expected AllowanceChanged, got {other:?}")),
+        }
+        Ok(CheckOutcome::Passed)
+    }
+
+    async fn check_blocks(&self) -> Result<CheckOutcome, String> {
+        let log_length = self
+            .blocks(Nat::from(0u64), Nat::from(0u64))
+            .await?
+            .log_length;
+        let tx_id = self
+            .transfer(self.transfer_arg(None, None))
+            .await?
+            .map_err(|e| format!("transfer failed: {e:?}"))?;
+
+        let blocks = self.blocks(tx_id.clone(), Nat::from(1u64)).await?;
+        expect_eq(
+            blocks.log_length,
+            log_length + Nat::from(1u64),
+            "log length",
+        )?;
+        match blocks.blocks.first() {
+            Some(block) if block.id == tx_id => match &block.block {
+                Value::Map(_) => Ok(CheckOutcome::Passed),
+                other => Err(format!("block {tx_id} is not a map: {other:?}")),
+            },
+            _ => Err(format!("block {tx_id} is not returned by icrc3_get_blocks")),
+        }
+    }
+
+    async fn supported_standards(&self) -> Result<Vec<StandardRecord>, String> {
+        self.query("icrc1_supported_standards", ()).await
+    }
+
+    async fn fee(&self) -> Result<Nat, String> {
+        self.query("icrc1_fee", ()).await
+    }
+
+    async fn balance(&self, account: &Account) -> Result<Nat, String> {
+        self.query("icrc1_balance_of", (account,)).await
+    }
+
+    async fn allowance(&self, spender: &Account) -> Result<Nat, String> {
+        let args = AllowanceArgs {
+            account: self.owner,
+            spender: *spender,
+        };
+        let allowance: Allowance = self.query("icrc2_allowance", (args,)).await?;
+        Ok(allowance.allowance)
+    }
+
+    async fn blocks(&self, start: Nat, length: Nat) -> Result<GetBlocksResult, String> {
+        let args = vec![GetBlocksArgs { start, length }];
+        call(self.owner_client.query("icrc3_get_blocks", (args,)).await)
+    }
+
+    async fn transfer(&self, arg: TransferArg) -> Result<Result<Nat, TransferError>, String> {
+        call(self.owner_client.update("icrc1_transfer", (arg,)).await)
+    }
+
+    async fn approve(&self, args: ApproveArgs) -> Result<Result<Nat, ApproveError>, String> {
+        call(self.owner_client.update("icrc2_approve", (args,)).await)
+    }
+
+    async fn query<T, R>(&self, method: &str, args: T) -> Result<R, String>
+    where
+        T: candid::utils::ArgumentEncoder + Send + Sync,
+        R: serde::de::DeserializeOwned + CandidType,
+    {
+        call(self.owner_client.query(method, args).await)
+    }
+
+    fn transfer_arg(&self, created_at_time: Option<u64>, memo: Option<Memo>) -> TransferArg {
+        TransferArg {
+            from_subaccount: self.owner.subaccount,
+            to: self.recipient,
+            fee: None,
+            created_at_time,
+            memo,
+            amount: self.amount.clone(),
+        }
+    }
+
+    fn approve_args(
+        &self,
+        spender: &Account,
+        amount: Nat,
+        expected_allowance: Option<Nat>,
+    ) -> ApproveArgs {
+        ApproveArgs {
+            from_subaccount: self.owner.subaccount,
+            spender: *spender,
+            amount,
+            expected_allowance,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    fn transfer_from_args(&self, spender: &Account) -> TransferFromArgs {
+        TransferFromArgs {
+            spender_subaccount: spender.subaccount,
+            from: self.owner,
+            to: self.recipient,
+            amount: self.amount.clone(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    fn spender(&self) -> Result<(&C, &Account), String> {
+        self.spender
+            .as_ref()
+            .map(|(client, account)| (client, account))
+            .ok_or_else(|| "no spender configured".to_string())
+    }
+}
+
+fn call<R>(result: crate::CanisterClientResult<R>) -> Result<R, String> {
+    result.map_err(|e| format!("call failed: {e}"))
+}
+
+fn expect_eq<T: PartialEq + fmt::Debug>(actual: T, expected: T, what: &str) -> Result<(), String> {
+    match actual == expected {
+        true => Ok(()),
+        false => Err(format!("{what}: expected {expected:?}, got {actual:?}")),
+    }
+}
+
+fn expect_metadata(
+    actual: Option<MetadataValue>,
+    expected: MetadataValue,
+    key: &str,
+) -> Result<(), String> {
+    match actual {
+        Some(actual) => expect_eq(actual, expected, key),
+        None => Err(format!("metadata entry {key} is missing")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_report_failures() {
+        let mut report = ConformanceReport::default();
+        report.push("passed", Ok(CheckOutcome::Passed));
+        report.push("skipped", Ok(CheckOutcome::Skipped("not supported".into())));
+        assert!(report.is_success());
+
+        report.push("failed", Err("call failed".into()));
+        assert!(!report.is_success());
+        assert_eq!(
+            report
+                .failures()
+                .map(|result| result.name)
+                .collect::<Vec<_>>(),
+            vec!["failed"]
+        );
+        assert_eq!(
+            report.to_string(),
+            "[PASS] passed\n[SKIP] skipped: not supported\n[FAIL] failed: call failed\n"
+        );
+    }
+}
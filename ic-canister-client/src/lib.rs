@@ -2,8 +2,14 @@
 pub mod agent;
 
 pub mod client;
+#[cfg(feature = "icrc-conformance")]
+pub mod conformance;
 pub mod error;
 pub mod ic_client;
+pub mod permissions;
+pub mod registry;
+#[cfg(feature = "retry")]
+pub mod retry;
 
 #[cfg(feature = "state-machine-tests-client")]
 pub mod state_machine_tests;
@@ -11,14 +17,26 @@ pub mod state_machine_tests;
 #[cfg(feature = "pocket-ic-client")]
 pub mod pocket_ic;
 
+#[cfg(feature = "ic-agent-client")]
+pub use agent::dfx::DfxProject;
+#[cfg(feature = "ic-agent-client")]
+pub use agent::keystore::IdentityStore;
+#[cfg(feature = "ic-agent-client")]
+pub use agent::polling::{ExponentialBackoff, PendingCall, PollingStrategy};
 #[cfg(feature = "ic-agent-client")]
 pub use agent::{AgentError, IcAgentClient};
 pub use client::CanisterClient;
+#[cfg(feature = "icrc-conformance")]
+pub use conformance::{ConformanceReport, IcrcConformanceSuite};
 pub use error::{CanisterClientError, CanisterClientResult, IcError, IcResult};
 #[cfg(feature = "ic-agent-client")]
 pub use ic_agent;
 pub use ic_client::IcCanisterClient;
+pub use permissions::{Access, PermissionsMatrix, PermissionsReport};
 #[cfg(feature = "pocket-ic-client")]
 pub use pocket_ic::PocketIcClient;
+pub use registry::RegistryClient;
+#[cfg(feature = "retry")]
+pub use retry::{RetryPolicy, RetryingClient};
 #[cfg(feature = "state-machine-tests-client")]
 pub use state_machine_tests::StateMachineCanisterClient;
@@ -0,0 +1,447 @@
+//! Declarative testing of the access rules of a canister.
+//!
+//! A [`PermissionsMatrix`] lists the methods of the canister, usually introspected from its
+//! [`Idl`], a set of caller personas and the expected [`Access`] of every persona to every method.
+//! [`PermissionsMatrix::check`] calls every method as every persona and returns a
+//! [`PermissionsReport`] with the outcomes that differ from the expectations.
+//!
+//! A method without an expectation for a persona is reported as well, so the test fails as soon as
+//! a new endpoint is added to the canister without declaring who can call it:
+//!
+//! ```ignore
+//! let matrix = PermissionsMatrix::from_idl(&MyCanister::get_idl())
+//!     .with_persona("owner", owner)
+//!     .with_persona("anonymous", Principal::anonymous())
+//!     .allow_all("get_balance")
+//!     .allow("set_owner", "owner")
+//!     .deny("set_owner", "anonymous")
+//!     .with_args("set_owner", (alice(),))?;
+//!
+//! matrix.check(&pocket_ic, canister_id).await.assert_ok();
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use candid::types::internal::FuncMode;
+use candid::utils::ArgumentEncoder;
+use candid::Principal;
+use ic_canister::Idl;
+
+/// Whether a persona is allowed to call a method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Allowed,
+    Denied,
+}
+
+/// The kind of a canister method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    Query,
+    Update,
+}
+
+/// The outcome of a call, used to decide whether the call was allowed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// The method replied with the given candid encoded payload
+    Reply(Vec<u8>),
+    /// The call was rejected, or the method trapped, with the given message
+    Reject(String),
+}
+
+/// Decides whether a call was allowed from its outcome.
+pub type AccessClassifier = Box<dyn Fn(&str, &CallOutcome) -> Access + Send + Sync>;
+
+/// A mismatch between the expected and the actual access matrix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermissionsViolation {
+    /// The persona got an access different from the expected one
+    UnexpectedAccess {
+        method: String,
+        persona: String,
+        expected: Access,
+        actual: Access,
+        outcome: CallOutcome,
+    },
+    /// No access was declared for the persona on the method
+    MissingExpectation { method: String, persona: String },
+}
+
+impl fmt::Display for PermissionsViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedAccess {
+                method,
+                persona,
+                expected,
+                actual,
+                outcome,
+            } => write!(
+                f,
+                "method `{method}` called by `{persona}`: expected {expected:?}, got {actual:?} ({outcome:?})"
+            ),
+            Self::MissingExpectation { method, persona } => write!(
+                f,
+                "method `{method}` has no expected access for `{persona}`"
+            ),
+        }
+    }
+}
+
+/// The result of a [`PermissionsMatrix`] check
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PermissionsReport {
+    pub violations: Vec<PermissionsViolation>,
+}
+
+impl PermissionsReport {
+    /// Returns true if the canister matches the expected matrix.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Panics listing the violations, if any.
+    pub fn assert_ok(&self) {
+        assert!(self.is_ok(), "permissions matrix violated:\n{self}");
+    }
+}
+
+impl fmt::Display for PermissionsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for violation in &self.violations {
+            writeln!(f, "- {violation}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The expected allow/deny matrix of a canister.
+pub struct PermissionsMatrix {
+    methods: BTreeMap<String, MethodKind>,
+    personas: Vec<(String, Principal)>,
+    args: HashMap<String, Vec<u8>>,
+    expected: HashMap<(String, String), Access>,
+    public_methods: Vec<String>,
+    classifier: AccessClassifier,
+}
+
+impl PermissionsMatrix {
+    /// Creates a matrix for the given methods.
+    pub fn new(methods: impl IntoIterator<Item = (String, MethodKind)>) -> Self {
+        Self {
+            methods: methods.into_iter().collect(),
+            personas: Vec::new(),
+            args: HashMap::new(),
+            expected: HashMap::new(),
+            public_methods: Vec::new(),
+            classifier: Box::new(default_classifier),
+        }
+    }
+
+    /// Creates a matrix for the methods of the canister service.
+    ///
+    /// Panics if the idl actor is not a service.
+    pub fn from_idl(idl: &Idl) -> Self {
+        Self::new(idl_methods(idl))
+    }
+
+    /// Add a caller persona.
+    pub fn with_persona(mut self, name: impl Into<String>, principal: Principal) -> Self {
+        self.personas.push((name.into(), principal));
+        self
+    }
+
+    /// Set the arguments used to call the method. Methods without arguments are called with `()`.
+    pub fn with_args<T: ArgumentEncoder>(
+        mut self,
+        method: impl Into<String>,
+        args: T,
+    ) -> Result<Self, candid::Error> {
+        self.args.insert(method.into(), candid::encode_args(args)?);
+        Ok(self)
+    }
+
+    /// Expect the persona to be allowed to call the method.
+    pub fn allow(self, method: impl Into<String>, persona: impl Into<String>) -> Self {
+        self.expect(method, persona, Access::Allowed)
+    }
+
+    /// Expect the persona to be denied from calling the method.
+    pub fn deny(self, method: impl Into<String>, persona: impl Into<String>) -> Self {
+        self.expect(method, persona, Access::Denied)
+    }
+
+    /// Expect every persona to be allowed to call the method.
+    pub fn allow_all(mut self, method: impl Into<String>) -> Self {
+        self.public_methods.push(method.into());
+        self
+    }
+
+    /// Set the expected access of the persona to the method.
+    pub fn expect(
+        mut self,
+        method: impl Into<String>,
+        persona: impl Into<String>,
+        access: Access,
+    ) -> Self {
+        self.expected
+            .insert((method.into(), persona.into()), access);
+        self
+    }
+
+    /// Replace the function deciding whether a call was allowed.
+    ///
+    /// By default a reply is [`Access::Allowed`] and a reject is [`Access::Denied`]. A custom
+    /// classifier is needed if the canister denies the access with an error in the reply,
+    /// e.g. `Err(Unauthorized)`.
+    pub fn with_classifier(
+        mut self,
+        classifier: impl Fn(&str, &CallOutcome) -> Access + Send + Sync + 'static,
+    ) -> Self {
+        self.classifier = Box::new(classifier);
+        self
+    }
+
+    /// Returns the methods of the matrix.
+    pub fn methods(&self) -> impl Iterator<Item = (&str, MethodKind)> {
+        self.methods
+            .iter()
+            .map(|(name, kind)| (name.as_str(), *kind))
+    }
+
+    /// Returns the expected access of the persona to the method, if declared.
+    pub fn expected_access(&self, method: &str, persona: &str) -> Option<Access> {
+        if self.public_methods.iter().any(|m| m == method) {
+            return Some(Access::Allowed);
+        }
+
+        self.expected
+            .get(&(method.to_string(), persona.to_string()))
+            .copied()
+    }
+
+    /// Compare the outcomes of the calls with the expected matrix.
+    ///
+    /// `call` is invoked for every method and persona with the method name, kind, caller and
+    /// candid encoded arguments. Missing expectations are reported without calling the method.
+    pub async fn verify<F, Fut>(&self, mut call: F) -> PermissionsReport
+    where
+        F: FnMut(String, MethodKind, Principal, Vec<u8>) -> Fut,
+        Fut: std::future::Future<Output = CallOutcome>,
+    {
+        let mut report = PermissionsReport::default();
+        let empty_args = candid::encode_args(()).expect("empty arguments are encodable");
+
+        for (method, kind) in &self.methods {
+            for (persona, principal) in &self.personas {
+                let Some(expected) = self.expected_access(method, persona) else {
+                    report
+                        .violations
+                        .push(PermissionsViolation::MissingExpectation {
+                            method: method.clone(),
+                            persona: persona.clone(),
+                        });
+                    continue;
+                };
+
+                let args = self.args.get(method).unwrap_or(&empty_args).clone();
+                let outcome = call(method.clone(), *kind, *principal, args).await;
+                let actual = (self.classifier)(method, &outcome);
+
+                if actual != expected {
+                    report
+                        .violations
+                        .push(PermissionsViolation::UnexpectedAccess {
+                            method: method.clone(),
+                            persona: persona.clone(),
+                            expected,
+                            actual,
+                            outcome,
+                        });
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Call every method of the canister as every persona and compare the outcomes with the
+    /// expected matrix.
+    ///
+    /// The calls are executed in order and update calls modify the state of the canister, so the
+    /// canister should be installed for the purpose of the check.
+    #[cfg(feature = "pocket-ic-client")]
+    pub async fn check(
+        &self,
+        client: &ic_exports::pocket_ic::nio::PocketIcAsync,
+        canister: Principal,
+    ) -> PermissionsReport {
+        use ic_exports::pocket_ic::WasmResult;
+
+        self.verify(|method, kind, caller, args| async move {
+            let result = match kind {
+                MethodKind::Query => client.query_call(canister, caller, method, args).await,
+                MethodKind::Update => client.update_call(canister, caller, method, args).await,
+            };
+
+            match result {
+                Ok(WasmResult::Reply(reply)) => CallOutcome::Reply(reply),
+                Ok(WasmResult::Reject(message)) => CallOutcome::Reject(message),
+                Err(error) => CallOutcome::Reject(error.to_string()),
+            }
+        })
+        .await
+    }
+}
+
+fn default_classifier(_method: &str, outcome: &CallOutcome) -> Access {
+    match outcome {
+        CallOutcome::Reply(_) => Access::Allowed,
+        CallOutcome::Reject(_) => Access::Denied,
+    }
+}
+
+/// Returns the methods of the canister service described by the idl.
+///
+/// Panics if the idl actor is not a service.
+pub fn idl_methods(idl: &Idl) -> Vec<(String, MethodKind)> {
+    let env = &idl.env.env;
+    let service = env
+        .as_service(&idl.actor)
+        .expect("the idl actor is not a service");
+
+    service
+        .iter()
+        .map(|(name, ty)| {
+            let func = env.as_func(ty).expect("service methods are functions");
+            let kind = match func
+                .modes
+                .iter()
+                .any(|mode| matches!(mode, FuncMode::Query | FuncMode::CompositeQuery))
+            {
+                true => MethodKind::Query,
+                false => MethodKind::Update,
+            };
+            (name.clone(), kind)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn owner() -> Principal {
+        Principal::from_slice(&[1; 29])
+    }
+
+    fn matrix() -> PermissionsMatrix {
+        PermissionsMatrix::new([
+            ("get".to_string(), MethodKind::Query),
+            ("set".to_string(), MethodKind::Update),
+        ])
+        .with_persona("owner", owner())
+        .with_persona("anonymous", Principal::anonymous())
+        .allow_all("get")
+        .allow("set", "owner")
+        .deny("set", "anonymous")
+    }
+
+    // Only the owner passes the guard of `set`
+    async fn guarded_call(method: String, caller: Principal) -> CallOutcome {
+        match method.as_str() {
+            "set" if caller != owner() => CallOutcome::Reject("unauthorized".to_string()),
+            _ => CallOutcome::Reply(vec![]),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_pass_when_matrix_matches() {
+        let calls = RefCell::new(vec![]);
+        let report = matrix()
+            .verify(|method, kind, caller, _| {
+                calls.borrow_mut().push((method.clone(), kind));
+                guarded_call(method, caller)
+            })
+            .await;
+
+        report.assert_ok();
+        assert_eq!(calls.borrow().len(), 4);
+        assert!(calls
+            .borrow()
+            .contains(&("set".to_string(), MethodKind::Update)));
+    }
+
+    #[tokio::test]
+    async fn should_report_missing_guard() {
+        let report = matrix()
+            .verify(|_, _, _, _| async { CallOutcome::Reply(vec![]) })
+            .await;
+
+        assert_eq!(report.violations.len(), 1);
+        assert!(matches!(
+            &report.violations[0],
+            PermissionsViolation::UnexpectedAccess { method, persona, expected: Access::Denied, actual: Access::Allowed, .. }
+                if method == "set" && persona == "anonymous"
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_report_new_method_without_expectations() {
+        let matrix = PermissionsMatrix::new([
+            ("get".to_string(), MethodKind::Query),
+            ("set".to_string(), MethodKind::Update),
+            ("drop_all".to_string(), MethodKind::Update),
+        ])
+        .with_persona("owner", owner())
+        .allow_all("get")
+        .allow("set", "owner");
+
+        let report = matrix
+            .verify(|method, _, caller, _| guarded_call(method, caller))
+            .await;
+
+        assert_eq!(
+            report.violations,
+            vec![PermissionsViolation::MissingExpectation {
+                method: "drop_all".to_string(),
+                persona: "owner".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_use_custom_classifier_and_args() {
+        let matrix = matrix()
+            .with_args("set", (42u32,))
+            .unwrap()
+            .with_classifier(|_, outcome| match outcome {
+                CallOutcome::Reply(reply) if reply.is_empty() => Access::Denied,
+                _ => Access::Allowed,
+            });
+
+        let report = matrix
+            .verify(|method, _, caller, args| async move {
+                if method == "set" && caller == Principal::anonymous() {
+                    // the canister replies with an empty payload to unauthorized callers
+                    return CallOutcome::Reply(vec![]);
+                }
+                assert_eq!(
+                    args,
+                    if method == "set" {
+                        candid::encode_args((42u32,)).unwrap()
+                    } else {
+                        candid::encode_args(()).unwrap()
+                    }
+                );
+                CallOutcome::Reply(args)
+            })
+            .await;
+
+        report.assert_ok();
+    }
+}
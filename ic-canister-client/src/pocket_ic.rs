@@ -41,6 +41,21 @@ impl PocketIcClient {
         &self.client
     }
 
+    /// Creates a canister controlled by the caller with the cycles, and returns its id.
+    ///
+    /// Same as `IcAgentClient::provisional_create_canister_with_cycles` on a local replica, for
+    /// bootstrap code shared by the tests and the local deployments.
+    pub async fn provisional_create_canister_with_cycles(&self, cycles: u128) -> Principal {
+        let canister_id = self.client.create_canister(Some(self.caller)).await;
+        self.client.add_cycles(canister_id, cycles).await;
+        canister_id
+    }
+
+    /// Adds the cycles to the balance of the canister, and returns the new balance.
+    pub async fn provisional_top_up_canister(&self, canister_id: Principal, cycles: u128) -> u128 {
+        self.client.add_cycles(canister_id, cycles).await
+    }
+
     /// Performs update call with the given arguments.
     pub async fn update<T, R>(&self, method: &str, args: T) -> CanisterClientResult<R>
     where
@@ -0,0 +1,205 @@
+//! Service discovery through a registry canister.
+//!
+//! Canisters reference their dependencies by name, e.g. `"ledger"`, and resolve the principals
+//! from a registry canister instead of receiving them in the init arguments.
+//! The registry canister must implement the following interface:
+//!
+//! ```candid
+//! service : {
+//!   lookup : (text) -> (opt principal) query;
+//!   register : (text, principal) -> ();
+//! }
+//! ```
+//!
+//! The resolved principals are cached by the [`RegistryClient`] for the configured TTL:
+//!
+//! ```ignore
+//! let registry = RegistryClient::new(IcCanisterClient::new(registry_id));
+//! let ledger = registry.resolve("ledger").await?.expect("ledger is registered");
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use candid::Principal;
+
+use crate::{CanisterClient, CanisterClientResult};
+
+/// The default time to live of the cached entries
+pub const DEFAULT_REGISTRY_CACHE_TTL: Duration = Duration::from_secs(600);
+
+struct CacheEntry {
+    principal: Principal,
+    expires_at_nanos: u64,
+}
+
+/// Client of a registry canister, caching the resolved principals.
+pub struct RegistryClient<C: CanisterClient> {
+    client: C,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl<C: CanisterClient> RegistryClient<C> {
+    /// Creates a client of the registry canister with the default cache TTL.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            ttl: DEFAULT_REGISTRY_CACHE_TTL,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Set the time to live of the cached entries.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Returns the principal registered with the given name, or None if the name is not registered.
+    ///
+    /// The principal is returned from the cache if it has been resolved less than the TTL ago.
+    /// Names which are not registered are not cached.
+    pub async fn resolve(&self, name: &str) -> CanisterClientResult<Option<Principal>> {
+        let now = now_nanos();
+        if let Some(entry) = self
+            .cache
+            .lock()
+            .expect("registry cache poisoned")
+            .get(name)
+        {
+            if entry.expires_at_nanos > now {
+                return Ok(Some(entry.principal));
+            }
+        }
+
+        let principal: Option<Principal> = self.client.query("lookup", (name,)).await?;
+        let mut cache = self.cache.lock().expect("registry cache poisoned");
+        match principal {
+            Some(principal) => {
+                cache.insert(
+                    name.to_string(),
+                    CacheEntry {
+                        principal,
+                        expires_at_nanos: now.saturating_add(self.ttl.as_nanos() as u64),
+                    },
+                );
+            }
+            None => {
+                cache.remove(name);
+            }
+        }
+
+        Ok(principal)
+    }
+
+    /// Register the principal with the given name in the registry canister.
+    pub async fn register(&self, name: &str, principal: Principal) -> CanisterClientResult<()> {
+        let () = self.client.update("register", (name, principal)).await?;
+        self.invalidate(name);
+        Ok(())
+    }
+
+    /// Remove the name from the cache, so the next resolution queries the registry.
+    pub fn invalidate(&self, name: &str) {
+        self.cache
+            .lock()
+            .expect("registry cache poisoned")
+            .remove(name);
+    }
+
+    /// Remove all the cached entries.
+    pub fn clear_cache(&self) {
+        self.cache.lock().expect("registry cache poisoned").clear();
+    }
+}
+
+fn now_nanos() -> u64 {
+    #[cfg(target_family = "wasm")]
+    {
+        ic_exports::ic_kit::ic::time()
+    }
+    #[cfg(not(target_family = "wasm"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("get current timestamp error")
+            .as_nanos() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use candid::utils::ArgumentEncoder;
+    use candid::CandidType;
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    /// A registry which knows only the ledger
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        lookups: Arc<AtomicU32>,
+    }
+
+    fn ledger() -> Principal {
+        Principal::from_slice(&[1; 29])
+    }
+
+    #[async_trait::async_trait]
+    impl CanisterClient for MockRegistry {
+        async fn update<T, R>(&self, _method: &str, _args: T) -> CanisterClientResult<R>
+        where
+            T: ArgumentEncoder + Send + Sync,
+            R: DeserializeOwned + CandidType,
+        {
+            Ok(candid::decode_one(&candid::encode_one(())?)?)
+        }
+
+        async fn query<T, R>(&self, _method: &str, args: T) -> CanisterClientResult<R>
+        where
+            T: ArgumentEncoder + Send + Sync,
+            R: DeserializeOwned + CandidType,
+        {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let (name,): (String,) = candid::decode_args(&candid::encode_args(args)?)?;
+            let principal = (name == "ledger").then(ledger);
+            Ok(candid::decode_one(&candid::encode_one(principal)?)?)
+        }
+    }
+
+    #[tokio::test]
+    async fn should_cache_resolved_principals() {
+        let mock = MockRegistry::default();
+        let registry = RegistryClient::new(mock.clone());
+
+        assert_eq!(registry.resolve("ledger").await.unwrap(), Some(ledger()));
+        assert_eq!(registry.resolve("ledger").await.unwrap(), Some(ledger()));
+        assert_eq!(mock.lookups.load(Ordering::SeqCst), 1);
+
+        // unknown names are not cached
+        assert_eq!(registry.resolve("minter").await.unwrap(), None);
+        assert_eq!(registry.resolve("minter").await.unwrap(), None);
+        assert_eq!(mock.lookups.load(Ordering::SeqCst), 3);
+
+        registry.invalidate("ledger");
+        assert_eq!(registry.resolve("ledger").await.unwrap(), Some(ledger()));
+        assert_eq!(mock.lookups.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn should_query_the_registry_when_entries_expire() {
+        let mock = MockRegistry::default();
+        let registry = RegistryClient::new(mock.clone()).with_ttl(Duration::ZERO);
+
+        registry.resolve("ledger").await.unwrap();
+        registry.resolve("ledger").await.unwrap();
+        assert_eq!(mock.lookups.load(Ordering::SeqCst), 2);
+
+        registry.register("ledger", ledger()).await.unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+//! Retries of the canister calls rejected with transient errors.
+//!
+//! The [`RetryingClient`] wraps any [`CanisterClient`] and calls the method again, after an
+//! exponential backoff, while the call fails with an error classified as retryable by its
+//! [`RetryPolicy`], e.g. a `SysTransient` rejection or a 429/503 response of a boundary node:
+//!
+//! ```ignore
+//! let agent_client = IcAgentClient::with_identity(canister_id, path, url, None).await?;
+//! let client = RetryingClient::new(agent_client)
+//!     .with_policy(RetryPolicy::default().with_max_attempts(5));
+//! let balance: Nat = client.query("balance_of", (account,)).await?;
+//! ```
+//!
+//! The arguments of a call are encoded again at every attempt, so they must be `Clone`, which is
+//! why [`RetryingClient`] doesn't implement [`CanisterClient`] itself.
+//! Only idempotent update methods should be retried, as a retried update call may have been
+//! executed by the canister before the error was returned.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use candid::utils::ArgumentEncoder;
+use candid::CandidType;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use serde::de::DeserializeOwned;
+
+use crate::{CanisterClient, CanisterClientError, CanisterClientResult};
+
+type RetryableFn = dyn Fn(&CanisterClientError) -> bool + Send + Sync;
+
+/// Decides which errors are retried, how many times, and the delays between the attempts.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts of a call, including the first one
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Factor of the delay after every attempt
+    pub multiplier: f64,
+    is_retryable: Arc<RetryableFn>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            is_retryable: Arc::new(is_transient_error),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_delays(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Replaces the classification of the retryable errors, which is [`is_transient_error`] by
+    /// default.
+    pub fn with_retryable(
+        mut self,
+        is_retryable: impl Fn(&CanisterClientError) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.is_retryable = Arc::new(is_retryable);
+        self
+    }
+
+    /// Returns `true` if the call should be attempted again after the error.
+    pub fn is_retryable(&self, error: &CanisterClientError) -> bool {
+        (self.is_retryable)(error)
+    }
+
+    /// Returns the delay before the attempt following the failed `attempt`, starting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        self.initial_delay.mul_f64(factor).min(self.max_delay)
+    }
+}
+
+/// Returns `true` for the errors which are expected to go away by themselves: the `SysTransient`
+/// rejections, and the 429, 502, 503 and 504 responses of the boundary nodes.
+pub fn is_transient_error(error: &CanisterClientError) -> bool {
+    match error {
+        CanisterClientError::CanisterError((code, _)) => *code == RejectionCode::SysTransient,
+        #[cfg(feature = "ic-agent-client")]
+        CanisterClientError::IcAgentError(ic_agent::AgentError::HttpError(payload)) => {
+            matches!(payload.status, 429 | 502 | 503 | 504)
+        }
+        _ => false,
+    }
+}
+
+/// Client retrying the calls of the inner client which fail with a retryable error.
+#[derive(Clone)]
+pub struct RetryingClient<C: CanisterClient> {
+    client: C,
+    policy: RetryPolicy,
+}
+
+impl<C: CanisterClient> RetryingClient<C> {
+    /// Creates a client retrying with the default policy.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.client
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Call an update method on the canister, retrying on the retryable errors.
+    pub async fn update<T, R>(&self, method: &str, args: T) -> CanisterClientResult<R>
+    where
+        T: ArgumentEncoder + Clone + Send + Sync,
+        R: DeserializeOwned + CandidType,
+    {
+        self.retry(|| self.client.update(method, args.clone()))
+            .await
+    }
+
+    /// Call a query method on the canister, retrying on the retryable errors.
+    pub async fn query<T, R>(&self, method: &str, args: T) -> CanisterClientResult<R>
+    where
+        T: ArgumentEncoder + Clone + Send + Sync,
+        R: DeserializeOwned + CandidType,
+    {
+        self.retry(|| self.client.query(method, args.clone())).await
+    }
+
+    /// Runs the call until it succeeds, fails with an error which is not retryable, or the
+    /// maximum number of attempts is reached, and returns its last result.
+    pub async fn retry<R, F, Fut>(&self, mut call: F) -> CanisterClientResult<R>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = CanisterClientResult<R>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match call().await {
+                Err(e) if attempt < self.policy.max_attempts && self.policy.is_retryable(&e) => {
+                    tokio::time::sleep(self.policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// A canister which rejects the first calls with the error
+    #[derive(Clone)]
+    struct FlakyCanister {
+        calls: Arc<AtomicU32>,
+        failures: u32,
+        code: RejectionCode,
+    }
+
+    impl FlakyCanister {
+        fn new(failures: u32, code: RejectionCode) -> Self {
+            Self {
+                calls: Arc::default(),
+                failures,
+                code,
+            }
+        }
+
+        fn call<R: DeserializeOwned + CandidType>(&self) -> CanisterClientResult<R> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if calls <= self.failures {
+                return Err(CanisterClientError::CanisterError((
+                    self.code,
+                    "rejected".to_string(),
+                )));
+            }
+            Ok(candid::decode_one(&candid::encode_one(calls)?)?)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CanisterClient for FlakyCanister {
+        async fn update<T, R>(&self, _method: &str, _args: T) -> CanisterClientResult<R>
+        where
+            T: ArgumentEncoder + Send + Sync,
+            R: DeserializeOwned + CandidType,
+        {
+            self.call()
+        }
+
+        async fn query<T, R>(&self, _method: &str, _args: T) -> CanisterClientResult<R>
+        where
+            T: ArgumentEncoder + Send + Sync,
+            R: DeserializeOwned + CandidType,
+        {
+            self.call()
+        }
+    }
+
+    fn no_delay() -> RetryPolicy {
+        RetryPolicy::default().with_delays(Duration::ZERO, Duration::ZERO)
+    }
+
+    #[tokio::test]
+    async fn should_retry_transient_errors() {
+        let canister = FlakyCanister::new(2, RejectionCode::SysTransient);
+        let client = RetryingClient::new(canister.clone()).with_policy(no_delay());
+
+        let calls: u32 = client.query("get", (1u64,)).await.unwrap();
+        assert_eq!(calls, 3);
+
+        let canister = FlakyCanister::new(5, RejectionCode::SysTransient);
+        let client = RetryingClient::new(canister.clone()).with_policy(no_delay());
+        assert!(client.update::<_, u32>("set", (1u64,)).await.is_err());
+        assert_eq!(canister.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_other_errors() {
+        let canister = FlakyCanister::new(1, RejectionCode::CanisterError);
+        let client = RetryingClient::new(canister.clone()).with_policy(no_delay());
+        assert!(client.update::<_, u32>("set", ()).await.is_err());
+        assert_eq!(canister.calls.load(Ordering::SeqCst), 1);
+
+        let client = client.with_policy(no_delay().with_retryable(|_| true));
+        let calls: u32 = client.update("set", ()).await.unwrap();
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn should_compute_backoff_delays() {
+        let policy = RetryPolicy::default()
+            .with_delays(Duration::from_millis(100), Duration::from_millis(500))
+            .with_multiplier(3.0);
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(300));
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+    }
+}
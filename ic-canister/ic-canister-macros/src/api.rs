@@ -392,10 +392,11 @@ pub(crate) fn generate_exports(input: TokenStream) -> TokenStream {
         struct_name,
         struct_vis,
     } = generate_input;
-    let methods = METHODS_EXPORTS.lock().unwrap();
+    // The methods are drained, so several traits of the same crate can generate their exports
+    let methods: Vec<_> = METHODS_EXPORTS.lock().unwrap().drain(..).collect();
 
-    let methods = methods.iter().map(|method| {
-        let owned: ExportMethodData = method.clone();
+    let methods = methods.into_iter().map(|method| {
+        let owned: ExportMethodData = method;
         let ExportMethodData { method_name, export_name, arg_count, is_async, is_return_type_async, return_type } = owned;
 
         let method = Ident::new(&method_name, Span::call_site());
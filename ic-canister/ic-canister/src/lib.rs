@@ -253,6 +253,12 @@
 //! println!("{result}");
 //! ```
 //!
+//! ## Composing canisters from trait canisters
+//!
+//! A canister can implement several trait canisters, each contributing its endpoints and its
+//! stable state. See [mixin] to check that the endpoints and the stable memory ids of the traits
+//! don't collide, and to generate the IDL of the composed canister.
+//!
 //! # Inter-canister calls
 //!
 //! When another canister needs to call these API methods, the [canister_call]` macro can be used.
@@ -527,6 +533,8 @@ use ic_exports::ic_cdk::api::call::{CallResult, RejectionCode};
 pub mod idl;
 pub use idl::*;
 
+pub mod mixin;
+
 pub enum MethodType {
     Query,
     Update,
@@ -0,0 +1,204 @@
+//! Composition of canisters from trait canisters, e.g. `Metrics`, `Auction` or an ownership trait.
+//!
+//! Every trait canister implemented by a canister is a mixin contributing endpoints and, possibly,
+//! stable structures. When several mixins are assembled, their endpoints must have distinct names
+//! and their stable structures must use distinct memory ids, otherwise the mixins silently
+//! overwrite each other's data.
+//!
+//! The memory ids are checked at compile time with [`assert_disjoint_memory_ids`], and the
+//! endpoints when the IDL of the canister is generated:
+//!
+//! ```ignore
+//! const METRICS_MEMORY_IDS: &[u8] = &[10, 11];
+//! const OWNER_MEMORY_IDS: &[u8] = &[20];
+//! const CANISTER_MEMORY_IDS: &[u8] = &[0, 1, 2];
+//!
+//! ic_canister::assert_disjoint_memory_ids!(METRICS_MEMORY_IDS, OWNER_MEMORY_IDS, CANISTER_MEMORY_IDS);
+//!
+//! pub fn idl() -> Result<Idl, CompositionError> {
+//!     CanisterComposition::new(generate_idl!())
+//!         .with(Mixin::new("metrics", <MyCanister as Metrics>::get_idl()).with_memory_ids(METRICS_MEMORY_IDS))
+//!         .with(Mixin::new("owner", <MyCanister as Ownable>::get_idl()).with_memory_ids(OWNER_MEMORY_IDS))
+//!         .idl()
+//! }
+//! ```
+//!
+//! The endpoints of every mixin are exported with [`generate_exports!`](crate::generate_exports)
+//! as for any trait canister.
+
+use std::collections::HashMap;
+
+use ic_exports::candid::types::{Type, TypeInner};
+use thiserror::Error;
+
+use crate::Idl;
+
+/// Name of the canister's own endpoints in the errors
+pub const CANISTER_MIXIN_NAME: &str = "canister";
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum CompositionError {
+    #[error("method `{method}` is declared by both `{first}` and `{second}`")]
+    DuplicateMethod {
+        method: String,
+        first: &'static str,
+        second: &'static str,
+    },
+
+    #[error("memory id {memory_id} is used by both `{first}` and `{second}`")]
+    DuplicateMemoryId {
+        memory_id: u8,
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+/// A building block of a canister: the endpoints and the memory ids of a trait canister.
+pub struct Mixin {
+    name: &'static str,
+    idl: Idl,
+    memory_ids: &'static [u8],
+}
+
+impl Mixin {
+    /// Creates a mixin with the endpoints of the IDL, usually returned by the `get_idl()` method of
+    /// the trait canister.
+    pub fn new(name: &'static str, idl: Idl) -> Self {
+        Self {
+            name,
+            idl,
+            memory_ids: &[],
+        }
+    }
+
+    /// Declares the memory ids of the stable structures of the mixin.
+    pub fn with_memory_ids(mut self, memory_ids: &'static [u8]) -> Self {
+        self.memory_ids = memory_ids;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn memory_ids(&self) -> &'static [u8] {
+        self.memory_ids
+    }
+
+    /// Returns the names of the endpoints of the mixin.
+    pub fn methods(&self) -> Vec<&str> {
+        service_methods(&self.idl.actor)
+    }
+}
+
+/// A canister assembled from its own endpoints and a set of mixins.
+pub struct CanisterComposition {
+    canister: Mixin,
+    mixins: Vec<Mixin>,
+}
+
+impl CanisterComposition {
+    /// Starts the composition with the canister's own IDL, usually from `generate_idl!()`.
+    pub fn new(canister_idl: Idl) -> Self {
+        Self {
+            canister: Mixin::new(CANISTER_MIXIN_NAME, canister_idl),
+            mixins: vec![],
+        }
+    }
+
+    /// Declares the memory ids of the canister's own stable structures.
+    pub fn with_memory_ids(mut self, memory_ids: &'static [u8]) -> Self {
+        self.canister = self.canister.with_memory_ids(memory_ids);
+        self
+    }
+
+    /// Adds a mixin to the canister.
+    pub fn with(mut self, mixin: Mixin) -> Self {
+        self.mixins.push(mixin);
+        self
+    }
+
+    /// Checks that the endpoints and the memory ids of the canister and its mixins are distinct.
+    pub fn validate(&self) -> Result<(), CompositionError> {
+        let mut methods = HashMap::new();
+        let mut memory_ids = HashMap::new();
+        for mixin in self.parts() {
+            for method in mixin.methods() {
+                if let Some(first) = methods.insert(method, mixin.name) {
+                    return Err(CompositionError::DuplicateMethod {
+                        method: method.to_string(),
+                        first,
+                        second: mixin.name,
+                    });
+                }
+            }
+
+            for &memory_id in mixin.memory_ids {
+                if let Some(first) = memory_ids.insert(memory_id, mixin.name) {
+                    return Err(CompositionError::DuplicateMemoryId {
+                        memory_id,
+                        first,
+                        second: mixin.name,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the composition and returns the IDL of the canister with the endpoints of all the
+    /// mixins.
+    pub fn idl(self) -> Result<Idl, CompositionError> {
+        self.validate()?;
+
+        let mut idl = self.canister.idl;
+        for mixin in &self.mixins {
+            idl.merge(&mixin.idl);
+        }
+        Ok(idl)
+    }
+
+    fn parts(&self) -> impl Iterator<Item = &Mixin> {
+        std::iter::once(&self.canister).chain(&self.mixins)
+    }
+}
+
+fn service_methods(actor: &Type) -> Vec<&str> {
+    match actor.0.as_ref() {
+        TypeInner::Service(methods) => methods.iter().map(|(name, _)| name.as_str()).collect(),
+        TypeInner::Class(_, service) => service_methods(service),
+        _ => vec![],
+    }
+}
+
+/// Returns the first memory id used by more than one of the sets, if any.
+pub const fn find_duplicate_memory_id(sets: &[&[u8]]) -> Option<u8> {
+    let mut used = [false; 256];
+    let mut i = 0;
+    while i < sets.len() {
+        let mut j = 0;
+        while j < sets[i].len() {
+            let memory_id = sets[i][j];
+            if used[memory_id as usize] {
+                return Some(memory_id);
+            }
+            used[memory_id as usize] = true;
+            j += 1;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Fails to compile if any memory id is used by more than one of the given sets of memory ids,
+/// e.g. the constants declaring the memory ids of the mixins of a canister.
+#[macro_export]
+macro_rules! assert_disjoint_memory_ids {
+    ($($memory_ids:expr),+ $(,)?) => {
+        const _: () = ::std::assert!(
+            $crate::mixin::find_duplicate_memory_id(&[$($memory_ids),+]).is_none(),
+            "the same memory id is used by several mixins of the canister",
+        );
+    };
+}
@@ -5,7 +5,13 @@ pub use utils::*;
 
 pub mod principal;
 
+pub mod routing;
+
+pub mod self_upgrade;
+
 pub mod types;
 pub use types::*;
 
 pub mod tokens;
+
+pub mod wasm;
@@ -106,6 +106,35 @@ struct ProvisionalTopUpCanisterInput {
     pub amount: Nat,
 }
 
+#[derive(CandidType, Deserialize)]
+struct TakeCanisterSnapshotInput {
+    pub canister_id: CanisterId,
+    pub replace_snapshot: Option<SnapshotId>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LoadCanisterSnapshotInput {
+    pub canister_id: CanisterId,
+    pub snapshot_id: SnapshotId,
+    pub sender_canister_version: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct DeleteCanisterSnapshotInput {
+    pub canister_id: CanisterId,
+    pub snapshot_id: SnapshotId,
+}
+
+pub type SnapshotId = Vec<u8>;
+
+/// A snapshot of the memories of a canister, taken by the management canister
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CanisterSnapshot {
+    pub id: SnapshotId,
+    pub taken_at_timestamp: u64,
+    pub total_size: u64,
+}
+
 #[async_trait]
 pub trait ManagementPrincipalExt: Sealed {
     fn accept_cycles() -> u64;
@@ -143,6 +172,14 @@ pub trait ManagementPrincipalExt: Sealed {
     async fn deposit_cycles(&self) -> Result<(), (RejectionCode, String)>;
     async fn raw_rand(&self) -> Result<Vec<u8>, (RejectionCode, String)>;
     async fn provisional_top_up(&self, amount: Nat) -> Result<(), (RejectionCode, String)>;
+    async fn take_snapshot(
+        &self,
+        replace_snapshot: Option<SnapshotId>,
+    ) -> Result<CanisterSnapshot, (RejectionCode, String)>;
+    async fn load_snapshot(&self, snapshot_id: SnapshotId) -> Result<(), (RejectionCode, String)>;
+    async fn list_snapshots(&self) -> Result<Vec<CanisterSnapshot>, (RejectionCode, String)>;
+    async fn delete_snapshot(&self, snapshot_id: SnapshotId)
+        -> Result<(), (RejectionCode, String)>;
 }
 
 #[async_trait]
@@ -345,6 +382,64 @@ impl ManagementPrincipalExt for Principal {
         )
         .await
     }
+
+    /// Takes a snapshot of a stopped canister, replacing the given snapshot if any.
+    async fn take_snapshot(
+        &self,
+        replace_snapshot: Option<SnapshotId>,
+    ) -> Result<CanisterSnapshot, (RejectionCode, String)> {
+        virtual_canister_call!(
+            Principal::management_canister(),
+            "take_canister_snapshot",
+            (TakeCanisterSnapshotInput {
+                canister_id: *self,
+                replace_snapshot,
+            },),
+            CanisterSnapshot
+        )
+        .await
+    }
+
+    /// Restores the memories and the code of a stopped canister from the snapshot.
+    async fn load_snapshot(&self, snapshot_id: SnapshotId) -> Result<(), (RejectionCode, String)> {
+        virtual_canister_call!(
+            Principal::management_canister(),
+            "load_canister_snapshot",
+            (LoadCanisterSnapshotInput {
+                canister_id: *self,
+                snapshot_id,
+                sender_canister_version: None,
+            },),
+            ()
+        )
+        .await
+    }
+
+    async fn list_snapshots(&self) -> Result<Vec<CanisterSnapshot>, (RejectionCode, String)> {
+        virtual_canister_call!(
+            Principal::management_canister(),
+            "list_canister_snapshots",
+            (CanisterIDArg { canister_id: *self },),
+            Vec<CanisterSnapshot>
+        )
+        .await
+    }
+
+    async fn delete_snapshot(
+        &self,
+        snapshot_id: SnapshotId,
+    ) -> Result<(), (RejectionCode, String)> {
+        virtual_canister_call!(
+            Principal::management_canister(),
+            "delete_canister_snapshot",
+            (DeleteCanisterSnapshotInput {
+                canister_id: *self,
+                snapshot_id,
+            },),
+            ()
+        )
+        .await
+    }
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug)]
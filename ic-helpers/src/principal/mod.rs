@@ -11,3 +11,5 @@ mod private {
 pub mod ledger;
 #[cfg(feature = "management_canister")]
 pub mod management;
+#[cfg(feature = "management_canister")]
+pub mod upgrade;
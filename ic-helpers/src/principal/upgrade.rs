@@ -0,0 +1,145 @@
+//! Upgrade of child canisters protected by a snapshot.
+//!
+//! Before upgrading a child canister, a factory can take a snapshot of its memories, so a failed
+//! upgrade, or an upgrade leaving the canister unhealthy, is rolled back automatically:
+//!
+//! ```ignore
+//! let snapshot = upgrade_with_snapshot(child, wasm, (), UpgradeOptions::default(), |child| async move {
+//!     virtual_canister_call!(child, "health_check", (), bool)
+//!         .await
+//!         .map_err(|(_, msg)| msg)?
+//!         .then_some(())
+//!         .ok_or_else(|| "unhealthy".to_string())
+//! })
+//! .await?;
+//! ```
+//!
+//! The factory must be a controller of the child canister.
+
+use std::future::Future;
+
+use ic_exports::candid::utils::ArgumentEncoder;
+use ic_exports::candid::Principal;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use thiserror::Error;
+
+use super::management::{
+    CanisterSnapshot, InstallCodeMode, ManagementPrincipalExt, SnapshotId, WasmModule,
+};
+use crate::wasm::{validate_wasm, WasmRequirements, WasmValidationError};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SnapshotUpgradeError {
+    /// The module doesn't meet the requirements. The canister hasn't been touched.
+    #[error("invalid wasm module: {0}")]
+    InvalidWasm(#[from] WasmValidationError),
+
+    /// The canister could not be stopped or its snapshot could not be taken. The upgrade hasn't
+    /// been attempted.
+    #[error("failed to take a snapshot of the canister: {0}")]
+    Snapshot(String),
+
+    /// The upgrade or the health check failed, and the canister has been restored from the snapshot.
+    #[error("upgrade failed and the canister has been restored: {reason}")]
+    RolledBack {
+        reason: String,
+        snapshot: CanisterSnapshot,
+    },
+
+    /// The upgrade or the health check failed, and the snapshot could not be restored.
+    /// The snapshot is kept and can be loaded manually.
+    #[error("upgrade failed ({reason}) and the canister could not be restored: {restore_error}")]
+    RollbackFailed {
+        reason: String,
+        restore_error: String,
+        snapshot: CanisterSnapshot,
+    },
+}
+
+/// Options of [`upgrade_with_snapshot`]
+#[derive(Default)]
+pub struct UpgradeOptions {
+    /// The requirements the module is validated against before the canister is stopped
+    pub wasm_requirements: Option<WasmRequirements>,
+    /// The snapshot replaced by the new one, e.g. the one of the previous upgrade, as the number
+    /// of snapshots per canister is limited.
+    pub replace_snapshot: Option<SnapshotId>,
+    /// Delete the snapshot once the upgrade succeeded
+    pub delete_snapshot_on_success: bool,
+}
+
+/// Upgrade the canister with the given module, and restore its previous state if the upgrade or the
+/// health check fails.
+///
+/// The canister is stopped to take the snapshot, upgraded and started again before the health check.
+/// On success, the snapshot is returned, unless it is deleted according to the options.
+pub async fn upgrade_with_snapshot<T, F, Fut>(
+    canister: Principal,
+    wasm_module: WasmModule,
+    arg: T,
+    options: UpgradeOptions,
+    health_check: F,
+) -> Result<Option<CanisterSnapshot>, SnapshotUpgradeError>
+where
+    T: ArgumentEncoder + Send,
+    F: FnOnce(Principal) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    if let Some(requirements) = &options.wasm_requirements {
+        validate_wasm(&wasm_module, requirements)?;
+    }
+
+    canister
+        .stop()
+        .await
+        .map_err(|e| SnapshotUpgradeError::Snapshot(call_error(e)))?;
+    let snapshot = match canister.take_snapshot(options.replace_snapshot).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            // leave the canister as it was
+            let _ = canister.start().await;
+            return Err(SnapshotUpgradeError::Snapshot(call_error(e)));
+        }
+    };
+
+    let upgrade_result = match canister
+        .install_code(InstallCodeMode::Upgrade, wasm_module, arg)
+        .await
+    {
+        Ok(()) => match canister.start().await {
+            Ok(()) => health_check(canister).await,
+            Err(e) => Err(call_error(e)),
+        },
+        Err(e) => Err(call_error(e)),
+    };
+
+    match upgrade_result {
+        Ok(()) if options.delete_snapshot_on_success => {
+            // the upgrade succeeded: a failure to delete the snapshot is not an error
+            let _ = canister.delete_snapshot(snapshot.id).await;
+            Ok(None)
+        }
+        Ok(()) => Ok(Some(snapshot)),
+        Err(reason) => match restore(canister, &snapshot).await {
+            Ok(()) => Err(SnapshotUpgradeError::RolledBack { reason, snapshot }),
+            Err(restore_error) => Err(SnapshotUpgradeError::RollbackFailed {
+                reason,
+                restore_error,
+                snapshot,
+            }),
+        },
+    }
+}
+
+async fn restore(canister: Principal, snapshot: &CanisterSnapshot) -> Result<(), String> {
+    canister.stop().await.map_err(call_error)?;
+    canister
+        .load_snapshot(snapshot.id.clone())
+        .await
+        .map_err(call_error)?;
+    canister.start().await.map_err(call_error)
+}
+
+fn call_error((code, msg): (RejectionCode, String)) -> String {
+    format!("{code:?}: {msg}")
+}
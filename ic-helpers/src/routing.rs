@@ -0,0 +1,270 @@
+//! Blue/green routing between two implementations of a service inside a canister.
+//!
+//! The [`BlueGreenRouter`] holds the current (blue) and the new (green) implementation of a service
+//! trait and picks one of them for each caller, according to a [`RoutingConfig`] that can be
+//! changed at runtime. This permits A/B testing and gradual rollout of rewritten handlers:
+//!
+//! ```ignore
+//! trait Pricing {
+//!     fn quote(&self, amount: u64) -> u64;
+//! }
+//!
+//! let mut router: BlueGreenRouter<dyn Pricing> =
+//!     BlueGreenRouter::new(Box::new(LegacyPricing), Box::new(NewPricing));
+//!
+//! // 10% of the callers, and the team principals, use the new implementation
+//! router.set_config(RoutingConfig {
+//!     green_percent: 10,
+//!     green_allowlist: team_principals,
+//!     ..Default::default()
+//! })?;
+//!
+//! let quote = router.route(&ic::caller()).quote(amount);
+//! ```
+//!
+//! The split by percentage is sticky: a caller is always routed to the same implementation
+//! as long as the percentage doesn't change.
+
+use candid::{CandidType, Deserialize, Principal};
+use thiserror::Error;
+
+/// The implementation a call is routed to
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deployment {
+    Blue,
+    Green,
+}
+
+/// How the calls are split between the blue and the green implementations
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingConfig {
+    /// The percentage of the callers routed to the green implementation, from 0 to 100
+    pub green_percent: u8,
+    /// Callers always routed to the green implementation
+    pub green_allowlist: Vec<Principal>,
+    /// Callers always routed to the blue implementation, e.g. to exclude critical integrations
+    /// from a rollout
+    pub blue_allowlist: Vec<Principal>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RoutingError {
+    #[error("green percentage must be at most 100, got {0}")]
+    InvalidPercentage(u8),
+}
+
+/// Routes the calls to one of two implementations of a service.
+pub struct BlueGreenRouter<S: ?Sized> {
+    blue: Box<S>,
+    green: Box<S>,
+    config: RoutingConfig,
+}
+
+impl<S: ?Sized> BlueGreenRouter<S> {
+    /// Creates a router sending all the calls to the blue implementation.
+    pub fn new(blue: Box<S>, green: Box<S>) -> Self {
+        Self {
+            blue,
+            green,
+            config: RoutingConfig::default(),
+        }
+    }
+
+    /// Returns the routing configuration.
+    pub fn config(&self) -> &RoutingConfig {
+        &self.config
+    }
+
+    /// Replace the routing configuration.
+    pub fn set_config(&mut self, config: RoutingConfig) -> Result<(), RoutingError> {
+        if config.green_percent > 100 {
+            return Err(RoutingError::InvalidPercentage(config.green_percent));
+        }
+
+        self.config = config;
+        Ok(())
+    }
+
+    /// Returns the implementation the calls of the caller are routed to.
+    ///
+    /// The allowlists take precedence over the percentage, the blue allowlist being checked first.
+    pub fn deployment_for(&self, caller: &Principal) -> Deployment {
+        if self.config.blue_allowlist.contains(caller) {
+            return Deployment::Blue;
+        }
+        if self.config.green_allowlist.contains(caller) {
+            return Deployment::Green;
+        }
+
+        match caller_bucket(caller) < self.config.green_percent {
+            true => Deployment::Green,
+            false => Deployment::Blue,
+        }
+    }
+
+    /// Returns the implementation serving the caller.
+    pub fn route(&self, caller: &Principal) -> &S {
+        self.get(self.deployment_for(caller))
+    }
+
+    /// Returns the implementation serving the caller, mutably.
+    pub fn route_mut(&mut self, caller: &Principal) -> &mut S {
+        match self.deployment_for(caller) {
+            Deployment::Blue => &mut self.blue,
+            Deployment::Green => &mut self.green,
+        }
+    }
+
+    /// Returns the given implementation.
+    pub fn get(&self, deployment: Deployment) -> &S {
+        match deployment {
+            Deployment::Blue => &self.blue,
+            Deployment::Green => &self.green,
+        }
+    }
+
+    /// Replace the green implementation, e.g. with a fixed version of it.
+    pub fn set_green(&mut self, green: Box<S>) {
+        self.green = green;
+    }
+
+    /// Complete the rollout: the green implementation becomes the blue one and receives all the
+    /// calls. The previous blue implementation is returned.
+    pub fn promote_green(&mut self, next_green: Box<S>) -> Box<S> {
+        let blue = std::mem::replace(&mut self.green, next_green);
+        self.config = RoutingConfig::default();
+        std::mem::replace(&mut self.blue, blue)
+    }
+}
+
+/// Returns a bucket in `0..100` for the caller, stable across canister upgrades.
+fn caller_bucket(caller: &Principal) -> u8 {
+    // FNV-1a
+    let hash = caller
+        .as_slice()
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        });
+    (hash % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    trait Greeter {
+        fn greet(&self) -> &'static str;
+    }
+
+    struct Old;
+    struct New;
+
+    impl Greeter for Old {
+        fn greet(&self) -> &'static str {
+            "old"
+        }
+    }
+
+    impl Greeter for New {
+        fn greet(&self) -> &'static str {
+            "new"
+        }
+    }
+
+    fn principal(id: u32) -> Principal {
+        Principal::from_slice(&id.to_le_bytes())
+    }
+
+    fn router() -> BlueGreenRouter<dyn Greeter> {
+        BlueGreenRouter::new(Box::new(Old), Box::new(New))
+    }
+
+    #[test]
+    fn should_route_all_calls_to_blue_by_default() {
+        let router = router();
+        assert!((0..100).all(|id| router.route(&principal(id)).greet() == "old"));
+    }
+
+    #[test]
+    fn should_split_by_percentage() {
+        let mut router = router();
+        router
+            .set_config(RoutingConfig {
+                green_percent: 30,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let green = (0..10_000)
+            .filter(|id| router.deployment_for(&principal(*id)) == Deployment::Green)
+            .count();
+        assert!((2_500..3_500).contains(&green));
+
+        // the routing is sticky
+        let caller = principal(42);
+        let deployment = router.deployment_for(&caller);
+        assert!((0..10).all(|_| router.deployment_for(&caller) == deployment));
+
+        router
+            .set_config(RoutingConfig {
+                green_percent: 100,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!((0..100).all(|id| router.route(&principal(id)).greet() == "new"));
+    }
+
+    #[test]
+    fn should_apply_allowlists() {
+        let mut router = router();
+        router
+            .set_config(RoutingConfig {
+                green_percent: 100,
+                green_allowlist: vec![principal(1)],
+                blue_allowlist: vec![principal(1), principal(2)],
+            })
+            .unwrap();
+
+        assert_eq!(router.deployment_for(&principal(1)), Deployment::Blue);
+        assert_eq!(router.deployment_for(&principal(2)), Deployment::Blue);
+        assert_eq!(router.deployment_for(&principal(3)), Deployment::Green);
+
+        router
+            .set_config(RoutingConfig {
+                green_allowlist: vec![principal(1)],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(router.route(&principal(1)).greet(), "new");
+        assert_eq!(router.route(&principal(3)).greet(), "old");
+    }
+
+    #[test]
+    fn should_reject_invalid_percentage() {
+        let mut router = router();
+        assert_eq!(
+            router.set_config(RoutingConfig {
+                green_percent: 101,
+                ..Default::default()
+            }),
+            Err(RoutingError::InvalidPercentage(101))
+        );
+    }
+
+    #[test]
+    fn should_promote_green() {
+        let mut router = router();
+        router
+            .set_config(RoutingConfig {
+                green_percent: 50,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let old = router.promote_green(Box::new(Old));
+        assert_eq!(old.greet(), "old");
+        assert_eq!(router.config(), &RoutingConfig::default());
+        assert!((0..100).all(|id| router.route(&principal(id)).greet() == "new"));
+    }
+}
@@ -0,0 +1,522 @@
+//! Controlled self-upgrade of a canister through proposals.
+//!
+//! A canister can't install code on itself, so the upgrade is performed by an upgrader canister,
+//! e.g. the factory, which is a controller of the canister. [`SelfUpgrade`] gates the upgrade:
+//! 1. an admin proposes the hash of the new module, then uploads the module in chunks;
+//! 2. once the module matches the hash, the admins approve the proposal;
+//! 3. with enough approvals, the proposal becomes executable after a timelock;
+//! 4. an admin executes the proposal: the module is sent to the upgrader with a one-way call,
+//!    so no call of the canister is pending while it is upgraded;
+//! 5. the canister completes the proposal in its `post_upgrade`, or the upgrader reports the failure.
+//!
+//! The upgrader canister must implement the following interface:
+//!
+//! ```candid
+//! service : {
+//!   upgrade_canister : (record { canister_id : principal; wasm_module : blob; arg : blob; wasm_hash : blob }) -> ();
+//! }
+//! ```
+//!
+//! The [`SelfUpgrade`] state must be preserved across upgrades, e.g. in a stable cell:
+//!
+//! ```ignore
+//! #[update]
+//! fn execute_upgrade(&self) -> Result<(), SelfUpgradeError> {
+//!     let request = self.upgrade.borrow_mut().start_execution(ic::caller(), ic::time())?;
+//!     let upgrader = self.upgrade.borrow().config().upgrader;
+//!     self_upgrade::send_upgrade_request(upgrader, request)
+//! }
+//!
+//! #[post_upgrade]
+//! fn post_upgrade(&self) {
+//!     self.upgrade.borrow_mut().complete(ic::time());
+//! }
+//! ```
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_exports::ic_cdk::api::call::notify;
+use ic_exports::ic_kit::ic;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// The method of the upgrader canister called to upgrade the canister
+pub const UPGRADE_CANISTER_METHOD: &str = "upgrade_canister";
+
+#[derive(CandidType, Deserialize, Debug, Error, Clone, PartialEq, Eq)]
+pub enum SelfUpgradeError {
+    #[error("the caller is not allowed to perform this operation")]
+    Unauthorized,
+
+    #[error("there is no upgrade proposal")]
+    NoProposal,
+
+    #[error("the upgrade proposal is {0}, the operation is not allowed")]
+    InvalidStatus(String),
+
+    #[error("the uploaded module doesn't match the proposed hash")]
+    HashMismatch,
+
+    #[error("the proposal has already been approved by the caller")]
+    AlreadyApproved,
+
+    #[error("the proposal is timelocked until {0}")]
+    Timelocked(u64),
+
+    #[error("failed to send the upgrade request: {0}")]
+    Request(String),
+}
+
+pub type SelfUpgradeResult<T> = Result<T, SelfUpgradeError>;
+
+/// The rules of the upgrades
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SelfUpgradeConfig {
+    /// The principals allowed to propose, approve and execute the upgrades
+    pub admins: Vec<Principal>,
+    /// The number of distinct admins who must approve a proposal
+    pub required_approvals: u32,
+    /// The delay between the last required approval and the execution, in nanoseconds
+    pub timelock_nanos: u64,
+    /// The canister installing the code, which must be a controller of the canister
+    pub upgrader: Principal,
+}
+
+/// The status of an upgrade proposal
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum UpgradeStatus {
+    /// The module is being uploaded
+    Uploading,
+    /// The module is uploaded, the proposal waits for approvals
+    AwaitingApprovals,
+    /// The proposal is approved and can be executed after the given timestamp
+    Approved {
+        executable_at: u64,
+    },
+    /// The module has been sent to the upgrader
+    Executing {
+        started_at: u64,
+    },
+    Completed {
+        completed_at: u64,
+    },
+    Failed {
+        reason: String,
+    },
+    Cancelled,
+}
+
+impl UpgradeStatus {
+    fn name(&self) -> String {
+        match self {
+            UpgradeStatus::Uploading => "uploading",
+            UpgradeStatus::AwaitingApprovals => "awaiting approvals",
+            UpgradeStatus::Approved { .. } => "approved",
+            UpgradeStatus::Executing { .. } => "executing",
+            UpgradeStatus::Completed { .. } => "completed",
+            UpgradeStatus::Failed { .. } => "failed",
+            UpgradeStatus::Cancelled => "cancelled",
+        }
+        .to_string()
+    }
+
+    /// True if the proposal can't change anymore
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            UpgradeStatus::Completed { .. }
+                | UpgradeStatus::Failed { .. }
+                | UpgradeStatus::Cancelled
+        )
+    }
+}
+
+/// A proposed upgrade of the canister
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UpgradeProposal {
+    pub id: u64,
+    pub proposer: Principal,
+    /// The SHA-256 hash of the module
+    pub wasm_hash: Vec<u8>,
+    /// The candid encoded argument of the upgrade
+    pub arg: Vec<u8>,
+    pub created_at: u64,
+    pub approvals: Vec<Principal>,
+    pub status: UpgradeStatus,
+    #[serde(with = "serde_bytes")]
+    wasm_module: Vec<u8>,
+}
+
+impl UpgradeProposal {
+    /// The number of bytes of the module uploaded so far
+    pub fn uploaded_bytes(&self) -> u64 {
+        self.wasm_module.len() as u64
+    }
+}
+
+/// The request sent to the upgrader canister
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UpgradeRequest {
+    pub canister_id: Principal,
+    #[serde(with = "serde_bytes")]
+    pub wasm_module: Vec<u8>,
+    pub arg: Vec<u8>,
+    pub wasm_hash: Vec<u8>,
+}
+
+/// Gates the upgrades of the canister behind proposals, approvals and a timelock.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SelfUpgrade {
+    config: SelfUpgradeConfig,
+    proposal: Option<UpgradeProposal>,
+    next_id: u64,
+}
+
+impl SelfUpgrade {
+    pub fn new(config: SelfUpgradeConfig) -> Self {
+        Self {
+            config,
+            proposal: None,
+            next_id: 0,
+        }
+    }
+
+    /// Returns the rules of the upgrades.
+    pub fn config(&self) -> &SelfUpgradeConfig {
+        &self.config
+    }
+
+    /// Replace the rules of the upgrades. The approvals of the current proposal are kept.
+    pub fn set_config(&mut self, config: SelfUpgradeConfig) {
+        self.config = config;
+    }
+
+    /// Returns the last proposal, whatever its status.
+    pub fn proposal(&self) -> Option<&UpgradeProposal> {
+        self.proposal.as_ref()
+    }
+
+    /// Propose to upgrade the canister to the module with the given hash.
+    /// A proposal which is not being executed is replaced.
+    pub fn propose(
+        &mut self,
+        caller: Principal,
+        wasm_hash: Vec<u8>,
+        arg: Vec<u8>,
+        now: u64,
+    ) -> SelfUpgradeResult<u64> {
+        self.check_admin(caller)?;
+        if let Some(proposal) = &self.proposal {
+            if let UpgradeStatus::Executing { .. } = proposal.status {
+                return Err(SelfUpgradeError::InvalidStatus(proposal.status.name()));
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.proposal = Some(UpgradeProposal {
+            id,
+            proposer: caller,
+            wasm_hash,
+            arg,
+            created_at: now,
+            approvals: vec![],
+            status: UpgradeStatus::Uploading,
+            wasm_module: vec![],
+        });
+
+        Ok(id)
+    }
+
+    /// Append a chunk to the module of the proposal. Only the proposer can upload the module.
+    pub fn upload_chunk(&mut self, caller: Principal, chunk: &[u8]) -> SelfUpgradeResult<()> {
+        let proposal = self.proposal_with_status(|status| *status == UpgradeStatus::Uploading)?;
+        if proposal.proposer != caller {
+            return Err(SelfUpgradeError::Unauthorized);
+        }
+
+        proposal.wasm_module.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    /// Complete the upload of the module and check its hash.
+    /// If the hash doesn't match, the uploaded chunks are discarded.
+    pub fn finish_upload(&mut self, caller: Principal) -> SelfUpgradeResult<()> {
+        let proposal = self.proposal_with_status(|status| *status == UpgradeStatus::Uploading)?;
+        if proposal.proposer != caller {
+            return Err(SelfUpgradeError::Unauthorized);
+        }
+
+        if Sha256::digest(&proposal.wasm_module).as_slice() != proposal.wasm_hash {
+            proposal.wasm_module.clear();
+            return Err(SelfUpgradeError::HashMismatch);
+        }
+
+        proposal.status = UpgradeStatus::AwaitingApprovals;
+        Ok(())
+    }
+
+    /// Approve the proposal. The timelock starts when the required number of approvals is reached.
+    pub fn approve(&mut self, caller: Principal, now: u64) -> SelfUpgradeResult<UpgradeStatus> {
+        self.check_admin(caller)?;
+        let required_approvals = self.config.required_approvals as usize;
+        let timelock_nanos = self.config.timelock_nanos;
+
+        let proposal =
+            self.proposal_with_status(|status| *status == UpgradeStatus::AwaitingApprovals)?;
+        if proposal.approvals.contains(&caller) {
+            return Err(SelfUpgradeError::AlreadyApproved);
+        }
+
+        proposal.approvals.push(caller);
+        if proposal.approvals.len() >= required_approvals {
+            proposal.status = UpgradeStatus::Approved {
+                executable_at: now.saturating_add(timelock_nanos),
+            };
+        }
+        Ok(proposal.status.clone())
+    }
+
+    /// Cancel the proposal, unless it is being executed.
+    pub fn cancel(&mut self, caller: Principal) -> SelfUpgradeResult<()> {
+        self.check_admin(caller)?;
+        let proposal = self.proposal_with_status(|status| {
+            !status.is_final() && !matches!(status, UpgradeStatus::Executing { .. })
+        })?;
+
+        proposal.status = UpgradeStatus::Cancelled;
+        proposal.wasm_module.clear();
+        Ok(())
+    }
+
+    /// Mark the approved proposal as executing and returns the request to send to the upgrader,
+    /// e.g. with [`send_upgrade_request`].
+    pub fn start_execution(
+        &mut self,
+        caller: Principal,
+        now: u64,
+    ) -> SelfUpgradeResult<UpgradeRequest> {
+        self.check_admin(caller)?;
+        let proposal =
+            self.proposal_with_status(|status| matches!(status, UpgradeStatus::Approved { .. }))?;
+        if let UpgradeStatus::Approved { executable_at } = proposal.status {
+            if now < executable_at {
+                return Err(SelfUpgradeError::Timelocked(executable_at));
+            }
+        }
+
+        proposal.status = UpgradeStatus::Executing { started_at: now };
+        Ok(UpgradeRequest {
+            canister_id: ic::id(),
+            wasm_module: std::mem::take(&mut proposal.wasm_module),
+            arg: proposal.arg.clone(),
+            wasm_hash: proposal.wasm_hash.clone(),
+        })
+    }
+
+    /// Mark the executing proposal as completed, to be called in the `post_upgrade` method.
+    /// Returns the completed proposal, or None if no proposal was executing, e.g. for an upgrade
+    /// which was not proposed.
+    pub fn complete(&mut self, now: u64) -> Option<&UpgradeProposal> {
+        let proposal = self
+            .proposal_with_status(|status| matches!(status, UpgradeStatus::Executing { .. }))
+            .ok()?;
+
+        proposal.status = UpgradeStatus::Completed { completed_at: now };
+        Some(proposal)
+    }
+
+    /// Mark the executing proposal as failed. Only the upgrader can report a failure.
+    pub fn fail(&mut self, caller: Principal, reason: String) -> SelfUpgradeResult<()> {
+        if caller != self.config.upgrader {
+            return Err(SelfUpgradeError::Unauthorized);
+        }
+
+        let proposal =
+            self.proposal_with_status(|status| matches!(status, UpgradeStatus::Executing { .. }))?;
+        proposal.status = UpgradeStatus::Failed { reason };
+        Ok(())
+    }
+
+    fn check_admin(&self, caller: Principal) -> SelfUpgradeResult<()> {
+        match self.config.admins.contains(&caller) {
+            true => Ok(()),
+            false => Err(SelfUpgradeError::Unauthorized),
+        }
+    }
+
+    fn proposal_with_status(
+        &mut self,
+        is_expected: impl FnOnce(&UpgradeStatus) -> bool,
+    ) -> SelfUpgradeResult<&mut UpgradeProposal> {
+        let proposal = self.proposal.as_mut().ok_or(SelfUpgradeError::NoProposal)?;
+        match is_expected(&proposal.status) {
+            true => Ok(proposal),
+            false => Err(SelfUpgradeError::InvalidStatus(proposal.status.name())),
+        }
+    }
+}
+
+/// Send the upgrade request to the upgrader with a one-way call.
+///
+/// If the request can't be sent, the proposal stays in the executing status, and must be failed
+/// with [`SelfUpgrade::fail`] by the upgrader.
+pub fn send_upgrade_request(upgrader: Principal, request: UpgradeRequest) -> SelfUpgradeResult<()> {
+    notify(upgrader, UPGRADE_CANISTER_METHOD, (request,))
+        .map_err(|code| SelfUpgradeError::Request(format!("{code:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_exports::ic_kit::MockContext;
+
+    use super::*;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+    const TIMELOCK: u64 = 1_000;
+
+    fn admin(id: u8) -> Principal {
+        Principal::from_slice(&[id; 29])
+    }
+
+    fn upgrader() -> Principal {
+        Principal::from_slice(&[42; 29])
+    }
+
+    fn self_upgrade() -> SelfUpgrade {
+        SelfUpgrade::new(SelfUpgradeConfig {
+            admins: vec![admin(1), admin(2), admin(3)],
+            required_approvals: 2,
+            timelock_nanos: TIMELOCK,
+            upgrader: upgrader(),
+        })
+    }
+
+    fn uploaded_proposal(upgrade: &mut SelfUpgrade) {
+        let hash = Sha256::digest(WASM).to_vec();
+        upgrade.propose(admin(1), hash, vec![], 0).unwrap();
+        upgrade.upload_chunk(admin(1), &WASM[..4]).unwrap();
+        upgrade.upload_chunk(admin(1), &WASM[4..]).unwrap();
+        upgrade.finish_upload(admin(1)).unwrap();
+    }
+
+    #[test]
+    fn should_execute_approved_proposal_after_timelock() {
+        MockContext::new().inject();
+        let mut upgrade = self_upgrade();
+        uploaded_proposal(&mut upgrade);
+        assert_eq!(
+            upgrade.proposal().unwrap().uploaded_bytes(),
+            WASM.len() as u64
+        );
+
+        assert_eq!(
+            upgrade.start_execution(admin(1), 10),
+            Err(SelfUpgradeError::InvalidStatus(
+                "awaiting approvals".to_string()
+            ))
+        );
+        assert_eq!(
+            upgrade.approve(admin(1), 10),
+            Ok(UpgradeStatus::AwaitingApprovals)
+        );
+        assert_eq!(
+            upgrade.approve(admin(1), 10),
+            Err(SelfUpgradeError::AlreadyApproved)
+        );
+        assert_eq!(
+            upgrade.approve(admin(2), 20),
+            Ok(UpgradeStatus::Approved {
+                executable_at: 20 + TIMELOCK
+            })
+        );
+
+        assert_eq!(
+            upgrade.start_execution(admin(3), 20),
+            Err(SelfUpgradeError::Timelocked(20 + TIMELOCK))
+        );
+        let request = upgrade.start_execution(admin(3), 20 + TIMELOCK).unwrap();
+        assert_eq!(request.wasm_module, WASM);
+        assert_eq!(request.wasm_hash, Sha256::digest(WASM).to_vec());
+
+        assert_eq!(
+            upgrade.cancel(admin(1)),
+            Err(SelfUpgradeError::InvalidStatus("executing".to_string()))
+        );
+        assert_eq!(
+            upgrade.complete(5_000).unwrap().status,
+            UpgradeStatus::Completed {
+                completed_at: 5_000
+            }
+        );
+        assert!(upgrade.complete(6_000).is_none());
+    }
+
+    #[test]
+    fn should_reject_unauthorized_callers() {
+        let mut upgrade = self_upgrade();
+        let outsider = admin(9);
+
+        assert_eq!(
+            upgrade.propose(outsider, vec![], vec![], 0),
+            Err(SelfUpgradeError::Unauthorized)
+        );
+        uploaded_proposal(&mut upgrade);
+        assert_eq!(
+            upgrade.approve(outsider, 0),
+            Err(SelfUpgradeError::Unauthorized)
+        );
+        assert_eq!(
+            upgrade.fail(admin(1), "error".to_string()),
+            Err(SelfUpgradeError::Unauthorized)
+        );
+
+        upgrade.propose(admin(1), vec![], vec![], 0).unwrap();
+        assert_eq!(
+            upgrade.upload_chunk(admin(2), WASM),
+            Err(SelfUpgradeError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn should_reject_module_not_matching_hash() {
+        let mut upgrade = self_upgrade();
+        upgrade
+            .propose(admin(1), Sha256::digest(WASM).to_vec(), vec![], 0)
+            .unwrap();
+        upgrade.upload_chunk(admin(1), &WASM[..4]).unwrap();
+
+        assert_eq!(
+            upgrade.finish_upload(admin(1)),
+            Err(SelfUpgradeError::HashMismatch)
+        );
+        assert_eq!(upgrade.proposal().unwrap().uploaded_bytes(), 0);
+        assert_eq!(upgrade.proposal().unwrap().status, UpgradeStatus::Uploading);
+    }
+
+    #[test]
+    fn should_report_failures_and_cancellations() {
+        MockContext::new().inject();
+        let mut upgrade = self_upgrade();
+        uploaded_proposal(&mut upgrade);
+        upgrade.approve(admin(1), 0).unwrap();
+        upgrade.approve(admin(2), 0).unwrap();
+        upgrade.start_execution(admin(1), TIMELOCK).unwrap();
+
+        upgrade.fail(upgrader(), "trap".to_string()).unwrap();
+        assert_eq!(
+            upgrade.proposal().unwrap().status,
+            UpgradeStatus::Failed {
+                reason: "trap".to_string()
+            }
+        );
+
+        let id = upgrade.propose(admin(2), vec![], vec![], 0).unwrap();
+        assert_eq!(id, 1);
+        upgrade.cancel(admin(3)).unwrap();
+        assert_eq!(upgrade.proposal().unwrap().status, UpgradeStatus::Cancelled);
+        assert_eq!(
+            upgrade.cancel(admin(3)),
+            Err(SelfUpgradeError::InvalidStatus("cancelled".to_string()))
+        );
+    }
+}
@@ -0,0 +1,485 @@
+//! Validation of canister wasm modules before they are installed.
+//!
+//! [`validate_wasm`] parses a wasm module, plain or gzipped, and checks it against a set of
+//! [`WasmRequirements`]: the size limit, the methods exported for an expected Candid interface and
+//! the metadata sections, e.g. `candid:service` or `git_commit_id`. A factory can reject a broken
+//! module before any `install_code` call:
+//!
+//! ```ignore
+//! let requirements = WasmRequirements::default()
+//!     .with_interface(MyCanister::idl())
+//!     .with_metadata("candid:service");
+//! let info = validate_wasm(&wasm, &requirements)?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+
+use candid::types::internal::FuncMode;
+use ic_canister::Idl;
+use thiserror::Error;
+
+/// The maximum size of a module passed to `install_code`
+pub const MAX_INSTALL_CODE_WASM_SIZE: usize = 2 * 1024 * 1024;
+
+/// The maximum size of a decompressed module
+pub const MAX_DECOMPRESSED_WASM_SIZE: usize = 100 * 1024 * 1024;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const WASM_VERSION: &[u8] = &[1, 0, 0, 0];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+const CUSTOM_SECTION_ID: u8 = 0;
+const EXPORT_SECTION_ID: u8 = 7;
+const FUNC_EXPORT_KIND: u8 = 0;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WasmValidationError {
+    #[error("the module size {size} exceeds the limit of {max_size} bytes")]
+    TooLarge { size: usize, max_size: usize },
+
+    #[error("failed to decompress the module: {0}")]
+    Decompression(String),
+
+    #[error("malformed wasm module: {0}")]
+    Malformed(String),
+
+    #[error("the module doesn't match the interface: {}", .0.join(", "))]
+    InterfaceMismatch(Vec<String>),
+
+    #[error("missing metadata section {0}")]
+    MissingMetadata(String),
+}
+
+/// The kind of a canister method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    Query,
+    CompositeQuery,
+    Update,
+}
+
+impl fmt::Display for MethodKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MethodKind::Query => write!(f, "query"),
+            MethodKind::CompositeQuery => write!(f, "composite query"),
+            MethodKind::Update => write!(f, "update"),
+        }
+    }
+}
+
+/// The information extracted from a wasm module
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasmInfo {
+    /// The size of the decompressed module
+    pub size: usize,
+    /// The canister methods exported by the module
+    pub methods: BTreeMap<String, MethodKind>,
+    /// The content of the public and private metadata sections, by name without the
+    /// `icp:public ` or `icp:private ` prefix
+    pub metadata: BTreeMap<String, Vec<u8>>,
+}
+
+/// The checks performed by [`validate_wasm`]
+pub struct WasmRequirements {
+    /// The maximum size of the module, compressed if it is gzipped
+    pub max_size: usize,
+    /// The interface whose methods must be exported with the same kind
+    pub interface: Option<Idl>,
+    /// The metadata sections which must be present
+    pub metadata: Vec<String>,
+}
+
+impl Default for WasmRequirements {
+    fn default() -> Self {
+        Self {
+            max_size: MAX_INSTALL_CODE_WASM_SIZE,
+            interface: None,
+            metadata: vec![],
+        }
+    }
+}
+
+impl WasmRequirements {
+    /// Set the maximum size of the module.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Require the methods of the interface to be exported.
+    pub fn with_interface(mut self, interface: Idl) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Require the metadata section with the given name, e.g. `candid:service`.
+    pub fn with_metadata(mut self, name: impl Into<String>) -> Self {
+        self.metadata.push(name.into());
+        self
+    }
+}
+
+/// Parse the module, plain or gzipped, and check it against the requirements.
+pub fn validate_wasm(
+    wasm: &[u8],
+    requirements: &WasmRequirements,
+) -> Result<WasmInfo, WasmValidationError> {
+    if wasm.len() > requirements.max_size {
+        return Err(WasmValidationError::TooLarge {
+            size: wasm.len(),
+            max_size: requirements.max_size,
+        });
+    }
+
+    let info = if wasm.starts_with(GZIP_MAGIC) {
+        parse_wasm(&decompress(wasm)?)?
+    } else {
+        parse_wasm(wasm)?
+    };
+
+    if let Some(interface) = &requirements.interface {
+        check_interface(&info, interface)?;
+    }
+
+    if let Some(missing) = requirements
+        .metadata
+        .iter()
+        .find(|name| !info.metadata.contains_key(*name))
+    {
+        return Err(WasmValidationError::MissingMetadata(missing.clone()));
+    }
+
+    Ok(info)
+}
+
+/// Parse the exported methods and the metadata sections of a plain wasm module.
+pub fn parse_wasm(wasm: &[u8]) -> Result<WasmInfo, WasmValidationError> {
+    let mut reader = Reader::new(wasm);
+    if reader.bytes(4)? != WASM_MAGIC {
+        return Err(malformed("invalid magic number"));
+    }
+    if reader.bytes(4)? != WASM_VERSION {
+        return Err(malformed("unsupported version"));
+    }
+
+    let mut info = WasmInfo {
+        size: wasm.len(),
+        ..Default::default()
+    };
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader::new(reader.bytes(size)?);
+
+        match id {
+            CUSTOM_SECTION_ID => {
+                let name = section.name()?;
+                let metadata_name = name
+                    .strip_prefix("icp:public ")
+                    .or_else(|| name.strip_prefix("icp:private "));
+                if let Some(metadata_name) = metadata_name {
+                    info.metadata
+                        .insert(metadata_name.to_string(), section.rest().to_vec());
+                }
+            }
+            EXPORT_SECTION_ID => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    let kind = section.byte()?;
+                    section.u32()?;
+
+                    if kind != FUNC_EXPORT_KIND {
+                        continue;
+                    }
+                    if let Some((method, kind)) = canister_method(&name) {
+                        info.methods.insert(method.to_string(), kind);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(info)
+}
+
+/// Returns the method exported by the function, if any
+fn canister_method(export: &str) -> Option<(&str, MethodKind)> {
+    if let Some(method) = export.strip_prefix("canister_query ") {
+        Some((method, MethodKind::Query))
+    } else if let Some(method) = export.strip_prefix("canister_composite_query ") {
+        Some((method, MethodKind::CompositeQuery))
+    } else {
+        export
+            .strip_prefix("canister_update ")
+            .map(|method| (method, MethodKind::Update))
+    }
+}
+
+fn check_interface(info: &WasmInfo, interface: &Idl) -> Result<(), WasmValidationError> {
+    let env = &interface.env.env;
+    let service = env
+        .as_service(&interface.actor)
+        .map_err(|e| WasmValidationError::InterfaceMismatch(vec![e.to_string()]))?;
+
+    let mut mismatches = vec![];
+    for (name, ty) in service {
+        let expected = match env.as_func(ty) {
+            Ok(func) if func.modes.contains(&FuncMode::CompositeQuery) => {
+                MethodKind::CompositeQuery
+            }
+            Ok(func) if func.modes.contains(&FuncMode::Query) => MethodKind::Query,
+            Ok(_) => MethodKind::Update,
+            Err(e) => {
+                mismatches.push(format!("{name}: {e}"));
+                continue;
+            }
+        };
+
+        match info.methods.get(name) {
+            None => mismatches.push(format!("{name} is not exported")),
+            Some(kind) if *kind != expected => mismatches.push(format!(
+                "{name} is exported as {kind} instead of {expected}"
+            )),
+            Some(_) => {}
+        }
+    }
+
+    match mismatches.is_empty() {
+        true => Ok(()),
+        false => Err(WasmValidationError::InterfaceMismatch(mismatches)),
+    }
+}
+
+fn decompress(wasm: &[u8]) -> Result<Vec<u8>, WasmValidationError> {
+    let mut decompressed = vec![];
+    flate2::read::GzDecoder::new(wasm)
+        .take(MAX_DECOMPRESSED_WASM_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| WasmValidationError::Decompression(e.to_string()))?;
+
+    if decompressed.len() > MAX_DECOMPRESSED_WASM_SIZE {
+        return Err(WasmValidationError::TooLarge {
+            size: decompressed.len(),
+            max_size: MAX_DECOMPRESSED_WASM_SIZE,
+        });
+    }
+
+    Ok(decompressed)
+}
+
+fn malformed(reason: &str) -> WasmValidationError {
+    WasmValidationError::Malformed(reason.to_string())
+}
+
+/// Reads the wasm binary encoding
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn byte(&mut self) -> Result<u8, WasmValidationError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], WasmValidationError> {
+        if self.data.len() < len {
+            return Err(malformed("unexpected end of the module"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Reads an unsigned LEB128 integer
+    fn u32(&mut self) -> Result<u32, WasmValidationError> {
+        let mut result = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            result |= ((byte & 0x7f) as u32)
+                .checked_shl(shift)
+                .ok_or_else(|| malformed("invalid integer"))?;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(malformed("invalid integer"))
+    }
+
+    fn name(&mut self) -> Result<String, WasmValidationError> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("invalid UTF-8 name"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use candid::types::internal::TypeContainer;
+    use candid::types::{Function, TypeInner};
+
+    use super::*;
+
+    fn leb(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        [leb(name.len()), name.as_bytes().to_vec()].concat()
+    }
+
+    fn section(id: u8, content: Vec<u8>) -> Vec<u8> {
+        [vec![id], leb(content.len()), content].concat()
+    }
+
+    /// A module exporting the given functions, with a public metadata section
+    fn module(exports: &[&str]) -> Vec<u8> {
+        let mut export_section = leb(exports.len());
+        for (index, export) in exports.iter().enumerate() {
+            export_section.extend(name(export));
+            export_section.push(FUNC_EXPORT_KIND);
+            export_section.extend(leb(index));
+        }
+
+        [
+            WASM_MAGIC.to_vec(),
+            WASM_VERSION.to_vec(),
+            section(EXPORT_SECTION_ID, export_section),
+            section(
+                CUSTOM_SECTION_ID,
+                [name("icp:public git_commit_id"), b"abc123".to_vec()].concat(),
+            ),
+            section(CUSTOM_SECTION_ID, name("name")),
+        ]
+        .concat()
+    }
+
+    fn interface(methods: &[(&str, Option<FuncMode>)]) -> Idl {
+        let service = methods
+            .iter()
+            .map(|(name, mode)| {
+                let func = TypeInner::Func(Function {
+                    modes: mode.iter().cloned().collect(),
+                    args: vec![],
+                    rets: vec![],
+                });
+                (name.to_string(), func.into())
+            })
+            .collect();
+        Idl::new(TypeContainer::new(), TypeInner::Service(service).into())
+    }
+
+    #[test]
+    fn should_parse_module() {
+        let wasm = module(&[
+            "canister_query get",
+            "canister_update set",
+            "canister_composite_query aggregate",
+            "canister_init",
+            "memory_helper",
+        ]);
+
+        let info = parse_wasm(&wasm).unwrap();
+        assert_eq!(info.size, wasm.len());
+        assert_eq!(
+            info.methods.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("aggregate".to_string(), MethodKind::CompositeQuery),
+                ("get".to_string(), MethodKind::Query),
+                ("set".to_string(), MethodKind::Update),
+            ]
+        );
+        assert_eq!(info.metadata["git_commit_id"], b"abc123");
+
+        assert!(matches!(
+            parse_wasm(b"\0asm"),
+            Err(WasmValidationError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_wasm(&wasm[..wasm.len() - 1]),
+            Err(WasmValidationError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn should_check_interface() {
+        let wasm = module(&["canister_query get", "canister_update set"]);
+
+        let requirements = WasmRequirements::default()
+            .with_interface(interface(&[("get", Some(FuncMode::Query)), ("set", None)]));
+        assert!(validate_wasm(&wasm, &requirements).is_ok());
+
+        let requirements = WasmRequirements::default().with_interface(interface(&[
+            ("get", None),
+            ("set", None),
+            ("remove", None),
+        ]));
+        assert_eq!(
+            validate_wasm(&wasm, &requirements),
+            Err(WasmValidationError::InterfaceMismatch(vec![
+                "get is exported as query instead of update".to_string(),
+                "remove is not exported".to_string(),
+            ]))
+        );
+    }
+
+    #[test]
+    fn should_check_metadata_and_size() {
+        let wasm = module(&[]);
+
+        let requirements = WasmRequirements::default().with_metadata("git_commit_id");
+        assert!(validate_wasm(&wasm, &requirements).is_ok());
+
+        let requirements = requirements.with_metadata("candid:service");
+        assert_eq!(
+            validate_wasm(&wasm, &requirements),
+            Err(WasmValidationError::MissingMetadata(
+                "candid:service".to_string()
+            ))
+        );
+
+        let requirements = WasmRequirements::default().with_max_size(10);
+        assert!(matches!(
+            validate_wasm(&wasm, &requirements),
+            Err(WasmValidationError::TooLarge { max_size: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn should_validate_gzipped_module() {
+        let wasm = module(&["canister_update set"]);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&wasm).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let info = validate_wasm(&gzipped, &WasmRequirements::default()).unwrap();
+        assert_eq!(info.size, wasm.len());
+        assert_eq!(info.methods["set"], MethodKind::Update);
+    }
+}
@@ -0,0 +1,145 @@
+//! Fault injection for chaos testing, enabled by the `chaos` feature.
+//!
+//! When a [`FaultConfig`] is set, the functions of the [`ic`](crate::ic) module misbehave:
+//! - inter-canister calls are rejected with `RejectionCode::SysTransient` at the configured rate;
+//! - the time returned by `ic::time` is shifted by the configured offset.
+//!
+//! Other crates of the SDK read the configuration when their `chaos` feature is enabled, e.g. to
+//! make memory grow operations fail or to delay the execution of the scheduler tasks.
+//!
+//! The configuration lives in the canister heap, so each PocketIC test can set its own faults
+//! through a test-only endpoint of the canister:
+//! ```ignore
+//! #[cfg(feature = "chaos")]
+//! #[update]
+//! fn set_fault_config(&self, config: ic_kit::chaos::FaultConfig) {
+//!     ic_kit::chaos::set_fault_config(config);
+//! }
+//! ```
+//! The faults are drawn from a pseudo random generator seeded by the configuration, so a failing
+//! test can be reproduced with the same seed.
+
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use candid::{CandidType, Deserialize};
+
+/// The faults to inject. Rates are expressed in per mille, 0 disables the fault.
+#[derive(CandidType, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct FaultConfig {
+    /// Seed of the generator used to draw the faults
+    pub seed: u64,
+    /// Rate of the inter-canister calls rejected without being sent
+    pub call_reject_per_mille: u16,
+    /// Rate of the memory grow operations that fail
+    pub memory_grow_failure_per_mille: u16,
+    /// Delay added before the execution of each async task
+    pub task_delay_millis: u64,
+    /// Offset added to the time returned by `ic::time`, can be negative
+    pub clock_offset_nanos: i64,
+}
+
+thread_local! {
+    static CONFIG: RefCell<FaultConfig> = RefCell::new(FaultConfig::default());
+    static RNG_STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Set the faults to inject and reseed the generator.
+pub fn set_fault_config(config: FaultConfig) {
+    // xorshift state must not be zero
+    RNG_STATE.with(|state| state.set(config.seed.max(1)));
+    CONFIG.with(|current| *current.borrow_mut() = config);
+}
+
+/// Returns the current fault configuration.
+pub fn fault_config() -> FaultConfig {
+    CONFIG.with(|config| config.borrow().clone())
+}
+
+/// Disable all the faults.
+pub fn reset() {
+    set_fault_config(FaultConfig::default());
+}
+
+/// Draws whether the next inter-canister call must be rejected.
+pub fn should_reject_call() -> bool {
+    roll(CONFIG.with(|config| config.borrow().call_reject_per_mille))
+}
+
+/// Draws whether the next memory grow operation must fail.
+pub fn should_fail_memory_grow() -> bool {
+    roll(CONFIG.with(|config| config.borrow().memory_grow_failure_per_mille))
+}
+
+/// Returns the delay to add before the execution of an async task.
+pub fn task_delay() -> Duration {
+    Duration::from_millis(CONFIG.with(|config| config.borrow().task_delay_millis))
+}
+
+/// Applies the configured clock offset to the given time in nanoseconds.
+pub fn adjust_time(time: u64) -> u64 {
+    let offset = CONFIG.with(|config| config.borrow().clock_offset_nanos);
+    time.saturating_add_signed(offset)
+}
+
+fn roll(per_mille: u16) -> bool {
+    if per_mille == 0 {
+        return false;
+    }
+
+    let value = RNG_STATE.with(|state| {
+        let mut x = state.get().max(1);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    });
+    value % 1000 < per_mille as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_inject_faults_by_default() {
+        reset();
+        assert!((0..1000).all(|_| !should_reject_call() && !should_fail_memory_grow()));
+        assert_eq!(task_delay(), Duration::ZERO);
+        assert_eq!(adjust_time(100), 100);
+    }
+
+    #[test]
+    fn should_inject_faults_at_the_configured_rate() {
+        set_fault_config(FaultConfig {
+            seed: 42,
+            call_reject_per_mille: 1000,
+            memory_grow_failure_per_mille: 500,
+            task_delay_millis: 10,
+            clock_offset_nanos: -50,
+        });
+
+        assert!((0..100).all(|_| should_reject_call()));
+        let failures = (0..10_000).filter(|_| should_fail_memory_grow()).count();
+        assert!((4_000..6_000).contains(&failures));
+        assert_eq!(task_delay(), Duration::from_millis(10));
+        assert_eq!(adjust_time(100), 50);
+        assert_eq!(adjust_time(10), 0);
+    }
+
+    #[test]
+    fn faults_are_reproducible() {
+        let config = FaultConfig {
+            seed: 7,
+            call_reject_per_mille: 300,
+            ..Default::default()
+        };
+
+        set_fault_config(config.clone());
+        let first: Vec<_> = (0..100).map(|_| should_reject_call()).collect();
+        set_fault_config(config);
+        let second: Vec<_> = (0..100).map(|_| should_reject_call()).collect();
+        assert_eq!(first, second);
+    }
+}
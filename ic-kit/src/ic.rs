@@ -30,6 +30,9 @@ pub fn id() -> Principal {
 /// The time in nanoseconds.
 #[inline(always)]
 pub fn time() -> u64 {
+    #[cfg(feature = "chaos")]
+    return crate::chaos::adjust_time(get_context().time());
+    #[cfg(not(feature = "chaos"))]
     get_context().time()
 }
 
@@ -130,6 +133,10 @@ pub fn call_raw<S: Into<String>>(
     args_raw: Vec<u8>,
     cycles: u64,
 ) -> CallResponse<Vec<u8>> {
+    #[cfg(feature = "chaos")]
+    if crate::chaos::should_reject_call() {
+        return Box::pin(async { Err(injected_call_rejection()) });
+    }
     get_context().call_raw(id, method, args_raw, cycles)
 }
 
@@ -140,7 +147,7 @@ pub fn call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>, S: Into<String>>
     method: S,
     args: T,
 ) -> CallResponse<R> {
-    get_context().call_with_payment(id, method, args, 0)
+    call_with_payment(id, method, args, 0)
 }
 
 #[inline(always)]
@@ -150,9 +157,21 @@ pub fn call_with_payment<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>, S:
     args: T,
     cycles: u64,
 ) -> CallResponse<R> {
+    #[cfg(feature = "chaos")]
+    if crate::chaos::should_reject_call() {
+        return Box::pin(async { Err(injected_call_rejection()) });
+    }
     get_context().call_with_payment(id, method, args, cycles)
 }
 
+#[cfg(feature = "chaos")]
+fn injected_call_rejection() -> (crate::RejectionCode, String) {
+    (
+        crate::RejectionCode::SysTransient,
+        "call rejected by fault injection".to_string(),
+    )
+}
+
 /// Set the certified data of the canister, this method traps if data.len > 32.
 #[inline(always)]
 pub fn set_certified_data(data: &[u8]) {
@@ -2,6 +2,8 @@ pub use handler::*;
 pub use interface::*;
 pub use mock::*;
 
+#[cfg(feature = "chaos")]
+pub mod chaos;
 mod handler;
 pub mod inject;
 mod interface;
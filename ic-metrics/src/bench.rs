@@ -0,0 +1,170 @@
+//! Micro-benchmarks executed inside the canister, to measure the instructions spent on a real subnet.
+//!
+//! Benchmarks are registered with [`register_benchmark`], usually in the canister `init` and
+//! `post_upgrade` methods, and exposed with the [`export_benchmarks`](crate::export_benchmarks) macro:
+//!
+//! ```ignore
+//! ic_metrics::export_benchmarks!();
+//!
+//! #[init]
+//! fn init(&self) {
+//!     ic_metrics::bench::register_benchmark("insert_1000", || {
+//!         let mut map = StableBTreeMap::new(VectorMemory::default());
+//!         for i in 0..1000u64 {
+//!             map.insert(i, i);
+//!         }
+//!     });
+//! }
+//! ```
+//!
+//! The endpoints are generated only when the canister is built with its `bench` feature enabled:
+//! ```toml
+//! [features]
+//! bench = []
+//! ```
+//! They are not part of the canister candid interface:
+//! - `__bench_list : () -> (vec text) query` returns the names of the registered benchmarks;
+//! - `__bench_run : (vec text, nat32) -> (vec BenchResult) query` runs the benchmarks with the given
+//!   names, or all of them if no name is given, for the given number of iterations.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use ic_exports::candid::{CandidType, Deserialize};
+
+type Benchmark = Box<dyn Fn()>;
+
+thread_local! {
+    static BENCHMARKS: RefCell<BTreeMap<String, Benchmark>> = RefCell::new(BTreeMap::new());
+}
+
+/// The result of a benchmark run
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BenchResult {
+    pub name: String,
+    pub iterations: u32,
+    pub total_instructions: u64,
+    pub instructions_per_iteration: u64,
+}
+
+/// Register a benchmark with the given name, replacing the one previously registered with the same name.
+pub fn register_benchmark(name: impl Into<String>, bench: impl Fn() + 'static) {
+    BENCHMARKS.with(|benchmarks| benchmarks.borrow_mut().insert(name.into(), Box::new(bench)));
+}
+
+/// Returns the names of the registered benchmarks, sorted.
+pub fn benchmark_names() -> Vec<String> {
+    BENCHMARKS.with(|benchmarks| benchmarks.borrow().keys().cloned().collect())
+}
+
+/// Run the benchmark with the given name `iterations` times.
+/// Returns None if no benchmark is registered with the name.
+pub fn run_benchmark(name: &str, iterations: u32) -> Option<BenchResult> {
+    BENCHMARKS.with(|benchmarks| {
+        let benchmarks = benchmarks.borrow();
+        let bench = benchmarks.get(name)?;
+        let iterations = iterations.max(1);
+
+        let start = instruction_counter();
+        for _ in 0..iterations {
+            bench();
+        }
+        let total_instructions = instruction_counter().saturating_sub(start);
+
+        Some(BenchResult {
+            name: name.to_string(),
+            iterations,
+            total_instructions,
+            instructions_per_iteration: total_instructions / iterations as u64,
+        })
+    })
+}
+
+/// Run the benchmarks with the given names, or all the registered benchmarks if `names` is empty.
+/// Unknown names are skipped.
+pub fn run_benchmarks(names: Vec<String>, iterations: u32) -> Vec<BenchResult> {
+    let names = match names.is_empty() {
+        true => benchmark_names(),
+        false => names,
+    };
+
+    names
+        .iter()
+        .filter_map(|name| run_benchmark(name, iterations))
+        .collect()
+}
+
+/// Returns the number of instructions executed in the current message.
+///
+/// This function is only available for the wasm target and returns 0 on other targets.
+pub fn instruction_counter() -> u64 {
+    #[cfg(target_family = "wasm")]
+    {
+        ic_exports::ic_cdk::api::performance_counter(0)
+    }
+    #[cfg(not(target_family = "wasm"))]
+    {
+        0
+    }
+}
+
+/// Generates the hidden `__bench_list` and `__bench_run` query endpoints running the registered
+/// benchmarks. The endpoints are generated only if the `bench` feature of the canister crate is enabled.
+/// See the [`bench`](crate::bench) module.
+#[macro_export]
+macro_rules! export_benchmarks {
+    () => {
+        #[cfg(all(target_family = "wasm", feature = "bench"))]
+        #[export_name = "canister_query __bench_list"]
+        fn __bench_list() {
+            ::ic_exports::ic_cdk::setup();
+            ::ic_exports::ic_cdk::api::call::reply(($crate::bench::benchmark_names(),));
+        }
+
+        #[cfg(all(target_family = "wasm", feature = "bench"))]
+        #[export_name = "canister_query __bench_run"]
+        fn __bench_run() {
+            ::ic_exports::ic_cdk::setup();
+            let (names, iterations): (Vec<String>, u32) =
+                ::ic_exports::ic_cdk::api::call::arg_data(Default::default());
+            ::ic_exports::ic_cdk::api::call::reply(($crate::bench::run_benchmarks(
+                names, iterations,
+            ),));
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn should_run_registered_benchmarks() {
+        let runs = Rc::new(Cell::new(0));
+        let runs_t = runs.clone();
+        register_benchmark("b", move || runs_t.set(runs_t.get() + 1));
+        register_benchmark("a", || ());
+
+        assert_eq!(benchmark_names(), vec!["a".to_string(), "b".to_string()]);
+
+        let result = run_benchmark("b", 3).unwrap();
+        assert_eq!(result.name, "b");
+        assert_eq!(result.iterations, 3);
+        assert_eq!(runs.get(), 3);
+        assert_eq!(run_benchmark("c", 3), None);
+
+        let results = run_benchmarks(vec![], 1);
+        assert_eq!(
+            results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert_eq!(runs.get(), 4);
+
+        let results = run_benchmarks(vec!["b".to_string(), "c".to_string()], 2);
+        assert_eq!(results.len(), 1);
+        assert_eq!(runs.get(), 6);
+    }
+}
@@ -0,0 +1,48 @@
+//! Opt-in queries of the call rates and of the calls in flight of the canister methods.
+//!
+//! A canister exposing them implements [`CallMetrics`] next to [`Metrics`](crate::Metrics):
+//!
+//! ```ignore
+//! impl Metrics for MyCanister {}
+//! impl ic_metrics::call_metrics::CallMetrics for MyCanister {}
+//! ```
+
+use candid::Principal;
+use ic_canister::{generate_exports, generate_idl, query, Canister, Idl, PreUpdate};
+
+use crate::inflight::{self, InFlightHealth, MethodInFlight};
+use crate::rates::{self, MethodRate, RateWindow};
+
+/// Queries of the [`rates`](crate::rates) and of the calls [`inflight`](crate::inflight).
+///
+/// They are not part of [`Metrics`](crate::Metrics), so implementing it doesn't change the candid
+/// interface of a canister. Canisters exposing them implement this trait as well and merge its idl.
+pub trait CallMetrics: Canister {
+    /// Returns the call rates of the methods over the given window.
+    #[query(trait = true)]
+    fn get_rate_metrics(&self, window: RateWindow) -> Vec<MethodRate> {
+        rates::rates(window)
+    }
+
+    /// Returns the async calls in flight of the methods.
+    #[query(trait = true)]
+    fn get_in_flight_metrics(&self) -> Vec<MethodInFlight> {
+        inflight::in_flight()
+    }
+
+    /// Health check of the calls in flight: unhealthy if a call is in flight for longer than
+    /// `max_call_age_secs`, e.g. because its callback never completed.
+    #[query(trait = true)]
+    fn get_in_flight_health(&self, max_call_age_secs: u64) -> InFlightHealth {
+        inflight::health(max_call_age_secs.saturating_mul(1_000_000_000))
+    }
+
+    // Important: This function *must* be defined to be the
+    // last one in the trait because it depends on the order
+    // of expansion of update/query(trait = true) methods.
+    fn get_idl() -> Idl {
+        generate_idl!()
+    }
+}
+
+generate_exports!(CallMetrics);
@@ -0,0 +1,218 @@
+//! Tracking of the async update calls in flight, by method.
+//!
+//! A call is counted from the start of the method until its future completes, across the awaits,
+//! by holding the guard returned by [`track_call`]:
+//!
+//! ```ignore
+//! #[update]
+//! async fn transfer(&self, args: TransferArgs) -> Result<u64, Error> {
+//!     let _guard = ic_metrics::inflight::track_call("transfer");
+//!     self.do_transfer(args).await
+//! }
+//! ```
+//!
+//! When a callback traps, the future of the call is dropped by the cleanup of the CDK, which drops
+//! the guard as well. A call staying in flight for long is a callback which never completed, and
+//! is reported by the `get_in_flight_health` query of the
+//! [`CallMetrics`](crate::call_metrics::CallMetrics) trait.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use ic_exports::candid::{CandidType, Deserialize};
+
+/// The calls in flight of a method
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MethodInFlight {
+    pub method: String,
+    /// Number of calls in flight
+    pub in_flight: u64,
+    /// The largest number of calls in flight at the same time
+    pub peak: u64,
+    /// Start timestamp in nanoseconds of the oldest call in flight
+    pub oldest_started_at: Option<u64>,
+}
+
+/// Health of the calls in flight
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InFlightHealth {
+    /// False if any call is in flight for longer than the maximum age
+    pub healthy: bool,
+    /// Number of calls in flight, for all the methods
+    pub in_flight: u64,
+    /// The methods with calls in flight for longer than the maximum age
+    pub stuck_methods: Vec<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+struct MethodCalls {
+    /// Start timestamps of the calls in flight, by call id
+    started_at: BTreeMap<u64, u64>,
+    peak: u64,
+}
+
+impl MethodCalls {
+    fn oldest_started_at(&self) -> Option<u64> {
+        self.started_at.values().min().copied()
+    }
+}
+
+/// The calls in flight of the methods.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct InFlightMetrics {
+    methods: BTreeMap<String, MethodCalls>,
+    next_call_id: u64,
+}
+
+impl InFlightMetrics {
+    /// Counts the start of a call of the method at the given timestamp, returns the id of the call.
+    pub fn start_call_at(&mut self, method: &str, now: u64) -> u64 {
+        let call_id = self.next_call_id;
+        self.next_call_id += 1;
+
+        let calls = self.methods.entry(method.to_string()).or_default();
+        calls.started_at.insert(call_id, now);
+        calls.peak = calls.peak.max(calls.started_at.len() as u64);
+        call_id
+    }
+
+    /// Counts the completion of the call.
+    pub fn finish_call(&mut self, method: &str, call_id: u64) {
+        if let Some(calls) = self.methods.get_mut(method) {
+            calls.started_at.remove(&call_id);
+        }
+    }
+
+    /// Returns the calls in flight of every method which has been called.
+    pub fn in_flight(&self) -> Vec<MethodInFlight> {
+        self.methods
+            .iter()
+            .map(|(method, calls)| MethodInFlight {
+                method: method.clone(),
+                in_flight: calls.started_at.len() as u64,
+                peak: calls.peak,
+                oldest_started_at: calls.oldest_started_at(),
+            })
+            .collect()
+    }
+
+    /// Returns the health of the calls in flight at the given timestamp: a call started more than
+    /// `max_call_age_nanos` ago is considered stuck.
+    pub fn health_at(&self, max_call_age_nanos: u64, now: u64) -> InFlightHealth {
+        let stuck_methods: Vec<String> = self
+            .methods
+            .iter()
+            .filter(|(_, calls)| {
+                calls
+                    .oldest_started_at()
+                    .is_some_and(|started_at| now.saturating_sub(started_at) > max_call_age_nanos)
+            })
+            .map(|(method, _)| method.clone())
+            .collect();
+
+        InFlightHealth {
+            healthy: stuck_methods.is_empty(),
+            in_flight: self
+                .methods
+                .values()
+                .map(|calls| calls.started_at.len() as u64)
+                .sum(),
+            stuck_methods,
+        }
+    }
+}
+
+thread_local! {
+    static IN_FLIGHT: RefCell<InFlightMetrics> = RefCell::new(InFlightMetrics::default());
+}
+
+/// Counts a call of the method as in flight until the guard is dropped.
+#[must_use = "the call is counted as completed when the guard is dropped"]
+pub struct InFlightGuard {
+    method: String,
+    call_id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|metrics| metrics.borrow_mut().finish_call(&self.method, self.call_id));
+    }
+}
+
+/// Counts a call of the method as in flight until the returned guard is dropped.
+pub fn track_call(method: &str) -> InFlightGuard {
+    let call_id = IN_FLIGHT.with(|metrics| {
+        metrics
+            .borrow_mut()
+            .start_call_at(method, ic_exports::ic_kit::ic::time())
+    });
+
+    InFlightGuard {
+        method: method.to_string(),
+        call_id,
+    }
+}
+
+/// Returns the calls in flight of every method.
+pub fn in_flight() -> Vec<MethodInFlight> {
+    IN_FLIGHT.with(|metrics| metrics.borrow().in_flight())
+}
+
+/// Returns the health of the calls in flight, see [`InFlightMetrics::health_at`].
+pub fn health(max_call_age_nanos: u64) -> InFlightHealth {
+    IN_FLIGHT.with(|metrics| {
+        metrics
+            .borrow()
+            .health_at(max_call_age_nanos, ic_exports::ic_kit::ic::time())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_calls_in_flight() {
+        let mut metrics = InFlightMetrics::default();
+        let first = metrics.start_call_at("transfer", 100);
+        let second = metrics.start_call_at("transfer", 200);
+        let other = metrics.start_call_at("approve", 300);
+        metrics.finish_call("approve", other);
+        metrics.finish_call("transfer", first);
+
+        assert_eq!(
+            metrics.in_flight(),
+            vec![
+                MethodInFlight {
+                    method: "approve".to_string(),
+                    in_flight: 0,
+                    peak: 1,
+                    oldest_started_at: None,
+                },
+                MethodInFlight {
+                    method: "transfer".to_string(),
+                    in_flight: 1,
+                    peak: 2,
+                    oldest_started_at: Some(200),
+                },
+            ]
+        );
+
+        metrics.finish_call("transfer", second);
+        assert_eq!(metrics.in_flight()[1].in_flight, 0);
+    }
+
+    #[test]
+    fn should_report_stuck_methods() {
+        let mut metrics = InFlightMetrics::default();
+        metrics.start_call_at("transfer", 100);
+        metrics.start_call_at("approve", 900);
+
+        let health = metrics.health_at(500, 1_000);
+        assert!(!health.healthy);
+        assert_eq!(health.in_flight, 2);
+        assert_eq!(health.stuck_methods, vec!["transfer".to_string()]);
+
+        assert!(metrics.health_at(1_000, 1_000).healthy);
+    }
+}
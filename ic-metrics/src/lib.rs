@@ -51,6 +51,21 @@
 //! overwritten.
 //!
 //! For the further example you can refer to the tests in the `canister-b` crate.
+//!
+//! The `wasm64` feature enables the wasm64 target, it requires a nightly toolchain. This crate is the
+//! only one reading the wasm memory directly: the storage and scheduler crates only use 64 bits
+//! stable memory offsets and sizes, and build for wasm64 without any feature.
+
+#![cfg_attr(all(target_arch = "wasm64", feature = "wasm64"), feature(simd_wasm64))]
+
+#[cfg(all(target_arch = "wasm64", not(feature = "wasm64")))]
+compile_error!("the wasm64 target requires the `wasm64` feature");
+
+pub mod bench;
+pub mod call_metrics;
+pub mod inflight;
+pub mod rates;
+pub mod shipper;
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -141,10 +156,14 @@ fn curr_values() -> MetricsData {
             }
         },
         heap_memory_size: {
-            #[cfg(target_family = "wasm")]
+            #[cfg(target_arch = "wasm32")]
             {
                 (core::arch::wasm32::memory_size(0) as u64) * WASM_PAGE_SIZE
             }
+            #[cfg(all(target_arch = "wasm64", feature = "wasm64"))]
+            {
+                (core::arch::wasm64::memory_size(0) as u64) * WASM_PAGE_SIZE
+            }
             #[cfg(not(target_family = "wasm"))]
             {
                 0
@@ -0,0 +1,194 @@
+//! Windowed call rates of the canister methods.
+//!
+//! The calls are counted in buckets of [`BUCKET_SECS`] seconds, kept for the largest window, so the
+//! rates over the last minute, five minutes or hour can be computed in the canister instead of
+//! exporting raw counters and computing deltas externally:
+//!
+//! ```ignore
+//! #[update]
+//! async fn transfer(&self, args: TransferArgs) -> Result<u64, Error> {
+//!     let result = self.do_transfer(args).await;
+//!     ic_metrics::rates::record_call("transfer", result.is_err());
+//!     result
+//! }
+//! ```
+//!
+//! The rates are exposed by the `get_rate_metrics` query of the
+//! [`CallMetrics`](crate::call_metrics::CallMetrics) trait.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use ic_exports::candid::{CandidType, Deserialize};
+
+/// The duration of a bucket in seconds
+pub const BUCKET_SECS: u64 = 10;
+
+/// The window over which the rates are computed
+#[derive(CandidType, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RateWindow {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+}
+
+impl RateWindow {
+    pub fn secs(&self) -> u64 {
+        match self {
+            RateWindow::OneMinute => 60,
+            RateWindow::FiveMinutes => 5 * 60,
+            RateWindow::OneHour => 60 * 60,
+        }
+    }
+}
+
+/// The rates of a method over a window
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct MethodRate {
+    pub method: String,
+    pub window: RateWindow,
+    pub calls: u64,
+    pub errors: u64,
+    pub calls_per_sec: f64,
+    pub errors_per_min: f64,
+    /// The ratio of failed calls, 0 if there were no calls
+    pub error_rate: f64,
+}
+
+#[derive(CandidType, Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+struct CallCounters {
+    calls: u64,
+    errors: u64,
+}
+
+/// Counts the calls of the methods in time buckets.
+#[derive(CandidType, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct RateMetrics {
+    /// The counters of each method, by bucket start timestamp in seconds
+    methods: BTreeMap<String, BTreeMap<u64, CallCounters>>,
+}
+
+impl RateMetrics {
+    /// Count a call of the method at the given timestamp.
+    pub fn record_call_at(&mut self, method: &str, is_error: bool, now_secs: u64) {
+        let buckets = self.methods.entry(method.to_string()).or_default();
+
+        let counters = buckets
+            .entry(now_secs - now_secs % BUCKET_SECS)
+            .or_default();
+        counters.calls += 1;
+        counters.errors += is_error as u64;
+
+        // Keep only the buckets of the largest window
+        let oldest_to_keep = now_secs.saturating_sub(RateWindow::OneHour.secs() + BUCKET_SECS);
+        *buckets = buckets.split_off(&oldest_to_keep);
+    }
+
+    /// Returns the rates of every method over the window ending at the given timestamp.
+    pub fn rates_at(&self, window: RateWindow, now_secs: u64) -> Vec<MethodRate> {
+        let window_start = now_secs.saturating_sub(window.secs());
+
+        self.methods
+            .iter()
+            .map(|(method, buckets)| {
+                let CallCounters { calls, errors } = buckets.range(window_start..).fold(
+                    CallCounters::default(),
+                    |acc, (_, counters)| CallCounters {
+                        calls: acc.calls + counters.calls,
+                        errors: acc.errors + counters.errors,
+                    },
+                );
+
+                let window_secs = window.secs() as f64;
+                MethodRate {
+                    method: method.clone(),
+                    window,
+                    calls,
+                    errors,
+                    calls_per_sec: calls as f64 / window_secs,
+                    errors_per_min: errors as f64 * 60.0 / window_secs,
+                    error_rate: match calls {
+                        0 => 0.0,
+                        _ => errors as f64 / calls as f64,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+thread_local! {
+    static RATES: RefCell<RateMetrics> = RefCell::new(RateMetrics::default());
+}
+
+/// Count a call of the method, `is_error` tells whether the call failed.
+pub fn record_call(method: &str, is_error: bool) {
+    RATES.with(|rates| {
+        rates
+            .borrow_mut()
+            .record_call_at(method, is_error, now_secs())
+    });
+}
+
+/// Returns the rates of every method over the last window.
+pub fn rates(window: RateWindow) -> Vec<MethodRate> {
+    RATES.with(|rates| rates.borrow().rates_at(window, now_secs()))
+}
+
+fn now_secs() -> u64 {
+    ic_exports::ic_kit::ic::time() / 1_000_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_rates_over_windows() {
+        let mut metrics = RateMetrics::default();
+        let now = 10_000;
+
+        // 60 calls in the last minute, 20 of them failed
+        for i in 0..60 {
+            metrics.record_call_at("transfer", i % 3 == 0, now - 59 + i);
+        }
+        // 240 older calls, all succeeded
+        for i in 0..240 {
+            metrics.record_call_at("transfer", false, now - 300 + i);
+        }
+        metrics.record_call_at("balance", false, now - 1_000);
+
+        let rates = metrics.rates_at(RateWindow::OneMinute, now);
+        assert_eq!(rates.len(), 2);
+        let balance = &rates[0];
+        assert_eq!(balance.method, "balance");
+        assert_eq!(balance.calls, 0);
+        assert_eq!(balance.error_rate, 0.0);
+
+        let transfer = &rates[1];
+        assert_eq!(transfer.calls, 60);
+        assert_eq!(transfer.errors, 20);
+        assert_eq!(transfer.calls_per_sec, 1.0);
+        assert_eq!(transfer.errors_per_min, 20.0);
+
+        let rates = metrics.rates_at(RateWindow::FiveMinutes, now);
+        assert_eq!(rates[1].calls, 300);
+        assert_eq!(rates[1].calls_per_sec, 1.0);
+        assert_eq!(rates[1].errors_per_min, 4.0);
+
+        let rates = metrics.rates_at(RateWindow::OneHour, now);
+        assert_eq!(rates[0].calls, 1);
+    }
+
+    #[test]
+    fn should_drop_buckets_older_than_the_largest_window() {
+        let mut metrics = RateMetrics::default();
+        metrics.record_call_at("transfer", false, 0);
+        metrics.record_call_at("transfer", true, 10_000);
+
+        assert_eq!(metrics.methods["transfer"].len(), 1);
+        let rates = metrics.rates_at(RateWindow::OneHour, 10_000);
+        assert_eq!(rates[0].calls, 1);
+        assert_eq!(rates[0].error_rate, 1.0);
+    }
+}
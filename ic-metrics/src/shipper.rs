@@ -0,0 +1,87 @@
+//! Periodic push of the metrics to an aggregator canister.
+//!
+//! The canisters of a fleet, e.g. the ones spawned by a factory, can report into one place by
+//! starting a shipper in their `init` and `post_upgrade` methods:
+//!
+//! ```ignore
+//! #[init]
+//! fn init(&self, aggregator: Principal) {
+//!     ic_metrics::shipper::start_metrics_shipper(aggregator, Duration::from_secs(300));
+//! }
+//! ```
+//!
+//! The aggregator canister must expose the `push_metrics : (MetricsSnapshot) -> ()` update method,
+//! the caller of the method is the canister the metrics belong to.
+
+use std::time::Duration;
+
+use candid::Principal;
+use ic_canister_client::{CanisterClient, CanisterClientResult, IcCanisterClient};
+use ic_exports::candid::{CandidType, Deserialize};
+
+use crate::rates::{self, MethodRate, RateWindow};
+use crate::{curr_values, MetricsData};
+
+/// The metrics of a canister at a point in time
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    pub timestamp_nanos: u64,
+    pub data: MetricsData,
+    /// The call rates of the methods over the last minute, five minutes and hour
+    pub rates: Vec<MethodRate>,
+}
+
+impl MetricsSnapshot {
+    /// Take a snapshot of the current metrics of the canister.
+    pub fn current() -> Self {
+        Self {
+            timestamp_nanos: ic_exports::ic_kit::ic::time(),
+            data: curr_values(),
+            rates: [
+                RateWindow::OneMinute,
+                RateWindow::FiveMinutes,
+                RateWindow::OneHour,
+            ]
+            .into_iter()
+            .flat_map(rates::rates)
+            .collect(),
+        }
+    }
+}
+
+/// Typed client of an aggregator canister.
+#[derive(Debug, Clone)]
+pub struct AggregatorClient<C: CanisterClient> {
+    client: C,
+}
+
+impl<C: CanisterClient> AggregatorClient<C> {
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+
+    /// Push the snapshot to the aggregator.
+    pub async fn push_metrics(&self, snapshot: MetricsSnapshot) -> CanisterClientResult<()> {
+        self.client.update("push_metrics", (snapshot,)).await
+    }
+}
+
+/// Push a snapshot of the metrics to the aggregator canister at the given interval.
+///
+/// A push which fails is not retried, the next snapshot is pushed at the next interval.
+///
+/// This function is only available for the wasm target and won't do
+/// anything on other targets
+pub fn start_metrics_shipper(aggregator: Principal, interval: Duration) {
+    if cfg!(target_family = "wasm") {
+        use ic_exports::ic_cdk_timers;
+
+        let client = AggregatorClient::new(IcCanisterClient::new(aggregator));
+        ic_cdk_timers::set_timer_interval(interval, move || {
+            let client = client.clone();
+            ic_exports::ic_kit::ic::spawn(async move {
+                let _ = client.push_metrics(MetricsSnapshot::current()).await;
+            });
+        });
+    }
+}
@@ -0,0 +1,198 @@
+//! Generator of an example canister wiring together the crates of the SDK: stable state, task
+//! scheduler, metrics, role-based access control and a PocketIC test suite.
+//!
+//! The generated crate depends on the SDK crates by path, so it builds against the checkout
+//! it's generated from:
+//!
+//! ```sh
+//! cargo run -p ic-scaffold -- my-notes --out ../my-notes
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ScaffoldError {
+    #[error("invalid crate name `{0}`: expected lowercase letters, digits, `-` or `_`, starting with a letter")]
+    InvalidName(String),
+
+    #[error("the output directory {0} already exists")]
+    OutputExists(PathBuf),
+
+    #[error("failed to write {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, ScaffoldError>;
+
+/// Files of the generated crate: path and template
+const TEMPLATES: &[(&str, &str)] = &[
+    ("Cargo.toml", include_str!("../templates/Cargo.toml.tmpl")),
+    ("README.md", include_str!("../templates/README.md.tmpl")),
+    ("src/main.rs", include_str!("../templates/main.rs.tmpl")),
+    ("src/lib.rs", include_str!("../templates/lib.rs.tmpl")),
+    (
+        "src/canister.rs",
+        include_str!("../templates/canister.rs.tmpl"),
+    ),
+    ("src/state.rs", include_str!("../templates/state.rs.tmpl")),
+    ("src/tasks.rs", include_str!("../templates/tasks.rs.tmpl")),
+    (
+        "tests/pocket_ic.rs",
+        include_str!("../templates/pocket_ic.rs.tmpl"),
+    ),
+];
+
+/// The example canister to generate
+#[derive(Debug, Clone)]
+pub struct Scaffold {
+    crate_name: String,
+    sdk_path: PathBuf,
+}
+
+impl Scaffold {
+    /// Creates the scaffold of a crate with the name, depending on the SDK of this checkout.
+    pub fn new(crate_name: &str) -> Result<Self> {
+        let valid = crate_name.starts_with(|c: char| c.is_ascii_lowercase())
+            && crate_name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid {
+            return Err(ScaffoldError::InvalidName(crate_name.to_string()));
+        }
+
+        Ok(Self {
+            crate_name: crate_name.to_string(),
+            sdk_path: Path::new(env!("CARGO_MANIFEST_DIR"))
+                .parent()
+                .expect("the scaffold crate is in the SDK workspace")
+                .to_path_buf(),
+        })
+    }
+
+    /// Sets the path of the SDK checkout the generated crate depends on.
+    pub fn with_sdk_path(mut self, sdk_path: impl Into<PathBuf>) -> Self {
+        self.sdk_path = sdk_path.into();
+        self
+    }
+
+    /// Name of the canister struct: the crate name in upper camel case.
+    pub fn struct_name(&self) -> String {
+        self.crate_name
+            .split(['-', '_'])
+            .map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Renders the files of the crate, with their path relative to the crate root.
+    pub fn render(&self) -> Vec<(&'static str, String)> {
+        let crate_ident = self.crate_name.replace('-', "_");
+        let wasm_name = format!("{}.wasm", self.crate_name);
+        let struct_name = self.struct_name();
+        let sdk_path = self.sdk_path.display().to_string();
+
+        TEMPLATES
+            .iter()
+            .map(|(path, template)| {
+                let content = template
+                    .replace("{{crate_name}}", &self.crate_name)
+                    .replace("{{crate_ident}}", &crate_ident)
+                    .replace("{{struct_name}}", &struct_name)
+                    .replace("{{wasm_name}}", &wasm_name)
+                    .replace("{{sdk_path}}", &sdk_path);
+                (*path, content)
+            })
+            .collect()
+    }
+
+    /// Writes the crate to the directory, which must not exist, and returns the written files.
+    pub fn generate(&self, out_dir: &Path) -> Result<Vec<PathBuf>> {
+        if out_dir.exists() {
+            return Err(ScaffoldError::OutputExists(out_dir.to_path_buf()));
+        }
+
+        let mut written = Vec::with_capacity(TEMPLATES.len());
+        for (path, content) in self.render() {
+            let path = out_dir.join(path);
+            let parent = path
+                .parent()
+                .expect("the files are in the output directory");
+            fs::create_dir_all(parent)
+                .and_then(|()| fs::write(&path, content))
+                .map_err(|source| ScaffoldError::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+            written.push(path);
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_render_templates() {
+        let scaffold = Scaffold::new("my-notes").unwrap().with_sdk_path("/sdk");
+        assert_eq!(scaffold.struct_name(), "MyNotes");
+
+        for (path, content) in scaffold.render() {
+            assert!(!content.contains("{{"), "placeholder left in {path}");
+        }
+
+        let files = scaffold.render();
+        let (_, manifest) = files
+            .iter()
+            .find(|(path, _)| *path == "Cargo.toml")
+            .unwrap();
+        assert!(manifest.contains("name = \"my-notes\""));
+        assert!(manifest.contains("path = \"/sdk/ic-task-scheduler\""));
+        let (_, tests) = files
+            .iter()
+            .find(|(path, _)| *path == "tests/pocket_ic.rs")
+            .unwrap();
+        assert!(tests.contains("use my_notes::{Role, MyNotes};"));
+        assert!(tests.contains("release/my-notes.wasm"));
+    }
+
+    #[test]
+    fn should_generate_crate() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("my-notes");
+        let scaffold = Scaffold::new("my-notes").unwrap();
+
+        let files = scaffold.generate(&out_dir).unwrap();
+        assert_eq!(files.len(), TEMPLATES.len());
+        assert!(out_dir.join("src/canister.rs").exists());
+        assert!(out_dir.join("tests/pocket_ic.rs").exists());
+
+        assert!(matches!(
+            scaffold.generate(&out_dir),
+            Err(ScaffoldError::OutputExists(_))
+        ));
+    }
+
+    #[test]
+    fn should_reject_invalid_names() {
+        for name in ["", "My-Notes", "1notes", "notes!"] {
+            assert!(matches!(
+                Scaffold::new(name),
+                Err(ScaffoldError::InvalidName(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use ic_scaffold::Scaffold;
+
+const USAGE: &str = "usage: canister-sdk-scaffold <crate-name> [--out <dir>] [--sdk-path <dir>]";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(crate_name) = args.next() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let mut out_dir = PathBuf::from(&crate_name);
+    let mut sdk_path = None;
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--out", Some(value)) => out_dir = value.into(),
+            ("--sdk-path", Some(value)) => sdk_path = Some(PathBuf::from(value)),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let scaffold = Scaffold::new(&crate_name).map(|scaffold| match sdk_path {
+        Some(sdk_path) => scaffold.with_sdk_path(sdk_path),
+        None => scaffold,
+    });
+    match scaffold.and_then(|scaffold| scaffold.generate(&out_dir)) {
+        Ok(files) => {
+            for file in files {
+                println!("created {}", file.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use candid::{CandidType, Deserialize};
+use thiserror::Error;
+
+use crate::SessionId;
+
+#[derive(CandidType, Debug, Error, Deserialize, PartialEq, Eq, Clone)]
+pub enum SessionError {
+    #[error("the anonymous principal cannot open a session")]
+    AnonymousCaller,
+    #[error("session {0} not found")]
+    NotFound(SessionId),
+    #[error("session {0} has expired")]
+    Expired(SessionId),
+    #[error("session {0} belongs to another user")]
+    Unauthorized(SessionId),
+    #[error("stable memory error: {0}")]
+    StableMemory(String),
+}
+
+impl From<ic_stable_structures::Error> for SessionError {
+    fn from(e: ic_stable_structures::Error) -> Self {
+        Self::StableMemory(e.to_string())
+    }
+}
+
+/// Result type for the session management
+pub type Result<T> = std::result::Result<T, SessionError>;
@@ -0,0 +1,14 @@
+//! Session management for canisters whose users authenticate with Internet Identity.
+//!
+//! A session is created by an authenticated update call: the caller principal is the one
+//! derived from the Internet Identity delegation, so it is used as the user id of the session.
+//! Sessions are stored in stable memory together with the metadata of the device that opened
+//! them, and they expire after a configurable time to live.
+
+mod error;
+mod manager;
+mod session;
+
+pub use error::{Result, SessionError};
+pub use manager::SessionManager;
+pub use session::{DeviceInfo, Session, SessionId, Timestamp};
@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use candid::Principal;
+use ic_exports::ic_kit::ic;
+use ic_stable_structures::stable_structures::Memory;
+use ic_stable_structures::{
+    BTreeMapStructure, CellStructure, MultimapStructure, PrincipalKey, StableBTreeMap, StableCell,
+    StableMultimap,
+};
+
+use crate::{DeviceInfo, Result, Session, SessionError, SessionId, Timestamp};
+
+/// Stores the sessions of the users in stable memory.
+pub struct SessionManager<M: Memory> {
+    /// Sessions by id
+    sessions: StableBTreeMap<SessionId, Session, M>,
+    /// Expiration index, used to remove expired sessions in order
+    expirations: StableBTreeMap<(Timestamp, SessionId), PrincipalKey, M>,
+    /// Sessions of each user with their expiration time
+    user_sessions: StableMultimap<PrincipalKey, SessionId, Timestamp, M>,
+    /// Id of the last created session
+    last_session_id: StableCell<SessionId, M>,
+    session_ttl: Duration,
+}
+
+impl<M: Memory> SessionManager<M> {
+    /// Creates a new session manager.
+    /// Sessions created by the manager expire after `session_ttl`.
+    pub fn new(
+        sessions_memory: M,
+        expirations_memory: M,
+        user_sessions_memory: M,
+        last_session_id_memory: M,
+        session_ttl: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            sessions: StableBTreeMap::new(sessions_memory),
+            expirations: StableBTreeMap::new(expirations_memory),
+            user_sessions: StableMultimap::new(user_sessions_memory),
+            last_session_id: StableCell::new(last_session_id_memory, 0)?,
+            session_ttl,
+        })
+    }
+
+    /// Opens a new session for the caller on the given device.
+    ///
+    /// Must be called from an authenticated update call: the caller principal, derived from the
+    /// Internet Identity delegation, becomes the user id of the session.
+    pub fn create_session(&mut self, device: DeviceInfo) -> Result<SessionId> {
+        let user = ic::caller();
+        if user == Principal::anonymous() {
+            return Err(SessionError::AnonymousCaller);
+        }
+
+        let now = ic::time();
+        let id = *self.last_session_id.get() + 1;
+        self.last_session_id.set(id)?;
+
+        let session = Session {
+            user,
+            device,
+            created_at: now,
+            expires_at: now.saturating_add(self.session_ttl.as_nanos() as u64),
+        };
+        self.insert_session(id, session);
+
+        Ok(id)
+    }
+
+    /// Guard that resolves the session to the id of its user.
+    ///
+    /// Fails if the session does not exist, if it is expired, or if it was not opened by the caller.
+    pub fn authenticate(&self, id: SessionId) -> Result<Principal> {
+        self.caller_session(id).map(|session| session.user)
+    }
+
+    /// Extends the expiration time of a session of the caller by the session time to live.
+    pub fn refresh_session(&mut self, id: SessionId) -> Result<Session> {
+        let mut session = self.caller_session(id)?;
+        self.remove_session(id);
+        session.expires_at = ic::time().saturating_add(self.session_ttl.as_nanos() as u64);
+        self.insert_session(id, session.clone());
+
+        Ok(session)
+    }
+
+    /// Closes a session of the caller.
+    pub fn logout(&mut self, id: SessionId) -> Result<()> {
+        let session = self.sessions.get(&id).ok_or(SessionError::NotFound(id))?;
+        if session.user != ic::caller() {
+            return Err(SessionError::Unauthorized(id));
+        }
+
+        self.remove_session(id);
+        Ok(())
+    }
+
+    /// Closes all the sessions of the caller on all the devices.
+    /// Returns the number of closed sessions.
+    pub fn logout_all(&mut self) -> usize {
+        let user = PrincipalKey(ic::caller());
+        let ids: Vec<_> = self.user_sessions.range(&user).map(|(id, _)| id).collect();
+        for id in &ids {
+            self.remove_session(*id);
+        }
+        ids.len()
+    }
+
+    /// Returns a session by id.
+    pub fn get_session(&self, id: SessionId) -> Option<Session> {
+        self.sessions.get(&id)
+    }
+
+    /// Returns all the sessions of a user, including the expired ones not yet removed.
+    pub fn user_sessions(&self, user: Principal) -> Vec<(SessionId, Session)> {
+        self.user_sessions
+            .range(&PrincipalKey(user))
+            .filter_map(|(id, _)| self.sessions.get(&id).map(|session| (id, session)))
+            .collect()
+    }
+
+    /// Removes at most `limit` expired sessions and returns the number of removed sessions.
+    ///
+    /// This is meant to be called periodically, e.g. from a timer, to reclaim the memory used
+    /// by the sessions that have not been closed by their users.
+    pub fn remove_expired(&mut self, limit: usize) -> usize {
+        let now = ic::time();
+        let expired: Vec<_> = self
+            .expirations
+            .range(..=(now, SessionId::MAX))
+            .take(limit)
+            .map(|((_, id), _)| id)
+            .collect();
+
+        for id in &expired {
+            self.remove_session(*id);
+        }
+        expired.len()
+    }
+
+    /// Number of stored sessions, including the expired ones not yet removed.
+    pub fn len(&self) -> u64 {
+        self.sessions.len()
+    }
+
+    /// Returns whether there are no stored sessions.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    fn caller_session(&self, id: SessionId) -> Result<Session> {
+        let session = self.sessions.get(&id).ok_or(SessionError::NotFound(id))?;
+
+        if session.user != ic::caller() {
+            return Err(SessionError::Unauthorized(id));
+        }
+
+        if session.is_expired(ic::time()) {
+            return Err(SessionError::Expired(id));
+        }
+
+        Ok(session)
+    }
+
+    fn insert_session(&mut self, id: SessionId, session: Session) {
+        let user = PrincipalKey(session.user);
+        self.expirations.insert((session.expires_at, id), user);
+        self.user_sessions.insert(&user, &id, session.expires_at);
+        self.sessions.insert(id, session);
+    }
+
+    fn remove_session(&mut self, id: SessionId) -> Option<Session> {
+        let session = self.sessions.remove(&id)?;
+        self.expirations.remove(&(session.expires_at, id));
+        self.user_sessions.remove(&PrincipalKey(session.user), &id);
+        Some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_exports::ic_kit::mock_principals::{alice, bob};
+    use ic_exports::ic_kit::MockContext;
+    use ic_stable_structures::VectorMemory;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn session_manager() -> SessionManager<VectorMemory> {
+        SessionManager::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+            TTL,
+        )
+        .unwrap()
+    }
+
+    fn device(name: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn should_create_and_authenticate_session() {
+        MockContext::new().with_caller(alice()).inject();
+        let mut sessions = session_manager();
+
+        let now = ic::time();
+        let id = sessions.create_session(device("laptop")).unwrap();
+        assert_eq!(sessions.authenticate(id), Ok(alice()));
+
+        let session = sessions.get_session(id).unwrap();
+        assert_eq!(session.user, alice());
+        assert_eq!(session.device, device("laptop"));
+        assert_eq!(session.created_at, now);
+        assert_eq!(session.expires_at, now + TTL.as_nanos() as u64);
+    }
+
+    #[test]
+    fn should_reject_anonymous_caller() {
+        MockContext::new().inject();
+        let mut sessions = session_manager();
+
+        assert_eq!(
+            sessions.create_session(device("laptop")),
+            Err(SessionError::AnonymousCaller)
+        );
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn should_not_authenticate_other_users() {
+        let ctx = MockContext::new().with_caller(alice()).inject();
+        let mut sessions = session_manager();
+        let id = sessions.create_session(device("laptop")).unwrap();
+
+        ctx.update_caller(bob());
+        assert_eq!(
+            sessions.authenticate(id),
+            Err(SessionError::Unauthorized(id))
+        );
+        assert_eq!(sessions.logout(id), Err(SessionError::Unauthorized(id)));
+        assert_eq!(
+            sessions.authenticate(id + 1),
+            Err(SessionError::NotFound(id + 1))
+        );
+    }
+
+    #[test]
+    fn should_expire_and_refresh_sessions() {
+        let ctx = MockContext::new().with_caller(alice()).inject();
+        let mut sessions = session_manager();
+        let first = sessions.create_session(device("laptop")).unwrap();
+        let second = sessions.create_session(device("phone")).unwrap();
+
+        ctx.add_time(TTL.as_nanos() as u64 / 2);
+        sessions.refresh_session(first).unwrap();
+
+        ctx.add_time(TTL.as_nanos() as u64 / 2);
+        assert_eq!(sessions.authenticate(first), Ok(alice()));
+        assert_eq!(
+            sessions.authenticate(second),
+            Err(SessionError::Expired(second))
+        );
+
+        assert_eq!(sessions.remove_expired(10), 1);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(
+            sessions.authenticate(second),
+            Err(SessionError::NotFound(second))
+        );
+
+        ctx.add_time(TTL.as_nanos() as u64);
+        assert_eq!(sessions.remove_expired(10), 1);
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn should_logout_from_all_devices() {
+        let ctx = MockContext::new().with_caller(alice()).inject();
+        let mut sessions = session_manager();
+        let laptop = sessions.create_session(device("laptop")).unwrap();
+        let phone = sessions.create_session(device("phone")).unwrap();
+
+        ctx.update_caller(bob());
+        let bob_session = sessions.create_session(device("tablet")).unwrap();
+
+        ctx.update_caller(alice());
+        assert_eq!(
+            sessions.user_sessions(alice()),
+            vec![
+                (laptop, sessions.get_session(laptop).unwrap()),
+                (phone, sessions.get_session(phone).unwrap())
+            ]
+        );
+
+        sessions.logout(laptop).unwrap();
+        assert_eq!(sessions.user_sessions(alice()).len(), 1);
+
+        assert_eq!(sessions.logout_all(), 1);
+        assert!(sessions.user_sessions(alice()).is_empty());
+        assert_eq!(sessions.get_session(bob_session).unwrap().user, bob());
+    }
+}
@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+
+/// Unique identifier of a session
+pub type SessionId = u64;
+
+/// Timestamp in nanoseconds
+pub type Timestamp = u64;
+
+/// Metadata of the device that opened a session
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Human readable name of the device, e.g. "Alice's laptop"
+    pub name: String,
+    /// User agent of the browser that opened the session, if known
+    pub user_agent: Option<String>,
+}
+
+/// A session opened by a user
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The principal of the user, derived from the Internet Identity delegation
+    pub user: Principal,
+    /// The device that opened the session
+    pub device: DeviceInfo,
+    /// Creation time of the session
+    pub created_at: Timestamp,
+    /// Time after which the session is no more valid
+    pub expires_at: Timestamp,
+}
+
+impl Session {
+    /// Returns whether the session is expired at the given time
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at <= now
+    }
+}
+
+impl Storable for Session {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("serialization of session failed"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("deserialization of session failed")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -43,7 +43,83 @@ fn unboundedmap_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, multimap_benchmark, unboundedmap_benchmark);
+/// Same values as `unboundedmap_benchmark`, with a tight bound on the value size.
+fn boundedmap_benchmark(c: &mut Criterion) {
+    let mut map = StableBTreeMap::new(VectorMemory::default());
+    let key1_count = 10000u64;
+
+    c.bench_function("boundedmap_benchmark", |b| {
+        b.iter(|| {
+            for k1 in 0..key1_count {
+                let value = StringValue(Alphanumeric.sample_string(&mut rand::thread_rng(), 128));
+                map.insert(k1, BoundedStorable::<_, 128>(value));
+            }
+            for k1 in 0..key1_count {
+                assert!(map.get(&k1).is_some())
+            }
+        })
+    });
+}
+
+/// Values of several KB, stored as a whole or split into chunks of different sizes.
+fn large_values_benchmark(c: &mut Criterion) {
+    const VALUE_SIZE: usize = 8 * 1024;
+    let key1_count = 100u64;
+
+    let mut group = c.benchmark_group("large_values_benchmark");
+
+    let mut map = StableBTreeMap::new(VectorMemory::default());
+    group.bench_function("unbounded", |b| {
+        b.iter(|| {
+            for k1 in 0..key1_count {
+                let value =
+                    StringValue(Alphanumeric.sample_string(&mut rand::thread_rng(), VALUE_SIZE));
+                map.insert(k1, value);
+            }
+            for k1 in 0..key1_count {
+                assert!(map.get(&k1).is_some())
+            }
+        })
+    });
+
+    let mut map = StableChunkedMap::<_, _, _, 1024>::new(VectorMemory::default());
+    group.bench_function("chunks_1024", |b| {
+        b.iter(|| {
+            for k1 in 0..key1_count {
+                let value =
+                    StringValue(Alphanumeric.sample_string(&mut rand::thread_rng(), VALUE_SIZE));
+                map.insert(k1, value);
+            }
+            for k1 in 0..key1_count {
+                assert!(map.get(&k1).is_some())
+            }
+        })
+    });
+
+    let mut map = StableChunkedMap::<_, _, _, 4096>::new(VectorMemory::default());
+    group.bench_function("chunks_4096", |b| {
+        b.iter(|| {
+            for k1 in 0..key1_count {
+                let value =
+                    StringValue(Alphanumeric.sample_string(&mut rand::thread_rng(), VALUE_SIZE));
+                map.insert(k1, value);
+            }
+            for k1 in 0..key1_count {
+                assert!(map.get(&k1).is_some())
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    multimap_benchmark,
+    unboundedmap_benchmark,
+    boundedmap_benchmark,
+    large_values_benchmark
+);
 criterion_main!(benches);
 
 mod types {
@@ -0,0 +1,166 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_quote, Data, DeriveInput, Fields, Index, LitInt};
+
+/// Options of the derive, from the `#[storable(...)]` attribute
+#[derive(Default)]
+struct StorableOptions {
+    max_size: Option<LitInt>,
+    fixed_size: bool,
+}
+
+impl StorableOptions {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("storable"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("max_size") {
+                    options.max_size = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("fixed_size") {
+                    options.fixed_size = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `max_size = <bytes>` or `fixed_size`"))
+                }
+            })?;
+        }
+
+        if options.fixed_size && options.max_size.is_some() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "`max_size` and `fixed_size` are mutually exclusive",
+            ));
+        }
+        if options.fixed_size && !input.generics.params.is_empty() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "`fixed_size` is not supported for generic structs",
+            ));
+        }
+
+        Ok(options)
+    }
+}
+
+/// Derives `ic_stable_structures::Storable` for a struct whose fields are all `Storable`.
+///
+/// The fields are serialized in declaration order; the fixed-size fields are written as is and
+/// the other fields are prefixed by their length. The bound is computed from the bounds of the
+/// fields: the struct is fixed-size if all the fields are, and unbounded if any field is.
+///
+/// Attributes:
+/// - `#[storable(max_size = N)]` declares the struct as bounded to `N` bytes, e.g. for a struct
+///   with a `String` field of limited length. The caller is responsible for respecting the limit.
+/// - `#[storable(fixed_size)]` fails to compile if any field is not fixed-size.
+#[proc_macro_derive(Storable, attributes(storable))]
+pub fn derive_storable(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand_storable(input) {
+        Ok(output) => output.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_storable(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let options = StorableOptions::parse(&input)?;
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Storable can only be derived for structs",
+        ));
+    };
+
+    let krate = quote! { ::ic_stable_structures };
+    let field_types: Vec<_> = data.fields.iter().map(|field| &field.ty).collect();
+    let accessors: Vec<TokenStream2> = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let name = &field.ident;
+                quote! { #name }
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|i| {
+                let index = Index::from(i);
+                quote! { #index }
+            })
+            .collect(),
+        Fields::Unit => vec![],
+    };
+
+    let decode = quote! { #krate::derive::decode_field(&bytes, &mut offset) };
+    let construct = match &data.fields {
+        Fields::Named(_) => quote! { Self { #(#accessors: #decode,)* } },
+        Fields::Unnamed(_) => {
+            let decoders = accessors.iter().map(|_| &decode);
+            quote! { Self(#(#decoders,)*) }
+        }
+        Fields::Unit => quote! { Self },
+    };
+
+    let bound = match &options.max_size {
+        Some(max_size) => quote! {
+            #krate::Bound::Bounded {
+                max_size: #max_size,
+                is_fixed_size: false,
+            }
+        },
+        None => quote! {
+            #krate::derive::struct_bound(&[#(<#field_types as #krate::Storable>::BOUND,)*])
+        },
+    };
+
+    let ident = &input.ident;
+    let fixed_size_check = options.fixed_size.then(|| {
+        let message = format!("not all the fields of `{ident}` are fixed-size");
+        quote! {
+            const _: () = ::std::assert!(
+                #krate::derive::is_fixed_size(&<#ident as #krate::Storable>::BOUND),
+                #message,
+            );
+        }
+    });
+
+    let mut generics = input.generics.clone();
+    let type_params: Vec<_> = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let where_clause = generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote! { #param: #krate::Storable });
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #krate::Storable for #ident #ty_generics #where_clause {
+            fn to_bytes(&self) -> ::std::borrow::Cow<[u8]> {
+                let mut buf = ::std::vec::Vec::new();
+                #(#krate::derive::encode_field(&mut buf, &self.#accessors);)*
+                ::std::borrow::Cow::Owned(buf)
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn from_bytes(bytes: ::std::borrow::Cow<[u8]>) -> Self {
+                let mut offset = 0;
+                #construct
+            }
+
+            const BOUND: #krate::Bound = #bound;
+        }
+
+        #fixed_size_check
+    })
+}
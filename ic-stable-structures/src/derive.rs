@@ -0,0 +1,170 @@
+//! Runtime support of the `Storable` derive macro. Not a public API.
+//!
+//! The fields are serialized in declaration order. A fixed-size field is written as is, any other
+//! field is prefixed by its length as a 4-byte little-endian integer.
+
+use std::mem::size_of;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+const LEN_PREFIX_SIZE: u32 = size_of::<u32>() as u32;
+
+/// Returns the bound of a struct from the bounds of its fields.
+pub const fn struct_bound(fields: &[Bound]) -> Bound {
+    let mut max_size = 0;
+    let mut is_fixed_size = true;
+    let mut i = 0;
+    while i < fields.len() {
+        match &fields[i] {
+            Bound::Unbounded => return Bound::Unbounded,
+            Bound::Bounded {
+                max_size: field_size,
+                is_fixed_size: true,
+            } => max_size += *field_size,
+            Bound::Bounded {
+                max_size: field_size,
+                is_fixed_size: false,
+            } => {
+                max_size += *field_size + LEN_PREFIX_SIZE;
+                is_fixed_size = false;
+            }
+        }
+        i += 1;
+    }
+
+    Bound::Bounded {
+        max_size,
+        is_fixed_size,
+    }
+}
+
+/// True if the bound is fixed-size.
+pub const fn is_fixed_size(bound: &Bound) -> bool {
+    matches!(
+        bound,
+        Bound::Bounded {
+            is_fixed_size: true,
+            ..
+        }
+    )
+}
+
+/// Append the field to the buffer.
+pub fn encode_field<T: Storable>(buf: &mut Vec<u8>, field: &T) {
+    let bytes = field.to_bytes();
+    if !is_fixed_size(&T::BOUND) {
+        let len = u32::try_from(bytes.len()).expect("field is larger than 4 GiB");
+        buf.extend_from_slice(&len.to_le_bytes());
+    }
+    buf.extend_from_slice(&bytes);
+}
+
+/// Read the field at `offset` and moves the offset after it.
+pub fn decode_field<T: Storable>(bytes: &[u8], offset: &mut usize) -> T {
+    let len = match T::BOUND {
+        Bound::Bounded {
+            max_size,
+            is_fixed_size: true,
+        } => max_size as usize,
+        _ => {
+            let prefix = &bytes[*offset..*offset + LEN_PREFIX_SIZE as usize];
+            *offset += LEN_PREFIX_SIZE as usize;
+            u32::from_le_bytes(prefix.try_into().expect("expected 4 bytes")) as usize
+        }
+    };
+
+    let field = T::from_bytes(bytes[*offset..*offset + len].to_vec().into());
+    *offset += len;
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use dfinity_stable_structures::storable::Bound;
+
+    use crate::test_utils::{str_val, StringValue};
+    use crate::Storable;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Storable)]
+    #[storable(fixed_size)]
+    struct Point {
+        x: u64,
+        y: u32,
+    }
+
+    #[derive(Debug, PartialEq, Eq, Storable)]
+    struct Account(u64, StringValue, Point);
+
+    #[derive(Debug, PartialEq, Eq, Storable)]
+    #[storable(max_size = 64)]
+    struct Profile {
+        name: String,
+        age: u32,
+    }
+
+    #[derive(Debug, PartialEq, Eq, Storable)]
+    struct Pair<A, B> {
+        first: A,
+        second: B,
+    }
+
+    #[derive(Debug, PartialEq, Eq, Storable)]
+    struct Marker;
+
+    fn roundtrip<T: Storable>(value: &T) -> T {
+        T::from_bytes(Cow::Owned(value.to_bytes().into_owned()))
+    }
+
+    #[test]
+    fn should_roundtrip_structs() {
+        let point = Point { x: 1, y: 2 };
+        assert_eq!(point.to_bytes().len(), 12);
+        assert_eq!(roundtrip(&point), point);
+
+        let account = Account(42, str_val(10), point.clone());
+        assert_eq!(roundtrip(&account), account);
+
+        let profile = Profile {
+            name: "alice".to_string(),
+            age: 30,
+        };
+        assert_eq!(roundtrip(&profile), profile);
+
+        let pair = Pair {
+            first: str_val(3),
+            second: 7u64,
+        };
+        assert_eq!(roundtrip(&pair), pair);
+
+        assert_eq!(roundtrip(&Marker), Marker);
+    }
+
+    #[test]
+    fn should_compute_bounds_from_fields() {
+        assert_eq!(
+            Point::BOUND,
+            Bound::Bounded {
+                max_size: 12,
+                is_fixed_size: true
+            }
+        );
+        assert_eq!(Account::BOUND, Bound::Unbounded);
+        assert_eq!(
+            Profile::BOUND,
+            Bound::Bounded {
+                max_size: 64,
+                is_fixed_size: false
+            }
+        );
+        assert_eq!(
+            Pair::<u64, Point>::BOUND,
+            Bound::Bounded {
+                max_size: 20,
+                is_fixed_size: true
+            }
+        );
+    }
+}
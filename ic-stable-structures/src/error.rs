@@ -15,6 +15,58 @@ pub enum Error {
     IncompatibleElementType,
     #[error("bad magic number: actual: {actual:?}, expected: {expected:?}")]
     BadMagic { actual: [u8; 3], expected: [u8; 3] },
+    #[error("no structure with the journal target id {0}")]
+    UnknownJournalTarget(u8),
+    #[error("the journal operation is not supported by the target structure")]
+    UnsupportedJournalOp,
+    #[error("a transaction is pending in the journal")]
+    JournalPending,
+    #[error("journal append at index {index} is past the end of the structure of length {len}")]
+    JournalAppendGap { index: u64, len: u64 },
+    #[error(
+        "journal append at index {index} conflicts with a value appended outside of the journal"
+    )]
+    JournalAppendConflict { index: u64 },
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(&'static str),
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedSnapshotVersion(u8),
+    #[error("invalid region name `{0}`: it must have 1 to 32 bytes")]
+    InvalidRegionName(String),
+    #[error("the memory is used by something else than a region")]
+    MemoryNotARegion,
+    #[error("the memory is reserved by the region `{actual}`, not `{expected}`")]
+    RegionNameMismatch { expected: String, actual: String },
+    #[error("the pagination cursor is not valid for this structure")]
+    InvalidCursor,
+    #[error("memory id {id} requested by `{label}` is already registered by `{registered_by}`")]
+    MemoryIdInUse {
+        id: u8,
+        label: String,
+        registered_by: String,
+    },
+    #[error("all the memory ids are used")]
+    NoFreeMemoryId,
+    #[error("the memory registry has no memory id allocator")]
+    NoMemoryIdAllocator,
+    #[error("the counter `{0}` overflows")]
+    CounterOverflow(String),
+    #[error("the counter `{0}` can't be negative")]
+    CounterUnderflow(String),
+    #[error("the checksum of a stored value doesn't match its bytes")]
+    ChecksumMismatch,
+    #[error("failed to serialize the heap state: {0}")]
+    StateSerialization(String),
+    #[error("the structure can't have more than {limit} entries")]
+    EntryLimitExceeded { limit: u64 },
+    #[error("the structure can't use more than {limit} bytes of stable memory")]
+    MemoryLimitExceeded { limit: u64 },
+    #[error("the node of the edge is not in the graph")]
+    NodeNotFound,
+    #[error("a scan of the map is already open")]
+    ScanInProgress,
+    #[error("invalid fixture: {0}")]
+    InvalidFixture(String),
 }
 
 impl From<cell::InitError> for Error {
@@ -0,0 +1,249 @@
+//! Seeding of the structures from JSON and CSV fixtures, for the tests.
+//!
+//! An integration test can upload a realistic dataset to a test canister in a single call, which
+//! seeds its structures from the fixture, instead of inserting the entries one update call at a
+//! time:
+//!
+//! ```ignore
+//! #[update]
+//! fn seed_balances(csv: String) -> u64 {
+//!     BALANCES.with(|map| {
+//!         seed_map_from_csv(&mut *map.borrow_mut(), &csv, |record| {
+//!             Ok((record.parse::<u64>("account")?, record.parse::<u64>("balance")?))
+//!         })
+//!     })
+//!     .unwrap()
+//! }
+//! ```
+
+use std::rc::Rc;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+
+use crate::structure::BTreeMapStructure;
+use crate::{Error, Result};
+
+/// Row of a CSV fixture, with its fields by column name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvRecord {
+    headers: Rc<[String]>,
+    fields: Vec<String>,
+    line: usize,
+}
+
+impl CsvRecord {
+    /// Returns the field of the column, or `None` if the fixture has no such column.
+    pub fn get(&self, column: &str) -> Option<&str> {
+        let index = self.headers.iter().position(|header| header == column)?;
+        Some(&self.fields[index])
+    }
+
+    /// Parses the field of the column.
+    pub fn parse<T>(&self, column: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let field = self.get(column).ok_or_else(|| {
+            Error::InvalidFixture(format!("line {}: no column `{column}`", self.line))
+        })?;
+        field.parse().map_err(|e| {
+            Error::InvalidFixture(format!("line {}: column `{column}`: {e}", self.line))
+        })
+    }
+
+    /// The fields in the order of the columns
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Line of the record in the fixture, starting from 1 for the header
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+/// Parses a JSON fixture holding an array of records.
+pub fn parse_json_fixture<T: DeserializeOwned>(json: &str) -> Result<Vec<T>> {
+    serde_json::from_str(json).map_err(|e| Error::InvalidFixture(e.to_string()))
+}
+
+/// Parses a CSV fixture whose first line holds the names of the columns.
+///
+/// The fields are separated by commas, and the fields holding commas, quotes or line breaks are
+/// quoted, with the quotes doubled. The empty lines are skipped.
+pub fn parse_csv_fixture(csv: &str) -> Result<Vec<CsvRecord>> {
+    let mut rows = parse_csv_rows(csv)?.into_iter();
+    let Some((_, headers)) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let headers: Rc<[String]> = headers.into();
+
+    rows.map(|(line, fields)| {
+        if fields.len() != headers.len() {
+            return Err(Error::InvalidFixture(format!(
+                "line {line}: expected {} fields, found {}",
+                headers.len(),
+                fields.len()
+            )));
+        }
+        Ok(CsvRecord {
+            headers: headers.clone(),
+            fields,
+            line,
+        })
+    })
+    .collect()
+}
+
+/// Inserts the records of a JSON fixture in the map, mapped to entries by `to_entry`, and returns
+/// the number of records.
+pub fn seed_map_from_json<T, K, V>(
+    map: &mut impl BTreeMapStructure<K, V>,
+    json: &str,
+    to_entry: impl FnMut(T) -> (K, V),
+) -> Result<u64>
+where
+    T: DeserializeOwned,
+{
+    let records = parse_json_fixture::<T>(json)?;
+    let count = records.len() as u64;
+    for (key, value) in records.into_iter().map(to_entry) {
+        map.insert(key, value);
+    }
+    Ok(count)
+}
+
+/// Inserts the records of a CSV fixture in the map, mapped to entries by `to_entry`, and returns
+/// the number of records. Fails without changing the map if a record is not valid.
+pub fn seed_map_from_csv<K, V>(
+    map: &mut impl BTreeMapStructure<K, V>,
+    csv: &str,
+    to_entry: impl FnMut(&CsvRecord) -> Result<(K, V)>,
+) -> Result<u64> {
+    let entries = parse_csv_fixture(csv)?
+        .iter()
+        .map(to_entry)
+        .collect::<Result<Vec<_>>>()?;
+    let count = entries.len() as u64;
+    for (key, value) in entries {
+        map.insert(key, value);
+    }
+    Ok(count)
+}
+
+/// Splits the CSV in rows of fields, with the line of every row
+fn parse_csv_rows(csv: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                push_row(&mut rows, row_line, std::mem::take(&mut fields));
+                line += 1;
+                row_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(Error::InvalidFixture(format!(
+            "line {row_line}: unterminated quoted field"
+        )));
+    }
+    fields.push(field);
+    push_row(&mut rows, row_line, fields);
+    Ok(rows)
+}
+
+/// Adds the row unless it is an empty line
+fn push_row(rows: &mut Vec<(usize, Vec<String>)>, line: usize, fields: Vec<String>) {
+    if fields.len() > 1 || !fields[0].is_empty() {
+        rows.push((line, fields));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::StableBTreeMap;
+
+    fn balance_entry(record: &CsvRecord) -> Result<(u64, u64)> {
+        Ok((record.parse("account")?, record.parse("balance")?))
+    }
+
+    #[test]
+    fn should_seed_map_from_json() {
+        #[derive(Deserialize)]
+        struct Balance {
+            account: u64,
+            balance: u64,
+        }
+
+        let json = r#"[{"account": 1, "balance": 100}, {"account": 2, "balance": 50}]"#;
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        let count = seed_map_from_json(&mut map, json, |record: Balance| {
+            (record.account, record.balance)
+        })
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(map.get(&1), Some(100));
+        assert!(parse_json_fixture::<Vec<u64>>("{").is_err());
+    }
+
+    #[test]
+    fn should_seed_map_from_csv() {
+        let csv = "account,balance\r\n1,100\r\n\r\n2,50\r\n";
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        let count = seed_map_from_csv(&mut map, csv, balance_entry).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(map.get(&2), Some(50));
+
+        let invalid = "account,balance\n3,a lot\n";
+        assert!(matches!(
+            seed_map_from_csv(&mut map, invalid, balance_entry),
+            Err(Error::InvalidFixture(_))
+        ));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn should_parse_quoted_csv_fields() {
+        let csv = "name,bio\n\"Doe, John\",\"says \"\"hi\"\"\nand leaves\"\nJane,";
+        let records = parse_csv_fixture(csv).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get("name"), Some("Doe, John"));
+        assert_eq!(records[0].get("bio"), Some("says \"hi\"\nand leaves"));
+        assert_eq!(records[1].get("bio"), Some(""));
+        assert_eq!(records[1].line(), 4);
+        assert!(parse_csv_fixture("a,b\n1\n").is_err());
+        assert!(parse_csv_fixture("a\n\"1").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Order-independent hashes of the content of the structures.
+//!
+//! The hash of a dataset is the XOR of the SHA-256 hashes of its entries, so it's updated in
+//! constant time when an entry is added or removed, and two canisters, e.g. a primary and its
+//! replica or a canister before and after a migration, compare their datasets by comparing their
+//! hashes before running a full diff. The entries of logs and vectors include their index, so the
+//! same value at two positions doesn't cancel out.
+//!
+//! The hash is maintained by the [`HashedBTreeMap`](crate::HashedBTreeMap) and
+//! [`HashedLog`](crate::HashedLog) structures, and is computed from scratch for any exportable
+//! structure with [`DatasetHash::of_snapshot`].
+
+use std::borrow::Cow;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+use sha2::{Digest, Sha256};
+
+use crate::{Result, Snapshot, StructureKind};
+
+const DIGEST_SIZE: usize = 32;
+const DATASET_HASH_SIZE: usize = DIGEST_SIZE + 8;
+
+/// Hash of the content of a structure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DatasetHash {
+    /// XOR of the hashes of the entries
+    pub digest: [u8; DIGEST_SIZE],
+    /// Number of entries
+    pub entries: u64,
+}
+
+impl DatasetHash {
+    /// Adds an entry with the given serialized fields.
+    pub fn add_entry(&mut self, fields: &[&[u8]]) {
+        self.xor(&entry_hash(fields));
+        self.entries += 1;
+    }
+
+    /// Removes an entry with the given serialized fields, which must have been added before.
+    pub fn remove_entry(&mut self, fields: &[&[u8]]) {
+        self.xor(&entry_hash(fields));
+        self.entries -= 1;
+    }
+
+    /// Computes the hash of the structure exported to the snapshot.
+    pub fn of_snapshot(snapshot: &[u8]) -> Result<Self> {
+        let snapshot = Snapshot::parse(snapshot)?;
+        let indexed = matches!(
+            snapshot.header().kind,
+            StructureKind::Log | StructureKind::Vec
+        );
+
+        let mut hash = Self::default();
+        for (index, entry) in snapshot.entries().enumerate() {
+            let index = (index as u64).to_le_bytes();
+            let mut fields = entry?;
+            if indexed {
+                fields.insert(0, &index);
+            }
+            hash.add_entry(&fields);
+        }
+        Ok(hash)
+    }
+
+    fn xor(&mut self, hash: &[u8; DIGEST_SIZE]) {
+        for (byte, other) in self.digest.iter_mut().zip(hash) {
+            *byte ^= other;
+        }
+    }
+}
+
+impl Storable for DatasetHash {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: DATASET_HASH_SIZE as u32,
+        is_fixed_size: true,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::with_capacity(DATASET_HASH_SIZE);
+        buf.extend_from_slice(&self.digest);
+        buf.extend_from_slice(&self.entries.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            digest: bytes[..DIGEST_SIZE]
+                .try_into()
+                .expect("digest: expected 32 bytes"),
+            entries: u64::from_le_bytes(
+                bytes[DIGEST_SIZE..DATASET_HASH_SIZE]
+                    .try_into()
+                    .expect("entries: expected 8 bytes"),
+            ),
+        }
+    }
+}
+
+/// Hash of an entry: SHA-256 of its fields, each prefixed by its length.
+fn entry_hash(fields: &[&[u8]]) -> [u8; DIGEST_SIZE] {
+    let mut hasher = Sha256::new();
+    for field in fields {
+        hasher.update((field.len() as u32).to_le_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(hash: &mut DatasetHash, fields: &[&str]) {
+        let fields: Vec<&[u8]> = fields.iter().map(|field| field.as_bytes()).collect();
+        hash.add_entry(&fields);
+    }
+
+    #[test]
+    fn should_not_depend_on_order() {
+        let mut first = DatasetHash::default();
+        add(&mut first, &["a", "1"]);
+        add(&mut first, &["b", "2"]);
+
+        let mut second = DatasetHash::default();
+        add(&mut second, &["b", "2"]);
+        add(&mut second, &["c", "3"]);
+        add(&mut second, &["a", "1"]);
+        assert_ne!(first, second);
+
+        second.remove_entry(&["c".as_bytes(), "3".as_bytes()]);
+        assert_eq!(first, second);
+        assert_eq!(first.entries, 2);
+
+        // The fields are length-prefixed
+        let mut split = DatasetHash::default();
+        add(&mut split, &["a1"]);
+        add(&mut split, &["b", "2"]);
+        assert_ne!(first, split);
+
+        assert_eq!(DatasetHash::from_bytes(first.to_bytes()), first);
+    }
+}
@@ -0,0 +1,164 @@
+//! Persistence of the heap state across the upgrades, next to the stable structures.
+//!
+//! `ic_cdk::storage::stable_save` writes from the start of the stable memory, which belongs to
+//! the memory manager, so it corrupts the stable structures. The [`HeapStateStore`] writes the
+//! state to a region of the memory manager instead:
+//!
+//! ```ignore
+//! #[pre_upgrade]
+//! fn pre_upgrade() {
+//!     let mut store = HeapStateStore::reserve(&MEMORY_MANAGER, HEAP_STATE_MEMORY_ID).unwrap();
+//!     STATE.with(|state| store.save(&*state.borrow())).unwrap();
+//! }
+//!
+//! #[post_upgrade]
+//! fn post_upgrade() {
+//!     let mut store = HeapStateStore::reserve(&MEMORY_MANAGER, HEAP_STATE_MEMORY_ID).unwrap();
+//!     if let Some(state) = store.take().unwrap() {
+//!         STATE.with(|s| *s.borrow_mut() = state);
+//!     }
+//! }
+//! ```
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::memory_manager::{
+    MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
+};
+use dfinity_stable_structures::Memory;
+
+use crate::{Error, MemoryRegion, Result};
+
+const HEAP_STATE_REGION_NAME: &str = "heap-state";
+const HEAP_STATE_VERSION: u32 = 1;
+
+/// Offset of the state, after its length
+const STATE_OFFSET: u64 = 8;
+
+/// Candid-encoded heap state stored in a memory region.
+pub struct HeapStateStore<M: Memory> {
+    region: MemoryRegion<M>,
+}
+
+impl<M: Memory> HeapStateStore<M> {
+    /// Opens the store in the memory, reserving a region for it if the memory is empty.
+    pub fn new(memory: M) -> Result<Self> {
+        Ok(Self {
+            region: MemoryRegion::new(memory, HEAP_STATE_REGION_NAME, HEAP_STATE_VERSION)?,
+        })
+    }
+
+    /// Saves the state, replacing the previously saved one, e.g. in `pre_upgrade`.
+    pub fn save<T: CandidType>(&mut self, state: &T) -> Result<()> {
+        let bytes =
+            candid::encode_one(state).map_err(|e| Error::StateSerialization(e.to_string()))?;
+        self.region.write(STATE_OFFSET, &bytes)?;
+        self.region.write(0, &(bytes.len() as u64).to_le_bytes())
+    }
+
+    /// Returns the saved state, or `None` if no state is saved.
+    pub fn restore<T>(&self) -> Result<Option<T>>
+    where
+        T: CandidType + for<'de> Deserialize<'de>,
+    {
+        let len = self.saved_len();
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let mut bytes = vec![0; len as usize];
+        self.region.read(STATE_OFFSET, &mut bytes);
+        candid::decode_one(&bytes)
+            .map(Some)
+            .map_err(|e| Error::StateSerialization(e.to_string()))
+    }
+
+    /// Returns the saved state and clears it, e.g. in `post_upgrade`, so an upgrade which doesn't
+    /// save the state doesn't restore an outdated one.
+    pub fn take<T>(&mut self) -> Result<Option<T>>
+    where
+        T: CandidType + for<'de> Deserialize<'de>,
+    {
+        let state = self.restore()?;
+        self.clear()?;
+        Ok(state)
+    }
+
+    /// Clears the saved state. The memory of the region is kept for the next save.
+    pub fn clear(&mut self) -> Result<()> {
+        self.region.write(0, &0u64.to_le_bytes())
+    }
+
+    /// Size of the encoded state, in bytes
+    pub fn saved_len(&self) -> u64 {
+        let mut len = [0; 8];
+        self.region.read(0, &mut len);
+        u64::from_le_bytes(len)
+    }
+}
+
+impl<M: Memory> HeapStateStore<VirtualMemory<M>> {
+    /// Opens the store in the memory with the id of the memory manager, see
+    /// [`HeapStateStore::new`].
+    pub fn reserve(manager: &IcMemoryManager<M>, memory_id: MemoryId) -> Result<Self> {
+        Self::new(manager.get(memory_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[derive(CandidType, Deserialize, Debug, PartialEq, Default)]
+    struct State {
+        owner: String,
+        balances: BTreeMap<String, u64>,
+    }
+
+    #[test]
+    fn should_save_and_take_state() {
+        let manager = IcMemoryManager::init(VectorMemory::default());
+        let mut map = StableBTreeMap::new(manager.get(MemoryId::new(0)));
+        map.insert(1u64, 10u64);
+
+        let state = State {
+            owner: "alice".to_string(),
+            balances: [("bob".to_string(), 5)].into(),
+        };
+        let mut store = HeapStateStore::reserve(&manager, MemoryId::new(1)).unwrap();
+        assert_eq!(store.restore::<State>().unwrap(), None);
+        store.save(&state).unwrap();
+
+        let mut store = HeapStateStore::reserve(&manager, MemoryId::new(1)).unwrap();
+        assert_eq!(store.take::<State>().unwrap(), Some(state));
+        assert_eq!(store.take::<State>().unwrap(), None);
+
+        let map = StableBTreeMap::<u64, u64, _>::new(manager.get(MemoryId::new(0)));
+        assert_eq!(map.get(&1), Some(10));
+    }
+
+    #[test]
+    fn should_replace_larger_state() {
+        let mut store = HeapStateStore::new(VectorMemory::default()).unwrap();
+        store.save(&vec![1u8; 100_000]).unwrap();
+        store.save(&"short".to_string()).unwrap();
+
+        assert_eq!(store.restore::<String>().unwrap().as_deref(), Some("short"));
+        assert!(store.restore::<u64>().is_err());
+    }
+
+    #[test]
+    fn should_not_take_memory_of_other_region() {
+        let memory = VectorMemory::default();
+        MemoryRegion::new(memory.clone(), "orderbook", 1).unwrap();
+
+        assert!(matches!(
+            HeapStateStore::new(memory),
+            Err(Error::RegionNameMismatch { .. })
+        ));
+    }
+}
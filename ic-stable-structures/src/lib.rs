@@ -1,21 +1,46 @@
+// Allows the derive macros to refer to this crate from its own tests
+extern crate self as ic_stable_structures;
+
 mod structure;
 
+#[doc(hidden)]
+pub mod derive;
+
 mod error;
+#[cfg(feature = "fixtures")]
+mod fixtures;
+mod hash;
+mod heap_state;
 mod memory;
 #[cfg(feature = "memory-mapped-files-memory")]
 mod memory_mapped_files;
+mod memory_pool;
+mod memory_registry;
+mod region;
+mod snapshot;
+mod stats;
 
 #[cfg(test)]
 mod test_utils;
 
 pub use dfinity_stable_structures as stable_structures;
 pub use error::{Error, Result};
+#[cfg(feature = "fixtures")]
+pub use fixtures::*;
+pub use hash::DatasetHash;
+pub use heap_state::HeapStateStore;
+pub use ic_stable_structures_derive::Storable;
 pub use memory::*;
 #[cfg(feature = "memory-mapped-files-memory")]
 pub use memory_mapped_files::*;
+pub use memory_pool::{MemoryPool, PooledMemory};
+pub use memory_registry::{MemoryRegistry, DEFAULT_ALLOCATOR_MEMORY_ID};
+pub use region::*;
+pub use snapshot::*;
 pub use stable_structures::memory_manager::{
     MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
 };
 pub use stable_structures::storable::Bound;
 pub use stable_structures::{FileMemory, Storable, VectorMemory};
+pub use stats::*;
 pub use structure::*;
@@ -1,3 +1,8 @@
+#[cfg(feature = "operation-counters")]
+use std::cell::Cell;
+#[cfg(feature = "operation-counters")]
+use std::rc::Rc;
+
 use dfinity_stable_structures::memory_manager::{
     MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
 };
@@ -25,3 +30,146 @@ impl<M: Memory> MemoryManager<VirtualMemory<M>, MemoryId> for IcMemoryManager<M>
 pub fn default_ic_memory_manager() -> IcMemoryManager<DefaultMemoryImpl> {
     IcMemoryManager::init(DefaultMemoryImpl::default())
 }
+
+/// A memory whose grow operations fail at the rate configured with
+/// `ic_kit::chaos::set_fault_config`, to test the behaviour of the canister
+/// when the stable memory is exhausted.
+#[cfg(feature = "chaos")]
+#[derive(Clone, Default)]
+pub struct FaultyMemory<M: Memory>(pub M);
+
+#[cfg(feature = "chaos")]
+impl<M: Memory> Memory for FaultyMemory<M> {
+    fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn grow(&self, pages: u64) -> i64 {
+        if ic_kit::chaos::should_fail_memory_grow() {
+            return -1;
+        }
+        self.0.grow(pages)
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.0.read(offset, dst)
+    }
+
+    fn write(&self, offset: u64, src: &[u8]) {
+        self.0.write(offset, src)
+    }
+}
+
+/// Operations on a [`CountingMemory`] since its creation or its last reset
+#[cfg(feature = "operation-counters")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationCounters {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Number of pages added to the memory
+    pub pages_grown: u64,
+}
+
+/// A memory counting the operations of the structure stored in it, to find which structure
+/// burns the instructions of a canister.
+///
+/// The counters include the reads and writes of the metadata of the structure, e.g. the nodes of
+/// a BTree, which are most of the cost of the operations. The clones of the memory share the
+/// counters, so the canister keeps a clone to report them:
+///
+/// ```ignore
+/// let memory = CountingMemory::new(MEMORY_MANAGER.with(|mm| mm.get(BALANCES_MEMORY_ID)));
+/// let balances = StableBTreeMap::new(memory.clone());
+/// let counters = memory.counters();
+/// ```
+#[cfg(feature = "operation-counters")]
+#[derive(Clone, Default)]
+pub struct CountingMemory<M: Memory> {
+    memory: M,
+    counters: Rc<Cell<OperationCounters>>,
+}
+
+#[cfg(feature = "operation-counters")]
+impl<M: Memory> CountingMemory<M> {
+    pub fn new(memory: M) -> Self {
+        Self {
+            memory,
+            counters: Default::default(),
+        }
+    }
+
+    /// Returns the operations since the creation of the memory or the last reset.
+    pub fn counters(&self) -> OperationCounters {
+        self.counters.get()
+    }
+
+    pub fn reset_counters(&self) {
+        self.counters.set(OperationCounters::default());
+    }
+
+    fn count(&self, f: impl FnOnce(&mut OperationCounters)) {
+        let mut counters = self.counters.get();
+        f(&mut counters);
+        self.counters.set(counters);
+    }
+}
+
+#[cfg(feature = "operation-counters")]
+impl<M: Memory> Memory for CountingMemory<M> {
+    fn size(&self) -> u64 {
+        self.memory.size()
+    }
+
+    fn grow(&self, pages: u64) -> i64 {
+        let result = self.memory.grow(pages);
+        if result >= 0 {
+            self.count(|counters| counters.pages_grown += pages);
+        }
+        result
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.count(|counters| {
+            counters.reads += 1;
+            counters.bytes_read += dst.len() as u64;
+        });
+        self.memory.read(offset, dst)
+    }
+
+    fn write(&self, offset: u64, src: &[u8]) {
+        self.count(|counters| {
+            counters.writes += 1;
+            counters.bytes_written += src.len() as u64;
+        });
+        self.memory.write(offset, src)
+    }
+}
+
+#[cfg(all(test, feature = "operation-counters"))]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[test]
+    fn should_count_operations_of_structure() {
+        let memory = CountingMemory::new(VectorMemory::default());
+        let mut map = StableBTreeMap::<u64, u64, _>::new(memory.clone());
+        let created = memory.counters();
+        assert!(created.pages_grown > 0);
+
+        map.insert(1, 10);
+        let inserted = memory.counters();
+        assert!(inserted.writes > created.writes);
+        assert!(inserted.bytes_written > created.bytes_written);
+
+        memory.reset_counters();
+        assert_eq!(map.get(&1), Some(10));
+        let counters = memory.counters();
+        assert!(counters.reads > 0);
+        assert_eq!(counters.writes, 0);
+    }
+}
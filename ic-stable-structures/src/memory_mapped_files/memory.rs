@@ -150,7 +150,7 @@ impl Memory for MemoryMappedFileMemory {
     fn grow(&self, pages: u64) -> i64 {
         let mut memory = self.0.write();
         let old_size = memory.len();
-        let bytes_to_add = (pages * WASM_PAGE_SIZE_IN_BYTES) as usize;
+        let bytes_to_add = to_usize(pages * WASM_PAGE_SIZE_IN_BYTES);
         let new_length = memory
             .resize(old_size + bytes_to_add)
             .expect("failed to resize memory-mapped file");
@@ -164,14 +164,20 @@ impl Memory for MemoryMappedFileMemory {
     fn read(&self, offset: u64, dst: &mut [u8]) {
         self.0
             .read()
-            .read(offset as usize, dst)
+            .read(to_usize(offset), dst)
             .expect("invalid memory-mapped file read")
     }
 
     fn write(&self, offset: u64, src: &[u8]) {
         self.0
             .write()
-            .write(offset as usize, src)
+            .write(to_usize(offset), src)
             .expect("invalid memory-mapped file write")
     }
 }
+
+/// Converts a stable memory offset or size, which is always 64 bits, to an address.
+/// Panics if it doesn't fit the address space, e.g. on 32 bits targets.
+fn to_usize(value: u64) -> usize {
+    usize::try_from(value).expect("stable memory offset exceeds the address space")
+}
@@ -0,0 +1,309 @@
+//! Many small memories multiplexed onto a single memory.
+//!
+//! The memory manager supports 255 memories, which is not enough for a structure per user or per
+//! market. A [`MemoryPool`] splits a single memory, e.g. the memory of one id of the memory
+//! manager, into any number of [`PooledMemory`]s identified by a `u32`:
+//!
+//! ```ignore
+//! let pool = MemoryPool::init(manager.get(MemoryId::new(10)));
+//! let user_log = StableLog::new(pool.get(2 * user_index), pool.get(2 * user_index + 1))?;
+//! ```
+//!
+//! The pooled memories grow by pages, which are allocated at the end of the pool and mapped to
+//! the pooled memory in a table stored in the pool, so a pooled memory occupies at least a page
+//! of 64 KiB.
+//!
+//! The pages of a pooled memory which is not used anymore, e.g. the memory of a deleted user, are
+//! released with [`MemoryPool::release`] and reused by the next growths of the pooled memories.
+//!
+//! Only the pooled memories can be released: the dfinity `MemoryManager` never frees the buckets
+//! of a memory id, so the pages of a structure stored directly on a memory id stay allocated to it
+//! after the structure is cleared. The structures which may be deleted, e.g. the datasets of a
+//! user, are to be stored in pooled memories to get their pages back.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use dfinity_stable_structures::memory_manager::{
+    MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
+};
+use dfinity_stable_structures::Memory;
+
+use crate::stats::WASM_PAGE_SIZE;
+use crate::{BTreeMapStructure, StableBTreeMap};
+
+const PAGE_TABLE_MEMORY_ID: u8 = 0;
+const PAGES_MEMORY_ID: u8 = 1;
+
+struct PoolState<M: Memory> {
+    /// Page of the pool by pooled memory id and page index in the pooled memory
+    page_table: StableBTreeMap<(u32, u32), u32, VirtualMemory<M>>,
+    pages: VirtualMemory<M>,
+    /// Heap copy of the page table
+    memories: BTreeMap<u32, Vec<u32>>,
+    /// Allocated pages which are not in the page table
+    free_pages: Vec<u32>,
+}
+
+/// A memory split into any number of pooled memories.
+pub struct MemoryPool<M: Memory> {
+    state: Rc<RefCell<PoolState<M>>>,
+}
+
+impl<M: Memory> MemoryPool<M> {
+    /// Creates the pool in the memory, or loads it if the memory already contains it.
+    pub fn init(memory: M) -> Self {
+        let manager = IcMemoryManager::init(memory);
+        let page_table = StableBTreeMap::new(manager.get(MemoryId::new(PAGE_TABLE_MEMORY_ID)));
+        let pages = manager.get(MemoryId::new(PAGES_MEMORY_ID));
+
+        let mut memories = BTreeMap::<u32, Vec<u32>>::new();
+        let mut used_pages = BTreeSet::new();
+        for ((id, _), page) in page_table.iter() {
+            memories.entry(id).or_default().push(page);
+            used_pages.insert(page);
+        }
+        let free_pages = (0..pages.size() as u32)
+            .rev()
+            .filter(|page| !used_pages.contains(page))
+            .collect();
+
+        Self {
+            state: Rc::new(RefCell::new(PoolState {
+                page_table,
+                pages,
+                memories,
+                free_pages,
+            })),
+        }
+    }
+
+    /// Returns the pooled memory with the id.
+    pub fn get(&self, id: u32) -> PooledMemory<M> {
+        PooledMemory {
+            id,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Returns the ids of the pooled memories with allocated pages, sorted.
+    pub fn memory_ids(&self) -> Vec<u32> {
+        self.state.borrow().memories.keys().copied().collect()
+    }
+
+    /// Number of pages allocated to the pool, including the released pages
+    pub fn allocated_pages(&self) -> u64 {
+        self.state.borrow().pages.size()
+    }
+
+    /// Number of released pages which are not reused yet
+    pub fn free_pages(&self) -> u64 {
+        self.state.borrow().free_pages.len() as u64
+    }
+
+    /// Releases the pages of the pooled memory, and returns their number. The memory is empty
+    /// afterwards.
+    ///
+    /// The structures stored in the memory must be dropped before, as their data is overwritten
+    /// when the pages are reused.
+    pub fn release(&self, id: u32) -> u64 {
+        let mut state = self.state.borrow_mut();
+        let Some(pages) = state.memories.remove(&id) else {
+            return 0;
+        };
+
+        for index in 0..pages.len() as u32 {
+            state.page_table.remove(&(id, index));
+        }
+        state.free_pages.extend(pages.iter().rev());
+        pages.len() as u64
+    }
+}
+
+/// A memory of a [`MemoryPool`].
+#[derive(Clone)]
+pub struct PooledMemory<M: Memory> {
+    id: u32,
+    state: Rc<RefCell<PoolState<M>>>,
+}
+
+impl<M: Memory> PooledMemory<M> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Calls `f` with the pages of the pool, the pool offset and the length of every contiguous
+    /// part of the range.
+    fn for_each_part(
+        &self,
+        offset: u64,
+        len: usize,
+        mut f: impl FnMut(&VirtualMemory<M>, u64, usize, usize),
+    ) {
+        let state = self.state.borrow();
+        let pages = state
+            .memories
+            .get(&self.id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let end = offset + len as u64;
+        assert!(
+            end <= pages.len() as u64 * WASM_PAGE_SIZE,
+            "{offset}+{len} is out of bounds of the pooled memory {}",
+            self.id
+        );
+
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let page = pages[(position / WASM_PAGE_SIZE) as usize];
+            let in_page = position % WASM_PAGE_SIZE;
+            let part_len = ((WASM_PAGE_SIZE - in_page) as usize).min(len - done);
+            f(
+                &state.pages,
+                u64::from(page) * WASM_PAGE_SIZE + in_page,
+                done,
+                part_len,
+            );
+            done += part_len;
+        }
+    }
+}
+
+impl<M: Memory> Memory for PooledMemory<M> {
+    fn size(&self) -> u64 {
+        self.state
+            .borrow()
+            .memories
+            .get(&self.id)
+            .map_or(0, |pages| pages.len() as u64)
+    }
+
+    fn grow(&self, pages: u64) -> i64 {
+        let mut state = self.state.borrow_mut();
+        let size = state.memories.get(&self.id).map_or(0, Vec::len) as u64;
+        let reused = pages.min(state.free_pages.len() as u64) as usize;
+        let new_pages = pages - reused as u64;
+        let first_new_page = state.pages.size();
+        if first_new_page + new_pages > u64::from(u32::MAX)
+            || (new_pages > 0 && state.pages.grow(new_pages) < 0)
+        {
+            return -1;
+        }
+
+        let free_len = state.free_pages.len();
+        let mut grown: Vec<u32> = state.free_pages.drain(free_len - reused..).rev().collect();
+        // The reused pages contain the data of the released memory, while new pages are zeroed.
+        let zeroes = vec![0; WASM_PAGE_SIZE as usize];
+        for page in &grown {
+            state
+                .pages
+                .write(u64::from(*page) * WASM_PAGE_SIZE, &zeroes);
+        }
+        grown.extend((first_new_page..first_new_page + new_pages).map(|page| page as u32));
+
+        for (index, page) in grown.into_iter().enumerate() {
+            let index = (size + index as u64) as u32;
+            state.page_table.insert((self.id, index), page);
+            state.memories.entry(self.id).or_default().push(page);
+        }
+        size as i64
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.for_each_part(offset, dst.len(), |pages, pool_offset, start, len| {
+            pages.read(pool_offset, &mut dst[start..start + len])
+        });
+    }
+
+    fn write(&self, offset: u64, src: &[u8]) {
+        self.for_each_part(offset, src.len(), |pages, pool_offset, start, len| {
+            pages.write(pool_offset, &src[start..start + len])
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_isolate_pooled_memories() {
+        let memory = VectorMemory::default();
+        let pool = MemoryPool::init(memory.clone());
+        let mut maps: Vec<_> = (0..300)
+            .map(|id| StableBTreeMap::<u32, u64, _>::new(pool.get(id)))
+            .collect();
+        for (id, map) in maps.iter_mut().enumerate() {
+            map.insert(1, id as u64);
+        }
+        assert_eq!(pool.memory_ids().len(), 300);
+
+        let pool = MemoryPool::init(memory);
+        for id in 0..300 {
+            let map = StableBTreeMap::<u32, u64, _>::new(pool.get(id));
+            assert_eq!(map.get(&1), Some(id as u64));
+        }
+    }
+
+    #[test]
+    fn should_read_and_write_across_pages() {
+        let pool = MemoryPool::init(VectorMemory::default());
+        let first = pool.get(1);
+        let second = pool.get(2);
+        assert_eq!(first.grow(1), 0);
+        assert_eq!(second.grow(1), 0);
+        assert_eq!(first.grow(1), 1);
+        assert_eq!(first.size(), 2);
+        assert_eq!(pool.allocated_pages(), 3);
+
+        let data: Vec<u8> = (0..100).collect();
+        first.write(WASM_PAGE_SIZE - 50, &data);
+        second.write(WASM_PAGE_SIZE - 50, &[0; 50]);
+
+        let mut read = vec![0; 100];
+        first.read(WASM_PAGE_SIZE - 50, &mut read);
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn should_reuse_released_pages() {
+        let memory = VectorMemory::default();
+        let pool = MemoryPool::init(memory.clone());
+        let mut map = StableBTreeMap::<u32, u64, _>::new(pool.get(1));
+        map.insert(1, 10);
+        let mut other = StableBTreeMap::<u32, u64, _>::new(pool.get(2));
+        other.insert(2, 20);
+        let allocated = pool.allocated_pages();
+
+        map.clear();
+        drop(map.into_memory());
+        let released = pool.release(1);
+        assert!(released > 0);
+        assert_eq!(pool.free_pages(), released);
+        assert_eq!(pool.get(1).size(), 0);
+
+        let pool = MemoryPool::init(memory);
+        assert_eq!(pool.free_pages(), released);
+        let mut map = StableBTreeMap::<u32, u64, _>::new(pool.get(3));
+        assert_eq!(map.get(&1), None);
+        map.insert(3, 30);
+        assert_eq!(pool.allocated_pages(), allocated);
+        assert_eq!(pool.free_pages(), 0);
+
+        let other = StableBTreeMap::<u32, u64, _>::new(pool.get(2));
+        assert_eq!(other.get(&2), Some(20));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn should_panic_on_read_out_of_bounds() {
+        let pool = MemoryPool::init(VectorMemory::default());
+        let memory = pool.get(1);
+        memory.grow(1);
+        memory.read(WASM_PAGE_SIZE - 1, &mut [0; 2]);
+    }
+}
@@ -0,0 +1,281 @@
+//! Declaration of the memory ids used by the structures of a canister.
+//!
+//! Two structures sharing a memory id silently corrupt each other. Getting the memories through a
+//! [`MemoryRegistry`] records the label of the structure using each id, and fails when a second
+//! structure requests an id which is already used:
+//!
+//! ```ignore
+//! thread_local! {
+//!     static MEMORIES: MemoryRegistry<DefaultMemoryImpl> =
+//!         MemoryRegistry::new(default_ic_memory_manager());
+//!
+//!     static BALANCES: RefCell<StableBTreeMap<Principal, u64, VirtualMemory<DefaultMemoryImpl>>> =
+//!         RefCell::new(StableBTreeMap::new(
+//!             MEMORIES.with(|memories| memories.get_memory_by_id(1, "balances")),
+//!         ));
+//! }
+//! ```
+//!
+//! The registry is kept in heap memory and checks the ids requested since the start of the
+//! canister, so the structures initialized lazily are checked when they are first accessed.
+//!
+//! Libraries don't hard-code their ids, which could clash with the ids of the application: a
+//! registry created with [`MemoryRegistry::with_allocator`] allocates the ids by name, and stores
+//! the allocations in a reserved memory, so a name gets the same id after an upgrade:
+//!
+//! ```ignore
+//! let memories = MemoryRegistry::with_allocator(manager, DEFAULT_ALLOCATOR_MEMORY_ID);
+//! let sessions = StableBTreeMap::new(memories.allocate("ic-sessions/sessions")?);
+//! ```
+//!
+//! The ids are allocated from the highest one, skipping the ids registered by the application and
+//! the ids whose memory is already used.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use dfinity_stable_structures::memory_manager::{
+    MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
+};
+use dfinity_stable_structures::Memory;
+
+use crate::stats::MAX_MEMORY_ID;
+use crate::{BTreeMapStructure, Error, Result, StableBTreeMap};
+
+/// Default id of the memory storing the allocated memory ids: the last id of the memory manager
+pub const DEFAULT_ALLOCATOR_MEMORY_ID: u8 = MAX_MEMORY_ID;
+
+const ALLOCATOR_LABEL: &str = "memory id allocator";
+
+/// A memory manager recording the label of the structure using each memory id.
+pub struct MemoryRegistry<M: Memory> {
+    manager: IcMemoryManager<M>,
+    labels: RefCell<BTreeMap<u8, String>>,
+    /// Memory ids allocated by name, stored in the reserved memory
+    allocations: Option<RefCell<StableBTreeMap<String, u8, VirtualMemory<M>>>>,
+}
+
+impl<M: Memory> MemoryRegistry<M> {
+    pub fn new(manager: IcMemoryManager<M>) -> Self {
+        Self {
+            manager,
+            labels: RefCell::default(),
+            allocations: None,
+        }
+    }
+
+    /// Creates a registry allocating memory ids by name, see [`MemoryRegistry::allocate`], and
+    /// storing the allocations in the memory with the id `allocator_id`.
+    pub fn with_allocator(manager: IcMemoryManager<M>, allocator_id: u8) -> Self {
+        let allocations = StableBTreeMap::new(manager.get(MemoryId::new(allocator_id)));
+        Self {
+            manager,
+            labels: RefCell::new(BTreeMap::from([(
+                allocator_id,
+                ALLOCATOR_LABEL.to_string(),
+            )])),
+            allocations: Some(RefCell::new(allocations)),
+        }
+    }
+
+    /// Returns the memory with the id for the structure with the label.
+    ///
+    /// Fails if the id is already registered, even with the same label.
+    pub fn register(&self, id: u8, label: &str) -> Result<VirtualMemory<M>> {
+        if let Some(registered_by) = self.label(id).or_else(|| self.allocated_name(id)) {
+            return Err(Error::MemoryIdInUse {
+                id,
+                label: label.to_string(),
+                registered_by,
+            });
+        }
+
+        self.labels.borrow_mut().insert(id, label.to_string());
+        Ok(self.manager.get(MemoryId::new(id)))
+    }
+
+    /// Returns the memory allocated to the name, allocating a free memory id on the first call.
+    ///
+    /// Fails if the registry has no allocator, if the memory of the name is already requested, or
+    /// if all the memory ids are used.
+    pub fn allocate(&self, name: &str) -> Result<VirtualMemory<M>> {
+        let allocations = self
+            .allocations
+            .as_ref()
+            .ok_or(Error::NoMemoryIdAllocator)?;
+        let allocated = allocations.borrow().get(&name.to_string());
+        let id = match allocated {
+            Some(id) => id,
+            None => {
+                let id = self.free_id().ok_or(Error::NoFreeMemoryId)?;
+                allocations.borrow_mut().insert(name.to_string(), id);
+                id
+            }
+        };
+
+        if let Some(registered_by) = self.label(id) {
+            return Err(Error::MemoryIdInUse {
+                id,
+                label: name.to_string(),
+                registered_by,
+            });
+        }
+        self.labels.borrow_mut().insert(id, name.to_string());
+        Ok(self.manager.get(MemoryId::new(id)))
+    }
+
+    /// Returns the memory ids allocated by name, including the ones not requested since the start
+    /// of the canister.
+    pub fn allocations(&self) -> Vec<(String, u8)> {
+        self.allocations
+            .as_ref()
+            .map(|allocations| allocations.borrow().iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the memory with the id for the structure with the label, see
+    /// [`MemoryRegistry::register`].
+    ///
+    /// Panics if the id is already registered.
+    pub fn get_memory_by_id(&self, id: u8, label: &str) -> VirtualMemory<M> {
+        self.register(id, label)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Returns the label of the structure using the memory id.
+    pub fn label(&self, id: u8) -> Option<String> {
+        self.labels.borrow().get(&id).cloned()
+    }
+
+    /// Returns the registered memory ids with their labels, sorted by id.
+    pub fn registered(&self) -> Vec<(u8, String)> {
+        self.labels
+            .borrow()
+            .iter()
+            .map(|(id, label)| (*id, label.clone()))
+            .collect()
+    }
+
+    /// Returns the underlying memory manager, e.g. for the memory statistics.
+    pub fn manager(&self) -> &IcMemoryManager<M> {
+        &self.manager
+    }
+
+    fn allocated_name(&self, id: u8) -> Option<String> {
+        self.allocations
+            .as_ref()?
+            .borrow()
+            .iter()
+            .find_map(|(name, allocated)| (allocated == id).then_some(name))
+    }
+
+    /// Highest memory id which is not registered, not allocated and not used
+    fn free_id(&self) -> Option<u8> {
+        (0..=MAX_MEMORY_ID).rev().find(|&id| {
+            self.label(id).is_none()
+                && self.allocated_name(id).is_none()
+                && self.manager.get(MemoryId::new(id)).size() == 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    fn new_registry() -> MemoryRegistry<VectorMemory> {
+        MemoryRegistry::new(IcMemoryManager::init(VectorMemory::default()))
+    }
+
+    #[test]
+    fn should_register_memory_ids() {
+        let registry = new_registry();
+        let mut map = StableBTreeMap::<u64, u64, _>::new(registry.register(1, "balances").unwrap());
+        map.insert(1, 10);
+        registry.register(2, "history").unwrap();
+
+        assert_eq!(
+            registry.registered(),
+            vec![(1, "balances".to_string()), (2, "history".to_string())]
+        );
+        assert_eq!(registry.label(1).as_deref(), Some("balances"));
+        assert_eq!(registry.label(3), None);
+        assert!(registry.manager().get(MemoryId::new(1)).size() > 0);
+    }
+
+    #[test]
+    fn should_reject_duplicate_memory_id() {
+        let registry = new_registry();
+        registry.register(1, "balances").unwrap();
+
+        match registry.register(1, "allowances") {
+            Err(Error::MemoryIdInUse {
+                id,
+                label,
+                registered_by,
+            }) => {
+                assert_eq!(id, 1);
+                assert_eq!(label, "allowances");
+                assert_eq!(registered_by, "balances");
+            }
+            _ => panic!("the duplicate memory id is not rejected"),
+        }
+        assert_eq!(registry.label(1).as_deref(), Some("balances"));
+    }
+
+    #[test]
+    fn should_allocate_memory_ids_by_name() {
+        let memory = VectorMemory::default();
+        let registry = MemoryRegistry::with_allocator(IcMemoryManager::init(memory.clone()), 0);
+        let mut map = StableBTreeMap::<u64, u64, _>::new(registry.register(254, "app").unwrap());
+        map.insert(1, 10);
+
+        let mut sessions =
+            StableBTreeMap::<u64, u64, _>::new(registry.allocate("sessions").unwrap());
+        sessions.insert(1, 10);
+        registry.allocate("logs").unwrap();
+        assert_eq!(
+            registry.allocations(),
+            vec![("logs".to_string(), 252), ("sessions".to_string(), 253)]
+        );
+        assert!(matches!(
+            registry.allocate("logs"),
+            Err(Error::MemoryIdInUse { id: 252, .. })
+        ));
+        assert!(matches!(
+            registry.register(252, "other"),
+            Err(Error::MemoryIdInUse { id: 252, .. })
+        ));
+
+        let registry = MemoryRegistry::with_allocator(IcMemoryManager::init(memory), 0);
+        registry.allocate("logs").unwrap();
+        let sessions = StableBTreeMap::<u64, u64, _>::new(registry.allocate("sessions").unwrap());
+        assert_eq!(sessions.get(&1), Some(10));
+        assert!(matches!(
+            registry.register(253, "app"),
+            Err(Error::MemoryIdInUse { id: 253, .. })
+        ));
+        // The memory of the application is used, so it's skipped even if not registered yet.
+        registry.allocate("events").unwrap();
+        assert_eq!(registry.allocations()[0], ("events".to_string(), 251));
+    }
+
+    #[test]
+    fn should_fail_to_allocate_without_allocator() {
+        assert!(matches!(
+            new_registry().allocate("logs"),
+            Err(Error::NoMemoryIdAllocator)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "memory id 1 requested by `allowances`")]
+    fn should_panic_on_duplicate_memory_id() {
+        let registry = new_registry();
+        registry.get_memory_by_id(1, "balances");
+        registry.get_memory_by_id(1, "allowances");
+    }
+}
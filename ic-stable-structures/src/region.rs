@@ -0,0 +1,225 @@
+//! Memory regions for custom binary layouts.
+//!
+//! A region is a memory of the memory manager reserved for data which is not stored in one of the
+//! structures of this crate, e.g. a hand-written index or a layout inherited from another canister.
+//! The region starts with a header holding its name and the version of its layout, so a memory
+//! used by a structure or by another region is never taken over by mistake:
+//!
+//! ```ignore
+//! let mut region = MemoryRegion::reserve(&manager, MemoryId::new(20), "orderbook", 1)?;
+//! region.write(0, &level.to_bytes())?;
+//! ```
+//!
+//! The offsets of the [`MemoryRegion::read`] and [`MemoryRegion::write`] methods are relative to
+//! the end of the header.
+
+use dfinity_stable_structures::memory_manager::{
+    MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
+};
+use dfinity_stable_structures::Memory;
+
+use crate::stats::WASM_PAGE_SIZE;
+use crate::{Error, Result};
+
+const REGION_MAGIC: &[u8; 4] = b"ICRG";
+
+/// Largest length of the name of a region, in bytes
+pub const MAX_REGION_NAME_LEN: usize = 32;
+
+/// Size of the header of a region: magic, name length, name and version
+pub const REGION_HEADER_SIZE: u64 = 64;
+
+const NAME_LEN_OFFSET: u64 = 4;
+const NAME_OFFSET: u64 = 5;
+const VERSION_OFFSET: u64 = NAME_OFFSET + MAX_REGION_NAME_LEN as u64;
+
+/// A memory reserved for a custom binary layout, with a name and a versioned header.
+pub struct MemoryRegion<M: Memory> {
+    memory: M,
+    name: String,
+    version: u32,
+}
+
+impl<M: Memory> MemoryRegion<M> {
+    /// Reserves the memory for the region with the name, or opens the region if the memory
+    /// already contains it.
+    ///
+    /// The version is written in the header of a new region; an existing region keeps its
+    /// version, which the caller compares with the version of its layout to migrate the data.
+    ///
+    /// Fails if the memory is used by a structure or by a region with another name.
+    pub fn new(memory: M, name: &str, version: u32) -> Result<Self> {
+        if name.is_empty() || name.len() > MAX_REGION_NAME_LEN {
+            return Err(Error::InvalidRegionName(name.to_string()));
+        }
+
+        if memory.size() == 0 {
+            if memory.grow(1) < 0 {
+                return Err(Error::OutOfStableMemory);
+            }
+            let region = Self {
+                memory,
+                name: name.to_string(),
+                version,
+            };
+            region.write_header();
+            return Ok(region);
+        }
+
+        let mut magic = [0; REGION_MAGIC.len()];
+        memory.read(0, &mut magic);
+        if &magic != REGION_MAGIC {
+            return Err(Error::MemoryNotARegion);
+        }
+
+        let mut name_len = [0; 1];
+        memory.read(NAME_LEN_OFFSET, &mut name_len);
+        let mut stored_name = vec![0; (name_len[0] as usize).min(MAX_REGION_NAME_LEN)];
+        memory.read(NAME_OFFSET, &mut stored_name);
+        let stored_name = String::from_utf8_lossy(&stored_name).into_owned();
+        if stored_name != name {
+            return Err(Error::RegionNameMismatch {
+                expected: name.to_string(),
+                actual: stored_name,
+            });
+        }
+
+        let mut stored_version = [0; 4];
+        memory.read(VERSION_OFFSET, &mut stored_version);
+
+        Ok(Self {
+            memory,
+            name: stored_name,
+            version: u32::from_le_bytes(stored_version),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the layout of the region
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Updates the version of the layout, e.g. after a migration of the data.
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+        self.write_header();
+    }
+
+    /// Number of bytes available for the data without growing the region.
+    pub fn capacity(&self) -> u64 {
+        self.memory.size() * WASM_PAGE_SIZE - REGION_HEADER_SIZE
+    }
+
+    /// Reads the data at `offset`. The bytes past the capacity of the region are read as zeros.
+    pub fn read(&self, offset: u64, dst: &mut [u8]) {
+        let readable = self.capacity().saturating_sub(offset).min(dst.len() as u64) as usize;
+        let (available, past_end) = dst.split_at_mut(readable);
+        if !available.is_empty() {
+            self.memory.read(REGION_HEADER_SIZE + offset, available);
+        }
+        past_end.fill(0);
+    }
+
+    /// Writes the data at `offset`, growing the region if needed.
+    pub fn write(&mut self, offset: u64, src: &[u8]) -> Result<()> {
+        let end = offset + src.len() as u64;
+        if end > self.capacity() {
+            let missing = end - self.capacity();
+            if self.memory.grow(missing.div_ceil(WASM_PAGE_SIZE)) < 0 {
+                return Err(Error::OutOfStableMemory);
+            }
+        }
+
+        self.memory.write(REGION_HEADER_SIZE + offset, src);
+        Ok(())
+    }
+
+    /// Returns the memory of the region, including its header.
+    pub fn into_memory(self) -> M {
+        self.memory
+    }
+
+    fn write_header(&self) {
+        let mut header = [0; REGION_HEADER_SIZE as usize];
+        header[..REGION_MAGIC.len()].copy_from_slice(REGION_MAGIC);
+        header[NAME_LEN_OFFSET as usize] = self.name.len() as u8;
+        header[NAME_OFFSET as usize..NAME_OFFSET as usize + self.name.len()]
+            .copy_from_slice(self.name.as_bytes());
+        header[VERSION_OFFSET as usize..VERSION_OFFSET as usize + 4]
+            .copy_from_slice(&self.version.to_le_bytes());
+        self.memory.write(0, &header);
+    }
+}
+
+impl<M: Memory> MemoryRegion<VirtualMemory<M>> {
+    /// Reserves the memory with the id of the memory manager for the region, see
+    /// [`MemoryRegion::new`].
+    pub fn reserve(
+        manager: &IcMemoryManager<M>,
+        memory_id: MemoryId,
+        name: &str,
+        version: u32,
+    ) -> Result<Self> {
+        Self::new(manager.get(memory_id), name, version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[test]
+    fn should_reserve_and_reopen_region() {
+        let manager = IcMemoryManager::init(VectorMemory::default());
+        let mut region = MemoryRegion::reserve(&manager, MemoryId::new(5), "orderbook", 1).unwrap();
+        assert_eq!(region.capacity(), WASM_PAGE_SIZE - REGION_HEADER_SIZE);
+
+        region.write(10, b"level").unwrap();
+        region.write(WASM_PAGE_SIZE, b"far").unwrap();
+        region.set_version(2);
+
+        let region = MemoryRegion::reserve(&manager, MemoryId::new(5), "orderbook", 1).unwrap();
+        assert_eq!(region.name(), "orderbook");
+        assert_eq!(region.version(), 2);
+
+        let mut buf = [0; 5];
+        region.read(10, &mut buf);
+        assert_eq!(&buf, b"level");
+        let mut buf = [0; 3];
+        region.read(WASM_PAGE_SIZE, &mut buf);
+        assert_eq!(&buf, b"far");
+
+        let mut buf = [1; 4];
+        region.read(region.capacity() - 2, &mut buf);
+        assert_eq!(buf, [0; 4]);
+    }
+
+    #[test]
+    fn should_not_take_over_used_memory() {
+        let manager = IcMemoryManager::init(VectorMemory::default());
+        MemoryRegion::reserve(&manager, MemoryId::new(1), "orderbook", 1).unwrap();
+        assert!(matches!(
+            MemoryRegion::reserve(&manager, MemoryId::new(1), "candles", 1),
+            Err(Error::RegionNameMismatch { .. })
+        ));
+
+        let mut map = StableBTreeMap::<u64, u64, _>::new(manager.get(MemoryId::new(2)));
+        map.insert(1, 1);
+        assert!(matches!(
+            MemoryRegion::reserve(&manager, MemoryId::new(2), "orderbook", 1),
+            Err(Error::MemoryNotARegion)
+        ));
+
+        assert!(matches!(
+            MemoryRegion::reserve(&manager, MemoryId::new(3), &"x".repeat(33), 1),
+            Err(Error::InvalidRegionName(_))
+        ));
+    }
+}
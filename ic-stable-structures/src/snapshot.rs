@@ -0,0 +1,678 @@
+//! Portable snapshots of the stable structures, for backups and off-chain analysis.
+//!
+//! A snapshot is a versioned, self-describing byte stream: a header with the kind of the structure,
+//! the type names of the fields of its entries and the number of entries, followed by the entries
+//! with every field serialized with [`Storable`] and prefixed by its length. The snapshots of
+//! several structures are combined with [`BundleExporter`].
+//!
+//! The snapshots are restored with [`Import::import`], or with a [`SnapshotImporter`] when they
+//! are uploaded in chunks.
+//!
+//! All the integers are little-endian.
+
+use std::any::type_name;
+use std::borrow::Cow;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+use crate::{Error, Result};
+
+/// Version of the snapshot format written by this crate
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"ICSS";
+const BUNDLE_MAGIC: &[u8; 4] = b"ICSB";
+const TRUNCATED: &str = "truncated snapshot";
+
+/// Kind of the structure of a snapshot, which defines the fields of its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureKind {
+    /// A single entry with the value
+    Cell = 0,
+    /// Entries with the key and the value
+    BTreeMap = 1,
+    /// Entries with the value, in index order
+    Log = 2,
+    /// Entries with the value, in index order
+    Vec = 3,
+    /// Entries with the first key, the second key and the value
+    Multimap = 4,
+    /// Entries with the key
+    Set = 5,
+}
+
+impl TryFrom<u8> for StructureKind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Ok(match value {
+            0 => Self::Cell,
+            1 => Self::BTreeMap,
+            2 => Self::Log,
+            3 => Self::Vec,
+            4 => Self::Multimap,
+            5 => Self::Set,
+            _ => return Err(Error::InvalidSnapshot("unknown structure kind")),
+        })
+    }
+}
+
+/// Header of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub version: u8,
+    pub kind: StructureKind,
+    /// Type names of the fields of the entries, as returned by `std::any::type_name`
+    pub field_types: Vec<String>,
+    /// Number of entries
+    pub entries: u64,
+}
+
+impl SnapshotHeader {
+    /// Checks that the snapshot contains a structure of the given kind whose entries have `fields`
+    /// fields. The type names of the fields are not compared, as they change when a type is
+    /// renamed or moved to another module.
+    pub fn check_kind(&self, kind: StructureKind, fields: usize) -> Result<()> {
+        if self.kind != kind {
+            return Err(Error::InvalidSnapshot("structure kind mismatch"));
+        }
+        if self.field_types.len() != fields {
+            return Err(Error::InvalidSnapshot("wrong number of fields"));
+        }
+        Ok(())
+    }
+}
+
+/// Structure which can be exported to a snapshot.
+pub trait Export {
+    /// Serializes the content of the structure to a snapshot.
+    fn export(&self) -> Vec<u8>;
+}
+
+/// Writes the snapshot of a structure, entry by entry.
+pub struct SnapshotWriter {
+    buf: Vec<u8>,
+    fields: usize,
+}
+
+impl SnapshotWriter {
+    /// Writes the header of a snapshot with `entries` entries whose fields have the given types.
+    pub fn new(kind: StructureKind, field_types: &[&str], entries: u64) -> Self {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(SNAPSHOT_MAGIC);
+        buf.push(SNAPSHOT_VERSION);
+        buf.push(kind as u8);
+        buf.push(field_types.len() as u8);
+        for field_type in field_types {
+            write_prefixed(&mut buf, field_type.as_bytes());
+        }
+        buf.extend_from_slice(&entries.to_le_bytes());
+
+        Self {
+            buf,
+            fields: field_types.len(),
+        }
+    }
+
+    /// Writes an entry. The entry must have as many fields as declared in the header.
+    pub fn entry(&mut self, fields: &[&[u8]]) {
+        assert_eq!(
+            fields.len(),
+            self.fields,
+            "snapshot entry has a wrong number of fields"
+        );
+        for field in fields {
+            write_prefixed(&mut self.buf, field);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Returns the type name of `T`, to describe the fields of the entries.
+pub fn field_type<T>() -> &'static str {
+    type_name::<T>()
+}
+
+/// A parsed snapshot.
+pub struct Snapshot<'a> {
+    header: SnapshotHeader,
+    entries: &'a [u8],
+}
+
+impl<'a> Snapshot<'a> {
+    /// Parses the header of the snapshot. The entries are read lazily by [`Snapshot::entries`].
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let header = read_header(&mut reader)?;
+
+        Ok(Self {
+            header,
+            entries: reader.remaining(),
+        })
+    }
+
+    pub fn header(&self) -> &SnapshotHeader {
+        &self.header
+    }
+
+    /// Checks that the snapshot contains a structure of the given kind with entries of the given
+    /// field types.
+    pub fn check_layout(&self, kind: StructureKind, field_types: &[&str]) -> Result<()> {
+        if self.header.kind != kind {
+            return Err(Error::InvalidSnapshot("structure kind mismatch"));
+        }
+        if self.header.field_types != field_types {
+            return Err(Error::InvalidSnapshot("field types mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Iterates over the fields of the entries.
+    pub fn entries(&self) -> SnapshotEntries<'a> {
+        SnapshotEntries {
+            reader: Reader::new(self.entries),
+            fields: self.header.field_types.len(),
+            remaining: self.header.entries,
+        }
+    }
+}
+
+/// Iterator over the entries of a snapshot, yielding the serialized fields of every entry.
+pub struct SnapshotEntries<'a> {
+    reader: Reader<'a>,
+    fields: usize,
+    remaining: u64,
+}
+
+impl<'a> Iterator for SnapshotEntries<'a> {
+    type Item = Result<Vec<&'a [u8]>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let entry = (0..self.fields)
+            .map(|_| self.reader.read_prefixed())
+            .collect::<Result<Vec<_>>>();
+        if entry.is_err() {
+            // Stop after a truncated entry
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+}
+
+/// Exports several structures, e.g. all the structures of a memory manager, to a single bundle.
+///
+/// Every structure is identified by the id of its memory in the memory manager and a name.
+#[derive(Default)]
+pub struct BundleExporter<'a> {
+    sections: Vec<(u8, String, &'a dyn Export)>,
+}
+
+impl<'a> BundleExporter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a structure to the bundle.
+    pub fn with(mut self, memory_id: u8, name: &str, structure: &'a dyn Export) -> Self {
+        self.sections.push((memory_id, name.to_string(), structure));
+        self
+    }
+
+    /// Serializes the structures to a bundle.
+    pub fn export(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(BUNDLE_MAGIC);
+        buf.push(SNAPSHOT_VERSION);
+        buf.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        for (memory_id, name, structure) in &self.sections {
+            buf.push(*memory_id);
+            write_prefixed(&mut buf, name.as_bytes());
+            let snapshot = structure.export();
+            buf.extend_from_slice(&(snapshot.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&snapshot);
+        }
+        buf
+    }
+}
+
+/// The snapshot of a structure in a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleSection<'a> {
+    pub memory_id: u8,
+    pub name: String,
+    pub snapshot: &'a [u8],
+}
+
+/// Returns the sections of a bundle written by [`BundleExporter`].
+pub fn parse_bundle(bytes: &[u8]) -> Result<Vec<BundleSection<'_>>> {
+    let mut reader = Reader::new(bytes);
+    if reader.read(BUNDLE_MAGIC.len())? != BUNDLE_MAGIC {
+        return Err(Error::InvalidSnapshot("bad bundle magic"));
+    }
+    let version = reader.read_u8()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(Error::UnsupportedSnapshotVersion(version));
+    }
+
+    let sections = reader.read_u32()?;
+    (0..sections)
+        .map(|_| {
+            let memory_id = reader.read_u8()?;
+            let name = String::from_utf8(reader.read_prefixed()?.to_vec())
+                .map_err(|_| Error::InvalidSnapshot("section name is not UTF-8"))?;
+            let len = reader.read_u64()?;
+            let snapshot = reader.read(len as usize)?;
+            Ok(BundleSection {
+                memory_id,
+                name,
+                snapshot,
+            })
+        })
+        .collect()
+}
+
+/// Structure which can be restored from a snapshot written by [`Export::export`].
+pub trait Import {
+    /// Checks that the snapshot contains a structure of the same kind.
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()>;
+
+    /// Removes the content of the structure before the import.
+    fn reset(&mut self) -> Result<()>;
+
+    /// Checks that the fields of an entry can be deserialized, see [`check_field`].
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()>;
+
+    /// Adds an entry of the snapshot to the structure.
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()>;
+
+    /// Replaces the content of the structure with the entries of the snapshot. Returns the number
+    /// of imported entries.
+    ///
+    /// The snapshot is validated before the structure is modified, so an invalid snapshot
+    /// leaves the structure untouched. Large snapshots are imported in several calls with
+    /// [`SnapshotImporter`].
+    fn import(&mut self, snapshot: &[u8]) -> Result<u64> {
+        let snapshot = Snapshot::parse(snapshot)?;
+        self.check_snapshot(snapshot.header())?;
+        for entry in snapshot.entries() {
+            self.check_entry(&entry?)?;
+        }
+
+        self.reset()?;
+        for entry in snapshot.entries() {
+            self.import_entry(&entry?)?;
+        }
+        Ok(snapshot.header().entries)
+    }
+}
+
+/// Progress of an import
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportProgress {
+    /// Number of entries imported so far
+    pub imported: u64,
+    /// Number of entries in the snapshot, known once its header is received
+    pub total: Option<u64>,
+}
+
+impl ImportProgress {
+    pub fn is_complete(&self) -> bool {
+        self.total == Some(self.imported)
+    }
+}
+
+/// Imports a snapshot received in chunks, e.g. uploaded in several update calls, applying the
+/// entries as soon as they are complete.
+///
+/// Unlike [`Import::import`], the structure is reset when the header is received and the entries
+/// are applied before the end of the snapshot is validated: an import failing midway leaves the
+/// structure partially restored, and must be restarted with a new importer.
+#[derive(Debug, Default)]
+pub struct SnapshotImporter {
+    /// Received bytes which are not imported yet
+    pending: Vec<u8>,
+    header: Option<SnapshotHeader>,
+    imported: u64,
+}
+
+impl SnapshotImporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Imports the complete entries of the chunk and of the previous chunks to the structure.
+    pub fn push_chunk(
+        &mut self,
+        structure: &mut dyn Import,
+        chunk: &[u8],
+    ) -> Result<ImportProgress> {
+        self.pending.extend_from_slice(chunk);
+        let mut reader = Reader::new(&self.pending);
+
+        if self.header.is_none() {
+            let header = match read_header(&mut reader) {
+                Ok(header) => header,
+                Err(Error::InvalidSnapshot(TRUNCATED)) => return Ok(self.progress()),
+                Err(e) => return Err(e),
+            };
+            structure.check_snapshot(&header)?;
+            structure.reset()?;
+            self.header = Some(header);
+        }
+
+        let header = self.header.as_ref().expect("header is read");
+        let mut consumed = reader.offset;
+        while self.imported < header.entries {
+            let entry = (0..header.field_types.len())
+                .map(|_| reader.read_prefixed())
+                .collect::<Result<Vec<_>>>();
+            match entry {
+                Ok(entry) => {
+                    structure.check_entry(&entry)?;
+                    structure.import_entry(&entry)?;
+                }
+                Err(Error::InvalidSnapshot(TRUNCATED)) => break,
+                Err(e) => return Err(e),
+            }
+            self.imported += 1;
+            consumed = reader.offset;
+        }
+
+        if self.imported == header.entries && consumed < self.pending.len() {
+            return Err(Error::InvalidSnapshot(
+                "trailing bytes after the last entry",
+            ));
+        }
+        self.pending.drain(..consumed);
+        Ok(self.progress())
+    }
+
+    pub fn progress(&self) -> ImportProgress {
+        ImportProgress {
+            imported: self.imported,
+            total: self.header.as_ref().map(|header| header.entries),
+        }
+    }
+
+    /// Completes the import. Returns the number of imported entries, fails if the snapshot is
+    /// incomplete.
+    pub fn finish(self) -> Result<u64> {
+        if !self.progress().is_complete() {
+            return Err(Error::InvalidSnapshot(TRUNCATED));
+        }
+        Ok(self.imported)
+    }
+}
+
+/// Checks the size of a field of an entry against the bound of its type.
+pub fn check_field<T: Storable>(bytes: &[u8]) -> Result<()> {
+    match T::BOUND {
+        Bound::Bounded {
+            max_size,
+            is_fixed_size,
+        } if bytes.len() > max_size as usize
+            || (is_fixed_size && bytes.len() != max_size as usize) =>
+        {
+            Err(Error::InvalidSnapshot(
+                "field size exceeds the bound of its type",
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Deserializes a field of an entry, checking its size against the bound of its type.
+pub fn decode_field<T: Storable>(bytes: &[u8]) -> Result<T> {
+    check_field::<T>(bytes)?;
+    Ok(T::from_bytes(Cow::Borrowed(bytes)))
+}
+
+fn read_header(reader: &mut Reader) -> Result<SnapshotHeader> {
+    if reader.read(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+        return Err(Error::InvalidSnapshot("bad magic"));
+    }
+    let version = reader.read_u8()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(Error::UnsupportedSnapshotVersion(version));
+    }
+    let kind = StructureKind::try_from(reader.read_u8()?)?;
+    let fields = reader.read_u8()?;
+    let field_types = (0..fields)
+        .map(|_| {
+            String::from_utf8(reader.read_prefixed()?.to_vec())
+                .map_err(|_| Error::InvalidSnapshot("field type is not UTF-8"))
+        })
+        .collect::<Result<_>>()?;
+    let entries = reader.read_u64()?;
+
+    Ok(SnapshotHeader {
+        version,
+        kind,
+        field_types,
+        entries,
+    })
+}
+
+fn write_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+    let len = u32::try_from(bytes.len()).expect("snapshot field is larger than 4 GiB");
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(Error::InvalidSnapshot(TRUNCATED))?;
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.read(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(
+            self.read(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn read_prefixed(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u32()?;
+        self.read(len as usize)
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::{
+        BTreeMapStructure, CellStructure, LogStructure, MultimapStructure, StableBTreeMap,
+        StableCell, StableLog, StableMultimap, StableVec, Storable, VecStructure,
+    };
+
+    #[test]
+    fn should_export_map() {
+        let mut map = StableBTreeMap::<u64, StringValue, _>::new(VectorMemory::default());
+        map.insert(1, str_val(1));
+        map.insert(2, str_val(2));
+
+        let bytes = map.export();
+        let snapshot = Snapshot::parse(&bytes).unwrap();
+        assert_eq!(
+            snapshot.header(),
+            &SnapshotHeader {
+                version: SNAPSHOT_VERSION,
+                kind: StructureKind::BTreeMap,
+                field_types: vec![
+                    field_type::<u64>().to_string(),
+                    field_type::<StringValue>().to_string()
+                ],
+                entries: 2,
+            }
+        );
+
+        let entries: Vec<_> = snapshot.entries().map(|entry| entry.unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1][0], 2u64.to_bytes().as_ref());
+        assert_eq!(entries[1][1], str_val(2).to_bytes().as_ref());
+    }
+
+    #[test]
+    fn should_export_bundle() {
+        let mut log = StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        log.append(str_val(3)).unwrap();
+        let cell = StableCell::new(VectorMemory::default(), 42u64).unwrap();
+        let mut multimap = StableMultimap::<u64, u64, u64, _>::new(VectorMemory::default());
+        multimap.insert(&1, &2, 3);
+
+        let bytes = BundleExporter::new()
+            .with(1, "history", &log)
+            .with(3, "counter", &cell)
+            .with(4, "allowances", &multimap)
+            .export();
+        let sections = parse_bundle(&bytes).unwrap();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0].memory_id, 1);
+        assert_eq!(sections[0].name, "history");
+
+        let counter = Snapshot::parse(sections[1].snapshot).unwrap();
+        assert_eq!(counter.header().kind, StructureKind::Cell);
+        let value = counter.entries().next().unwrap().unwrap();
+        assert_eq!(value, vec![cell.get().to_bytes().as_ref()]);
+
+        let allowances = Snapshot::parse(sections[2].snapshot).unwrap();
+        assert_eq!(allowances.header().field_types.len(), 3);
+        assert_eq!(allowances.entries().count(), 1);
+    }
+
+    #[test]
+    fn should_reject_invalid_snapshots() {
+        let map = StableBTreeMap::<u64, u64, _>::new(VectorMemory::default());
+        let mut bytes = map.export();
+
+        assert!(matches!(
+            Snapshot::parse(&bytes[..6]),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        bytes[4] = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            Snapshot::parse(&bytes),
+            Err(Error::UnsupportedSnapshotVersion(_))
+        ));
+    }
+
+    #[test]
+    fn should_import_exported_structures() {
+        let mut map = StableBTreeMap::<u64, StringValue, _>::new(VectorMemory::default());
+        map.insert(1, str_val(1));
+        map.insert(2, str_val(2));
+        let mut restored = StableBTreeMap::<u64, StringValue, _>::new(VectorMemory::default());
+        restored.insert(3, str_val(3));
+
+        assert_eq!(restored.import(&map.export()).unwrap(), 2);
+        assert_eq!(
+            restored.iter().collect::<Vec<_>>(),
+            map.iter().collect::<Vec<_>>()
+        );
+
+        let cell = StableCell::new(VectorMemory::default(), 42u64).unwrap();
+        let mut restored = StableCell::new(VectorMemory::default(), 0u64).unwrap();
+        restored.import(&cell.export()).unwrap();
+        assert_eq!(*restored.get(), 42);
+    }
+
+    #[test]
+    fn should_validate_snapshot_before_import() {
+        let mut map = StableBTreeMap::<u64, u64, _>::new(VectorMemory::default());
+        map.insert(1, 1);
+        let mut log = StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        log.append(str_val(1)).unwrap();
+
+        let mut restored = StableBTreeMap::<u64, u64, _>::new(VectorMemory::default());
+        restored.insert(5, 5);
+        assert!(restored.import(&log.export()).is_err());
+
+        let snapshot = map.export();
+        assert!(restored.import(&snapshot[..snapshot.len() - 1]).is_err());
+
+        let mut writer = SnapshotWriter::new(
+            StructureKind::BTreeMap,
+            &[field_type::<u64>(), field_type::<u64>()],
+            1,
+        );
+        writer.entry(&[&[1, 2, 3], &[0; 8]]);
+        assert!(restored.import(&writer.finish()).is_err());
+
+        assert_eq!(restored.get(&5), Some(5));
+        assert_eq!(restored.len(), 1);
+    }
+
+    #[test]
+    fn should_import_snapshot_in_chunks() {
+        let mut vec = StableVec::<u64, _>::new(VectorMemory::default()).unwrap();
+        for i in 0..10 {
+            vec.push(&i).unwrap();
+        }
+        let snapshot = vec.export();
+
+        let mut restored = StableVec::<u64, _>::new(VectorMemory::default()).unwrap();
+        restored.push(&100).unwrap();
+        let mut importer = SnapshotImporter::new();
+        let mut last_progress = ImportProgress::default();
+        for chunk in snapshot.chunks(7) {
+            let progress = importer.push_chunk(&mut restored, chunk).unwrap();
+            assert!(progress.imported >= last_progress.imported);
+            last_progress = progress;
+        }
+
+        assert_eq!(importer.finish().unwrap(), 10);
+        assert_eq!(
+            restored.iter().collect::<Vec<_>>(),
+            vec.iter().collect::<Vec<_>>()
+        );
+
+        let mut importer = SnapshotImporter::new();
+        importer
+            .push_chunk(&mut restored, &snapshot[..snapshot.len() / 2])
+            .unwrap();
+        assert!(!importer.progress().is_complete());
+        assert!(importer.finish().is_err());
+    }
+}
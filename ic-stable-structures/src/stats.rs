@@ -0,0 +1,165 @@
+use std::ops::Add;
+
+use dfinity_stable_structures::memory_manager::{MemoryId, MemoryManager as IcMemoryManager};
+use dfinity_stable_structures::{Memory, Storable};
+
+/// Size of a page of stable memory, in bytes
+pub const WASM_PAGE_SIZE: u64 = 65536;
+
+/// The largest memory id supported by the memory manager
+pub(crate) const MAX_MEMORY_ID: u8 = 254;
+
+/// Usage statistics of a stable structure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Number of entries in the structure
+    pub entries: u64,
+    /// Sum of the serialized sizes of the entries and of the auxiliary data of the structure,
+    /// e.g. its indices. The structure occupies more memory due to its layout.
+    pub bytes: u64,
+}
+
+impl Add for MemoryStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            entries: self.entries + other.entries,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+/// Stable structure able to report its memory usage.
+pub trait MemoryUsage {
+    /// Returns the usage statistics of the structure.
+    ///
+    /// The entries are read to compute the size of their data, so the cost is linear in the number
+    /// of entries: use it in queries or diagnostics, not in hot paths.
+    fn memory_stats(&self) -> MemoryStats;
+}
+
+/// Serialized size of the value, in bytes
+pub(crate) fn storable_size<T: Storable>(value: &T) -> u64 {
+    value.to_bytes().len() as u64
+}
+
+/// Stable memory occupied by a memory id of the memory manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryIdUsage {
+    pub memory_id: MemoryId,
+    /// Number of pages allocated to the memory
+    pub pages: u64,
+}
+
+impl MemoryIdUsage {
+    /// Number of bytes allocated to the memory
+    pub fn bytes(&self) -> u64 {
+        self.pages * WASM_PAGE_SIZE
+    }
+}
+
+/// Stable memory occupied by the memory ids of a memory manager
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsageSummary {
+    /// The memory ids with allocated pages, sorted by id
+    pub memories: Vec<MemoryIdUsage>,
+}
+
+impl MemoryUsageSummary {
+    /// Number of pages allocated to all the memory ids
+    pub fn total_pages(&self) -> u64 {
+        self.memories.iter().map(|memory| memory.pages).sum()
+    }
+
+    /// Number of bytes allocated to all the memory ids
+    pub fn total_bytes(&self) -> u64 {
+        self.total_pages() * WASM_PAGE_SIZE
+    }
+
+    /// Returns the usage of the memory id, if it has allocated pages.
+    pub fn get(&self, memory_id: MemoryId) -> Option<&MemoryIdUsage> {
+        self.memories
+            .iter()
+            .find(|memory| memory.memory_id == memory_id)
+    }
+}
+
+/// Returns the stable memory occupied by every memory id of the memory manager.
+///
+/// The memory manager allocates the pages by buckets, so the pages of a memory id are the memory
+/// reserved by its structures, which is larger than the data of the structures.
+pub fn memory_usage_summary<M: Memory>(manager: &IcMemoryManager<M>) -> MemoryUsageSummary {
+    let memories = (0..=MAX_MEMORY_ID)
+        .map(MemoryId::new)
+        .map(|memory_id| MemoryIdUsage {
+            memory_id,
+            pages: manager.get(memory_id).size(),
+        })
+        .filter(|memory| memory.pages > 0)
+        .collect();
+
+    MemoryUsageSummary { memories }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::{BTreeMapStructure, LogStructure, StableBTreeMap, StableLog};
+
+    #[test]
+    fn should_report_structure_stats() {
+        let mut map = StableBTreeMap::<u64, StringValue, _>::new(VectorMemory::default());
+        assert_eq!(map.memory_stats(), MemoryStats::default());
+
+        map.insert(1, str_val(10));
+        map.insert(2, str_val(20));
+        assert_eq!(
+            map.memory_stats(),
+            MemoryStats {
+                entries: 2,
+                bytes: 2 * 8 + 30
+            }
+        );
+
+        let mut log = StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        log.append(str_val(5)).unwrap();
+        assert_eq!(
+            map.memory_stats() + log.memory_stats(),
+            MemoryStats {
+                entries: 3,
+                bytes: 51
+            }
+        );
+    }
+
+    #[test]
+    fn should_summarize_memory_ids() {
+        let manager = IcMemoryManager::init(VectorMemory::default());
+        assert_eq!(
+            memory_usage_summary(&manager),
+            MemoryUsageSummary::default()
+        );
+
+        let mut map = StableBTreeMap::<u64, u64, _>::new(manager.get(MemoryId::new(3)));
+        map.insert(1, 1);
+        let _other = StableBTreeMap::<u64, u64, _>::new(manager.get(MemoryId::new(7)));
+
+        let summary = memory_usage_summary(&manager);
+        let ids: Vec<_> = summary
+            .memories
+            .iter()
+            .map(|memory| memory.memory_id)
+            .collect();
+        assert_eq!(ids, vec![MemoryId::new(3), MemoryId::new(7)]);
+        assert!(summary.get(MemoryId::new(3)).unwrap().pages > 0);
+        assert!(summary.get(MemoryId::new(4)).is_none());
+        assert_eq!(
+            summary.total_bytes(),
+            summary.total_pages() * WASM_PAGE_SIZE
+        );
+    }
+}
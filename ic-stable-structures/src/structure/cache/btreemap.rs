@@ -38,6 +38,11 @@ where
     pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
         &self.inner
     }
+
+    /// Returns the hits and misses of the reads through the cache, e.g. to tune its size.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
 }
 
 impl<K, V, M> BTreeMapStructure<K, V> for CachedStableBTreeMap<K, V, M>
@@ -76,7 +81,7 @@ where
     }
 
     fn contains_key(&self, key: &K) -> bool {
-        self.inner.contains_key(key)
+        self.cache.get(key).is_some() || self.inner.contains_key(key)
     }
 
     fn is_empty(&self) -> bool {
@@ -180,6 +185,20 @@ mod tests {
         assert_eq!(None, map.get(&4));
     }
 
+    #[test]
+    fn should_count_cache_hits() {
+        let mut map = CachedStableBTreeMap::<u32, Array<2>, _>::new(VectorMemory::default(), 2);
+        map.insert(1, Array([1u8, 1]));
+
+        map.get(&1);
+        map.get(&1);
+        map.insert(1, Array([1u8, 2]));
+        assert_eq!(Some(Array([1u8, 2])), map.get(&1));
+        assert!(map.contains_key(&1));
+
+        assert_eq!(map.cache_stats(), CacheStats { hits: 1, misses: 2 });
+    }
+
     #[test]
     fn should_clear() {
         let cache_items = 2;
@@ -1,13 +1,25 @@
 use std::convert::Infallible;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use parking_lot::Mutex;
 use schnellru::{ByLength, LruMap};
 
+/// Hits and misses of the reads through a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads served by the cache
+    pub hits: u64,
+    /// Reads which loaded the value from the underlying structure
+    pub misses: u64,
+}
+
 /// A wrapper around `LruCache`. This struct is thread safe, doesn't return any references to any
 /// elements inside.
 pub struct SyncLruCache<K, V> {
     inner: Mutex<LruMap<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl<K, V> SyncLruCache<K, V>
@@ -20,6 +32,8 @@ where
         Self {
             // Creating an inner LruMap with a fixed hasher
             inner: Mutex::new(LruMap::<K, V>::with_seed(ByLength::new(cap), [0, 1, 3, 4])),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
 
@@ -58,8 +72,10 @@ where
         F: FnOnce(&K) -> Result<Option<V>, E>,
     {
         if let Some(result) = self.get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
             return Ok(Some(result));
         }
+        self.misses.fetch_add(1, Ordering::Relaxed);
         let val = f(key)?;
         if let Some(val) = val.as_ref() {
             let val_clone = val.clone();
@@ -90,6 +106,14 @@ where
     pub fn clear(&self) {
         self.inner.lock().clear()
     }
+
+    /// Returns the hits and misses of the `get_or_insert_with` and `get_or_try_insert_with` reads.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -109,5 +133,9 @@ mod tests {
         assert_eq!(cache.get(&123u64), Some(vec![123u64, 123]));
         assert_eq!(cache.get_or_insert_with(&127u64, |_| None), None);
         assert_eq!(cache.get(&0u64), None);
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 2 });
+
+        cache.get_or_insert_with(&123u64, |_| None);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
     }
 }
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::*;
+
+/// A StableBTreeMap with a fully decoded heap copy of its entries.
+///
+/// The heap copy is built on the first read after a write, so reads are served at
+/// native speed without decoding the entries from stable memory.
+/// This is meant for small and read-mostly maps, e.g. configuration or reference data,
+/// because every write invalidates the whole copy.
+pub struct MaterializedView<K, V, M>
+where
+    K: Storable + Clone + Ord,
+    V: Storable + Clone,
+    M: Memory,
+{
+    inner: StableBTreeMap<K, V, M>,
+    view: OnceLock<BTreeMap<K, V>>,
+}
+
+impl<K, V, M> MaterializedView<K, V, M>
+where
+    K: Storable + Clone + Ord,
+    V: Storable + Clone,
+    M: Memory,
+{
+    /// Create new instance of the MaterializedView.
+    pub fn new(memory: M) -> Self {
+        Self::with_map(StableBTreeMap::new(memory))
+    }
+
+    /// Create new instance of the MaterializedView over an existing map.
+    pub fn with_map(inner: StableBTreeMap<K, V, M>) -> Self {
+        Self {
+            inner,
+            view: OnceLock::new(),
+        }
+    }
+
+    /// Returns the heap copy of the entries, building it if the map changed since the last read.
+    pub fn view(&self) -> &BTreeMap<K, V> {
+        self.view.get_or_init(|| self.inner.iter().collect())
+    }
+
+    /// Returns a reference to the value associated with `key`, without decoding it from stable memory.
+    pub fn get_ref(&self, key: &K) -> Option<&V> {
+        self.view().get(key)
+    }
+
+    /// Returns the inner collection so that the caller can have a readonly access to it that bypasses the view.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.inner
+    }
+
+    /// Returns whether the heap copy is up to date.
+    pub fn is_materialized(&self) -> bool {
+        self.view.get().is_some()
+    }
+
+    fn invalidate(&mut self) {
+        self.view.take();
+    }
+}
+
+impl<K, V, M> BTreeMapStructure<K, V> for MaterializedView<K, V, M>
+where
+    K: Storable + Clone + Ord,
+    V: Storable + Clone,
+    M: Memory,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.get_ref(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.invalidate();
+        self.inner.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let old_value = self.inner.remove(key);
+        if old_value.is_some() {
+            self.invalidate();
+        }
+        old_value
+    }
+
+    fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.view().contains_key(key)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.invalidate();
+        self.inner.clear()
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.view()
+            .first_key_value()
+            .map(|(key, value)| (key.clone(), value.clone()))
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.view()
+            .last_key_value()
+            .map(|(key, value)| (key.clone(), value.clone()))
+    }
+}
+
+/// WARN: the iterators bypass the view, use `view()` to iterate over the heap copy
+impl<K, V, M> IterableSortedMapStructure<K, V> for MaterializedView<K, V, M>
+where
+    K: Storable + Clone + Ord,
+    V: Storable + Clone,
+    M: Memory,
+{
+    type Iterator<'a> = dfinity_stable_structures::btreemap::Iter<'a, K, V, M> where Self: 'a;
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        self.inner.iter()
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        self.inner.range(key_range)
+    }
+
+    fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
+        self.inner.iter_upper_bound(bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::Array;
+
+    #[test]
+    fn should_materialize_lazily() {
+        let mut map = MaterializedView::<u32, Array<2>, _>::new(VectorMemory::default());
+        assert!(!map.is_materialized());
+
+        map.insert(1, Array([1u8, 1]));
+        map.insert(2, Array([2u8, 1]));
+        assert!(!map.is_materialized());
+
+        assert_eq!(map.get(&1), Some(Array([1u8, 1])));
+        assert!(map.is_materialized());
+        assert_eq!(map.get_ref(&2), Some(&Array([2u8, 1])));
+        assert_eq!(map.view().len(), 2);
+
+        assert_eq!(map.insert(1, Array([1u8, 2])), Some(Array([1u8, 1])));
+        assert!(!map.is_materialized());
+        assert_eq!(map.get(&1), Some(Array([1u8, 2])));
+
+        // removing a missing key keeps the view
+        assert_eq!(map.remove(&3), None);
+        assert!(map.is_materialized());
+
+        assert_eq!(map.remove(&1), Some(Array([1u8, 2])));
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.first_key_value(), Some((2, Array([2u8, 1]))));
+        assert_eq!(map.last_key_value(), Some((2, Array([2u8, 1]))));
+
+        map.clear();
+        assert!(map.is_empty());
+        assert!(map.view().is_empty());
+    }
+
+    #[test]
+    fn should_materialize_existing_map() {
+        let mut inner = StableBTreeMap::<u32, Array<2>, _>::new(VectorMemory::default());
+        inner.insert(1, Array([1u8, 1]));
+
+        let map = MaterializedView::with_map(inner);
+        assert_eq!(map.get(&1), Some(Array([1u8, 1])));
+        assert_eq!(map.len(), 1);
+    }
+}
@@ -1,7 +1,13 @@
 pub mod btreemap;
 pub mod lru;
+pub mod materialized;
 pub mod multimap;
+pub mod stable_lru;
+pub mod write_behind;
 
 pub use btreemap::CachedStableBTreeMap;
-pub use lru::SyncLruCache;
+pub use lru::{CacheStats, SyncLruCache};
+pub use materialized::MaterializedView;
 pub use multimap::CachedStableMultimap;
+pub use stable_lru::{StableLruCache, StableLruCacheState};
+pub use write_behind::WriteBehindBTreeMap;
@@ -40,6 +40,16 @@ where
     pub fn inner(&self) -> &StableMultimap<K1, K2, V, M> {
         &self.inner
     }
+
+    /// Returns an iterator over all the entries starting from the given pair of keys included,
+    /// see [`StableMultimap::iter_from`].
+    pub fn iter_from(
+        &self,
+        first_key: &K1,
+        second_key: &K2,
+    ) -> <StableMultimap<K1, K2, V, M> as MultimapStructure<K1, K2, V>>::Iterator<'_> {
+        self.inner.iter_from(first_key, second_key)
+    }
 }
 
 impl<K1, K2, V, M> MultimapStructure<K1, K2, V> for CachedStableMultimap<K1, K2, V, M>
@@ -88,7 +98,7 @@ where
         self.inner.remove_partial(first_key)
     }
 
-    fn len(&self) -> usize {
+    fn len(&self) -> u64 {
         self.inner.len()
     }
 
@@ -0,0 +1,373 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::num::NonZeroU64;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, CellStructure, StableBTreeMap, StableCell};
+use crate::{MemoryStats, MemoryUsage, Result};
+
+/// A cached value with the tick of its last access
+struct LruEntry<V> {
+    tick: u64,
+    value: V,
+}
+
+impl<V: Storable> Storable for LruEntry<V> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let value = self.value.to_bytes();
+        let mut buf = Vec::with_capacity(size_of::<u64>() + value.len());
+        buf.extend_from_slice(&self.tick.to_le_bytes());
+        buf.extend_from_slice(&value);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            tick: u64::from_le_bytes(bytes[..8].try_into().expect("tick: expected 8 bytes")),
+            value: V::from_bytes(bytes[8..].to_vec().into()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Stable LRU cache state
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StableLruCacheState {
+    /// Tick of the next access
+    next_tick: u64,
+    /// Sum of the serialized sizes of the cached values
+    total_bytes: u64,
+}
+
+const STABLE_LRU_CACHE_STATE_SIZE: usize = 2 * size_of::<u64>();
+
+impl Storable for StableLruCacheState {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: STABLE_LRU_CACHE_STATE_SIZE as u32,
+        is_fixed_size: true,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::with_capacity(STABLE_LRU_CACHE_STATE_SIZE);
+        buf.extend_from_slice(&self.next_tick.to_le_bytes());
+        buf.extend_from_slice(&self.total_bytes.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            next_tick: u64::from_le_bytes(
+                bytes[..8].try_into().expect("next_tick: expected 8 bytes"),
+            ),
+            total_bytes: u64::from_le_bytes(
+                bytes[8..16]
+                    .try_into()
+                    .expect("total_bytes: expected 8 bytes"),
+            ),
+        }
+    }
+}
+
+/// LRU cache stored in stable memory, so it survives the upgrades.
+///
+/// The cache holds at most `max_entries` entries and, optionally, at most `max_bytes` bytes of
+/// serialized values. When a limit is exceeded, the least recently used entries are evicted.
+/// A value larger than `max_bytes` is not cached.
+///
+/// The entries are kept in a map sorted by key and their access order in a map sorted by access
+/// tick, so every operation is O(log n).
+pub struct StableLruCache<K, V, EntriesMemory, OrderMemory, StateMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    EntriesMemory: Memory,
+    OrderMemory: Memory,
+    StateMemory: Memory,
+{
+    entries: StableBTreeMap<K, LruEntry<V>, EntriesMemory>,
+    /// The keys by tick of their last access
+    order: StableBTreeMap<u64, K, OrderMemory>,
+    state: StableCell<StableLruCacheState, StateMemory>,
+    max_entries: NonZeroU64,
+    max_bytes: Option<u64>,
+}
+
+impl<K, V, EntriesMemory, OrderMemory, StateMemory>
+    StableLruCache<K, V, EntriesMemory, OrderMemory, StateMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    EntriesMemory: Memory,
+    OrderMemory: Memory,
+    StateMemory: Memory,
+{
+    /// Creates new cache holding at most `max_entries` entries, or restores it from the memories.
+    ///
+    /// If the memories hold more entries than `max_entries`, the extra ones are evicted.
+    pub fn new(
+        entries_memory: EntriesMemory,
+        order_memory: OrderMemory,
+        state_memory: StateMemory,
+        max_entries: NonZeroU64,
+    ) -> Result<Self> {
+        let mut cache = Self {
+            entries: StableBTreeMap::new(entries_memory),
+            order: StableBTreeMap::new(order_memory),
+            state: StableCell::new(state_memory, StableLruCacheState::default())?,
+            max_entries,
+            max_bytes: None,
+        };
+        cache.evict()?;
+        Ok(cache)
+    }
+
+    /// Limit the sum of the serialized sizes of the cached values, evicting the extra entries.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Result<Self> {
+        self.max_bytes = Some(max_bytes);
+        self.evict()?;
+        Ok(self)
+    }
+
+    /// Number of entries in the cache
+    pub fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    /// Returns whether is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sum of the serialized sizes of the cached values
+    pub fn total_bytes(&self) -> u64 {
+        self.state.get().total_bytes
+    }
+
+    /// True if the key is in the cache. Doesn't update the access order.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns the value of the key and marks it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        let Some(entry) = self.entries.get(key) else {
+            return Ok(None);
+        };
+
+        let tick = self.next_tick()?;
+        self.order.remove(&entry.tick);
+        self.order.insert(tick, key.clone());
+        // the value is not cloned: the previous entry is returned by the map with the same value
+        let previous = self.entries.insert(
+            key.clone(),
+            LruEntry {
+                tick,
+                value: entry.value,
+            },
+        );
+
+        Ok(previous.map(|entry| entry.value))
+    }
+
+    /// Returns the value of the key without updating the access order.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.entries.get(key).map(|entry| entry.value)
+    }
+
+    /// Inserts the value as the most recently used, evicting the least recently used entries if
+    /// the cache is full. Returns the previous value of the key.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>> {
+        let tick = self.next_tick()?;
+        let size = value.to_bytes().len() as u64;
+        let previous = self.entries.insert(key.clone(), LruEntry { tick, value });
+        self.order.insert(tick, key);
+
+        let mut state = self.state.get().clone();
+        state.total_bytes += size;
+        if let Some(previous) = &previous {
+            self.order.remove(&previous.tick);
+            state.total_bytes -= previous.value.to_bytes().len() as u64;
+        }
+        self.state.set(state)?;
+
+        self.evict()?;
+        Ok(previous.map(|entry| entry.value))
+    }
+
+    /// Returns the value of the key, computing and inserting it if it is not in the cache.
+    pub fn get_or_insert_with(&mut self, key: &K, f: impl FnOnce(&K) -> V) -> Result<V>
+    where
+        V: Clone,
+    {
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+
+        let value = f(key);
+        self.insert(key.clone(), value.clone())?;
+        Ok(value)
+    }
+
+    /// Removes the key from the cache and returns its value.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>> {
+        let Some(entry) = self.entries.remove(key) else {
+            return Ok(None);
+        };
+
+        self.order.remove(&entry.tick);
+        let mut state = self.state.get().clone();
+        state.total_bytes -= entry.value.to_bytes().len() as u64;
+        self.state.set(state)?;
+
+        Ok(Some(entry.value))
+    }
+
+    /// Removes all the entries.
+    pub fn clear(&mut self) -> Result<()> {
+        self.entries.clear();
+        self.order.clear();
+        self.state.set(StableLruCacheState::default())
+    }
+
+    /// Evicts the least recently used entries until the limits are respected.
+    fn evict(&mut self) -> Result<()> {
+        while self.len() > self.max_entries.get()
+            || self
+                .max_bytes
+                .is_some_and(|max_bytes| self.total_bytes() > max_bytes)
+        {
+            let Some((_, key)) = self.order.first_key_value() else {
+                break;
+            };
+            self.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    fn next_tick(&mut self) -> Result<u64> {
+        let mut state = self.state.get().clone();
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        self.state.set(state)?;
+        Ok(tick)
+    }
+}
+
+impl<K, V, EntriesMemory, OrderMemory, StateMemory> MemoryUsage
+    for StableLruCache<K, V, EntriesMemory, OrderMemory, StateMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    EntriesMemory: Memory,
+    OrderMemory: Memory,
+    StateMemory: Memory,
+{
+    fn memory_stats(&self) -> MemoryStats {
+        let entries = self.entries.memory_stats();
+        MemoryStats {
+            entries: entries.entries,
+            bytes: entries.bytes
+                + self.order.memory_stats().bytes
+                + self.state.memory_stats().bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    type TestCache<V> = StableLruCache<u32, V, VectorMemory, VectorMemory, VectorMemory>;
+
+    fn cache<V: Storable>(max_entries: u64) -> TestCache<V> {
+        StableLruCache::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+            max_entries.try_into().unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn should_evict_least_recently_used() {
+        let mut cache = cache::<u64>(3);
+
+        for key in 0..3 {
+            cache.insert(key, key as u64 * 10).unwrap();
+        }
+        // 0 becomes the most recently used
+        assert_eq!(cache.get(&0).unwrap(), Some(0));
+        // 1 is not touched by peek
+        assert_eq!(cache.peek(&1), Some(10));
+
+        cache.insert(3, 30).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&0));
+
+        // replacing a value refreshes the entry
+        assert_eq!(cache.insert(2, 21).unwrap(), Some(20));
+        cache.insert(4, 40).unwrap();
+        assert!(!cache.contains_key(&0));
+        assert_eq!(cache.peek(&2), Some(21));
+
+        assert_eq!(cache.get_or_insert_with(&5, |key| *key as u64).unwrap(), 5);
+        assert_eq!(cache.get_or_insert_with(&5, |_| unreachable!()).unwrap(), 5);
+
+        assert_eq!(cache.remove(&5).unwrap(), Some(5));
+        assert_eq!(cache.remove(&5).unwrap(), None);
+        cache.clear().unwrap();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_respect_byte_budget() {
+        let mut cache = cache::<StringValue>(100).with_max_bytes(100).unwrap();
+
+        for key in 0..4 {
+            cache.insert(key, str_val(30)).unwrap();
+        }
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.total_bytes(), 90);
+        assert!(!cache.contains_key(&0));
+
+        cache.insert(1, str_val(10)).unwrap();
+        assert_eq!(cache.total_bytes(), 70);
+
+        // too large to be cached
+        cache.insert(10, str_val(101)).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.total_bytes(), 0);
+    }
+
+    #[test]
+    fn should_restore_from_memory() {
+        let memories = [
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        ];
+        let [entries, order, state] = memories.clone();
+        let mut cache =
+            TestCache::<u64>::new(entries, order, state, 10.try_into().unwrap()).unwrap();
+        for key in 0..10 {
+            cache.insert(key, key as u64).unwrap();
+        }
+        cache.get(&0).unwrap();
+        drop(cache);
+
+        // the least recently used entries are evicted to respect the new capacity
+        let [entries, order, state] = memories;
+        let cache = TestCache::<u64>::new(entries, order, state, 5.try_into().unwrap()).unwrap();
+        let mut keys = cache.entries.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec![0, 6, 7, 8, 9]);
+    }
+}
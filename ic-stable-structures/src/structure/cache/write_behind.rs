@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::*;
+
+/// A write-behind buffer for StableBTreeMap.
+///
+/// The inserts and removals are buffered in heap memory and written to the inner map by
+/// [`Self::flush`], so repeated writes to the same key within a call are written once. The reads
+/// see the buffered writes.
+///
+/// The buffer must be flushed at the end of every update call that writes to the map, and before
+/// an upgrade, as the heap memory is not preserved.
+pub struct WriteBehindBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    inner: StableBTreeMap<K, V, M>,
+    /// Buffered writes: the new value, or `None` for a removal
+    pending: BTreeMap<K, Option<V>>,
+}
+
+impl<K, V, M> WriteBehindBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    /// Create new instance of the WriteBehindBTreeMap with an empty buffer.
+    pub fn new(memory: M) -> Self {
+        Self::with_map(StableBTreeMap::new(memory))
+    }
+
+    /// Create new instance of the WriteBehindBTreeMap with an empty buffer.
+    pub fn with_map(inner: StableBTreeMap<K, V, M>) -> Self {
+        Self {
+            inner,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the inner collection so that the caller can have a readonly access to it that
+    /// bypasses the buffered writes.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.inner
+    }
+
+    /// Number of keys with a buffered write
+    pub fn pending_writes(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Writes the buffered inserts and removals to the inner map, and returns their number.
+    pub fn flush(&mut self) -> usize {
+        let writes = self.pending.len();
+        for (key, value) in std::mem::take(&mut self.pending) {
+            match value {
+                Some(value) => {
+                    self.inner.insert(key, value);
+                }
+                None => {
+                    self.inner.remove(&key);
+                }
+            }
+        }
+        writes
+    }
+}
+
+impl<K, V, M> BTreeMapStructure<K, V> for WriteBehindBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        match self.pending.get(key) {
+            Some(value) => value.clone(),
+            None => self.inner.get(key),
+        }
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old_value = self.get(&key);
+        self.pending.insert(key, Some(value));
+        old_value
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let old_value = self.get(key);
+        self.pending.insert(key.clone(), None);
+        old_value
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        match self.pending.get(key) {
+            Some(value) => value.is_some(),
+            None => self.inner.contains_key(key),
+        }
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        let stored = self
+            .inner
+            .iter()
+            .find(|(key, _)| !self.pending.contains_key(key));
+        let buffered = self
+            .pending
+            .iter()
+            .find_map(|(key, value)| Some((key.clone(), value.clone()?)));
+
+        match (stored, buffered) {
+            (Some(stored), Some(buffered)) => {
+                Some(std::cmp::min_by(stored, buffered, |a, b| a.0.cmp(&b.0)))
+            }
+            (stored, buffered) => stored.or(buffered),
+        }
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        let mut stored = self.inner.last_key_value();
+        while let Some((key, _)) = &stored {
+            if !self.pending.contains_key(key) {
+                break;
+            }
+            stored = self.inner.iter_upper_bound(key).next();
+        }
+        let buffered = self
+            .pending
+            .iter()
+            .rev()
+            .find_map(|(key, value)| Some((key.clone(), value.clone()?)));
+
+        match (stored, buffered) {
+            (Some(stored), Some(buffered)) => {
+                Some(std::cmp::max_by(stored, buffered, |a, b| a.0.cmp(&b.0)))
+            }
+            (stored, buffered) => stored.or(buffered),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        let mut len = self.inner.len();
+        for (key, value) in &self.pending {
+            match (self.inner.contains_key(key), value.is_some()) {
+                (false, true) => len += 1,
+                (true, false) => len -= 1,
+                _ => {}
+            }
+        }
+        len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+        self.inner.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::Array;
+
+    #[test]
+    fn should_buffer_writes_until_flush() {
+        let mut map = WriteBehindBTreeMap::<u32, Array<2>, _>::new(VectorMemory::default());
+
+        assert_eq!(None, map.insert(1, Array([1u8, 1])));
+        assert_eq!(Some(Array([1u8, 1])), map.insert(1, Array([1u8, 2])));
+        assert_eq!(None, map.insert(2, Array([2u8, 1])));
+        assert_eq!(Some(Array([2u8, 1])), map.remove(&2));
+
+        assert_eq!(Some(Array([1u8, 2])), map.get(&1));
+        assert!(!map.contains_key(&2));
+        assert_eq!(1, map.len());
+        assert!(map.inner().is_empty());
+
+        assert_eq!(2, map.flush());
+        assert_eq!(0, map.pending_writes());
+        assert_eq!(Some(Array([1u8, 2])), map.inner().get(&1));
+        assert_eq!(1, map.inner().len());
+    }
+
+    #[test]
+    fn should_merge_buffer_with_stored_entries() {
+        let mut map = WriteBehindBTreeMap::<u32, Array<2>, _>::new(VectorMemory::default());
+        for key in 1..=4 {
+            map.insert(key, Array([key as u8, 0]));
+        }
+        map.flush();
+
+        map.remove(&1);
+        map.remove(&4);
+        map.insert(3, Array([3u8, 1]));
+        map.insert(5, Array([5u8, 0]));
+
+        assert_eq!(Some((2, Array([2u8, 0]))), map.first_key_value());
+        assert_eq!(Some((5, Array([5u8, 0]))), map.last_key_value());
+        assert_eq!(3, map.len());
+
+        map.remove(&5);
+        assert_eq!(Some((3, Array([3u8, 1]))), map.last_key_value());
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(0, map.pending_writes());
+    }
+}
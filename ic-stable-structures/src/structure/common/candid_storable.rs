@@ -0,0 +1,97 @@
+//! Values stored with their Candid encoding, to store the types of the canister interface
+//! without writing a `Storable` implementation:
+//!
+//! ```ignore
+//! let users = StableBTreeMap::<Principal, CandidStorable<User>, _>::new(memory);
+//! users.insert(caller, CandidStorable(user));
+//! ```
+//!
+//! The Candid encoding is larger and slower than [`SerdeStorable`](crate::SerdeStorable), but
+//! supports the evolution of the types as the Candid interfaces do: the stored values decode to a
+//! type with new optional fields, which are `None`, or without some of their fields.
+
+use std::borrow::Cow;
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+/// Stores `T` encoded with Candid. The stored values are unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CandidStorable<T>(pub T);
+
+impl<T> CandidStorable<T> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Storable for CandidStorable<T>
+where
+    T: CandidType + for<'de> Deserialize<'de>,
+{
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        candid::encode_one(&self.0)
+            .expect("failed to encode the value with candid")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(candid::decode_one(&bytes).expect("failed to decode the value with candid"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+    struct UserV1 {
+        name: String,
+        age: u32,
+    }
+
+    #[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+    struct UserV2 {
+        name: String,
+        email: Option<String>,
+    }
+
+    #[test]
+    fn should_store_candid_values() {
+        let user = UserV1 {
+            name: "alice".to_string(),
+            age: 30,
+        };
+        let mut map =
+            StableBTreeMap::<u64, CandidStorable<UserV1>, _>::new(VectorMemory::default());
+        map.insert(1, CandidStorable(user.clone()));
+
+        assert_eq!(map.get(&1).unwrap().into_inner(), user);
+    }
+
+    #[test]
+    fn should_decode_stored_values_to_new_version() {
+        let stored = CandidStorable(UserV1 {
+            name: "alice".to_string(),
+            age: 30,
+        })
+        .to_bytes()
+        .into_owned();
+
+        let user = CandidStorable::<UserV2>::from_bytes(stored.into()).into_inner();
+        assert_eq!(
+            user,
+            UserV2 {
+                name: "alice".to_string(),
+                email: None,
+            }
+        );
+    }
+}
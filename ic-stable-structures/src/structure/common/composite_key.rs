@@ -0,0 +1,348 @@
+//! Composite keys with an order-preserving encoding.
+//!
+//! [`Key2`] and [`Key3`] are sorted by their first part, then by the following ones, and their
+//! serialized form is sorted the same way byte by byte: integers are big-endian with the sign bit
+//! flipped, principals are prefixed by their length, and strings and byte vectors are escaped and
+//! terminated. The encoding of the first parts of a key is then a byte prefix of the encoding of
+//! the whole key, so the keys are usable both in the structures comparing the decoded keys and in
+//! the ones comparing their bytes:
+//!
+//! ```ignore
+//! let mut balances = StableBTreeMap::<Key2<Principal, u64>, Nat, _>::new(memory);
+//! balances.insert(Key2(owner, subaccount), amount);
+//!
+//! let owner_balances = balances.range(Key2::prefix_range(owner));
+//! ```
+
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+
+use candid::Principal;
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+use crate::Bounded;
+
+/// A part of a composite key, encoded so that the byte order of the encodings is the order of
+/// the values.
+pub trait KeyPart: Ord + Clone {
+    /// Largest size of the encoding, in bytes, or `None` if unbounded
+    const MAX_ENCODED_SIZE: Option<u32>;
+
+    /// Appends the encoding of the value to the buffer.
+    fn encode_to(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a value from the start of the bytes, and returns it with the remaining bytes.
+    fn decode_from(bytes: &[u8]) -> (Self, &[u8]);
+}
+
+macro_rules! impl_key_part_for_unsigned {
+    ($($ty:ty),+) => {
+        $(
+            impl KeyPart for $ty {
+                const MAX_ENCODED_SIZE: Option<u32> = Some(std::mem::size_of::<$ty>() as u32);
+
+                fn encode_to(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn decode_from(bytes: &[u8]) -> (Self, &[u8]) {
+                    let (value, rest) = bytes.split_at(std::mem::size_of::<$ty>());
+                    (<$ty>::from_be_bytes(value.try_into().expect("fixed size")), rest)
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! impl_key_part_for_signed {
+    ($($ty:ty => $unsigned:ty),+) => {
+        $(
+            impl KeyPart for $ty {
+                const MAX_ENCODED_SIZE: Option<u32> = Some(std::mem::size_of::<$ty>() as u32);
+
+                fn encode_to(&self, buf: &mut Vec<u8>) {
+                    // Flipping the sign bit sorts the negative values before the positive ones
+                    let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                    flipped.encode_to(buf);
+                }
+
+                fn decode_from(bytes: &[u8]) -> (Self, &[u8]) {
+                    let (flipped, rest) = <$unsigned>::decode_from(bytes);
+                    ((flipped ^ (1 << (<$unsigned>::BITS - 1))) as $ty, rest)
+                }
+            }
+        )+
+    };
+}
+
+impl_key_part_for_unsigned!(u8, u16, u32, u64, u128);
+impl_key_part_for_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl KeyPart for bool {
+    const MAX_ENCODED_SIZE: Option<u32> = Some(1);
+
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode_from(bytes: &[u8]) -> (Self, &[u8]) {
+        (bytes[0] != 0, &bytes[1..])
+    }
+}
+
+/// Principals are sorted by length first, so the encoding is the length followed by the bytes.
+impl KeyPart for Principal {
+    const MAX_ENCODED_SIZE: Option<u32> = Some(1 + 29);
+
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        let bytes = self.as_slice();
+        buf.push(bytes.len() as u8);
+        buf.extend_from_slice(bytes);
+    }
+
+    fn decode_from(bytes: &[u8]) -> (Self, &[u8]) {
+        let len = bytes[0] as usize;
+        (Principal::from_slice(&bytes[1..=len]), &bytes[1 + len..])
+    }
+}
+
+/// Escape of a zero byte inside the bytes, sorted after the terminator
+const ESCAPED_ZERO: [u8; 2] = [0x00, 0xFF];
+/// End of the bytes, sorted before any byte of a longer value
+const TERMINATOR: [u8; 2] = [0x00, 0x01];
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    for byte in bytes {
+        match byte {
+            0 => buf.extend_from_slice(&ESCAPED_ZERO),
+            byte => buf.push(*byte),
+        }
+    }
+    buf.extend_from_slice(&TERMINATOR);
+}
+
+fn decode_bytes(bytes: &[u8]) -> (Vec<u8>, &[u8]) {
+    let mut value = Vec::new();
+    let mut index = 0;
+    loop {
+        match bytes[index] {
+            0 if bytes[index + 1] == TERMINATOR[1] => return (value, &bytes[index + 2..]),
+            0 => {
+                value.push(0);
+                index += 2;
+            }
+            byte => {
+                value.push(byte);
+                index += 1;
+            }
+        }
+    }
+}
+
+impl KeyPart for Vec<u8> {
+    const MAX_ENCODED_SIZE: Option<u32> = None;
+
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self, buf);
+    }
+
+    fn decode_from(bytes: &[u8]) -> (Self, &[u8]) {
+        decode_bytes(bytes)
+    }
+}
+
+impl KeyPart for String {
+    const MAX_ENCODED_SIZE: Option<u32> = None;
+
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), buf);
+    }
+
+    fn decode_from(bytes: &[u8]) -> (Self, &[u8]) {
+        let (value, rest) = decode_bytes(bytes);
+        (
+            String::from_utf8(value).expect("key part: expected an utf-8 string"),
+            rest,
+        )
+    }
+}
+
+const fn sum_sizes(sizes: &[Option<u32>]) -> Bound {
+    let mut max_size = 0;
+    let mut index = 0;
+    while index < sizes.len() {
+        match sizes[index] {
+            Some(size) => max_size += size,
+            None => return Bound::Unbounded,
+        }
+        index += 1;
+    }
+    Bound::Bounded {
+        max_size,
+        is_fixed_size: false,
+    }
+}
+
+/// Key made of two parts, sorted by the first part, then by the second one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Key2<A, B>(pub A, pub B);
+
+impl<A: KeyPart, B: KeyPart> Key2<A, B> {
+    /// Encoding of the first part, which is a byte prefix of the encoding of the keys starting
+    /// with it.
+    pub fn encode_prefix(first: &A) -> Vec<u8> {
+        let mut buf = Vec::new();
+        first.encode_to(&mut buf);
+        buf
+    }
+}
+
+impl<A: KeyPart, B: KeyPart + Bounded> Key2<A, B> {
+    /// Range of the keys starting with the first part.
+    pub fn prefix_range(first: A) -> RangeInclusive<Self> {
+        Self(first.clone(), B::MIN)..=Self(first, B::MAX)
+    }
+}
+
+impl<A: KeyPart, B: KeyPart> Storable for Key2<A, B> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::new();
+        self.0.encode_to(&mut buf);
+        self.1.encode_to(&mut buf);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (first, rest) = A::decode_from(&bytes);
+        let (second, _) = B::decode_from(rest);
+        Self(first, second)
+    }
+
+    const BOUND: Bound = sum_sizes(&[A::MAX_ENCODED_SIZE, B::MAX_ENCODED_SIZE]);
+}
+
+/// Key made of three parts, sorted by the first part, then by the second and third ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Key3<A, B, C>(pub A, pub B, pub C);
+
+impl<A: KeyPart, B: KeyPart, C: KeyPart> Key3<A, B, C> {
+    /// Encoding of the first part, which is a byte prefix of the encoding of the keys starting
+    /// with it.
+    pub fn encode_prefix(first: &A) -> Vec<u8> {
+        Key2::<A, B>::encode_prefix(first)
+    }
+
+    /// Encoding of the first two parts, which is a byte prefix of the encoding of the keys
+    /// starting with them.
+    pub fn encode_prefix2(first: &A, second: &B) -> Vec<u8> {
+        let mut buf = Vec::new();
+        first.encode_to(&mut buf);
+        second.encode_to(&mut buf);
+        buf
+    }
+}
+
+impl<A: KeyPart, B: KeyPart + Bounded, C: KeyPart + Bounded> Key3<A, B, C> {
+    /// Range of the keys starting with the first part.
+    pub fn prefix_range(first: A) -> RangeInclusive<Self> {
+        Self(first.clone(), B::MIN, C::MIN)..=Self(first, B::MAX, C::MAX)
+    }
+}
+
+impl<A: KeyPart, B: KeyPart, C: KeyPart + Bounded> Key3<A, B, C> {
+    /// Range of the keys starting with the first two parts.
+    pub fn prefix_range2(first: A, second: B) -> RangeInclusive<Self> {
+        Self(first.clone(), second.clone(), C::MIN)..=Self(first, second, C::MAX)
+    }
+}
+
+impl<A: KeyPart, B: KeyPart, C: KeyPart> Storable for Key3<A, B, C> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::new();
+        self.0.encode_to(&mut buf);
+        self.1.encode_to(&mut buf);
+        self.2.encode_to(&mut buf);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (first, rest) = A::decode_from(&bytes);
+        let (second, rest) = B::decode_from(rest);
+        let (third, _) = C::decode_from(rest);
+        Self(first, second, third)
+    }
+
+    const BOUND: Bound = sum_sizes(&[
+        A::MAX_ENCODED_SIZE,
+        B::MAX_ENCODED_SIZE,
+        C::MAX_ENCODED_SIZE,
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    fn assert_order_preserved<T: Storable + Ord + Clone + std::fmt::Debug>(mut keys: Vec<T>) {
+        let mut by_bytes = keys.clone();
+        keys.sort();
+        by_bytes.sort_by(|a, b| a.to_bytes().cmp(&b.to_bytes()));
+        assert_eq!(keys, by_bytes);
+
+        for key in keys {
+            assert_eq!(T::from_bytes(key.to_bytes()), key);
+        }
+    }
+
+    #[test]
+    fn should_preserve_order_of_ints() {
+        assert_order_preserved(vec![
+            Key2(1_u64, -5_i32),
+            Key2(0, i32::MAX),
+            Key2(1, 3),
+            Key2(256, 0),
+            Key2(1, i32::MIN),
+            Key2(u64::MAX, -1),
+        ]);
+    }
+
+    #[test]
+    fn should_preserve_order_of_strings_and_principals() {
+        assert_order_preserved(vec![
+            Key3("b".to_string(), Principal::anonymous(), 0_u8),
+            Key3("a\0".to_string(), Principal::management_canister(), 255),
+            Key3("a".to_string(), Principal::anonymous(), 255),
+            Key3("a".to_string(), Principal::management_canister(), 0),
+            Key3("ab".to_string(), Principal::from_slice(&[1; 29]), 1),
+            Key3(String::new(), Principal::from_slice(&[0xFF]), 7),
+        ]);
+    }
+
+    #[test]
+    fn should_scan_prefixes() {
+        let mut map = StableBTreeMap::<Key2<Principal, u64>, u64, _>::new(VectorMemory::default());
+        let alice = Principal::from_slice(&[1; 29]);
+        let bob = Principal::from_slice(&[2; 29]);
+        map.insert(Key2(alice, 2), 20);
+        map.insert(Key2(bob, 1), 10);
+        map.insert(Key2(alice, 1), 10);
+
+        let alice_entries: Vec<_> = map.range(Key2::prefix_range(alice)).collect();
+        assert_eq!(
+            alice_entries,
+            vec![(Key2(alice, 1), 10), (Key2(alice, 2), 20)]
+        );
+
+        let key = Key2(alice, 1_u64).to_bytes().into_owned();
+        assert!(key.starts_with(&Key2::<Principal, u64>::encode_prefix(&alice)));
+        assert!(matches!(
+            Key2::<Principal, u64>::BOUND,
+            Bound::Bounded { max_size: 38, .. }
+        ));
+        assert!(matches!(Key2::<String, u64>::BOUND, Bound::Unbounded));
+    }
+}
@@ -0,0 +1,118 @@
+//! Values compressed before they are stored, to reduce the stable memory used by large and
+//! repetitive values such as JSON documents.
+//!
+//! The values are compressed with DEFLATE, whose pure Rust implementation builds for wasm. Values
+//! smaller than the threshold, or which don't shrink, are stored uncompressed, so wrapping a type
+//! costs a single byte for its small values:
+//!
+//! ```ignore
+//! let documents = StableBTreeMap::<u64, Compressed<Document, 512>, _>::new(memory);
+//! documents.insert(id, Compressed(document));
+//! ```
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+/// Default size, in bytes, from which the values are compressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
+
+const UNCOMPRESSED: u8 = 0;
+const DEFLATE: u8 = 1;
+
+/// Stores `T` compressed if its serialized form is at least `THRESHOLD` bytes long.
+///
+/// The stored values are unbounded, whatever the bound of `T`, since the compression of a value
+/// is not guaranteed to shrink it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Compressed<T, const THRESHOLD: usize = DEFAULT_COMPRESSION_THRESHOLD>(pub T);
+
+impl<T, const THRESHOLD: usize> Compressed<T, THRESHOLD> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Storable, const THRESHOLD: usize> Storable for Compressed<T, THRESHOLD> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = self.0.to_bytes();
+        if bytes.len() >= THRESHOLD {
+            let mut encoder = DeflateEncoder::new(vec![DEFLATE], Compression::fast());
+            encoder
+                .write_all(&bytes)
+                .expect("writing to a vector never fails");
+            let compressed = encoder.finish().expect("writing to a vector never fails");
+            if compressed.len() < bytes.len() + 1 {
+                return compressed.into();
+            }
+        }
+
+        let mut buf = Vec::with_capacity(bytes.len() + 1);
+        buf.push(UNCOMPRESSED);
+        buf.extend_from_slice(&bytes);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match bytes[0] {
+            UNCOMPRESSED => Self(T::from_bytes(bytes[1..].to_vec().into())),
+            DEFLATE => {
+                let mut decompressed = Vec::new();
+                DeflateDecoder::new(&bytes[1..])
+                    .read_to_end(&mut decompressed)
+                    .expect("compressed value is corrupted");
+                Self(T::from_bytes(decompressed.into()))
+            }
+            encoding => panic!("unknown encoding of a compressed value: {encoding}"),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::{BTreeMapStructure, LogStructure, StableBTreeMap, StableLog};
+
+    #[test]
+    fn should_compress_large_values_only() {
+        let small = Compressed::<_, 64>(str_val(16));
+        assert_eq!(small.to_bytes()[0], UNCOMPRESSED);
+        assert_eq!(
+            Compressed::<StringValue, 64>::from_bytes(small.to_bytes()),
+            small
+        );
+
+        let large = Compressed::<_, 64>(str_val(1024));
+        let bytes = large.to_bytes();
+        assert_eq!(bytes[0], DEFLATE);
+        assert!(bytes.len() < 100);
+        assert_eq!(Compressed::<StringValue, 64>::from_bytes(bytes), large);
+    }
+
+    #[test]
+    fn should_store_compressed_values() {
+        let mut map =
+            StableBTreeMap::<u64, Compressed<StringValue>, _>::new(VectorMemory::default());
+        map.insert(1, Compressed(str_val(4096)));
+        assert_eq!(map.get(&1).unwrap().into_inner(), str_val(4096));
+
+        let mut log = StableLog::<Compressed<StringValue>, _>::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap();
+        log.append(Compressed(str_val(4096))).unwrap();
+        assert_eq!(log.get(0).unwrap().into_inner(), str_val(4096));
+    }
+}
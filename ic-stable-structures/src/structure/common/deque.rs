@@ -0,0 +1,342 @@
+use std::mem::size_of;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{CellStructure, StableCell, StableVec, VecStructure};
+use crate::{MemoryStats, MemoryUsage, Result};
+
+/// Deque indices state
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StableDequeIndices {
+    /// Index of the first element in the data vector
+    start: u64,
+    /// Number of elements in the deque
+    len: u64,
+}
+
+impl StableDequeIndices {
+    /// Index in a data vector of the given capacity of the element placed with the `n` offset from start.
+    fn nth_element(&self, n: u64, capacity: u64) -> Option<u64> {
+        (n < self.len).then(|| (self.start + n) % capacity)
+    }
+
+    /// Returns the number of elements in the deque
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+const STABLE_DEQUE_INDICES_SIZE: usize = 2 * size_of::<u64>();
+
+impl Storable for StableDequeIndices {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: STABLE_DEQUE_INDICES_SIZE as u32,
+        is_fixed_size: true,
+    };
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut buf = Vec::with_capacity(STABLE_DEQUE_INDICES_SIZE);
+        buf.extend_from_slice(&self.start.to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self {
+            start: u64::from_le_bytes(bytes[..8].try_into().expect("start: expected 8 bytes")),
+            len: u64::from_le_bytes(bytes[8..16].try_into().expect("len: expected 8 bytes")),
+        }
+    }
+}
+
+/// Stable double-ended queue implementation.
+///
+/// The elements are stored in a growable ring buffer, so pushing and popping at both ends is O(1)
+/// (amortized for the pushes, the data vector doubles its size when it's full).
+/// The memory of the popped elements is reused by the next pushes, it is released only by [`StableDeque::clear`].
+pub struct StableDeque<T: Storable + Clone, DataMemory: Memory, IndicesMemory: Memory> {
+    /// Vector with elements, its length is the capacity of the deque
+    data: StableVec<T, DataMemory>,
+    /// Indices that specify where are the first and last elements in the data vector
+    indices: StableCell<StableDequeIndices, IndicesMemory>,
+}
+
+impl<T: Storable + Clone, DataMemory: Memory, IndicesMemory: Memory>
+    StableDeque<T, DataMemory, IndicesMemory>
+{
+    /// Creates new deque
+    pub fn new(data_memory: DataMemory, indices_memory: IndicesMemory) -> Result<Self> {
+        Ok(Self {
+            data: StableVec::new(data_memory)?,
+            indices: StableCell::new(indices_memory, StableDequeIndices::default())?,
+        })
+    }
+
+    /// Number of elements in the deque
+    pub fn len(&self) -> u64 {
+        self.indices.get().len
+    }
+
+    /// Returns whether is empty
+    pub fn is_empty(&self) -> bool {
+        self.indices.get().len == 0
+    }
+
+    /// Number of elements the deque can hold without growing
+    pub fn capacity(&self) -> u64 {
+        self.data.len()
+    }
+
+    /// Removes all elements in the deque and releases the data vector
+    pub fn clear(&mut self) {
+        self.with_indices_data_mut(|indices, data| {
+            *indices = StableDequeIndices::default();
+            data.clear().expect("failed to clear the vector");
+        });
+    }
+
+    /// Appends an element to the back of the deque.
+    pub fn push_back(&mut self, val: &T) {
+        self.with_indices_data_mut(|indices, data| {
+            if indices.len == data.len() {
+                grow(indices, data);
+            }
+
+            let capacity = data.len();
+            if capacity == indices.len {
+                // the deque is empty and has no capacity
+                data.push(val).expect("failed to add new element");
+            } else {
+                let index = (indices.start + indices.len) % capacity;
+                data.set(index, val)
+                    .expect("new index should be inside data vector");
+            }
+            indices.len += 1;
+        })
+    }
+
+    /// Prepends an element to the front of the deque.
+    pub fn push_front(&mut self, val: &T) {
+        self.with_indices_data_mut(|indices, data| {
+            if indices.len == data.len() {
+                grow(indices, data);
+            }
+
+            let capacity = data.len();
+            if capacity == indices.len {
+                // the deque is empty and has no capacity
+                data.push(val).expect("failed to add new element");
+            } else {
+                indices.start = (indices.start + capacity - 1) % capacity;
+                data.set(indices.start, val)
+                    .expect("new index should be inside data vector");
+            }
+            indices.len += 1;
+        })
+    }
+
+    /// Removes the last element and returns it, or None if the deque is empty.
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.with_indices_data_mut(|indices, data| {
+            let index = indices.nth_element(indices.len.checked_sub(1)?, data.len())?;
+            indices.len -= 1;
+            data.get(index)
+        })
+    }
+
+    /// Removes the first element and returns it, or None if the deque is empty.
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.with_indices_data_mut(|indices, data| {
+            let index = indices.nth_element(0, data.len())?;
+            indices.len -= 1;
+            indices.start = match indices.len {
+                0 => 0,
+                _ => (index + 1) % data.len(),
+            };
+            data.get(index)
+        })
+    }
+
+    /// Get the first element if it exists.
+    pub fn front(&self) -> Option<T> {
+        self.get(0)
+    }
+
+    /// Get the last element if it exists.
+    pub fn back(&self) -> Option<T> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    /// Get the `n`-th element from the front.
+    pub fn get(&self, n: u64) -> Option<T> {
+        let index = self.indices.get().nth_element(n, self.data.len())?;
+        self.data.get(index)
+    }
+
+    /// Returns an iterator over the elements, from the front to the back.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = T> + ExactSizeIterator + '_ {
+        let indices = self.indices.get().clone();
+        let capacity = self.data.len();
+        let len = usize::try_from(indices.len).expect("deque length exceeds the address space");
+        (0..len).map(move |n| {
+            // This should never panic, because `n < indices.len`.
+            let index = indices
+                .nth_element(n as u64, capacity)
+                .expect("element should be present");
+            self.data.get(index).expect("element should be present")
+        })
+    }
+
+    #[inline]
+    fn with_indices_data_mut<R>(
+        &mut self,
+        f: impl FnOnce(&mut StableDequeIndices, &mut StableVec<T, DataMemory>) -> R,
+    ) -> R {
+        let mut indices = self.indices.get().clone();
+        let result = f(&mut indices, &mut self.data);
+        self.indices
+            .set(indices)
+            .expect("failed to update the indices");
+        result
+    }
+}
+
+/// Doubles the capacity of a full, non-empty data vector, so the elements are not wrapped anymore.
+fn grow<T: Storable + Clone, M: Memory>(indices: &StableDequeIndices, data: &mut StableVec<T, M>) {
+    let capacity = data.len();
+    if capacity == 0 {
+        return;
+    }
+
+    // Move the wrapped elements right after the old end of the vector...
+    for index in 0..indices.start {
+        let element = data.get(index).expect("element should be present");
+        data.push(&element).expect("failed to add new element");
+    }
+    // ...and fill the rest of the new capacity. The filler values are never read.
+    let filler = data.get(indices.start).expect("element should be present");
+    while data.len() < capacity * 2 {
+        data.push(&filler).expect("failed to add new element");
+    }
+}
+
+impl<T: Storable + Clone, DataMemory: Memory, IndicesMemory: Memory> MemoryUsage
+    for StableDeque<T, DataMemory, IndicesMemory>
+{
+    /// The bytes include the free slots of the buffer, which are reused by the next pushes.
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.len(),
+            bytes: self.data.memory_stats().bytes + self.indices.memory_stats().bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::VecDeque;
+
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn check_deque(deque: &StableDeque<u64, VectorMemory, VectorMemory>, expected: &VecDeque<u64>) {
+        assert_eq!(deque.len(), expected.len() as u64);
+        assert_eq!(deque.front(), expected.front().copied());
+        assert_eq!(deque.back(), expected.back().copied());
+        assert_eq!(
+            deque.iter().collect::<Vec<_>>(),
+            expected.iter().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            deque.iter().rev().collect::<Vec<_>>(),
+            expected.iter().rev().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(deque.get(expected.len() as u64), None);
+    }
+
+    #[test]
+    fn indices_should_be_storable() {
+        let indices = StableDequeIndices { start: 3, len: 42 };
+        assert_eq!(StableDequeIndices::from_bytes(indices.to_bytes()), indices);
+    }
+
+    #[test]
+    fn should_push_and_pop_at_both_ends() {
+        let mut deque = StableDeque::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        assert!(deque.is_empty());
+        assert_eq!(deque.pop_back(), None);
+        assert_eq!(deque.pop_front(), None);
+
+        deque.push_back(&2);
+        deque.push_front(&1);
+        deque.push_back(&3);
+        deque.push_front(&0);
+        check_deque(&deque, &VecDeque::from([0, 1, 2, 3]));
+        assert_eq!(deque.capacity(), 4);
+
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_back(), Some(3));
+        check_deque(&deque, &VecDeque::from([1, 2]));
+
+        // the capacity of the popped elements is reused
+        deque.push_front(&0);
+        deque.push_back(&3);
+        assert_eq!(deque.capacity(), 4);
+        check_deque(&deque, &VecDeque::from([0, 1, 2, 3]));
+
+        deque.clear();
+        assert_eq!(deque.capacity(), 0);
+        check_deque(&deque, &VecDeque::new());
+    }
+
+    #[test]
+    fn should_behave_like_vec_deque() {
+        let mut deque = StableDeque::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        let mut expected = VecDeque::new();
+
+        for i in 0..500u64 {
+            match i % 7 {
+                0 | 3 => {
+                    deque.push_front(&i);
+                    expected.push_front(i);
+                }
+                1 | 4 | 5 => {
+                    deque.push_back(&i);
+                    expected.push_back(i);
+                }
+                2 => assert_eq!(deque.pop_front(), expected.pop_front()),
+                _ => assert_eq!(deque.pop_back(), expected.pop_back()),
+            }
+            check_deque(&deque, &expected);
+        }
+
+        while !expected.is_empty() {
+            assert_eq!(deque.pop_front(), expected.pop_front());
+            assert_eq!(deque.pop_back(), expected.pop_back());
+        }
+        check_deque(&deque, &expected);
+    }
+
+    #[test]
+    fn should_restore_from_memory() {
+        let data_memory = VectorMemory::default();
+        let indices_memory = VectorMemory::default();
+
+        let mut deque = StableDeque::new(data_memory.clone(), indices_memory.clone()).unwrap();
+        for i in 0..10u64 {
+            deque.push_front(&i);
+        }
+        drop(deque);
+
+        let deque = StableDeque::<u64, _, _>::new(data_memory, indices_memory).unwrap();
+        check_deque(&deque, &(0..10).rev().collect());
+    }
+}
@@ -0,0 +1,179 @@
+//! Values encrypted before they are stored, as a defense in depth for sensitive data.
+//!
+//! The values are encrypted with XChaCha20-Poly1305 using a key supplied by the application
+//! through the [`EncryptionKey`] trait, e.g. a key derived with vetKD and kept on the heap after
+//! `post_upgrade`:
+//!
+//! ```ignore
+//! struct PiiKey;
+//!
+//! impl EncryptionKey for PiiKey {
+//!     fn key() -> [u8; ENCRYPTION_KEY_SIZE] {
+//!         PII_KEY.with(|key| key.get().expect("the key is not initialized"))
+//!     }
+//! }
+//!
+//! let profiles = StableBTreeMap::<Principal, Encrypted<Profile, PiiKey>, _>::new(memory);
+//! ```
+//!
+//! The canisters have no synchronous source of randomness, so the nonce of a value is derived
+//! from the key and the value: the encryption is deterministic, and two equal values have the
+//! same ciphertext. The ciphertexts are authenticated, and reading a tampered value, or a value
+//! encrypted with another key, panics.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+use sha2::{Digest, Sha256};
+
+/// Size of the encryption keys, in bytes
+pub const ENCRYPTION_KEY_SIZE: usize = 32;
+
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+
+/// Provides the key used to encrypt and decrypt the values of [`Encrypted`].
+pub trait EncryptionKey {
+    fn key() -> [u8; ENCRYPTION_KEY_SIZE];
+}
+
+/// Stores `T` encrypted with the key provided by `K`.
+pub struct Encrypted<T, K> {
+    pub value: T,
+    _key: PhantomData<K>,
+}
+
+impl<T, K> Encrypted<T, K> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            _key: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Clone, K> Clone for Encrypted<T, K> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+impl<T: std::fmt::Debug, K> std::fmt::Debug for Encrypted<T, K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Encrypted").field(&self.value).finish()
+    }
+}
+
+impl<T: PartialEq, K> PartialEq for Encrypted<T, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq, K> Eq for Encrypted<T, K> {}
+
+impl<T: Storable, K: EncryptionKey> Storable for Encrypted<T, K> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let key = K::key();
+        let plaintext = self.value.to_bytes();
+
+        let digest = Sha256::new()
+            .chain_update(key)
+            .chain_update(&plaintext)
+            .finalize();
+        let nonce = XNonce::from_slice(&digest[..NONCE_SIZE]);
+
+        let ciphertext = XChaCha20Poly1305::new(&key.into())
+            .encrypt(nonce, plaintext.as_ref())
+            .expect("encryption of a value never fails");
+
+        let mut buf = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        buf.extend_from_slice(nonce);
+        buf.extend_from_slice(&ciphertext);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+        let plaintext = XChaCha20Poly1305::new(&K::key().into())
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .expect("failed to decrypt the value: wrong key or corrupted data");
+        Self::new(T::from_bytes(plaintext.into()))
+    }
+
+    const BOUND: Bound = match T::BOUND {
+        Bound::Bounded {
+            max_size,
+            is_fixed_size,
+        } => Bound::Bounded {
+            max_size: max_size + (NONCE_SIZE + TAG_SIZE) as u32,
+            is_fixed_size,
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    struct TestKey;
+
+    impl EncryptionKey for TestKey {
+        fn key() -> [u8; ENCRYPTION_KEY_SIZE] {
+            [7; ENCRYPTION_KEY_SIZE]
+        }
+    }
+
+    struct OtherKey;
+
+    impl EncryptionKey for OtherKey {
+        fn key() -> [u8; ENCRYPTION_KEY_SIZE] {
+            [8; ENCRYPTION_KEY_SIZE]
+        }
+    }
+
+    #[test]
+    fn should_store_encrypted_values() {
+        let mut map =
+            StableBTreeMap::<u64, Encrypted<StringValue, TestKey>, _>::new(VectorMemory::default());
+        map.insert(1, Encrypted::new(str_val(64)));
+        assert_eq!(map.get(&1).unwrap().into_inner(), str_val(64));
+
+        let bytes = Encrypted::<_, TestKey>::new(str_val(64))
+            .to_bytes()
+            .into_owned();
+        assert_eq!(bytes.len(), 64 + NONCE_SIZE + TAG_SIZE);
+        assert!(!bytes.windows(8).any(|window| window == b"QQQQQQQQ"));
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to decrypt")]
+    fn should_not_decrypt_with_another_key() {
+        let encrypted = Encrypted::<_, TestKey>::new(str_val(64));
+        Encrypted::<StringValue, OtherKey>::from_bytes(encrypted.to_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to decrypt")]
+    fn should_detect_tampering() {
+        let mut bytes = Encrypted::<_, TestKey>::new(str_val(64))
+            .to_bytes()
+            .into_owned();
+        bytes[NONCE_SIZE] ^= 1;
+        Encrypted::<StringValue, TestKey>::from_bytes(bytes.into());
+    }
+}
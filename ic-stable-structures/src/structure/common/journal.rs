@@ -0,0 +1,601 @@
+//! Write-ahead journal applying a batch of writes to several stable structures as a whole.
+//!
+//! A trap rolls back all the changes of the message, so the writes of a single message are always
+//! atomic. The writes of an update spanning several messages are not: when a canister updates a
+//! balance map before an inter-canister call and appends to its history log in the callback, a
+//! trap in the callback leaves the map updated and the log not. With the journal the writes are
+//! persisted in one step and applied afterwards, and the journal is replayed by the next call or
+//! by `post_upgrade` until it's applied completely:
+//!
+//! ```ignore
+//! let mut tx = Transaction::default();
+//! tx.insert(BALANCES, &account, &balance);
+//! tx.append(HISTORY, &operation);
+//!
+//! // Persist the writes before the await...
+//! journal.write(tx, &JournalTargets::new().with(BALANCES, &mut balances).with(HISTORY, &mut history))?;
+//! let reply = call_other_canister().await;
+//! // ...and apply them in the callback, or in the next call if the callback traps.
+//! journal.recover(&mut JournalTargets::new().with(BALANCES, &mut balances).with(HISTORY, &mut history))?;
+//! ```
+//!
+//! The journaled writes are idempotent: a replay of a partially applied journal gives the same
+//! state as a complete application.
+
+use std::borrow::Cow;
+use std::mem::size_of;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{
+    BTreeMapStructure, CellStructure, LogStructure, StableBTreeMap, StableCell, StableLog,
+    StableVec, VecStructure,
+};
+use crate::{Error, Result};
+
+/// Identifier of a structure in the journal, e.g. a constant per structure of the canister.
+/// It must not change while a journal is pending.
+pub type JournalTargetId = u8;
+
+/// A write to a structure, with the keys and values serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalOp {
+    /// Insert the value with the key into a map
+    Insert { key: Vec<u8>, value: Vec<u8> },
+    /// Remove the key from a map
+    Remove { key: Vec<u8> },
+    /// Append the value to a log or a vector at the given position
+    Append { index: u64, value: Vec<u8> },
+    /// Set the value of a cell
+    Set { value: Vec<u8> },
+}
+
+/// A write of the journal with the structure it targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub target: JournalTargetId,
+    pub op: JournalOp,
+}
+
+/// Structure which can be written through the journal.
+pub trait JournalTarget {
+    /// Number of values appended to the structure, `0` if it doesn't support appends.
+    /// The replays use it to skip the appends which are already applied.
+    fn appended_len(&self) -> u64 {
+        0
+    }
+
+    /// True if the structure can apply the operation.
+    fn supports(&self, op: &JournalOp) -> bool;
+
+    /// Applies the operation. Applying the same operation twice must have the same effect as
+    /// applying it once.
+    fn apply(&mut self, op: &JournalOp) -> Result<()>;
+}
+
+/// Batch of writes to apply atomically.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    entries: Vec<JournalEntry>,
+}
+
+impl Transaction {
+    /// Inserts the value with the key into a map.
+    pub fn insert<K: Storable, V: Storable>(
+        &mut self,
+        target: JournalTargetId,
+        key: &K,
+        value: &V,
+    ) -> &mut Self {
+        self.push(
+            target,
+            JournalOp::Insert {
+                key: key.to_bytes().into_owned(),
+                value: value.to_bytes().into_owned(),
+            },
+        )
+    }
+
+    /// Removes the key from a map.
+    pub fn remove<K: Storable>(&mut self, target: JournalTargetId, key: &K) -> &mut Self {
+        self.push(
+            target,
+            JournalOp::Remove {
+                key: key.to_bytes().into_owned(),
+            },
+        )
+    }
+
+    /// Appends the value to a log or a vector.
+    pub fn append<T: Storable>(&mut self, target: JournalTargetId, value: &T) -> &mut Self {
+        self.push(
+            target,
+            JournalOp::Append {
+                // Assigned when the transaction is written to the journal.
+                index: 0,
+                value: value.to_bytes().into_owned(),
+            },
+        )
+    }
+
+    /// Sets the value of a cell.
+    pub fn set<T: Storable>(&mut self, target: JournalTargetId, value: &T) -> &mut Self {
+        self.push(
+            target,
+            JournalOp::Set {
+                value: value.to_bytes().into_owned(),
+            },
+        )
+    }
+
+    /// Writes of the transaction in order.
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn push(&mut self, target: JournalTargetId, op: JournalOp) -> &mut Self {
+        self.entries.push(JournalEntry { target, op });
+        self
+    }
+}
+
+/// The structures written by the transactions, by their identifiers.
+#[derive(Default)]
+pub struct JournalTargets<'a> {
+    targets: Vec<(JournalTargetId, &'a mut dyn JournalTarget)>,
+}
+
+impl<'a> JournalTargets<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the structure with the identifier. A previous structure with the same identifier is
+    /// replaced.
+    pub fn with(mut self, id: JournalTargetId, target: &'a mut dyn JournalTarget) -> Self {
+        self.targets.retain(|(target_id, _)| *target_id != id);
+        self.targets.push((id, target));
+        self
+    }
+
+    fn get(&self, id: JournalTargetId) -> Result<&dyn JournalTarget> {
+        self.targets
+            .iter()
+            .find(|(target_id, _)| *target_id == id)
+            .map(|(_, target)| &**target)
+            .ok_or(Error::UnknownJournalTarget(id))
+    }
+
+    fn get_mut(&mut self, id: JournalTargetId) -> Result<&mut dyn JournalTarget> {
+        for (target_id, target) in self.targets.iter_mut() {
+            if *target_id == id {
+                return Ok(&mut **target);
+            }
+        }
+        Err(Error::UnknownJournalTarget(id))
+    }
+}
+
+/// Journal of the transaction in progress, stored in its own memory.
+pub struct StableJournal<M: Memory>(StableCell<JournalRecord, M>);
+
+impl<M: Memory> StableJournal<M> {
+    /// Creates the journal, or loads the pending transaction from the memory.
+    pub fn new(memory: M) -> Result<Self> {
+        Ok(Self(StableCell::new(memory, JournalRecord::default())?))
+    }
+
+    /// Writes of the transaction which is not completely applied yet.
+    pub fn pending(&self) -> &[JournalEntry] {
+        &self.0.get().entries
+    }
+
+    /// True if a transaction must be replayed with [`StableJournal::recover`].
+    pub fn is_pending(&self) -> bool {
+        !self.pending().is_empty()
+    }
+
+    /// Writes the transaction to the journal and applies it.
+    pub fn commit(&mut self, tx: Transaction, targets: &mut JournalTargets) -> Result<()> {
+        self.write(tx, targets)?;
+        self.recover(targets)?;
+        Ok(())
+    }
+
+    /// Writes the transaction to the journal without applying it. The transaction is applied by
+    /// the next [`StableJournal::recover`].
+    ///
+    /// Fails if a transaction is pending, or if a write targets an unknown structure or isn't
+    /// supported by its structure.
+    pub fn write(&mut self, tx: Transaction, targets: &JournalTargets) -> Result<()> {
+        if self.is_pending() {
+            return Err(Error::JournalPending);
+        }
+
+        let mut entries = tx.entries;
+        let mut appended: Vec<(JournalTargetId, u64)> = vec![];
+        for entry in &mut entries {
+            let target = targets.get(entry.target)?;
+            if !target.supports(&entry.op) {
+                return Err(Error::UnsupportedJournalOp);
+            }
+
+            if let JournalOp::Append { index, .. } = &mut entry.op {
+                let position = match appended.iter().position(|(id, _)| *id == entry.target) {
+                    Some(position) => position,
+                    None => {
+                        appended.push((entry.target, target.appended_len()));
+                        appended.len() - 1
+                    }
+                };
+                *index = appended[position].1;
+                appended[position].1 += 1;
+            }
+        }
+
+        self.0.set(JournalRecord { entries })
+    }
+
+    /// Applies the pending transaction, if any, and clears the journal. Returns true if a
+    /// transaction was applied.
+    ///
+    /// Call it at the start of the update methods and in `post_upgrade`.
+    pub fn recover(&mut self, targets: &mut JournalTargets) -> Result<bool> {
+        if !self.is_pending() {
+            return Ok(false);
+        }
+
+        for entry in self.pending() {
+            targets.get_mut(entry.target)?.apply(&entry.op)?;
+        }
+        self.0.set(JournalRecord::default())?;
+        Ok(true)
+    }
+}
+
+/// Content of the journal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct JournalRecord {
+    entries: Vec<JournalEntry>,
+}
+
+const INSERT_TAG: u8 = 0;
+const REMOVE_TAG: u8 = 1;
+const APPEND_TAG: u8 = 2;
+const SET_TAG: u8 = 3;
+
+impl Storable for JournalRecord {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            buf.push(entry.target);
+            let (tag, index, key, value): (u8, u64, &[u8], &[u8]) = match &entry.op {
+                JournalOp::Insert { key, value } => (INSERT_TAG, 0, key, value),
+                JournalOp::Remove { key } => (REMOVE_TAG, 0, key, &[]),
+                JournalOp::Append { index, value } => (APPEND_TAG, *index, &[], value),
+                JournalOp::Set { value } => (SET_TAG, 0, &[], value),
+            };
+            buf.push(tag);
+            buf.extend_from_slice(&index.to_le_bytes());
+            for bytes in [key, value] {
+                buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                buf.extend_from_slice(bytes);
+            }
+        }
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+        let count = reader.read_u32();
+        let entries = (0..count)
+            .map(|_| {
+                let target = reader.read(1)[0];
+                let tag = reader.read(1)[0];
+                let index = u64::from_le_bytes(reader.read(8).try_into().expect("8 bytes"));
+                let key = reader.read_prefixed();
+                let value = reader.read_prefixed();
+                let op = match tag {
+                    INSERT_TAG => JournalOp::Insert { key, value },
+                    REMOVE_TAG => JournalOp::Remove { key },
+                    APPEND_TAG => JournalOp::Append { index, value },
+                    SET_TAG => JournalOp::Set { value },
+                    _ => panic!("unknown journal operation tag: {tag}"),
+                };
+                JournalEntry { target, op }
+            })
+            .collect();
+
+        Self { entries }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> &'a [u8] {
+        let slice = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        slice
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.read(size_of::<u32>()).try_into().expect("4 bytes"))
+    }
+
+    fn read_prefixed(&mut self) -> Vec<u8> {
+        let len = self.read_u32() as usize;
+        self.read(len).to_vec()
+    }
+}
+
+fn decode<T: Storable>(bytes: &[u8]) -> T {
+    T::from_bytes(Cow::Borrowed(bytes))
+}
+
+/// Returns true if the append of `value` at `index` to a structure holding `len` values must be
+/// applied, false if it's already applied. Fails if another value was appended at its position
+/// outside of the journal.
+fn should_append<T: Storable>(
+    index: u64,
+    value: &[u8],
+    len: u64,
+    stored: impl FnOnce(u64) -> Option<T>,
+) -> Result<bool> {
+    match index.cmp(&len) {
+        std::cmp::Ordering::Less => match stored(index) {
+            Some(stored) if stored.to_bytes().as_ref() == value => Ok(false),
+            _ => Err(Error::JournalAppendConflict { index }),
+        },
+        std::cmp::Ordering::Equal => Ok(true),
+        std::cmp::Ordering::Greater => Err(Error::JournalAppendGap { index, len }),
+    }
+}
+
+impl<K, V, M> JournalTarget for StableBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn supports(&self, op: &JournalOp) -> bool {
+        matches!(op, JournalOp::Insert { .. } | JournalOp::Remove { .. })
+    }
+
+    fn apply(&mut self, op: &JournalOp) -> Result<()> {
+        match op {
+            JournalOp::Insert { key, value } => {
+                self.insert(decode(key), decode(value));
+            }
+            JournalOp::Remove { key } => {
+                self.remove(&decode(key));
+            }
+            _ => return Err(Error::UnsupportedJournalOp),
+        }
+        Ok(())
+    }
+}
+
+impl<T: Storable, M: Memory> JournalTarget for StableLog<T, M> {
+    fn appended_len(&self) -> u64 {
+        self.len()
+    }
+
+    fn supports(&self, op: &JournalOp) -> bool {
+        matches!(op, JournalOp::Append { .. })
+    }
+
+    fn apply(&mut self, op: &JournalOp) -> Result<()> {
+        match op {
+            JournalOp::Append { index, value } => {
+                if should_append(*index, value, self.len(), |index| self.get(index))? {
+                    self.append(decode(value))?;
+                }
+                Ok(())
+            }
+            _ => Err(Error::UnsupportedJournalOp),
+        }
+    }
+}
+
+impl<T: Storable, M: Memory> JournalTarget for StableVec<T, M> {
+    fn appended_len(&self) -> u64 {
+        self.len()
+    }
+
+    fn supports(&self, op: &JournalOp) -> bool {
+        matches!(op, JournalOp::Append { .. })
+    }
+
+    fn apply(&mut self, op: &JournalOp) -> Result<()> {
+        match op {
+            JournalOp::Append { index, value } => {
+                if should_append(*index, value, self.len(), |index| self.get(index))? {
+                    self.push(&decode(value))?;
+                }
+                Ok(())
+            }
+            _ => Err(Error::UnsupportedJournalOp),
+        }
+    }
+}
+
+impl<T: Storable, M: Memory> JournalTarget for StableCell<T, M> {
+    fn supports(&self, op: &JournalOp) -> bool {
+        matches!(op, JournalOp::Set { .. })
+    }
+
+    fn apply(&mut self, op: &JournalOp) -> Result<()> {
+        match op {
+            JournalOp::Set { value } => self.set(decode(value)),
+            _ => Err(Error::UnsupportedJournalOp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    const BALANCES: JournalTargetId = 0;
+    const HISTORY: JournalTargetId = 1;
+    const TOTAL: JournalTargetId = 2;
+
+    struct State {
+        balances: StableBTreeMap<u64, u64, VectorMemory>,
+        history: StableLog<StringValue, VectorMemory>,
+        total: StableCell<u64, VectorMemory>,
+    }
+
+    impl State {
+        fn new() -> Self {
+            Self {
+                balances: StableBTreeMap::new(VectorMemory::default()),
+                history: StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap(),
+                total: StableCell::new(VectorMemory::default(), 0).unwrap(),
+            }
+        }
+
+        fn targets(&mut self) -> JournalTargets<'_> {
+            JournalTargets::new()
+                .with(BALANCES, &mut self.balances)
+                .with(HISTORY, &mut self.history)
+                .with(TOTAL, &mut self.total)
+        }
+    }
+
+    fn transfer_tx() -> Transaction {
+        let mut tx = Transaction::default();
+        tx.insert(BALANCES, &1u64, &70u64)
+            .insert(BALANCES, &2u64, &30u64)
+            .remove(BALANCES, &3u64)
+            .append(HISTORY, &str_val(3))
+            .append(HISTORY, &str_val(4))
+            .set(TOTAL, &100u64);
+        tx
+    }
+
+    fn check_transfer_applied(state: &State) {
+        assert_eq!(state.balances.get(&1), Some(70));
+        assert_eq!(state.balances.get(&2), Some(30));
+        assert!(!state.balances.contains_key(&3));
+        assert_eq!(state.history.len(), 3);
+        assert_eq!(state.history.get(1), Some(str_val(3)));
+        assert_eq!(state.history.get(2), Some(str_val(4)));
+        assert_eq!(*state.total.get(), 100);
+    }
+
+    #[test]
+    fn should_commit_transaction() {
+        let mut state = State::new();
+        state.balances.insert(3, 10);
+        state.history.append(str_val(1)).unwrap();
+        let mut journal = StableJournal::new(VectorMemory::default()).unwrap();
+
+        journal.commit(transfer_tx(), &mut state.targets()).unwrap();
+
+        assert!(!journal.is_pending());
+        check_transfer_applied(&state);
+    }
+
+    #[test]
+    fn should_replay_partially_applied_transaction() {
+        let mut state = State::new();
+        state.balances.insert(3, 10);
+        state.history.append(str_val(1)).unwrap();
+        let memory = VectorMemory::default();
+        let mut journal = StableJournal::new(memory.clone()).unwrap();
+        journal.write(transfer_tx(), &state.targets()).unwrap();
+        assert_eq!(journal.pending().len(), 6);
+
+        // The application is interrupted after the first append.
+        let pending = journal.pending().to_vec();
+        let mut targets = state.targets();
+        for entry in &pending[..4] {
+            targets
+                .get_mut(entry.target)
+                .unwrap()
+                .apply(&entry.op)
+                .unwrap();
+        }
+        drop(targets);
+
+        // The journal is reloaded from the memory, e.g. after an upgrade.
+        let mut journal = StableJournal::new(memory).unwrap();
+        assert!(journal.is_pending());
+        assert!(journal.recover(&mut state.targets()).unwrap());
+        assert!(!journal.is_pending());
+        check_transfer_applied(&state);
+
+        assert!(!journal.recover(&mut state.targets()).unwrap());
+        check_transfer_applied(&state);
+    }
+
+    #[test]
+    fn should_reject_appends_made_outside_of_the_journal() {
+        let mut state = State::new();
+        let mut journal = StableJournal::new(VectorMemory::default()).unwrap();
+        journal.write(transfer_tx(), &state.targets()).unwrap();
+
+        state.history.append(str_val(5)).unwrap();
+        assert!(matches!(
+            journal.recover(&mut state.targets()),
+            Err(Error::JournalAppendConflict { index: 0 })
+        ));
+        assert!(journal.is_pending());
+    }
+
+    #[test]
+    fn should_reject_invalid_transactions() {
+        let mut state = State::new();
+        let mut journal = StableJournal::new(VectorMemory::default()).unwrap();
+
+        let mut tx = Transaction::default();
+        tx.insert(42, &1u64, &1u64);
+        assert!(matches!(
+            journal.write(tx, &state.targets()),
+            Err(Error::UnknownJournalTarget(42))
+        ));
+
+        let mut tx = Transaction::default();
+        tx.append(BALANCES, &1u64);
+        assert!(matches!(
+            journal.write(tx, &state.targets()),
+            Err(Error::UnsupportedJournalOp)
+        ));
+        assert!(!journal.is_pending());
+
+        journal.write(transfer_tx(), &state.targets()).unwrap();
+        assert!(matches!(
+            journal.write(transfer_tx(), &state.targets()),
+            Err(Error::JournalPending)
+        ));
+    }
+
+    #[test]
+    fn should_roundtrip_journal_record() {
+        let mut tx = transfer_tx();
+        tx.append(HISTORY, &str_val(0));
+        let record = JournalRecord {
+            entries: tx.entries().to_vec(),
+        };
+        assert_eq!(JournalRecord::from_bytes(record.to_bytes()), record);
+    }
+}
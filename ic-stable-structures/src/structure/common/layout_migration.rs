@@ -0,0 +1,203 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::ops::Bound as RangeBound;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, CellStructure, IterableSortedMapStructure, StableCell};
+use crate::Result;
+
+const NOT_STARTED_TAG: u8 = 0;
+const IN_PROGRESS_TAG: u8 = 1;
+const DONE_TAG: u8 = 2;
+
+/// Progress of a [`LayoutMigration`]. `migrated` is the number of entries of the source map read
+/// so far, including the dropped ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationStatus<K> {
+    NotStarted,
+    /// The entries up to `last_key` included are migrated
+    InProgress {
+        last_key: K,
+        migrated: u64,
+    },
+    Done {
+        migrated: u64,
+    },
+}
+
+impl<K: Storable> Storable for MigrationStatus<K> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::new();
+        match self {
+            Self::NotStarted => buf.push(NOT_STARTED_TAG),
+            Self::InProgress { last_key, migrated } => {
+                buf.push(IN_PROGRESS_TAG);
+                buf.extend_from_slice(&migrated.to_le_bytes());
+                buf.extend_from_slice(&last_key.to_bytes());
+            }
+            Self::Done { migrated } => {
+                buf.push(DONE_TAG);
+                buf.extend_from_slice(&migrated.to_le_bytes());
+            }
+        }
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let migrated = || {
+            u64::from_le_bytes(
+                bytes[1..1 + size_of::<u64>()]
+                    .try_into()
+                    .expect("migrated: expected 8 bytes"),
+            )
+        };
+        match bytes[0] {
+            NOT_STARTED_TAG => Self::NotStarted,
+            IN_PROGRESS_TAG => Self::InProgress {
+                last_key: K::from_bytes(bytes[1 + size_of::<u64>()..].to_vec().into()),
+                migrated: migrated(),
+            },
+            _ => Self::Done {
+                migrated: migrated(),
+            },
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Migration of the entries of a map to a map with another layout, e.g. another structure type or
+/// another encoding of the keys and values, over several messages.
+///
+/// Every [`Self::step`] converts a batch of entries of the source map and inserts them in the
+/// target map, e.g. from a task of the scheduler, and stores the last migrated key in stable
+/// memory, so the migration resumes where it stopped after an upgrade. Until the migration is done,
+/// the canister reads the source map and applies its writes to both maps. Afterwards, the source
+/// map can be cleared and its memory released, e.g. with
+/// [`MemoryPool::release`](crate::MemoryPool::release).
+pub struct LayoutMigration<K: Storable, M: Memory> {
+    status: StableCell<MigrationStatus<K>, M>,
+}
+
+impl<K, M> LayoutMigration<K, M>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    /// Creates the migration, or loads its progress from the memory.
+    pub fn new(memory: M) -> Result<Self> {
+        Ok(Self {
+            status: StableCell::new(memory, MigrationStatus::NotStarted)?,
+        })
+    }
+
+    pub fn status(&self) -> &MigrationStatus<K> {
+        self.status.get()
+    }
+
+    /// True if all the entries are migrated.
+    pub fn is_done(&self) -> bool {
+        matches!(self.status.get(), MigrationStatus::Done { .. })
+    }
+
+    /// Migrates at most `max_entries` entries following the last migrated key, and returns the
+    /// progress of the migration.
+    ///
+    /// `convert` returns the entry of the target map for an entry of the source map, or `None` to
+    /// drop the entry.
+    pub fn step<V, S, TK, TV>(
+        &mut self,
+        source: &S,
+        target: &mut impl BTreeMapStructure<TK, TV>,
+        max_entries: usize,
+        mut convert: impl FnMut(K, V) -> Option<(TK, TV)>,
+    ) -> Result<MigrationStatus<K>>
+    where
+        S: IterableSortedMapStructure<K, V>,
+    {
+        let (start, migrated) = match self.status.get() {
+            MigrationStatus::NotStarted => (RangeBound::Unbounded, 0),
+            MigrationStatus::InProgress { last_key, migrated } => {
+                (RangeBound::Excluded(last_key.clone()), *migrated)
+            }
+            MigrationStatus::Done { .. } => return Ok(self.status.get().clone()),
+        };
+        if max_entries == 0 {
+            return Ok(self.status.get().clone());
+        }
+
+        let batch: Vec<_> = source
+            .range((start, RangeBound::Unbounded))
+            .take(max_entries)
+            .collect();
+        let status = match batch.last() {
+            Some((key, _)) if batch.len() == max_entries => MigrationStatus::InProgress {
+                last_key: key.clone(),
+                migrated: migrated + batch.len() as u64,
+            },
+            _ => MigrationStatus::Done {
+                migrated: migrated + batch.len() as u64,
+            },
+        };
+        for (key, value) in batch {
+            if let Some((key, value)) = convert(key, value) {
+                target.insert(key, value);
+            }
+        }
+
+        self.status.set(status.clone())?;
+        Ok(status)
+    }
+
+    /// Restarts the migration from the first entry.
+    pub fn reset(&mut self) -> Result<()> {
+        self.status.set(MigrationStatus::NotStarted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::StableBTreeMap;
+
+    #[test]
+    fn should_migrate_in_steps_across_restarts() {
+        let mut source = StableBTreeMap::<u64, StringValue, _>::new(VectorMemory::default());
+        for key in 0..10 {
+            source.insert(key, str_val(key as usize + 1));
+        }
+        let mut target = StableBTreeMap::<u32, u64, _>::new(VectorMemory::default());
+        let convert =
+            |key: u64, value: StringValue| (key != 5).then_some((key as u32, value.0.len() as u64));
+
+        let memory = VectorMemory::default();
+        let mut migration = LayoutMigration::new(memory.clone()).unwrap();
+        assert_eq!(
+            migration.step(&source, &mut target, 4, convert).unwrap(),
+            MigrationStatus::InProgress {
+                last_key: 3,
+                migrated: 4
+            }
+        );
+
+        let mut migration = LayoutMigration::<u64, _>::new(memory).unwrap();
+        migration.step(&source, &mut target, 4, convert).unwrap();
+        assert_eq!(
+            migration.step(&source, &mut target, 4, convert).unwrap(),
+            MigrationStatus::Done { migrated: 10 }
+        );
+        assert!(migration.is_done());
+
+        assert_eq!(target.len(), 9);
+        assert_eq!(target.get(&9), Some(10));
+        assert_eq!(target.get(&5), None);
+
+        migration.reset().unwrap();
+        assert_eq!(migration.status(), &MigrationStatus::NotStarted);
+    }
+}
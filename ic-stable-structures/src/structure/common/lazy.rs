@@ -0,0 +1,101 @@
+//! Values whose stored bytes are decoded on demand, e.g. for the endpoints forwarding large values
+//! such as assets or certified payloads, which return the bytes without decoding and encoding the
+//! value again:
+//!
+//! ```ignore
+//! let assets = StableBTreeMap::<String, Lazy<Asset>, _>::new(memory);
+//! let body = assets.get(&path).map(|asset| asset.into_bytes());
+//! ```
+//!
+//! `Lazy<T>` has the same serialized form and bound as `T`, so the memory of a structure of `T`
+//! can be opened as a structure of `Lazy<T>`, and the other way around.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+/// Serialized `T`, decoded by [`Lazy::get`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lazy<T> {
+    bytes: Vec<u8>,
+    _value: PhantomData<T>,
+}
+
+impl<T: Storable> Lazy<T> {
+    /// Serializes the value.
+    pub fn new(value: &T) -> Self {
+        Self::from_raw(value.to_bytes().into_owned())
+    }
+
+    /// Wraps bytes already serialized by `T`, e.g. the body of a request to store as is.
+    pub fn from_raw(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            _value: PhantomData,
+        }
+    }
+
+    /// Decodes the value.
+    pub fn get(&self) -> T {
+        T::from_bytes(Cow::Borrowed(&self.bytes))
+    }
+
+    /// Serialized value
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the serialized value without copying it.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl<T: Storable> Storable for Lazy<T> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self::from_raw(bytes.into_owned())
+    }
+
+    const BOUND: Bound = T::BOUND;
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[test]
+    fn should_read_stored_bytes_without_decoding() {
+        let memory = VectorMemory::default();
+        let mut map = StableBTreeMap::<u64, StringValue, _>::new(memory.clone());
+        map.insert(1, str_val(1000));
+        drop(map);
+
+        let map = StableBTreeMap::<u64, Lazy<StringValue>, _>::new(memory);
+        let value = map.get(&1).unwrap();
+        assert_eq!(value.bytes(), str_val(1000).to_bytes().as_ref());
+        assert_eq!(value.get(), str_val(1000));
+    }
+
+    #[test]
+    fn should_store_raw_bytes() {
+        let mut map = StableBTreeMap::<u64, Lazy<StringValue>, _>::new(VectorMemory::default());
+        map.insert(1, Lazy::from_raw(str_val(10).to_bytes().into_owned()));
+        map.insert(2, Lazy::new(&str_val(20)));
+
+        assert_eq!(map.get(&1).unwrap().get(), str_val(10));
+        assert_eq!(
+            map.get(&2).unwrap().into_bytes(),
+            str_val(20).to_bytes().as_ref()
+        );
+    }
+}
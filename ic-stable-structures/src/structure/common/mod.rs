@@ -1,7 +1,38 @@
+pub mod candid_storable;
+pub mod composite_key;
+pub mod compressed;
+pub mod deque;
+pub mod encrypted;
+pub mod journal;
+pub mod layout_migration;
+pub mod lazy;
+pub mod pagination;
+pub mod principal_key;
+pub mod priority_queue;
 pub mod ring_buffer;
+pub mod serde_storable;
+pub mod tuning;
+pub mod versioned;
 
 use candid::Principal;
+pub use candid_storable::CandidStorable;
+pub use composite_key::{Key2, Key3, KeyPart};
+pub use compressed::{Compressed, DEFAULT_COMPRESSION_THRESHOLD};
+pub use deque::{StableDeque, StableDequeIndices};
+pub use encrypted::{Encrypted, EncryptionKey, ENCRYPTION_KEY_SIZE};
+pub use journal::{
+    JournalEntry, JournalOp, JournalTarget, JournalTargetId, JournalTargets, StableJournal,
+    Transaction,
+};
+pub use layout_migration::{LayoutMigration, MigrationStatus};
+pub use lazy::Lazy;
+pub use pagination::{paginate_log, paginate_map, paginate_vec, Cursor, Page};
+pub use principal_key::PrincipalKey;
+pub use priority_queue::StablePriorityQueue;
 pub use ring_buffer::{StableRingBuffer, StableRingBufferIndices};
+pub use serde_storable::SerdeStorable;
+pub use tuning::BoundedStorable;
+pub use versioned::{migrate_btreemap, Migrate, Migrations, Versioned};
 
 /// A trait for types that have a minimum and maximum value.
 pub trait Bounded {
@@ -92,6 +123,40 @@ impl Bounded for Principal {
     const MAX: Self = Principal::from_slice(&[0xFF; 29]);
 }
 
+/// A trait for types whose order is the lexicographic order of their encoded bytes, which permits
+/// to scan the keys of a map by prefix of their bytes, see
+/// [`StableBTreeMap::iter_prefix`](crate::StableBTreeMap::iter_prefix).
+pub trait ByteOrdered: dfinity_stable_structures::Storable + Ord {
+    /// Returns a value lower than or equal to all the values whose encoded bytes start with
+    /// `prefix`.
+    fn prefix_lower_bound(prefix: &[u8]) -> Self;
+}
+
+impl ByteOrdered for String {
+    fn prefix_lower_bound(prefix: &[u8]) -> Self {
+        let valid = match std::str::from_utf8(prefix) {
+            Ok(valid) => valid,
+            Err(e) => std::str::from_utf8(&prefix[..e.valid_up_to()]).expect("valid UTF-8"),
+        };
+        valid.to_string()
+    }
+}
+
+impl ByteOrdered for Vec<u8> {
+    fn prefix_lower_bound(prefix: &[u8]) -> Self {
+        prefix.to_vec()
+    }
+}
+
+impl<const N: usize> ByteOrdered for [u8; N] {
+    fn prefix_lower_bound(prefix: &[u8]) -> Self {
+        let mut bound = [0; N];
+        let len = prefix.len().min(N);
+        bound[..len].copy_from_slice(&prefix[..len]);
+        bound
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use candid::Principal;
@@ -0,0 +1,241 @@
+//! Cursor-based pagination over the stable structures.
+//!
+//! A page is read with a `limit` and the cursor returned with the previous page, or `None` for
+//! the first one. The cursor is opaque to the callers and can be returned from a query, e.g.:
+//!
+//! ```ignore
+//! #[query]
+//! fn list_users(cursor: Option<Cursor>) -> Page<(Principal, User)> {
+//!     USERS.with(|users| paginate_map(&*users.borrow(), cursor.as_ref(), 100))
+//!         .expect("invalid cursor")
+//! }
+//! ```
+//!
+//! Map cursors hold the last returned key, so the pages stay consistent when entries are
+//! inserted or removed between the calls. Log and vector cursors hold the index of the next
+//! item.
+
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::ops::Bound;
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::Storable;
+
+use crate::structure::{IterableSortedMapStructure, LogStructure, VecStructure};
+use crate::{Error, Result};
+
+const KEY_CURSOR: u8 = 0;
+const INDEX_CURSOR: u8 = 1;
+
+/// Opaque position of the next page.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cursor(Vec<u8>);
+
+impl Cursor {
+    fn from_key<K: Storable>(key: &K) -> Self {
+        let key = key.to_bytes();
+        let mut buf = Vec::with_capacity(1 + key.len());
+        buf.push(KEY_CURSOR);
+        buf.extend_from_slice(&key);
+        Self(buf)
+    }
+
+    fn from_index(index: u64) -> Self {
+        let mut buf = Vec::with_capacity(1 + size_of::<u64>());
+        buf.push(INDEX_CURSOR);
+        buf.extend_from_slice(&index.to_le_bytes());
+        Self(buf)
+    }
+
+    fn key<K: Storable>(&self) -> Result<K> {
+        match self.0.split_first() {
+            Some((&KEY_CURSOR, key)) => Ok(K::from_bytes(Cow::Borrowed(key))),
+            _ => Err(Error::InvalidCursor),
+        }
+    }
+
+    fn index(&self) -> Result<u64> {
+        match self.0.split_first() {
+            Some((&INDEX_CURSOR, index)) => index
+                .try_into()
+                .map(u64::from_le_bytes)
+                .map_err(|_| Error::InvalidCursor),
+            _ => Err(Error::InvalidCursor),
+        }
+    }
+}
+
+/// Items of a page, with the cursor of the next page if there are more items.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    fn empty() -> Self {
+        Self {
+            items: Vec::new(),
+            next_cursor: None,
+        }
+    }
+}
+
+/// Returns at most `limit` entries of the map, in key order, starting after the cursor.
+pub fn paginate_map<K, V, S>(map: &S, cursor: Option<&Cursor>, limit: usize) -> Result<Page<(K, V)>>
+where
+    K: Storable + Clone,
+    S: IterableSortedMapStructure<K, V>,
+{
+    let start = match cursor {
+        Some(cursor) => Bound::Excluded(cursor.key::<K>()?),
+        None => Bound::Unbounded,
+    };
+    if limit == 0 {
+        return Ok(Page::empty());
+    }
+
+    let mut entries = map.range((start, Bound::Unbounded)).peekable();
+    let items: Vec<_> = entries.by_ref().take(limit).collect();
+    let next_cursor = match (items.last(), entries.peek()) {
+        (Some((key, _)), Some(_)) => Some(Cursor::from_key(key)),
+        _ => None,
+    };
+
+    Ok(Page { items, next_cursor })
+}
+
+/// Returns at most `limit` items of the log with their indices, starting at the cursor.
+pub fn paginate_log<T, S>(log: &S, cursor: Option<&Cursor>, limit: usize) -> Result<Page<(u64, T)>>
+where
+    S: LogStructure<T>,
+{
+    paginate_indexed(log.len(), |index| log.get(index), cursor, limit)
+}
+
+/// Returns at most `limit` items of the vector with their indices, starting at the cursor.
+pub fn paginate_vec<T, S>(vec: &S, cursor: Option<&Cursor>, limit: usize) -> Result<Page<(u64, T)>>
+where
+    S: VecStructure<T>,
+{
+    paginate_indexed(vec.len(), |index| vec.get(index), cursor, limit)
+}
+
+fn paginate_indexed<T>(
+    len: u64,
+    get: impl Fn(u64) -> Option<T>,
+    cursor: Option<&Cursor>,
+    limit: usize,
+) -> Result<Page<(u64, T)>> {
+    let start = cursor.map(Cursor::index).transpose()?.unwrap_or(0);
+    let end = start.saturating_add(limit as u64).min(len);
+
+    let items: Vec<_> = (start..end)
+        .filter_map(|index| get(index).map(|item| (index, item)))
+        .collect();
+    let next_cursor = (end < len && limit > 0).then(|| Cursor::from_index(end));
+
+    Ok(Page { items, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{Decode, Encode};
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::structure::{BTreeMapStructure, StableBTreeMap, StableLog, StableVec};
+
+    fn collect_pages<T>(mut next: impl FnMut(Option<&Cursor>) -> Result<Page<T>>) -> Vec<Vec<T>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = next(cursor.as_ref()).unwrap();
+            pages.push(page.items);
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn should_paginate_map() {
+        let mut map = StableBTreeMap::<u32, u64, _>::new(VectorMemory::default());
+        for key in 0..7 {
+            map.insert(key, key as u64 * 10);
+        }
+
+        let pages = collect_pages(|cursor| paginate_map(&map, cursor, 3));
+        assert_eq!(
+            pages,
+            vec![
+                vec![(0, 0), (1, 10), (2, 20)],
+                vec![(3, 30), (4, 40), (5, 50)],
+                vec![(6, 60)],
+            ]
+        );
+    }
+
+    #[test]
+    fn should_resume_map_after_removed_key() {
+        let mut map = StableBTreeMap::<u32, u64, _>::new(VectorMemory::default());
+        for key in 0..4 {
+            map.insert(key, 0);
+        }
+
+        let page = paginate_map(&map, None, 2).unwrap();
+        map.remove(&1);
+        let next = paginate_map(&map, page.next_cursor.as_ref(), 2).unwrap();
+        assert_eq!(next.items, vec![(2, 0), (3, 0)]);
+        assert_eq!(next.next_cursor, None);
+    }
+
+    #[test]
+    fn should_paginate_log() {
+        let mut log =
+            StableLog::<u64, _>::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        for item in 0..4 {
+            log.append(item).unwrap();
+        }
+
+        let pages = collect_pages(|cursor| paginate_log(&log, cursor, 2));
+        assert_eq!(pages, vec![vec![(0, 0), (1, 1)], vec![(2, 2), (3, 3)]]);
+    }
+
+    #[test]
+    fn should_paginate_vec() {
+        let mut vec = StableVec::<u64, _>::new(VectorMemory::default()).unwrap();
+        for item in 0..5 {
+            vec.push(&item).unwrap();
+        }
+
+        let pages = collect_pages(|cursor| paginate_vec(&vec, cursor, 2));
+        assert_eq!(
+            pages,
+            vec![vec![(0, 0), (1, 1)], vec![(2, 2), (3, 3)], vec![(4, 4)]]
+        );
+    }
+
+    #[test]
+    fn should_reject_cursor_of_another_structure() {
+        let map = StableBTreeMap::<u32, u64, _>::new(VectorMemory::default());
+        let cursor = Cursor::from_index(1);
+        assert!(matches!(
+            paginate_map(&map, Some(&cursor), 1),
+            Err(Error::InvalidCursor)
+        ));
+    }
+
+    #[test]
+    fn should_encode_page_in_candid() {
+        let page = Page {
+            items: vec![(1u64, "one".to_string())],
+            next_cursor: Some(Cursor::from_index(2)),
+        };
+
+        let bytes = Encode!(&page).unwrap();
+        assert_eq!(Decode!(&bytes, Page<(u64, String)>).unwrap(), page);
+    }
+}
@@ -0,0 +1,58 @@
+//! Principals as keys of the stable structures.
+//!
+//! [`Principal`] is a foreign type, so it can't implement [`Storable`] itself. [`PrincipalKey`]
+//! stores the raw bytes of the principal, e.g. for the maps and multimaps indexed by user:
+//!
+//! ```ignore
+//! let mut profiles = StableBTreeMap::<PrincipalKey, Profile, _>::new(memory);
+//! profiles.insert(PrincipalKey(caller), profile);
+//! ```
+
+use std::borrow::Cow;
+
+use candid::Principal;
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+/// Principal wrapper used as a key in the stable structures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PrincipalKey(pub Principal);
+
+impl From<Principal> for PrincipalKey {
+    fn from(principal: Principal) -> Self {
+        Self(principal)
+    }
+}
+
+impl Storable for PrincipalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Principal::from_slice(&bytes))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 29,
+        is_fixed_size: false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_roundtrip_principal_key() {
+        for principal in [
+            Principal::anonymous(),
+            Principal::management_canister(),
+            Principal::from_slice(&[7; 29]),
+        ] {
+            let key = PrincipalKey(principal);
+            assert_eq!(key.to_bytes().as_ref(), principal.as_slice());
+            assert_eq!(PrincipalKey::from_bytes(key.to_bytes()), key);
+        }
+    }
+}
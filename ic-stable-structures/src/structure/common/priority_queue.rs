@@ -0,0 +1,251 @@
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{StableVec, VecStructure};
+use crate::{MemoryStats, MemoryUsage, Result};
+
+/// Stable priority queue implementation, popping the elements by ascending priority.
+///
+/// The queue is a binary heap, so `push` and `pop` are O(log n) and `peek` is O(1).
+/// The heap is stored in two vectors with the same layout, one for the priorities and one for the
+/// values, so only the priorities are read to restore the heap order.
+///
+/// The elements with the same priority are popped in an unspecified order.
+/// To pop the highest priority first, use a priority type with a reversed order.
+pub struct StablePriorityQueue<P, V, PriorityMemory, ValueMemory>
+where
+    P: Storable + Ord,
+    V: Storable,
+    PriorityMemory: Memory,
+    ValueMemory: Memory,
+{
+    priorities: StableVec<P, PriorityMemory>,
+    values: StableVec<V, ValueMemory>,
+}
+
+impl<P, V, PriorityMemory, ValueMemory> StablePriorityQueue<P, V, PriorityMemory, ValueMemory>
+where
+    P: Storable + Ord,
+    V: Storable,
+    PriorityMemory: Memory,
+    ValueMemory: Memory,
+{
+    /// Creates new priority queue
+    pub fn new(priority_memory: PriorityMemory, value_memory: ValueMemory) -> Result<Self> {
+        Ok(Self {
+            priorities: StableVec::new(priority_memory)?,
+            values: StableVec::new(value_memory)?,
+        })
+    }
+
+    /// Number of elements in the queue
+    pub fn len(&self) -> u64 {
+        self.priorities.len()
+    }
+
+    /// Returns whether is empty
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+    }
+
+    /// Removes all elements in the queue
+    pub fn clear(&mut self) -> Result<()> {
+        self.priorities.clear()?;
+        self.values.clear()
+    }
+
+    /// Adds an element to the queue.
+    pub fn push(&mut self, priority: &P, value: &V) -> Result<()> {
+        self.priorities.push(priority)?;
+        self.values.push(value)?;
+        self.sift_up(self.len() - 1, priority);
+        Ok(())
+    }
+
+    /// Returns the element with the lowest priority without removing it.
+    pub fn peek(&self) -> Option<(P, V)> {
+        Some((self.priorities.get(0)?, self.values.get(0)?))
+    }
+
+    /// Returns the lowest priority.
+    pub fn peek_priority(&self) -> Option<P> {
+        self.priorities.get(0)
+    }
+
+    /// Removes the element with the lowest priority and returns it.
+    pub fn pop(&mut self) -> Option<(P, V)> {
+        let last_priority = self.priorities.pop()?;
+        let last_value = self.values.pop()?;
+        if self.is_empty() {
+            return Some((last_priority, last_value));
+        }
+
+        // Move the last element to the root and restore the heap order
+        let top = self.get(0);
+        self.set(0, &last_priority, &last_value);
+        self.sift_down(0, &last_priority);
+        Some(top)
+    }
+
+    /// Removes and returns the elements whose priority is lower than or equal to the given one,
+    /// by ascending priority. E.g. the tasks whose deadline is reached.
+    pub fn pop_until(&mut self, max_priority: &P) -> Vec<(P, V)> {
+        let mut popped = vec![];
+        while self
+            .peek_priority()
+            .is_some_and(|priority| priority <= *max_priority)
+        {
+            popped.extend(self.pop());
+        }
+        popped
+    }
+
+    /// Returns an iterator over the elements, in an unspecified order.
+    pub fn iter(&self) -> impl Iterator<Item = (P, V)> + '_ {
+        self.priorities.iter().zip(self.values.iter())
+    }
+
+    /// Moves the element at `index` up until its parent has a lower or equal priority.
+    fn sift_up(&mut self, mut index: u64, priority: &P) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            let parent_priority = self.priority(parent);
+            if parent_priority <= *priority {
+                break;
+            }
+            self.swap(index, parent);
+            index = parent;
+        }
+    }
+
+    /// Moves the element at `index` down until its children have a greater or equal priority.
+    fn sift_down(&mut self, mut index: u64, priority: &P) {
+        let len = self.len();
+        loop {
+            let left = 2 * index + 1;
+            if left >= len {
+                break;
+            }
+
+            let right = left + 1;
+            let left_priority = self.priority(left);
+            let (child, child_priority) = if right < len {
+                let right_priority = self.priority(right);
+                if right_priority < left_priority {
+                    (right, right_priority)
+                } else {
+                    (left, left_priority)
+                }
+            } else {
+                (left, left_priority)
+            };
+
+            if *priority <= child_priority {
+                break;
+            }
+            self.swap(index, child);
+            index = child;
+        }
+    }
+
+    fn priority(&self, index: u64) -> P {
+        self.priorities
+            .get(index)
+            .expect("heap index should be inside the vector")
+    }
+
+    fn get(&self, index: u64) -> (P, V) {
+        let value = self
+            .values
+            .get(index)
+            .expect("heap index should be inside the vector");
+        (self.priority(index), value)
+    }
+
+    fn set(&mut self, index: u64, priority: &P, value: &V) {
+        self.priorities
+            .set(index, priority)
+            .expect("failed to set the priority");
+        self.values
+            .set(index, value)
+            .expect("failed to set the value");
+    }
+
+    fn swap(&mut self, a: u64, b: u64) {
+        let (a_priority, a_value) = self.get(a);
+        let (b_priority, b_value) = self.get(b);
+        self.set(a, &b_priority, &b_value);
+        self.set(b, &a_priority, &a_value);
+    }
+}
+
+impl<P, V, PriorityMemory, ValueMemory> MemoryUsage
+    for StablePriorityQueue<P, V, PriorityMemory, ValueMemory>
+where
+    P: Storable + Ord,
+    V: Storable,
+    PriorityMemory: Memory,
+    ValueMemory: Memory,
+{
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.len(),
+            bytes: self.priorities.memory_stats().bytes + self.values.memory_stats().bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    type TestQueue = StablePriorityQueue<u64, u32, VectorMemory, VectorMemory>;
+
+    fn queue() -> TestQueue {
+        StablePriorityQueue::new(VectorMemory::default(), VectorMemory::default()).unwrap()
+    }
+
+    #[test]
+    fn should_pop_by_ascending_priority() {
+        let mut queue = queue();
+        assert_eq!(queue.peek(), None);
+        assert_eq!(queue.pop(), None);
+
+        // pseudo-random priorities, with duplicates
+        let priorities = (0..200u64).map(|i| (i * 7919) % 101).collect::<Vec<_>>();
+        for (value, priority) in priorities.iter().enumerate() {
+            queue.push(priority, &(value as u32)).unwrap();
+        }
+        assert_eq!(queue.len(), 200);
+        assert_eq!(queue.peek_priority(), Some(0));
+
+        let mut popped = vec![];
+        while let Some((priority, value)) = queue.pop() {
+            assert_eq!(priorities[value as usize], priority);
+            popped.push(priority);
+        }
+
+        let mut expected = priorities;
+        expected.sort();
+        assert_eq!(popped, expected);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn should_pop_until_priority() {
+        let mut queue = queue();
+        for deadline in [30, 10, 50, 20, 40] {
+            queue.push(&deadline, &(deadline as u32 / 10)).unwrap();
+        }
+
+        assert_eq!(queue.pop_until(&5), vec![]);
+        assert_eq!(queue.pop_until(&30), vec![(10, 1), (20, 2), (30, 3)]);
+        assert_eq!(queue.peek(), Some((40, 4)));
+        assert_eq!(queue.iter().count(), 2);
+
+        queue.clear().unwrap();
+        assert!(queue.is_empty());
+    }
+}
@@ -6,7 +6,7 @@ use dfinity_stable_structures::storable::Bound;
 use dfinity_stable_structures::{Memory, Storable};
 
 use crate::structure::{CellStructure, StableCell, StableVec, VecStructure};
-use crate::Result;
+use crate::{MemoryStats, MemoryUsage, Result};
 
 /// Ring buffer indices state
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -274,6 +274,27 @@ impl<T: Storable + Clone, DataMemory: Memory, IndicesMemory: Memory>
         self.data.get(index)
     }
 
+    /// Returns an iterator over the elements, from the oldest to the most recent.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = T> + ExactSizeIterator + '_ {
+        let indices = self.indices.get().clone();
+        let len = usize::try_from(indices.len).expect("buffer length exceeds the address space");
+        (0..len).map(move |n| {
+            // This should never panic, because `n < indices.len`.
+            let index = indices
+                .nth_element(n as u64)
+                .expect("element should be present");
+            self.data.get(index).expect("element should be present")
+        })
+    }
+
+    /// Returns the `n` most recent elements, from the most recent to the oldest.
+    pub fn latest(&self, n: u64) -> Vec<T> {
+        self.iter()
+            .rev()
+            .take(usize::try_from(n).unwrap_or(usize::MAX))
+            .collect()
+    }
+
     #[inline]
     fn with_indices_data_mut<R>(
         &mut self,
@@ -288,6 +309,18 @@ impl<T: Storable + Clone, DataMemory: Memory, IndicesMemory: Memory>
     }
 }
 
+impl<T: Storable + Clone, DataMemory: Memory, IndicesMemory: Memory> MemoryUsage
+    for StableRingBuffer<T, DataMemory, IndicesMemory>
+{
+    /// The bytes include the free slots of the buffer, which are reused by the next pushes.
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.len(),
+            bytes: self.data.memory_stats().bytes + self.indices.memory_stats().bytes,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -366,6 +399,7 @@ mod tests {
         }
 
         assert_eq!(None, buffer.nth_element(expected.len() as _));
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), expected);
     }
 
     fn with_buffer(
@@ -410,6 +444,22 @@ mod tests {
         });
     }
 
+    #[test]
+    fn should_return_latest_elements() {
+        with_buffer(3, |buffer| {
+            assert!(buffer.latest(2).is_empty());
+
+            for i in 0..5 {
+                buffer.push(&i);
+            }
+
+            assert_eq!(buffer.latest(2), vec![4, 3]);
+            assert_eq!(buffer.latest(10), vec![4, 3, 2]);
+            assert_eq!(buffer.iter().len(), 3);
+            assert_eq!(buffer.iter().rev().collect::<Vec<_>>(), vec![4, 3, 2]);
+        });
+    }
+
     #[test]
     fn should_pop() {
         with_buffer(5, |buffer| {
@@ -0,0 +1,75 @@
+//! Values stored with their serde implementation, to store the types which implement
+//! `Serialize` and `Deserialize` without writing a `Storable` implementation, including the types
+//! of other crates:
+//!
+//! ```ignore
+//! let orders = StableBTreeMap::<u64, SerdeStorable<Order>, _>::new(memory);
+//! orders.insert(id, SerdeStorable(order));
+//! ```
+//!
+//! The values are encoded with bincode, which is compact and fast but doesn't support the schema
+//! evolution: changing the fields of `T` requires a migration of the stored values, e.g. with
+//! [`Versioned`](crate::Versioned).
+
+use std::borrow::Cow;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Stores `T` encoded with bincode. The stored values are unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SerdeStorable<T>(pub T);
+
+impl<T> SerdeStorable<T> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Storable for SerdeStorable<T> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        bincode::serialize(&self.0)
+            .expect("failed to serialize the value with bincode")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bincode::deserialize(&bytes).expect("failed to deserialize the value with bincode"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use dfinity_stable_structures::VectorMemory;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        owner: String,
+        amounts: Vec<u64>,
+        tags: BTreeMap<String, String>,
+    }
+
+    #[test]
+    fn should_store_serde_values() {
+        let order = Order {
+            owner: "alice".to_string(),
+            amounts: vec![1, 2, 3],
+            tags: [("side".to_string(), "buy".to_string())].into(),
+        };
+        let mut map = StableBTreeMap::<u64, SerdeStorable<Order>, _>::new(VectorMemory::default());
+        map.insert(1, SerdeStorable(order.clone()));
+
+        assert_eq!(map.get(&1).unwrap().into_inner(), order);
+    }
+}
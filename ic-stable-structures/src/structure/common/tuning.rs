@@ -0,0 +1,76 @@
+//! Knobs to tune the layout of the stable structures for the stored values.
+//!
+//! The dfinity `BTreeMap` derives the size of its nodes from the bounds of the keys and values:
+//! - for bounded types, each node reserves room for `max_size` bytes for every key and value,
+//!   so a too large bound wastes memory and increases the data read and written for each node;
+//! - for unbounded types, keys and values are stored in pages with overflows, which is slower
+//!   than a bounded layout for tiny values.
+//!
+//! Use [`BoundedStorable`] to give a tight bound to small values, and
+//! [`StableChunkedMap`](crate::StableChunkedMap) to split values of several KB into chunks.
+//! The `stable_storage_benchmark` compares the layouts for different value sizes.
+
+use std::borrow::Cow;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+/// Overrides the bound of `T` with `MAX_SIZE` bytes, to tune the node size of the
+/// structures storing it.
+///
+/// Storing a value whose serialized form exceeds `MAX_SIZE` bytes panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct BoundedStorable<T, const MAX_SIZE: u32>(pub T);
+
+impl<T, const MAX_SIZE: u32> BoundedStorable<T, MAX_SIZE> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Storable, const MAX_SIZE: u32> Storable for BoundedStorable<T, MAX_SIZE> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = self.0.to_bytes();
+        assert!(
+            bytes.len() <= MAX_SIZE as usize,
+            "value size {} exceeds the bound of {MAX_SIZE} bytes",
+            bytes.len()
+        );
+        bytes
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(T::from_bytes(bytes))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::{BTreeMapStructure, StableBTreeMap};
+
+    #[test]
+    fn bounded_storable_roundtrip() {
+        let mut map = StableBTreeMap::<u64, BoundedStorable<StringValue, 16>, _>::new(
+            VectorMemory::default(),
+        );
+
+        map.insert(1, BoundedStorable(str_val(16)));
+        assert_eq!(map.get(&1).unwrap().into_inner(), str_val(16));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bounded_storable_should_panic_on_larger_values() {
+        BoundedStorable::<_, 16>(str_val(17)).to_bytes();
+    }
+}
@@ -0,0 +1,366 @@
+//! Values stored with the version of their schema, upgraded to the current schema when read.
+//!
+//! Adding a field to a stored struct changes its serialized form, so the values written by the
+//! previous release can't be decoded anymore. Wrapping the value in [`Versioned`] stores the
+//! version of its schema next to it, and the values of older versions are upgraded by the
+//! [`Migrations`] of the type when they are read:
+//!
+//! ```ignore
+//! impl Migrate for UserV3 {
+//!     const VERSION: u16 = 3;
+//!
+//!     fn migrations() -> Migrations<Self> {
+//!         Migrations::new()
+//!             .with_type::<UserV1, UserV2>(1)
+//!             .with_type::<UserV2, UserV3>(2)
+//!     }
+//! }
+//!
+//! let users = StableBTreeMap::<Principal, Versioned<UserV3>, _>::new(memory);
+//! ```
+//!
+//! The upgraded values are not written back by the reads; [`migrate_btreemap`] rewrites them in
+//! batches, e.g. from a timer, so the migrations can be removed from a later release.
+//!
+//! For bounded types, the bound of the current schema must be large enough for the values of
+//! all the previous schemas, as the layout of the structures depends on it.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Bound as RangeBound, Deref};
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+
+type MigrationStep = Box<dyn Fn(Cow<[u8]>) -> Vec<u8>>;
+
+/// Upgrades of the serialized values of a type, from each previous schema version to the next.
+pub struct Migrations<T> {
+    steps: Vec<(u16, MigrationStep)>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for Migrations<T> {
+    fn default() -> Self {
+        Self {
+            steps: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Migrations<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the upgrade of the bytes of a value from `from_version` to `from_version + 1`.
+    pub fn with_step(
+        mut self,
+        from_version: u16,
+        step: impl Fn(Cow<[u8]>) -> Vec<u8> + 'static,
+    ) -> Self {
+        self.steps.push((from_version, Box::new(step)));
+        self
+    }
+
+    /// Adds the upgrade from `from_version` to `from_version + 1` by converting the value of
+    /// the previous schema `Old` into the value of the next schema `New`.
+    pub fn with_type<Old, New>(self, from_version: u16) -> Self
+    where
+        Old: Storable,
+        New: Storable + From<Old>,
+    {
+        self.with_step(from_version, |bytes| {
+            New::from(Old::from_bytes(bytes)).to_bytes().into_owned()
+        })
+    }
+
+    /// Upgrades the bytes of a value from `version` to `current_version`.
+    ///
+    /// Panics if an upgrade is missing.
+    fn upgrade<'a>(
+        &self,
+        mut version: u16,
+        current_version: u16,
+        mut bytes: Cow<'a, [u8]>,
+    ) -> Cow<'a, [u8]> {
+        while version < current_version {
+            let (_, step) = self
+                .steps
+                .iter()
+                .find(|(from_version, _)| *from_version == version)
+                .unwrap_or_else(|| panic!("no migration from schema version {version}"));
+            bytes = Cow::Owned(step(bytes));
+            version += 1;
+        }
+        bytes
+    }
+}
+
+/// A type whose schema is versioned.
+pub trait Migrate: Storable + Sized {
+    /// Version of the current schema, written with every stored value
+    const VERSION: u16;
+
+    /// Upgrades from the previous schema versions.
+    fn migrations() -> Migrations<Self>;
+}
+
+/// A value stored with the version of its schema.
+///
+/// Reading a value of an older version upgrades it with the [`Migrations`] of `T`, and panics
+/// if the version is newer than the current one, e.g. after a rollback of the canister.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned<T> {
+    value: T,
+    stored_version: u16,
+}
+
+impl<T: Migrate> Versioned<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            stored_version: T::VERSION,
+        }
+    }
+
+    /// Version of the schema the value was stored with
+    pub fn stored_version(&self) -> u16 {
+        self.stored_version
+    }
+
+    /// Returns `true` if the value was stored with a previous schema and not rewritten since.
+    pub fn is_outdated(&self) -> bool {
+        self.stored_version < T::VERSION
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Migrate> From<T> for Versioned<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T> Deref for Versioned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Migrate> Storable for Versioned<T> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let value = self.value.to_bytes();
+        let mut buf = Vec::with_capacity(size_of::<u16>() + value.len());
+        buf.extend_from_slice(&T::VERSION.to_le_bytes());
+        buf.extend_from_slice(&value);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let stored_version =
+            u16::from_le_bytes(bytes[..2].try_into().expect("version: expected 2 bytes"));
+        assert!(
+            stored_version <= T::VERSION,
+            "schema version {stored_version} is newer than the current version {}",
+            T::VERSION
+        );
+
+        let value = if stored_version == T::VERSION {
+            T::from_bytes(bytes[2..].to_vec().into())
+        } else {
+            let bytes =
+                T::migrations().upgrade(stored_version, T::VERSION, Cow::Borrowed(&bytes[2..]));
+            T::from_bytes(bytes.into_owned().into())
+        };
+
+        Self {
+            value,
+            stored_version,
+        }
+    }
+
+    const BOUND: Bound = match T::BOUND {
+        Bound::Bounded { max_size, .. } => Bound::Bounded {
+            max_size: max_size + size_of::<u16>() as u32,
+            is_fixed_size: false,
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    };
+}
+
+/// Rewrites the outdated values of at most `max_entries` entries of the map with the current
+/// schema, starting after the key `after`, or from the first key if `None`.
+///
+/// Returns the key to pass to the next call, or `None` when all the entries are migrated.
+pub fn migrate_btreemap<K, T, M>(
+    map: &mut StableBTreeMap<K, Versioned<T>, M>,
+    after: Option<&K>,
+    max_entries: usize,
+) -> Option<K>
+where
+    K: Storable + Ord + Clone,
+    T: Migrate,
+    M: Memory,
+{
+    if max_entries == 0 {
+        return after.cloned();
+    }
+
+    let start = match after {
+        Some(key) => RangeBound::Excluded(key.clone()),
+        None => RangeBound::Unbounded,
+    };
+    let batch: Vec<_> = map
+        .range((start, RangeBound::Unbounded))
+        .take(max_entries)
+        .collect();
+
+    let next = match batch.last() {
+        Some((key, _)) if batch.len() == max_entries => Some(key.clone()),
+        _ => None,
+    };
+    for (key, value) in batch {
+        if value.is_outdated() {
+            map.insert(key, Versioned::new(value.into_inner()));
+        }
+    }
+
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct UserV1 {
+        name: String,
+    }
+
+    impl Storable for UserV1 {
+        fn to_bytes(&self) -> Cow<[u8]> {
+            self.name.as_bytes().to_vec().into()
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Self {
+                name: String::from_utf8(bytes.into_owned()).unwrap(),
+            }
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    impl Migrate for UserV1 {
+        const VERSION: u16 = 1;
+
+        fn migrations() -> Migrations<Self> {
+            Migrations::new()
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct UserV2 {
+        age: u8,
+        name: String,
+    }
+
+    impl From<UserV1> for UserV2 {
+        fn from(user: UserV1) -> Self {
+            Self {
+                age: 0,
+                name: user.name,
+            }
+        }
+    }
+
+    impl Storable for UserV2 {
+        fn to_bytes(&self) -> Cow<[u8]> {
+            let mut buf = vec![self.age];
+            buf.extend_from_slice(self.name.as_bytes());
+            buf.into()
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Self {
+                age: bytes[0],
+                name: String::from_utf8(bytes[1..].to_vec()).unwrap(),
+            }
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    impl Migrate for UserV2 {
+        const VERSION: u16 = 2;
+
+        fn migrations() -> Migrations<Self> {
+            Migrations::new().with_type::<UserV1, UserV2>(1)
+        }
+    }
+
+    fn user_v1(name: &str) -> Versioned<UserV1> {
+        Versioned::new(UserV1 {
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn should_upgrade_on_read() {
+        let bytes = user_v1("alice").to_bytes().into_owned();
+
+        let user = Versioned::<UserV2>::from_bytes(bytes.into());
+        assert!(user.is_outdated());
+        assert_eq!(user.stored_version(), 1);
+        assert_eq!(user.name, "alice");
+        assert_eq!(user.age, 0);
+
+        let user = Versioned::<UserV2>::from_bytes(Versioned::new(user.into_inner()).to_bytes());
+        assert!(!user.is_outdated());
+    }
+
+    #[test]
+    #[should_panic(expected = "newer than the current version")]
+    fn should_panic_on_newer_version() {
+        let user = Versioned::new(UserV2 {
+            age: 30,
+            name: "bob".to_string(),
+        });
+        Versioned::<UserV1>::from_bytes(user.to_bytes());
+    }
+
+    #[test]
+    fn should_migrate_map_in_batches() {
+        let memory = VectorMemory::default();
+        let mut map = StableBTreeMap::<u64, Versioned<UserV1>, _>::new(memory.clone());
+        for key in 0..5 {
+            map.insert(key, user_v1(&key.to_string()));
+        }
+
+        let mut map = StableBTreeMap::<u64, Versioned<UserV2>, _>::new(memory);
+        let next = migrate_btreemap(&mut map, None, 3);
+        assert_eq!(next, Some(2));
+        assert!(!map.get(&2).unwrap().is_outdated());
+        assert!(map.get(&3).unwrap().is_outdated());
+
+        assert_eq!(migrate_btreemap(&mut map, next.as_ref(), 3), None);
+        assert!(map.iter().all(|(_, user)| !user.is_outdated()));
+    }
+}
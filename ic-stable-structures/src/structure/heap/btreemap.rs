@@ -0,0 +1,116 @@
+use std::collections::{btree_map, BTreeMap};
+use std::ops::{Bound, RangeBounds};
+
+use crate::structure::{BTreeMapStructure, IterableSortedMapStructure};
+
+/// Heap counterpart of [`StableBTreeMap`](crate::StableBTreeMap).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapBTreeMap<K, V>(BTreeMap<K, V>);
+
+impl<K, V> Default for HeapBTreeMap<K, V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> HeapBTreeMap<K, V> {
+    /// Create new empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> BTreeMapStructure<K, V> for HeapBTreeMap<K, V> {
+    fn get(&self, key: &K) -> Option<V> {
+        self.0.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.0.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.0.remove(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.0.contains_key(key)
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.0
+            .first_key_value()
+            .map(|(key, value)| (key.clone(), value.clone()))
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.0
+            .last_key_value()
+            .map(|(key, value)| (key.clone(), value.clone()))
+    }
+
+    fn pop_first(&mut self) -> Option<(K, V)> {
+        self.0.pop_first()
+    }
+
+    fn pop_last(&mut self) -> Option<(K, V)> {
+        self.0.pop_last()
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> IterableSortedMapStructure<K, V> for HeapBTreeMap<K, V> {
+    type Iterator<'a> = HeapBTreeMapIter<'a, K, V> where Self: 'a;
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        HeapBTreeMapIter(Some(self.0.range(..)))
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        // `BTreeMap::range` panics on inverted ranges, while the stable map returns no entries
+        let is_empty = match (key_range.start_bound(), key_range.end_bound()) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start >= end,
+            _ => false,
+        };
+        match is_empty {
+            true => HeapBTreeMapIter(None),
+            false => HeapBTreeMapIter(Some(self.0.range(key_range))),
+        }
+    }
+
+    fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
+        match self.0.range(..bound).next_back() {
+            Some((key, _)) => HeapBTreeMapIter(Some(self.0.range(key..))),
+            None => HeapBTreeMapIter(None),
+        }
+    }
+}
+
+/// Iterator over the entries of [`HeapBTreeMap`], cloning the keys and values.
+pub struct HeapBTreeMapIter<'a, K, V>(Option<btree_map::Range<'a, K, V>>);
+
+impl<K: Clone, V: Clone> Iterator for HeapBTreeMapIter<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .as_mut()?
+            .next()
+            .map(|(key, value)| (key.clone(), value.clone()))
+    }
+}
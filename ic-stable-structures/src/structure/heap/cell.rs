@@ -0,0 +1,24 @@
+use crate::structure::CellStructure;
+use crate::Result;
+
+/// Heap counterpart of [`StableCell`](crate::StableCell).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HeapCell<T>(T);
+
+impl<T> HeapCell<T> {
+    /// Create new cell with the given value.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> CellStructure<T> for HeapCell<T> {
+    fn get(&self) -> &T {
+        &self.0
+    }
+
+    fn set(&mut self, value: T) -> Result<()> {
+        self.0 = value;
+        Ok(())
+    }
+}
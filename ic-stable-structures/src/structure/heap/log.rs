@@ -0,0 +1,47 @@
+use crate::structure::LogStructure;
+use crate::Result;
+
+/// Heap counterpart of [`StableLog`](crate::StableLog).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapLog<T>(Vec<T>);
+
+impl<T> Default for HeapLog<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: Clone> HeapLog<T> {
+    /// Create new empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns iterator over the values of the log.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.0.iter().cloned()
+    }
+}
+
+impl<T: Clone> LogStructure<T> for HeapLog<T> {
+    fn get(&self, index: u64) -> Option<T> {
+        self.0.get(usize::try_from(index).ok()?).cloned()
+    }
+
+    fn append(&mut self, value: T) -> Result<u64> {
+        self.0.push(value);
+        Ok(self.0.len() as u64 - 1)
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
@@ -0,0 +1,154 @@
+//! Heap-backed counterparts of the stable structures.
+//!
+//! They implement the same traits as the stable structures, so code written against the traits
+//! can use either of them, e.g. the heap structures in unit tests, or for data which doesn't need
+//! to survive the upgrades. The heap structures don't require the values to be `Storable`.
+
+mod btreemap;
+mod cell;
+mod log;
+mod multimap;
+mod vec;
+
+pub use btreemap::{HeapBTreeMap, HeapBTreeMapIter};
+pub use cell::HeapCell;
+pub use log::HeapLog;
+pub use multimap::{HeapMultimap, HeapMultimapIter, HeapMultimapRangeIter};
+pub use vec::HeapVec;
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::structure::{
+        BTreeMapStructure, IterableSortedMapStructure, LogStructure, MultimapStructure,
+        VecStructure,
+    };
+    use crate::{StableBTreeMap, StableLog, StableMultimap, StableVec};
+
+    fn fill_map<M>(map: &mut M) -> Vec<Vec<(u32, u64)>>
+    where
+        M: BTreeMapStructure<u32, u64> + IterableSortedMapStructure<u32, u64>,
+    {
+        for key in [5, 1, 3, 9, 7] {
+            map.insert(key, key as u64 * 10);
+        }
+        map.remove(&9);
+
+        vec![
+            map.iter().collect(),
+            map.range(2..=5).collect(),
+            // inverted range
+            map.range((Bound::Included(5), Bound::Excluded(3)))
+                .collect(),
+            map.iter_upper_bound(&4).collect(),
+            map.iter_upper_bound(&1).collect(),
+            map.iter_from(&4).collect(),
+            map.first_key_value().into_iter().collect(),
+            map.last_key_value().into_iter().collect(),
+            map.pop_first().into_iter().collect(),
+            map.pop_last().into_iter().collect(),
+            map.iter().collect(),
+        ]
+    }
+
+    fn fill_multimap<M: MultimapStructure<u32, u32, u64>>(map: &mut M) -> Vec<(u32, u32, u64)> {
+        for (first_key, second_key) in [(1, 2), (1, 1), (2, 1), (3, 1), (3, 2)] {
+            map.insert(
+                &first_key,
+                &second_key,
+                first_key as u64 + second_key as u64,
+            );
+        }
+        assert_eq!(map.insert(&1, &1, 0), Some(2));
+        assert_eq!(map.remove(&2, &1), Some(3));
+        assert!(map.remove_partial(&3));
+        assert_eq!(map.range(&1).count(), 2);
+        assert_eq!(map.len(), 2);
+
+        map.iter().collect()
+    }
+
+    fn fill_vec<V: VecStructure<u64>>(vec: &mut V) -> Vec<Option<u64>> {
+        for item in 0..5 {
+            vec.push(&item).unwrap();
+        }
+        vec.set(1, &10).unwrap();
+        vec.pop();
+
+        (0..5).map(|index| vec.get(index)).collect()
+    }
+
+    fn fill_log<L: LogStructure<u64>>(log: &mut L) -> Vec<Option<u64>> {
+        for item in 0..3 {
+            assert_eq!(log.append(item * 2).unwrap(), item);
+        }
+
+        (0..4).map(|index| log.get(index)).collect()
+    }
+
+    #[test]
+    fn should_behave_as_stable_structures() {
+        assert_eq!(
+            fill_map(&mut HeapBTreeMap::new()),
+            fill_map(&mut StableBTreeMap::new(VectorMemory::default()))
+        );
+        assert_eq!(
+            fill_multimap(&mut HeapMultimap::new()),
+            fill_multimap(&mut StableMultimap::new(VectorMemory::default()))
+        );
+        assert_eq!(
+            fill_vec(&mut HeapVec::new()),
+            fill_vec(&mut StableVec::new(VectorMemory::default()).unwrap())
+        );
+        assert_eq!(
+            fill_log(&mut HeapLog::new()),
+            fill_log(
+                &mut StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn should_read_log_ranges() {
+        let mut log = StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        fill_log(&mut log);
+
+        assert_eq!(log.get_range(1..), vec![2, 4]);
+        assert_eq!(log.get_range(..=1), vec![0, 2]);
+        assert_eq!(log.get_range(2..10), vec![4]);
+        assert_eq!(log.tail(2), vec![2, 4]);
+        assert_eq!(log.tail(5), vec![0, 2, 4]);
+
+        let mut heap_log = HeapLog::new();
+        fill_log(&mut heap_log);
+        assert_eq!(heap_log.get_range(..), log.get_range(..));
+    }
+
+    #[test]
+    fn should_clear_heap_structures() {
+        let mut map = HeapBTreeMap::new();
+        fill_map(&mut map);
+        map.clear();
+        assert!(map.is_empty());
+
+        let mut multimap = HeapMultimap::new();
+        fill_multimap(&mut multimap);
+        multimap.clear();
+        assert!(multimap.is_empty());
+        assert_eq!(multimap.iter().count(), 0);
+
+        let mut vec = HeapVec::new();
+        fill_vec(&mut vec);
+        vec.clear().unwrap();
+        assert!(vec.is_empty());
+
+        let mut log = HeapLog::new();
+        fill_log(&mut log);
+        log.clear();
+        assert!(log.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{btree_map, BTreeMap};
+
+use crate::structure::MultimapStructure;
+
+/// Heap counterpart of [`StableMultimap`](crate::StableMultimap).
+///
+/// Unlike the stable multimap, the second key doesn't need to be [`Bounded`](crate::Bounded).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapMultimap<K1, K2, V> {
+    inner: BTreeMap<K1, BTreeMap<K2, V>>,
+    len: u64,
+}
+
+impl<K1, K2, V> Default for HeapMultimap<K1, K2, V> {
+    fn default() -> Self {
+        Self {
+            inner: BTreeMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<K1, K2, V> HeapMultimap<K1, K2, V>
+where
+    K1: Ord + Clone,
+    K2: Ord + Clone,
+    V: Clone,
+{
+    /// Create new empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K1, K2, V> MultimapStructure<K1, K2, V> for HeapMultimap<K1, K2, V>
+where
+    K1: Ord + Clone,
+    K2: Ord + Clone,
+    V: Clone,
+{
+    type Iterator<'a> = HeapMultimapIter<'a, K1, K2, V> where Self: 'a;
+
+    type RangeIterator<'a> = HeapMultimapRangeIter<'a, K2, V> where Self: 'a;
+
+    fn get(&self, first_key: &K1, second_key: &K2) -> Option<V> {
+        self.inner.get(first_key)?.get(second_key).cloned()
+    }
+
+    fn insert(&mut self, first_key: &K1, second_key: &K2, value: V) -> Option<V> {
+        let previous = self
+            .inner
+            .entry(first_key.clone())
+            .or_default()
+            .insert(second_key.clone(), value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    fn remove(&mut self, first_key: &K1, second_key: &K2) -> Option<V> {
+        let values = self.inner.get_mut(first_key)?;
+        let value = values.remove(second_key)?;
+        if values.is_empty() {
+            self.inner.remove(first_key);
+        }
+        self.len -= 1;
+        Some(value)
+    }
+
+    fn remove_partial(&mut self, first_key: &K1) -> bool {
+        match self.inner.remove(first_key) {
+            Some(values) => {
+                self.len -= values.len() as u64;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn range(&self, first_key: &K1) -> Self::RangeIterator<'_> {
+        HeapMultimapRangeIter(self.inner.get(first_key).map(BTreeMap::iter))
+    }
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        HeapMultimapIter {
+            outer: self.inner.iter(),
+            current: None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.len = 0;
+    }
+}
+
+/// Iterator over the entries of [`HeapMultimap`], cloning the keys and values.
+pub struct HeapMultimapIter<'a, K1, K2, V> {
+    outer: btree_map::Iter<'a, K1, BTreeMap<K2, V>>,
+    current: Option<(&'a K1, btree_map::Iter<'a, K2, V>)>,
+}
+
+impl<K1: Clone, K2: Clone, V: Clone> Iterator for HeapMultimapIter<'_, K1, K2, V> {
+    type Item = (K1, K2, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((first_key, values)) = &mut self.current {
+                if let Some((second_key, value)) = values.next() {
+                    return Some(((*first_key).clone(), second_key.clone(), value.clone()));
+                }
+            }
+
+            let (first_key, values) = self.outer.next()?;
+            self.current = Some((first_key, values.iter()));
+        }
+    }
+}
+
+/// Iterator over the entries of [`HeapMultimap`] with the same first key.
+pub struct HeapMultimapRangeIter<'a, K2, V>(Option<btree_map::Iter<'a, K2, V>>);
+
+impl<K2: Clone, V: Clone> Iterator for HeapMultimapRangeIter<'_, K2, V> {
+    type Item = (K2, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .as_mut()?
+            .next()
+            .map(|(key, value)| (key.clone(), value.clone()))
+    }
+}
@@ -0,0 +1,57 @@
+use crate::structure::VecStructure;
+use crate::Result;
+
+/// Heap counterpart of [`StableVec`](crate::StableVec).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapVec<T>(Vec<T>);
+
+impl<T> Default for HeapVec<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: Clone> HeapVec<T> {
+    /// Create new empty vector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns iterator over the elements in the vector
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.0.iter().cloned()
+    }
+}
+
+impl<T: Clone> VecStructure<T> for HeapVec<T> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.0.clear();
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn set(&mut self, index: u64, item: &T) -> Result<()> {
+        self.0[index as usize] = item.clone();
+        Ok(())
+    }
+
+    fn get(&self, index: u64) -> Option<T> {
+        self.0.get(usize::try_from(index).ok()?).cloned()
+    }
+
+    fn push(&mut self, item: &T) -> Result<()> {
+        self.0.push(item.clone());
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+}
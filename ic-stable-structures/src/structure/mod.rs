@@ -1,13 +1,15 @@
-use std::ops::RangeBounds;
+use std::ops::{Bound, RangeBounds};
 
 use crate::Result;
 
 mod cache;
 mod common;
+mod heap;
 mod stable_storage;
 
 pub use cache::*;
 pub use common::*;
+pub use heap::*;
 pub use stable_storage::*;
 
 pub trait BTreeMapStructure<K, V> {
@@ -36,6 +38,36 @@ pub trait BTreeMapStructure<K, V> {
     /// Returns the last key-value pair in the map.
     fn last_key_value(&self) -> Option<(K, V)>;
 
+    /// Inserts the entries, in the order of their keys.
+    ///
+    /// Inserting sorted keys visits the nodes of the stable BTree in order, so the consecutive
+    /// inserts touch the same pages, which is cheaper for initial loads and migrations. If several
+    /// entries have the same key, the last one is kept.
+    fn insert_many(&mut self, entries: impl IntoIterator<Item = (K, V)>)
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+
+    /// Removes and returns the first key-value pair in the map.
+    fn pop_first(&mut self) -> Option<(K, V)> {
+        let (key, _) = self.first_key_value()?;
+        let value = self.remove(&key)?;
+        Some((key, value))
+    }
+
+    /// Removes and returns the last key-value pair in the map.
+    fn pop_last(&mut self) -> Option<(K, V)> {
+        let (key, _) = self.last_key_value()?;
+        let value = self.remove(&key)?;
+        Some((key, value))
+    }
+
     /// Count of items in the map.
     fn len(&self) -> u64;
 
@@ -63,6 +95,15 @@ pub trait IterableSortedMapStructure<K, V> {
     /// Returns an iterator pointing to the first element below the given bound.
     /// Returns an empty iterator if there are no keys below the given bound.
     fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_>;
+
+    /// Returns an iterator over the entries in the map, in key order, starting from the given key
+    /// included.
+    fn iter_from(&self, key: &K) -> Self::Iterator<'_>
+    where
+        K: Clone,
+    {
+        self.range(key.clone()..)
+    }
 }
 
 pub trait CellStructure<T> {
@@ -71,6 +112,17 @@ pub trait CellStructure<T> {
 
     /// Updates value in stable memory.
     fn set(&mut self, value: T) -> Result<()>;
+
+    /// Updates value in stable memory with the closure, and returns the new value.
+    fn update(&mut self, f: impl FnOnce(&mut T)) -> Result<&T>
+    where
+        T: Clone,
+    {
+        let mut value = self.get().clone();
+        f(&mut value);
+        self.set(value)?;
+        Ok(self.get())
+    }
 }
 
 pub trait LogStructure<T> {
@@ -88,6 +140,28 @@ pub trait LogStructure<T> {
 
     /// Remove all items from the log.
     fn clear(&mut self);
+
+    /// Returns the values with the indices in `range`, clamped to the length of the log.
+    fn get_range(&self, range: impl RangeBounds<u64>) -> Vec<T> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => u64::MAX,
+        }
+        .min(self.len());
+
+        (start..end).filter_map(|index| self.get(index)).collect()
+    }
+
+    /// Returns the last `count` values of the log, oldest first.
+    fn tail(&self, count: u64) -> Vec<T> {
+        self.get_range(self.len().saturating_sub(count)..)
+    }
 }
 
 pub trait MultimapStructure<K1, K2, V> {
@@ -128,7 +202,10 @@ pub trait MultimapStructure<K1, K2, V> {
     fn remove_partial(&mut self, first_key: &K1) -> bool;
 
     /// Items count.
-    fn len(&self) -> usize;
+    ///
+    /// Breaking change: this used to return a `usize`, which truncated the count of stable
+    /// multimaps on 32 bits targets. It is now a `u64`, like the `len` of the other structures.
+    fn len(&self) -> u64;
 
     /// Is map empty.
     fn is_empty(&self) -> bool;
@@ -0,0 +1,297 @@
+use dfinity_stable_structures::Memory;
+
+use crate::stats::WASM_PAGE_SIZE;
+use crate::{Error, MemoryStats, MemoryUsage, Result};
+
+const MAGIC: [u8; 3] = *b"BMP";
+const LAYOUT_VERSION: u8 = 1;
+
+const LEN_OFFSET: u64 = 8;
+const COUNT_OFFSET: u64 = 16;
+/// Offset of the words of the bitmap, after the magic, the version, the length and the count
+const WORDS_OFFSET: u64 = 24;
+const WORD_BITS: u64 = u64::BITS as u64;
+
+/// Words read at once by the scans of the bitmap
+const SCAN_WORDS: u64 = 512;
+
+/// Set of integers stored as a bitmap in stable memory, one bit per integer.
+///
+/// The bitmap grows to the largest integer set, so it suits dense sets of small integers, e.g.
+/// the processed block heights or the claimed indices of an airdrop. The number of set bits is
+/// maintained, and [`StableBitmap::rank`] and the iteration scan the words of the bitmap.
+pub struct StableBitmap<M: Memory> {
+    memory: M,
+    len: u64,
+    count: u64,
+}
+
+impl<M: Memory> StableBitmap<M> {
+    /// Creates the bitmap in the memory, or loads it if the memory already contains a bitmap.
+    pub fn new(memory: M) -> Result<Self> {
+        if memory.size() == 0 {
+            if memory.grow(1) < 0 {
+                return Err(Error::OutOfStableMemory);
+            }
+            let bitmap = Self {
+                memory,
+                len: 0,
+                count: 0,
+            };
+            let mut header = [0; WORDS_OFFSET as usize];
+            header[..MAGIC.len()].copy_from_slice(&MAGIC);
+            header[MAGIC.len()] = LAYOUT_VERSION;
+            bitmap.memory.write(0, &header);
+            return Ok(bitmap);
+        }
+
+        let mut header = [0; WORDS_OFFSET as usize];
+        memory.read(0, &mut header);
+        let magic: [u8; 3] = header[..MAGIC.len()].try_into().expect("magic: 3 bytes");
+        if magic != MAGIC {
+            return Err(Error::BadMagic {
+                actual: magic,
+                expected: MAGIC,
+            });
+        }
+        if header[MAGIC.len()] != LAYOUT_VERSION {
+            return Err(Error::IncompatibleVersions);
+        }
+
+        Ok(Self {
+            memory,
+            len: read_u64(&header, LEN_OFFSET),
+            count: read_u64(&header, COUNT_OFFSET),
+        })
+    }
+
+    /// Number of bits of the bitmap: one more than the largest integer ever set
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if no bit was ever set.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of set bits
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true if the bit is set.
+    pub fn get(&self, index: u64) -> bool {
+        index < self.len && self.read_word(index / WORD_BITS) & bit_mask(index) != 0
+    }
+
+    /// Sets or clears the bit, growing the bitmap if needed, and returns its previous value.
+    pub fn set(&mut self, index: u64, value: bool) -> Result<bool> {
+        if index >= self.len {
+            if !value {
+                return Ok(false);
+            }
+            self.grow(index + 1)?;
+        }
+
+        let word_index = index / WORD_BITS;
+        let word = self.read_word(word_index);
+        let previous = word & bit_mask(index) != 0;
+        if previous != value {
+            self.write_word(word_index, word ^ bit_mask(index));
+            if value {
+                self.count += 1;
+            } else {
+                self.count -= 1;
+            }
+            self.memory.write(COUNT_OFFSET, &self.count.to_le_bytes());
+        }
+        Ok(previous)
+    }
+
+    /// Number of set bits lower than `index`
+    pub fn rank(&self, index: u64) -> u64 {
+        let index = index.min(self.len);
+        let full_words = index / WORD_BITS;
+        let mut rank = self
+            .words(0, full_words)
+            .map(|(_, word)| u64::from(word.count_ones()))
+            .sum();
+        if index % WORD_BITS != 0 {
+            let low_bits = bit_mask(index) - 1;
+            rank += u64::from((self.read_word(full_words) & low_bits).count_ones());
+        }
+        rank
+    }
+
+    /// Returns an iterator over the set bits, in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.words(0, self.len.div_ceil(WORD_BITS))
+            .flat_map(|(word_index, mut word)| {
+                std::iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+                    let bit = u64::from(word.trailing_zeros());
+                    word &= word - 1;
+                    Some(word_index * WORD_BITS + bit)
+                })
+            })
+    }
+
+    /// Clears all the bits. The memory is kept for the next bits.
+    pub fn clear(&mut self) {
+        let zeroes = vec![0; (SCAN_WORDS * 8) as usize];
+        let words = self.len.div_ceil(WORD_BITS);
+        for start in (0..words).step_by(SCAN_WORDS as usize) {
+            let end = (start + SCAN_WORDS).min(words);
+            self.memory.write(
+                WORDS_OFFSET + start * 8,
+                &zeroes[..((end - start) * 8) as usize],
+            );
+        }
+
+        self.len = 0;
+        self.count = 0;
+        self.write_len_and_count();
+    }
+
+    /// Returns the words in `start..end` with their index, reading them by batches.
+    fn words(&self, start: u64, end: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        (start..end)
+            .step_by(SCAN_WORDS as usize)
+            .flat_map(move |batch_start| {
+                let batch_end = (batch_start + SCAN_WORDS).min(end);
+                let mut bytes = vec![0; ((batch_end - batch_start) * 8) as usize];
+                self.memory.read(WORDS_OFFSET + batch_start * 8, &mut bytes);
+                bytes
+                    .chunks_exact(8)
+                    .map(|word| u64::from_le_bytes(word.try_into().expect("word: 8 bytes")))
+                    .enumerate()
+                    .map(move |(offset, word)| (batch_start + offset as u64, word))
+                    .collect::<Vec<_>>()
+            })
+    }
+
+    fn grow(&mut self, len: u64) -> Result<()> {
+        let bytes = WORDS_OFFSET + len.div_ceil(WORD_BITS) * 8;
+        let pages = bytes.div_ceil(WASM_PAGE_SIZE);
+        if pages > self.memory.size() && self.memory.grow(pages - self.memory.size()) < 0 {
+            return Err(Error::OutOfStableMemory);
+        }
+
+        self.len = len;
+        self.write_len_and_count();
+        Ok(())
+    }
+
+    fn read_word(&self, word_index: u64) -> u64 {
+        let mut word = [0; 8];
+        self.memory.read(WORDS_OFFSET + word_index * 8, &mut word);
+        u64::from_le_bytes(word)
+    }
+
+    fn write_word(&self, word_index: u64, word: u64) {
+        self.memory
+            .write(WORDS_OFFSET + word_index * 8, &word.to_le_bytes());
+    }
+
+    fn write_len_and_count(&self) {
+        self.memory.write(LEN_OFFSET, &self.len.to_le_bytes());
+        self.memory.write(COUNT_OFFSET, &self.count.to_le_bytes());
+    }
+}
+
+impl<M: Memory> MemoryUsage for StableBitmap<M> {
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.count,
+            bytes: self.len.div_ceil(WORD_BITS) * 8,
+        }
+    }
+}
+
+fn bit_mask(index: u64) -> u64 {
+    1 << (index % WORD_BITS)
+}
+
+fn read_u64(bytes: &[u8], offset: u64) -> u64 {
+    let offset = offset as usize;
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_set_and_get_bits() {
+        let mut bitmap = StableBitmap::new(VectorMemory::default()).unwrap();
+        assert!(bitmap.is_empty());
+
+        assert!(!bitmap.set(3, true).unwrap());
+        assert!(bitmap.set(3, true).unwrap());
+        assert!(!bitmap.set(100_000, true).unwrap());
+        assert!(!bitmap.set(200_000, false).unwrap());
+
+        assert!(bitmap.get(3));
+        assert!(!bitmap.get(4));
+        assert!(bitmap.get(100_000));
+        assert!(!bitmap.get(200_000));
+        assert_eq!(bitmap.len(), 100_001);
+        assert_eq!(bitmap.count(), 2);
+
+        assert!(bitmap.set(3, false).unwrap());
+        assert_eq!(bitmap.count(), 1);
+    }
+
+    #[test]
+    fn should_rank_and_iterate() {
+        let mut bitmap = StableBitmap::new(VectorMemory::default()).unwrap();
+        let bits = [0, 1, 63, 64, 65, 127, 40_000, 40_063];
+        for bit in bits {
+            bitmap.set(bit, true).unwrap();
+        }
+
+        assert_eq!(bitmap.iter().collect::<Vec<_>>(), bits);
+        assert_eq!(bitmap.rank(0), 0);
+        assert_eq!(bitmap.rank(1), 1);
+        assert_eq!(bitmap.rank(64), 3);
+        assert_eq!(bitmap.rank(66), 5);
+        assert_eq!(bitmap.rank(40_063), 7);
+        assert_eq!(bitmap.rank(u64::MAX), 8);
+    }
+
+    #[test]
+    fn should_restore_and_clear() {
+        let memory = VectorMemory::default();
+        let mut bitmap = StableBitmap::new(memory.clone()).unwrap();
+        bitmap.set(10, true).unwrap();
+        bitmap.set(20, true).unwrap();
+
+        let mut bitmap = StableBitmap::new(memory.clone()).unwrap();
+        assert_eq!(bitmap.count(), 2);
+        assert_eq!(bitmap.len(), 21);
+        assert!(bitmap.get(20));
+
+        bitmap.clear();
+        assert!(bitmap.is_empty());
+        bitmap.set(5, true).unwrap();
+        assert_eq!(bitmap.iter().collect::<Vec<_>>(), vec![5]);
+
+        let bitmap = StableBitmap::new(memory).unwrap();
+        assert_eq!(bitmap.iter().collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn should_reject_other_memory_content() {
+        let memory = VectorMemory::default();
+        crate::StableCell::new(memory.clone(), 1u64).unwrap();
+        assert!(matches!(
+            StableBitmap::new(memory),
+            Err(Error::BadMagic { .. })
+        ));
+    }
+}
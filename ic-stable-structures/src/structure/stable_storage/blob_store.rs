@@ -0,0 +1,185 @@
+use dfinity_stable_structures::{Memory, Storable};
+
+use super::chunked_map::Chunk;
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+
+/// Stores byte blobs of any size in stable memory, e.g. the assets or documents served by a
+/// canister, splitting every blob into chunks of `CHUNK_SIZE` bytes.
+///
+/// The blobs are written by appends and read by ranges, so a large blob is uploaded and served in
+/// several calls without being loaded in full.
+pub struct StableBlobStore<K, M, const CHUNK_SIZE: u32>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    chunks: StableBTreeMap<(K, u32), Chunk<CHUNK_SIZE>, M>,
+    sizes: StableBTreeMap<K, u64, M>,
+}
+
+impl<K, M, const CHUNK_SIZE: u32> StableBlobStore<K, M, CHUNK_SIZE>
+where
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    /// Create new instance of the store, with the chunks and the sizes of the blobs in separate
+    /// memories.
+    pub fn new(chunks_memory: M, sizes_memory: M) -> Self {
+        assert!(CHUNK_SIZE > 0, "chunk size must be greater than zero");
+        Self {
+            chunks: StableBTreeMap::new(chunks_memory),
+            sizes: StableBTreeMap::new(sizes_memory),
+        }
+    }
+
+    /// Replaces the blob with the data.
+    pub fn put(&mut self, key: K, data: &[u8]) {
+        self.remove(&key);
+        self.append(key, data);
+    }
+
+    /// Appends the data to the blob, creating it if needed, and returns the new size of the blob.
+    pub fn append(&mut self, key: K, data: &[u8]) -> u64 {
+        let size = self.size(&key).unwrap_or_default();
+        let new_size = size + data.len() as u64;
+        let chunk_size = CHUNK_SIZE as usize;
+
+        let mut index = (size / u64::from(CHUNK_SIZE)) as u32;
+        let filled = (size % u64::from(CHUNK_SIZE)) as usize;
+        let mut rest = data;
+        if filled > 0 {
+            let chunk_key = (key.clone(), index);
+            let mut chunk = self
+                .chunks
+                .get(&chunk_key)
+                .expect("the last chunk is stored");
+            let appended = rest.len().min(chunk_size - filled);
+            chunk.0.extend_from_slice(&rest[..appended]);
+            self.chunks.insert(chunk_key, chunk);
+            rest = &rest[appended..];
+            index += 1;
+        }
+        for part in rest.chunks(chunk_size) {
+            self.chunks
+                .insert((key.clone(), index), Chunk(part.to_vec()));
+            index += 1;
+        }
+
+        self.sizes.insert(key, new_size);
+        new_size
+    }
+
+    /// Reads at most `len` bytes of the blob starting at `offset`.
+    ///
+    /// Returns an empty vector if `offset` is past the end of the blob, and `None` if there is no
+    /// blob.
+    pub fn read(&self, key: &K, offset: u64, len: u64) -> Option<Vec<u8>> {
+        let size = self.size(key)?;
+        let end = offset.saturating_add(len).min(size);
+        if offset >= end {
+            return Some(Vec::new());
+        }
+
+        let chunk_size = u64::from(CHUNK_SIZE);
+        let first_chunk = (offset / chunk_size) as u32;
+        let last_chunk = ((end - 1) / chunk_size) as u32;
+        let mut bytes = Vec::with_capacity((end - offset) as usize);
+        for ((_, index), chunk) in self
+            .chunks
+            .range((key.clone(), first_chunk)..=(key.clone(), last_chunk))
+        {
+            let chunk_start = u64::from(index) * chunk_size;
+            let from = (offset.max(chunk_start) - chunk_start) as usize;
+            let to = (end - chunk_start).min(chunk.0.len() as u64) as usize;
+            bytes.extend_from_slice(&chunk.0[from..to]);
+        }
+
+        Some(bytes)
+    }
+
+    /// Returns the size of the blob in bytes.
+    pub fn size(&self, key: &K) -> Option<u64> {
+        self.sizes.get(key)
+    }
+
+    /// Removes the blob. Returns `false` if there is no blob.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(size) = self.sizes.remove(key) else {
+            return false;
+        };
+
+        let chunks = size.div_ceil(u64::from(CHUNK_SIZE)) as u32;
+        for index in 0..chunks {
+            self.chunks.remove(&(key.clone(), index));
+        }
+        true
+    }
+
+    /// Returns an iterator over the keys of the blobs with their sizes, in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (K, u64)> + '_ {
+        self.sizes.iter()
+    }
+
+    /// Number of blobs
+    pub fn len(&self) -> u64 {
+        self.sizes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// Removes all the blobs.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.sizes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn new_store() -> StableBlobStore<u64, VectorMemory, 16> {
+        StableBlobStore::new(VectorMemory::default(), VectorMemory::default())
+    }
+
+    #[test]
+    fn should_append_and_read_ranges() {
+        let mut store = new_store();
+        let data: Vec<u8> = (0..100).collect();
+
+        assert_eq!(store.append(1, &data[..10]), 10);
+        assert_eq!(store.append(1, &data[10..13]), 13);
+        assert_eq!(store.append(1, &data[13..]), 100);
+        assert_eq!(store.size(&1), Some(100));
+
+        assert_eq!(store.read(&1, 0, 100).unwrap(), data);
+        assert_eq!(store.read(&1, 15, 20).unwrap(), data[15..35]);
+        assert_eq!(store.read(&1, 90, 50).unwrap(), data[90..]);
+        assert_eq!(store.read(&1, 100, 10).unwrap(), Vec::<u8>::new());
+        assert_eq!(store.read(&2, 0, 10), None);
+    }
+
+    #[test]
+    fn should_put_and_remove_blobs() {
+        let mut store = new_store();
+        store.put(1, &[1; 40]);
+        store.put(2, &[]);
+        store.put(1, &[2; 5]);
+
+        assert_eq!(store.read(&1, 0, 100).unwrap(), vec![2; 5]);
+        assert_eq!(store.read(&2, 0, 100).unwrap(), Vec::<u8>::new());
+        assert_eq!(store.iter().collect::<Vec<_>>(), vec![(1, 5), (2, 0)]);
+
+        assert!(store.remove(&1));
+        assert!(!store.remove(&1));
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.chunks.len(), 0);
+
+        store.clear();
+        assert!(store.is_empty());
+    }
+}
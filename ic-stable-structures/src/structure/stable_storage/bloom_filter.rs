@@ -0,0 +1,217 @@
+use std::marker::PhantomData;
+
+use dfinity_stable_structures::{Memory, Storable};
+use sha2::{Digest, Sha256};
+
+use crate::stats::WASM_PAGE_SIZE;
+use crate::{Error, Result};
+
+const MAGIC: [u8; 3] = *b"BLM";
+const LAYOUT_VERSION: u8 = 1;
+
+const HASHES_OFFSET: u64 = 4;
+const BITS_LEN_OFFSET: u64 = 8;
+const ITEMS_OFFSET: u64 = 16;
+/// Offset of the bits of the filter, after the magic, the version, the number of hashes, the
+/// number of bits and the number of inserted items
+const BITS_OFFSET: u64 = 24;
+
+/// Bloom filter in stable memory: a set which may report an item never inserted as present, with
+/// a bounded probability, but never misses an inserted item.
+///
+/// The filter is a cheap pre-check in front of a larger structure, e.g. to skip the lookup of a
+/// transaction hash which was never seen. Its size is fixed when it's created, from the expected
+/// number of items and the false positive rate at that number of items; the rate grows if more
+/// items are inserted.
+pub struct StableBloomFilter<T: Storable, M: Memory> {
+    memory: M,
+    bits_len: u64,
+    hashes: u32,
+    items: u64,
+    _item: PhantomData<T>,
+}
+
+impl<T: Storable, M: Memory> StableBloomFilter<T, M> {
+    /// Creates a filter for `expected_items` with the false positive rate, or loads the filter if
+    /// the memory already contains one, in which case the parameters are ignored.
+    ///
+    /// Panics if `expected_items` is zero or the rate is not in `(0, 1)`.
+    pub fn new(memory: M, expected_items: u64, false_positive_rate: f64) -> Result<Self> {
+        if memory.size() > 0 {
+            return Self::load(memory);
+        }
+
+        assert!(expected_items > 0, "expected_items must be positive");
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false_positive_rate must be between 0 and 1"
+        );
+        let ln2 = std::f64::consts::LN_2;
+        let bits_len =
+            (-(expected_items as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64;
+        let hashes = ((bits_len as f64 / expected_items as f64) * ln2)
+            .round()
+            .max(1.0) as u32;
+
+        let pages = (BITS_OFFSET + bits_len.div_ceil(8)).div_ceil(WASM_PAGE_SIZE);
+        if memory.grow(pages) < 0 {
+            return Err(Error::OutOfStableMemory);
+        }
+
+        let filter = Self {
+            memory,
+            bits_len,
+            hashes,
+            items: 0,
+            _item: PhantomData,
+        };
+        let mut header = [0; BITS_OFFSET as usize];
+        header[..MAGIC.len()].copy_from_slice(&MAGIC);
+        header[MAGIC.len()] = LAYOUT_VERSION;
+        header[HASHES_OFFSET as usize..BITS_LEN_OFFSET as usize]
+            .copy_from_slice(&hashes.to_le_bytes());
+        header[BITS_LEN_OFFSET as usize..ITEMS_OFFSET as usize]
+            .copy_from_slice(&bits_len.to_le_bytes());
+        filter.memory.write(0, &header);
+        Ok(filter)
+    }
+
+    fn load(memory: M) -> Result<Self> {
+        let mut header = [0; BITS_OFFSET as usize];
+        memory.read(0, &mut header);
+        let magic: [u8; 3] = header[..MAGIC.len()].try_into().expect("magic: 3 bytes");
+        if magic != MAGIC {
+            return Err(Error::BadMagic {
+                actual: magic,
+                expected: MAGIC,
+            });
+        }
+        if header[MAGIC.len()] != LAYOUT_VERSION {
+            return Err(Error::IncompatibleVersions);
+        }
+
+        let field = |start: u64, end: u64| &header[start as usize..end as usize];
+        Ok(Self {
+            hashes: u32::from_le_bytes(field(HASHES_OFFSET, BITS_LEN_OFFSET).try_into().unwrap()),
+            bits_len: u64::from_le_bytes(field(BITS_LEN_OFFSET, ITEMS_OFFSET).try_into().unwrap()),
+            items: u64::from_le_bytes(field(ITEMS_OFFSET, BITS_OFFSET).try_into().unwrap()),
+            memory,
+            _item: PhantomData,
+        })
+    }
+
+    /// Adds the item to the filter.
+    pub fn insert(&mut self, item: &T) {
+        for bit in self.bits(item) {
+            let (offset, mask) = bit_position(bit);
+            let mut byte = [0];
+            self.memory.read(offset, &mut byte);
+            byte[0] |= mask;
+            self.memory.write(offset, &byte);
+        }
+
+        self.items += 1;
+        self.memory.write(ITEMS_OFFSET, &self.items.to_le_bytes());
+    }
+
+    /// Returns `false` if the item was never inserted, and `true` if it was probably inserted.
+    pub fn contains(&self, item: &T) -> bool {
+        self.bits(item).all(|bit| {
+            let (offset, mask) = bit_position(bit);
+            let mut byte = [0];
+            self.memory.read(offset, &mut byte);
+            byte[0] & mask != 0
+        })
+    }
+
+    /// Number of insertions, counting the items inserted several times
+    pub fn items(&self) -> u64 {
+        self.items
+    }
+
+    /// Number of bits of the filter
+    pub fn bits_len(&self) -> u64 {
+        self.bits_len
+    }
+
+    /// Number of bits set for every item
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    /// Estimated false positive rate with the current number of items
+    pub fn false_positive_rate(&self) -> f64 {
+        let hashes = f64::from(self.hashes);
+        let filled = 1.0 - (-hashes * self.items as f64 / self.bits_len as f64).exp();
+        filled.powf(hashes)
+    }
+
+    /// Removes all the items.
+    pub fn clear(&mut self) {
+        let zeroes = vec![0; WASM_PAGE_SIZE as usize];
+        let bytes = self.bits_len.div_ceil(8);
+        for start in (0..bytes).step_by(zeroes.len()) {
+            let len = (bytes - start).min(zeroes.len() as u64) as usize;
+            self.memory.write(BITS_OFFSET + start, &zeroes[..len]);
+        }
+
+        self.items = 0;
+        self.memory.write(ITEMS_OFFSET, &self.items.to_le_bytes());
+    }
+
+    /// Bits of the item, derived from two hashes with the double hashing scheme
+    fn bits(&self, item: &T) -> impl Iterator<Item = u64> {
+        let hash = Sha256::digest(item.to_bytes());
+        let first = u64::from_le_bytes(hash[..8].try_into().expect("8 bytes"));
+        let second = u64::from_le_bytes(hash[8..16].try_into().expect("8 bytes")) | 1;
+        let bits_len = self.bits_len;
+        (0..u64::from(self.hashes))
+            .map(move |index| first.wrapping_add(index.wrapping_mul(second)) % bits_len)
+    }
+}
+
+/// Offset of the byte of the bit, and the mask of the bit in the byte
+fn bit_position(bit: u64) -> (u64, u8) {
+    (BITS_OFFSET + bit / 8, 1 << (bit % 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_contain_inserted_items() {
+        let mut filter =
+            StableBloomFilter::<u64, _>::new(VectorMemory::default(), 1000, 0.01).unwrap();
+        assert_eq!(filter.bits_len(), 9586);
+        assert_eq!(filter.hashes(), 7);
+
+        for item in 0..1000 {
+            filter.insert(&item);
+        }
+        assert!((0..1000).all(|item| filter.contains(&item)));
+        assert_eq!(filter.items(), 1000);
+
+        let false_positives = (1000..11_000).filter(|item| filter.contains(item)).count();
+        assert!(false_positives < 200, "{false_positives} false positives");
+        assert!((filter.false_positive_rate() - 0.01).abs() < 0.001);
+
+        filter.clear();
+        assert!(!filter.contains(&1));
+        assert_eq!(filter.items(), 0);
+    }
+
+    #[test]
+    fn should_restore_filter() {
+        let memory = VectorMemory::default();
+        let mut filter = StableBloomFilter::<u64, _>::new(memory.clone(), 100, 0.01).unwrap();
+        filter.insert(&42);
+
+        let filter = StableBloomFilter::<u64, _>::new(memory, 1_000_000, 0.5).unwrap();
+        assert_eq!(filter.bits_len(), 959);
+        assert_eq!(filter.items(), 1);
+        assert!(filter.contains(&42));
+    }
+}
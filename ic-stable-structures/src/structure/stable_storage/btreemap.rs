@@ -1,9 +1,14 @@
-use std::ops::RangeBounds;
+use std::any::type_name;
+use std::ops::{Bound, RangeBounds};
 
 use dfinity_stable_structures::{btreemap, Memory, Storable};
 
+use crate::stats::storable_size;
 use crate::structure::BTreeMapStructure;
-use crate::IterableSortedMapStructure;
+use crate::{
+    check_field, decode_field, ByteOrdered, Export, Import, IterableSortedMapStructure,
+    MemoryStats, MemoryUsage, Result, SnapshotHeader, SnapshotWriter, StructureKind,
+};
 
 /// Stores key-value data in stable memory.
 pub struct StableBTreeMap<K, V, M: Memory>(btreemap::BTreeMap<K, V, M>)
@@ -26,6 +31,101 @@ where
     pub fn iter(&self) -> btreemap::Iter<'_, K, V, M> {
         self.0.iter()
     }
+
+    /// Iterate over the key-value pairs whose keys belong to the given range, in key order.
+    pub fn range(&self, key_range: impl RangeBounds<K>) -> btreemap::Iter<'_, K, V, M> {
+        self.0.range(key_range)
+    }
+
+    /// Iterate over the key-value pairs starting from the given key included, in key order.
+    pub fn iter_from(&self, key: &K) -> btreemap::Iter<'_, K, V, M> {
+        self.0.range(key.clone()..)
+    }
+
+    /// Iterate over the key-value pairs whose encoded keys start with `prefix`, in key order, e.g.
+    /// the keys `"user:123:*"` of a namespace.
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = (K, V)> + 'a
+    where
+        K: ByteOrdered,
+    {
+        self.0
+            .range(K::prefix_lower_bound(prefix)..)
+            .skip_while(move |(key, _)| key.to_bytes().as_ref() < prefix)
+            .take_while(move |(key, _)| key.to_bytes().starts_with(prefix))
+    }
+
+    /// Iterate over the keys in order, without decoding the values.
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.0.keys()
+    }
+
+    /// Iterate over the values in the order of their keys, without decoding the keys.
+    pub fn values(&self) -> impl Iterator<Item = V> + '_ {
+        self.0.values()
+    }
+
+    /// Removes the entries whose keys belong to the given range, and returns their number.
+    ///
+    /// The keys are removed one by one, without collecting them first, so the heap usage doesn't
+    /// depend on the size of the range. A large range may still exceed the instruction limit of a
+    /// message, see [`Self::retain`] to split the removal across messages.
+    pub fn remove_range(&mut self, key_range: impl RangeBounds<K>) -> u64 {
+        let mut start = key_range.start_bound().cloned();
+        let end = key_range.end_bound().cloned();
+        let mut removed = 0;
+        while let Some((key, _)) = self.0.range((start, end.clone())).next() {
+            self.0.remove(&key);
+            removed += 1;
+            start = Bound::Excluded(key);
+        }
+        removed
+    }
+
+    /// Visits at most `max_entries` entries starting after the key `after`, or from the first key
+    /// if `None`, and removes the entries for which `predicate` returns `false`.
+    ///
+    /// Returns the key to pass to the next call, or `None` when all the entries are visited.
+    pub fn retain(
+        &mut self,
+        after: Option<&K>,
+        max_entries: usize,
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Option<K> {
+        if max_entries == 0 {
+            return after.cloned();
+        }
+
+        let start = match after {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
+        let mut next = None;
+        let mut removed = Vec::new();
+        for (visited, (key, value)) in self
+            .0
+            .range((start, Bound::Unbounded))
+            .take(max_entries)
+            .enumerate()
+        {
+            if visited + 1 == max_entries {
+                next = Some(key.clone());
+            }
+            if !predicate(&key, &value) {
+                removed.push(key);
+            }
+        }
+
+        for key in &removed {
+            self.0.remove(key);
+        }
+        next
+    }
+
+    /// Returns the memory of the map, e.g. to release it with [`crate::MemoryPool::release`] after
+    /// clearing the map.
+    pub fn into_memory(self) -> M {
+        self.0.into_memory()
+    }
 }
 
 impl<K, V, M> BTreeMapStructure<K, V> for StableBTreeMap<K, V, M>
@@ -69,6 +169,14 @@ where
     fn last_key_value(&self) -> Option<(K, V)> {
         self.0.last_key_value()
     }
+
+    fn pop_first(&mut self) -> Option<(K, V)> {
+        self.0.pop_first()
+    }
+
+    fn pop_last(&mut self) -> Option<(K, V)> {
+        self.0.pop_last()
+    }
 }
 
 impl<K, V, M> IterableSortedMapStructure<K, V> for StableBTreeMap<K, V, M>
@@ -92,6 +200,114 @@ where
     }
 }
 
+impl<K, V, M> MemoryUsage for StableBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.0.len(),
+            bytes: self
+                .0
+                .iter()
+                .map(|(key, value)| storable_size(&key) + storable_size(&value))
+                .sum(),
+        }
+    }
+}
+
+impl<K, V, M> Export for StableBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn export(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(
+            StructureKind::BTreeMap,
+            &[type_name::<K>(), type_name::<V>()],
+            self.0.len(),
+        );
+        for (key, value) in self.0.iter() {
+            writer.entry(&[&key.to_bytes(), &value.to_bytes()]);
+        }
+        writer.finish()
+    }
+}
+
+impl<K, V, M> Import for StableBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()> {
+        header.check_kind(StructureKind::BTreeMap, 2)
+    }
+
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()> {
+        check_field::<K>(fields[0])?;
+        check_field::<V>(fields[1])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.clear();
+        Ok(())
+    }
+
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()> {
+        self.insert(decode_field(fields[0])?, decode_field(fields[1])?);
+        Ok(())
+    }
+}
+
+/// Copies at most `max_entries` entries of `source` to `target`, starting after the key `after`,
+/// or from the first key if `None`.
+///
+/// Returns the key to pass to the next call, or `None` when all the entries are copied.
+///
+/// The memory of a map is never shrunk, so a map whose size went down after a heavy churn keeps
+/// its largest size. Copying it to a new memory, e.g. over several messages from a task of the
+/// scheduler, compacts its entries, and the old memory is reclaimed afterwards, e.g. with
+/// [`MemoryPool::release`](crate::MemoryPool::release). Until the copy is complete, the writes
+/// must be applied to both maps.
+pub fn compact_btreemap<K, V, M, TargetMemory>(
+    source: &StableBTreeMap<K, V, M>,
+    target: &mut StableBTreeMap<K, V, TargetMemory>,
+    after: Option<&K>,
+    max_entries: usize,
+) -> Option<K>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    TargetMemory: Memory,
+{
+    if max_entries == 0 {
+        return after.cloned();
+    }
+
+    let start = match after {
+        Some(key) => Bound::Excluded(key.clone()),
+        None => Bound::Unbounded,
+    };
+    let mut next = None;
+    for (copied, (key, value)) in source
+        .range((start, Bound::Unbounded))
+        .take(max_entries)
+        .enumerate()
+    {
+        if copied + 1 == max_entries {
+            next = Some(key.clone());
+        }
+        target.insert(key, value);
+    }
+
+    next
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -101,6 +317,7 @@ mod tests {
 
     use super::*;
     use crate::test_utils::str_val;
+    use crate::MemoryPool;
 
     #[test]
     fn btreemap_works() {
@@ -129,6 +346,32 @@ mod tests {
         assert_eq!(map.len(), 1);
     }
 
+    #[test]
+    fn test_range_queries() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for i in 0..100u32 {
+            map.insert(i * 10, i);
+        }
+
+        let values: Vec<_> = map.range(150..200).map(|(_, v)| v).collect();
+        assert_eq!(values, vec![15, 16, 17, 18, 19]);
+
+        let values: Vec<_> = map.range(..=20).map(|(_, v)| v).collect();
+        assert_eq!(values, vec![0, 1, 2]);
+
+        assert_eq!(map.range(991..).next(), None);
+
+        let keys: Vec<_> = map.iter_from(&965).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![970, 980, 990]);
+
+        let keys: Vec<_> = map.iter_from(&970).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![970, 980, 990]);
+
+        // the trait method gives the same entries
+        let entries: Vec<_> = IterableSortedMapStructure::iter_from(&map, &965).collect();
+        assert_eq!(entries, vec![(970, 97), (980, 98), (990, 99)]);
+    }
+
     #[test]
     fn test_last_key_value() {
         let mut map = StableBTreeMap::new(VectorMemory::default());
@@ -310,6 +553,150 @@ mod tests {
         assert_eq!(map.last_key_value(), Some((4u32, str_4)));
     }
 
+    #[test]
+    fn test_compaction() {
+        let pool = MemoryPool::init(VectorMemory::default());
+        let mut source = StableBTreeMap::new(pool.get(0));
+        for key in 0..5_000u32 {
+            source.insert(key, str_val(100));
+        }
+        for key in 100..5_000u32 {
+            source.remove(&key);
+        }
+
+        let mut target = StableBTreeMap::new(pool.get(1));
+        let mut after = None;
+        loop {
+            after = compact_btreemap(&source, &mut target, after.as_ref(), 30);
+            if after.is_none() {
+                break;
+            }
+        }
+        assert_eq!(
+            target.iter().collect::<Vec<_>>(),
+            source.iter().collect::<Vec<_>>()
+        );
+
+        drop(source);
+        let released = pool.release(0);
+        assert!(pool.free_pages() > 0);
+        assert!(target.into_memory().size() < released);
+    }
+
+    #[test]
+    fn test_keys_and_values() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for key in [3u32, 1, 2] {
+            map.insert(key, str_val(key as usize * 100));
+        }
+
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(
+            map.values().collect::<Vec<_>>(),
+            vec![str_val(100), str_val(200), str_val(300)]
+        );
+    }
+
+    #[test]
+    fn test_iter_prefix() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for key in [
+            "user:12:name",
+            "user:123:age",
+            "user:123:name",
+            "user:124:name",
+            "users",
+        ] {
+            map.insert(key.to_string(), key.len() as u32);
+        }
+
+        let keys = |prefix: &str| {
+            map.iter_prefix(prefix.as_bytes())
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys("user:123:"), vec!["user:123:age", "user:123:name"]);
+        assert_eq!(keys("user:12").len(), 4);
+        assert_eq!(keys("user:125"), Vec::<String>::new());
+        assert_eq!(keys("").len(), 5);
+
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for key in [[1u8, 1], [1, 2], [2, 1]] {
+            map.insert(key, ());
+        }
+        assert_eq!(map.iter_prefix(&[1]).count(), 2);
+    }
+
+    #[test]
+    fn test_remove_range() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for key in 0u32..10 {
+            map.insert(key, key);
+        }
+
+        assert_eq!(map.remove_range(2..5), 3);
+        assert_eq!(map.remove_range(8..), 2);
+        assert_eq!(map.remove_range(20..), 0);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![0, 1, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_retain_in_chunks() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for key in 0u32..10 {
+            map.insert(key, key * 10);
+        }
+
+        let mut after = None;
+        let mut calls = 0;
+        loop {
+            after = map.retain(after.as_ref(), 3, |_, value| value % 20 == 0);
+            calls += 1;
+            if after.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(calls, 4);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        map.insert(2u32, str_val(0));
+
+        map.insert_many([
+            (3u32, str_val(3)),
+            (1, str_val(1)),
+            (2, str_val(2)),
+            (3, str_val(4)),
+        ]);
+
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            vec![(1, str_val(1)), (2, str_val(2)), (3, str_val(4))]
+        );
+    }
+
+    #[test]
+    fn test_pop_first_and_last() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        assert_eq!(map.pop_first(), None);
+        assert_eq!(map.pop_last(), None);
+
+        for key in [3u32, 1, 4, 2] {
+            map.insert(key, str_val(key as usize));
+        }
+
+        assert_eq!(map.pop_first(), Some((1, str_val(1))));
+        assert_eq!(map.pop_last(), Some((4, str_val(4))));
+        assert_eq!(map.pop_last(), Some((3, str_val(3))));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.pop_first(), Some((2, str_val(2))));
+        assert!(map.is_empty());
+    }
+
     #[test]
     fn btreemap_works_with_composite_keys() {
         let mut map = StableBTreeMap::new(VectorMemory::default());
@@ -0,0 +1,199 @@
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+
+/// Entry of a [`StableBTreeMap`] for a read-modify-write of a single key, returned by
+/// [`StableBTreeMap::entry`].
+///
+/// The value is read and decoded once when the entry is created, and written at most once by
+/// each of the methods below.
+pub enum MapEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    Occupied(OccupiedMapEntry<'a, K, V, M>),
+    Vacant(VacantMapEntry<'a, K, V, M>),
+}
+
+/// Entry of a key which is in the map
+pub struct OccupiedMapEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    map: &'a mut StableBTreeMap<K, V, M>,
+    key: K,
+    value: V,
+}
+
+/// Entry of a key which isn't in the map
+pub struct VacantMapEntry<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    map: &'a mut StableBTreeMap<K, V, M>,
+    key: K,
+}
+
+impl<K, V, M> StableBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    /// Returns the entry of `key` for a read-modify-write.
+    pub fn entry(&mut self, key: K) -> MapEntry<'_, K, V, M> {
+        match self.get(&key) {
+            Some(value) => MapEntry::Occupied(OccupiedMapEntry {
+                map: self,
+                key,
+                value,
+            }),
+            None => MapEntry::Vacant(VacantMapEntry { map: self, key }),
+        }
+    }
+}
+
+impl<K, V, M> MapEntry<'_, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    /// Key of the entry
+    pub fn key(&self) -> &K {
+        match self {
+            Self::Occupied(entry) => entry.key(),
+            Self::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Returns the value of the entry, inserting `default` first if the key isn't in the map.
+    pub fn or_insert(self, default: V) -> V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Returns the value of the entry, inserting the result of `default` first if the key isn't
+    /// in the map.
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> V {
+        match self {
+            Self::Occupied(entry) => entry.value,
+            Self::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Returns the value of the entry, inserting the default value first if the key isn't in the
+    /// map.
+    pub fn or_default(self) -> V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modifies the value of the entry with `f` and writes it to the map if the key is in the map.
+    pub fn and_modify(self, f: impl FnOnce(&mut V)) -> Self {
+        match self {
+            Self::Occupied(mut entry) => {
+                let mut value = entry.value.clone();
+                f(&mut value);
+                entry.insert(value);
+                Self::Occupied(entry)
+            }
+            Self::Vacant(entry) => Self::Vacant(entry),
+        }
+    }
+}
+
+impl<K, V, M> OccupiedMapEntry<'_, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    /// Key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Value of the entry
+    pub fn get(&self) -> &V {
+        &self.value
+    }
+
+    /// Replaces the value of the entry, and returns the previous value.
+    pub fn insert(&mut self, value: V) -> V {
+        self.map.insert(self.key.clone(), value.clone());
+        std::mem::replace(&mut self.value, value)
+    }
+
+    /// Removes the entry from the map, and returns its value.
+    pub fn remove(self) -> V {
+        self.map.remove(&self.key);
+        self.value
+    }
+}
+
+impl<K, V, M> VacantMapEntry<'_, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+{
+    /// Key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Inserts the value of the entry, and returns it.
+    pub fn insert(self, value: V) -> V {
+        self.map.insert(self.key, value.clone());
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_insert_or_modify_entries() {
+        let mut map = StableBTreeMap::<u32, u64, _>::new(VectorMemory::default());
+
+        assert_eq!(map.entry(1).or_insert(10), 10);
+        assert_eq!(map.entry(1).or_insert(20), 10);
+        assert_eq!(
+            map.entry(1).and_modify(|value| *value += 1).or_default(),
+            11
+        );
+        assert_eq!(map.entry(2).and_modify(|value| *value += 1).or_default(), 0);
+        assert_eq!(map.entry(3).or_insert_with(|| 30), 30);
+
+        assert_eq!(map.get(&1), Some(11));
+        assert_eq!(map.get(&2), Some(0));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn should_replace_and_remove_occupied_entry() {
+        let mut map = StableBTreeMap::<u32, u64, _>::new(VectorMemory::default());
+        map.insert(1, 10);
+
+        let MapEntry::Occupied(mut entry) = map.entry(1) else {
+            panic!("the key is in the map");
+        };
+        assert_eq!(entry.insert(20), 10);
+        assert_eq!(*entry.get(), 20);
+        assert_eq!(entry.remove(), 20);
+
+        assert!(map.is_empty());
+        assert!(matches!(map.entry(1), MapEntry::Vacant(_)));
+    }
+}
@@ -1,25 +1,176 @@
+use std::any::type_name;
+
 use dfinity_stable_structures::{cell, Memory, Storable};
 
+use crate::stats::storable_size;
 use crate::structure::CellStructure;
-use crate::Result;
+use crate::{
+    check_field, decode_field, Error, Export, Import, MemoryStats, MemoryUsage, Result,
+    SnapshotHeader, SnapshotWriter, StructureKind,
+};
+
+/// Identifier of a watcher registered with [`StableCell::watch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatcherId(u64);
+
+type Watcher<T> = Box<dyn Fn(&T, &T)>;
 
 /// Stores value in stable memory, providing `get()/set()` API.
-pub struct StableCell<T: Storable, M: Memory>(cell::Cell<T, M>);
+pub struct StableCell<T: Storable, M: Memory> {
+    cell: cell::Cell<T, M>,
+    watchers: Vec<(WatcherId, Watcher<T>)>,
+    next_watcher_id: u64,
+}
 
 impl<T: Storable, M: Memory> StableCell<T, M> {
     /// Create new storage for values with `T` type.
     pub fn new(memory: M, value: T) -> Result<Self> {
-        Ok(Self(cell::Cell::init(memory, value)?))
+        Ok(Self {
+            cell: cell::Cell::init(memory, value)?,
+            watchers: Vec::new(),
+            next_watcher_id: 0,
+        })
+    }
+
+    /// Registers a callback called with the previous and the new value after every update of the
+    /// cell, e.g. to invalidate a cache derived from the value.
+    ///
+    /// The watchers are kept in heap memory, so they must be registered again after an upgrade.
+    pub fn watch(&mut self, watcher: impl Fn(&T, &T) + 'static) -> WatcherId {
+        let id = WatcherId(self.next_watcher_id);
+        self.next_watcher_id += 1;
+        self.watchers.push((id, Box::new(watcher)));
+        id
+    }
+
+    /// Removes the watcher. Returns `false` if it was not registered.
+    pub fn unwatch(&mut self, id: WatcherId) -> bool {
+        let len = self.watchers.len();
+        self.watchers.retain(|(watcher_id, _)| *watcher_id != id);
+        self.watchers.len() != len
+    }
+}
+
+impl<T, M: Memory> StableCell<Option<T>, M>
+where
+    Option<T>: Storable,
+{
+    /// Create new storage for an optional value, `None` until it is set, e.g. for a configuration
+    /// which is set after the canister is installed.
+    pub fn new_unset(memory: M) -> Result<Self> {
+        Self::new(memory, None)
+    }
+
+    /// True if the value is set.
+    pub fn is_set(&self) -> bool {
+        self.cell.get().is_some()
+    }
+
+    /// Returns the value, setting it with `init` first if it is not set.
+    pub fn get_or_init(&mut self, init: impl FnOnce() -> T) -> Result<&T> {
+        if !self.is_set() {
+            self.set(Some(init()))?;
+        }
+        Ok(self.cell.get().as_ref().expect("the value is set above"))
     }
 }
 
 impl<T: Storable, M: Memory> CellStructure<T> for StableCell<T, M> {
     fn get(&self) -> &T {
-        self.0.get()
+        self.cell.get()
     }
 
     fn set(&mut self, value: T) -> Result<()> {
-        self.0.set(value)?;
+        let previous = self.cell.set(value)?;
+        for (_, watcher) in &self.watchers {
+            watcher(&previous, self.cell.get());
+        }
         Ok(())
     }
 }
+
+impl<T: Storable, M: Memory> MemoryUsage for StableCell<T, M> {
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: 1,
+            bytes: storable_size(self.cell.get()),
+        }
+    }
+}
+
+impl<T: Storable, M: Memory> Export for StableCell<T, M> {
+    fn export(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(StructureKind::Cell, &[type_name::<T>()], 1);
+        writer.entry(&[&self.cell.get().to_bytes()]);
+        writer.finish()
+    }
+}
+
+impl<T: Storable, M: Memory> Import for StableCell<T, M> {
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()> {
+        header.check_kind(StructureKind::Cell, 1)?;
+        if header.entries != 1 {
+            return Err(Error::InvalidSnapshot("a cell snapshot has a single entry"));
+        }
+        Ok(())
+    }
+
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()> {
+        check_field::<T>(fields[0])
+    }
+
+    /// A cell always holds a value, which is replaced by the imported entry.
+    fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()> {
+        self.set(decode_field(fields[0])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_notify_watchers() {
+        let mut cell = StableCell::new(VectorMemory::default(), 1u64).unwrap();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let watcher_changes = changes.clone();
+        let id = cell
+            .watch(move |previous, value| watcher_changes.borrow_mut().push((*previous, *value)));
+
+        cell.set(2).unwrap();
+        cell.set(3).unwrap();
+        assert!(cell.unwatch(id));
+        assert!(!cell.unwatch(id));
+        cell.set(4).unwrap();
+
+        assert_eq!(*changes.borrow(), vec![(1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn should_update_value() {
+        let mut cell = StableCell::new(VectorMemory::default(), 1u64).unwrap();
+        assert_eq!(*cell.update(|value| *value += 10).unwrap(), 11);
+        assert_eq!(*cell.get(), 11);
+    }
+
+    #[test]
+    fn should_init_unset_value() {
+        let mut cell = StableCell::<Option<u64>, _>::new_unset(VectorMemory::default()).unwrap();
+        assert!(!cell.is_set());
+        assert_eq!(*cell.get_or_init(|| 5).unwrap(), 5);
+        assert_eq!(*cell.get_or_init(|| 6).unwrap(), 5);
+        assert_eq!(*cell.get(), Some(5));
+
+        cell.set(None).unwrap();
+        assert!(!cell.is_set());
+    }
+}
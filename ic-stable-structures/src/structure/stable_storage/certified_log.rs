@@ -0,0 +1,294 @@
+//! Append-only log certified by the root hash of a Merkle tree over its entries.
+//!
+//! The entries are the leaves of a Merkle mountain range: a list of perfect binary trees, one per
+//! bit set in the length of the log, from the largest to the smallest. Appending an entry merges
+//! the trees of equal size, so it hashes `O(log n)` nodes, and the nodes are stored, so neither
+//! the root hash nor the witnesses read the entries.
+//!
+//! The root hash commits to the length and to every entry with its index. The canister passes it
+//! to `ic_cdk::api::set_certified_data` after the appends, and returns the entries of its queries
+//! with their [`MerkleWitness`] and the certificate, so the clients check the certificate and then
+//! verify the entries against the root hash with [`MerkleWitness::verify`].
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::{Memory, Storable};
+use sha2::{Digest, Sha256};
+
+use crate::structure::{BTreeMapStructure, LogStructure, StableBTreeMap, StableLog};
+use crate::Result;
+
+/// SHA-256 hash of a node of the tree
+pub type MerkleHash = [u8; 32];
+
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+const ROOT_TAG: u8 = 2;
+
+fn leaf_hash(index: u64, value: &[u8]) -> MerkleHash {
+    Sha256::new()
+        .chain_update([LEAF_TAG])
+        .chain_update(index.to_le_bytes())
+        .chain_update(value)
+        .finalize()
+        .into()
+}
+
+fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    Sha256::new()
+        .chain_update([NODE_TAG])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+fn root_hash(len: u64, peaks: Option<&MerkleHash>) -> MerkleHash {
+    let mut hasher = Sha256::new()
+        .chain_update([ROOT_TAG])
+        .chain_update(len.to_le_bytes());
+    if let Some(peaks) = peaks {
+        hasher.update(peaks);
+    }
+    hasher.finalize().into()
+}
+
+/// Height of the tree containing the entry at `index` in a log of `len` entries.
+fn tree_height(index: u64, len: u64) -> u8 {
+    let mut first_index = 0;
+    for height in (0..u64::BITS as u8).rev() {
+        if len & (1 << height) != 0 {
+            first_index += 1 << height;
+            if index < first_index {
+                return height;
+            }
+        }
+    }
+    unreachable!("the index is lower than the length")
+}
+
+/// Hashes proving that an entry is in the log with the given root hash.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MerkleWitness {
+    /// Index of the entry
+    pub index: u64,
+    /// Length of the log
+    pub len: u64,
+    /// Siblings of the path from the entry to the root of its tree, from the bottom
+    pub siblings: Vec<MerkleHash>,
+    /// Hash of the roots of the smaller trees
+    pub smaller_trees: Option<MerkleHash>,
+    /// Roots of the larger trees, from the smallest
+    pub larger_trees: Vec<MerkleHash>,
+}
+
+impl MerkleWitness {
+    /// Computes the root hash of the log from the serialized entry.
+    ///
+    /// Returns `None` if the witness doesn't match the shape of a log of `len` entries.
+    pub fn root_hash(&self, value: &[u8]) -> Option<MerkleHash> {
+        if self.index >= self.len {
+            return None;
+        }
+        let height = tree_height(self.index, self.len);
+        let smaller_mask = (1u64 << height) - 1;
+        if self.siblings.len() != usize::from(height)
+            || self.smaller_trees.is_some() != (self.len & smaller_mask != 0)
+            || self.larger_trees.len() != (self.len >> height >> 1).count_ones() as usize
+        {
+            return None;
+        }
+
+        let mut hash = leaf_hash(self.index, value);
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            hash = if (self.index >> level) & 1 == 0 {
+                node_hash(&hash, sibling)
+            } else {
+                node_hash(sibling, &hash)
+            };
+        }
+        if let Some(smaller_trees) = &self.smaller_trees {
+            hash = node_hash(&hash, smaller_trees);
+        }
+        for larger_tree in &self.larger_trees {
+            hash = node_hash(larger_tree, &hash);
+        }
+
+        Some(root_hash(self.len, Some(&hash)))
+    }
+
+    /// Returns `true` if the entry with this witness is in the log with the given root hash.
+    pub fn verify<T: Storable>(&self, value: &T, root_hash: &MerkleHash) -> bool {
+        self.root_hash(&value.to_bytes()).as_ref() == Some(root_hash)
+    }
+}
+
+/// [`StableLog`] maintaining a Merkle tree of its entries, whose root hash is suitable for the
+/// certified data of the canister.
+pub struct CertifiedLog<T: Storable, M: Memory> {
+    log: StableLog<T, M>,
+    /// Nodes of the trees by level and position in the level, the leaves being at level 0
+    nodes: StableBTreeMap<(u8, u64), MerkleHash, M>,
+}
+
+impl<T: Storable, M: Memory> CertifiedLog<T, M> {
+    /// Creates the log, or loads it and its tree from the memories.
+    pub fn new(index_memory: M, data_memory: M, nodes_memory: M) -> Result<Self> {
+        Ok(Self {
+            log: StableLog::new(index_memory, data_memory)?,
+            nodes: StableBTreeMap::new(nodes_memory),
+        })
+    }
+
+    /// Hash committing to the length of the log and to all its entries
+    pub fn root_hash(&self) -> MerkleHash {
+        let len = self.log.len();
+        let peaks = (0..u64::BITS as u8)
+            .filter(|&level| len & (1 << level) != 0)
+            .map(|level| self.tree_root(level, len))
+            .reduce(|smaller, tree| node_hash(&tree, &smaller));
+        root_hash(len, peaks.as_ref())
+    }
+
+    /// Returns the witness of the entry at `index` against the current root hash.
+    pub fn witness(&self, index: u64) -> Option<MerkleWitness> {
+        let len = self.log.len();
+        if index >= len {
+            return None;
+        }
+
+        let height = tree_height(index, len);
+        let siblings = (0..height)
+            .map(|level| self.node(level, (index >> level) ^ 1))
+            .collect();
+        let smaller_trees = (0..height)
+            .filter(|&level| len & (1 << level) != 0)
+            .map(|level| self.tree_root(level, len))
+            .reduce(|smaller, tree| node_hash(&tree, &smaller));
+        let larger_trees = (height + 1..u64::BITS as u8)
+            .filter(|&level| len & (1 << level) != 0)
+            .map(|level| self.tree_root(level, len))
+            .collect();
+
+        Some(MerkleWitness {
+            index,
+            len,
+            siblings,
+            smaller_trees,
+            larger_trees,
+        })
+    }
+
+    /// Root of the tree of the given height in a log of `len` entries
+    fn tree_root(&self, height: u8, len: u64) -> MerkleHash {
+        self.node(height, (len >> height) - 1)
+    }
+
+    fn node(&self, level: u8, position: u64) -> MerkleHash {
+        self.nodes
+            .get(&(level, position))
+            .expect("the nodes of the complete subtrees are stored")
+    }
+}
+
+impl<T: Storable, M: Memory> LogStructure<T> for CertifiedLog<T, M> {
+    fn get(&self, index: u64) -> Option<T> {
+        self.log.get(index)
+    }
+
+    fn append(&mut self, value: T) -> Result<u64> {
+        let mut hash = leaf_hash(self.log.len(), &value.to_bytes());
+        let index = self.log.append(value)?;
+
+        let mut level = 0;
+        let mut position = index;
+        self.nodes.insert((level, position), hash);
+        while position & 1 == 1 {
+            hash = node_hash(&self.node(level, position - 1), &hash);
+            level += 1;
+            position >>= 1;
+            self.nodes.insert((level, position), hash);
+        }
+
+        Ok(index)
+    }
+
+    fn len(&self) -> u64 {
+        self.log.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.log.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.log.clear();
+        self.nodes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    fn new_log() -> CertifiedLog<StringValue, VectorMemory> {
+        CertifiedLog::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn should_verify_all_entries() {
+        let mut log = new_log();
+        for len in 1..=13 {
+            log.append(str_val(len)).unwrap();
+            let root_hash = log.root_hash();
+
+            for index in 0..len as u64 {
+                let witness = log.witness(index).unwrap();
+                assert!(witness.verify(&str_val(index as usize + 1), &root_hash));
+                assert!(!witness.verify(&str_val(0), &root_hash));
+            }
+            assert_eq!(log.witness(len as u64), None);
+        }
+    }
+
+    #[test]
+    fn should_change_root_hash_on_append() {
+        let mut log = new_log();
+        let empty = log.root_hash();
+        log.append(str_val(1)).unwrap();
+        let witness = log.witness(0).unwrap();
+        let root_hash = log.root_hash();
+        assert_ne!(root_hash, empty);
+
+        log.append(str_val(2)).unwrap();
+        assert_ne!(log.root_hash(), root_hash);
+        assert!(!witness.verify(&str_val(1), &log.root_hash()));
+
+        log.clear();
+        assert_eq!(log.root_hash(), empty);
+    }
+
+    #[test]
+    fn should_reject_witness_with_wrong_shape() {
+        let mut log = new_log();
+        for value in 0..5 {
+            log.append(str_val(value)).unwrap();
+        }
+        let root_hash = log.root_hash();
+
+        let mut witness = log.witness(2).unwrap();
+        witness.index = 3;
+        assert!(!witness.verify(&str_val(2), &root_hash));
+
+        let mut witness = log.witness(4).unwrap();
+        witness.siblings.push([0; 32]);
+        assert!(!witness.verify(&str_val(4), &root_hash));
+    }
+}
@@ -0,0 +1,309 @@
+use std::borrow::Cow;
+use std::ops::RangeBounds;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+
+use crate::structure::{
+    BTreeMapStructure, IterableSortedMapStructure, StableBTreeMap, StableRotatingLog,
+};
+use crate::Result;
+
+const INSERT_TAG: u8 = 0;
+const UPDATE_TAG: u8 = 1;
+const REMOVE_TAG: u8 = 2;
+const CLEAR_TAG: u8 = 3;
+
+/// Change of a [`ChangelogBTreeMap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeEvent<K, V> {
+    /// The key was inserted with the value
+    Insert { key: K, value: V },
+    /// The value of the key was replaced
+    Update { key: K, value: V },
+    /// The key was removed
+    Remove { key: K },
+    /// All the keys were removed
+    Clear,
+}
+
+impl<K: Storable, V: Storable> Storable for ChangeEvent<K, V> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::new();
+        let push_key = |buf: &mut Vec<u8>, tag: u8, key: &K| {
+            let key = key.to_bytes();
+            buf.push(tag);
+            buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&key);
+        };
+        match self {
+            Self::Insert { key, value } => {
+                push_key(&mut buf, INSERT_TAG, key);
+                buf.extend_from_slice(&value.to_bytes());
+            }
+            Self::Update { key, value } => {
+                push_key(&mut buf, UPDATE_TAG, key);
+                buf.extend_from_slice(&value.to_bytes());
+            }
+            Self::Remove { key } => push_key(&mut buf, REMOVE_TAG, key),
+            Self::Clear => buf.push(CLEAR_TAG),
+        }
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        if bytes[0] == CLEAR_TAG {
+            return Self::Clear;
+        }
+
+        let key_len = u32::from_le_bytes(
+            bytes[1..5]
+                .try_into()
+                .expect("key length: expected 4 bytes"),
+        );
+        let key_end = 5 + key_len as usize;
+        let key = K::from_bytes(bytes[5..key_end].to_vec().into());
+        let value = || V::from_bytes(bytes[key_end..].to_vec().into());
+        match bytes[0] {
+            INSERT_TAG => Self::Insert {
+                key,
+                value: value(),
+            },
+            UPDATE_TAG => Self::Update {
+                key,
+                value: value(),
+            },
+            _ => Self::Remove { key },
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// [`StableBTreeMap`] recording its changes in a [`StableRotatingLog`].
+///
+/// Other subsystems, e.g. certification or replication to another canister, read the changes with
+/// [`Self::changes`] from the index following the last change they processed, instead of hooking
+/// every call site which updates the map. The log is bounded, so a consumer which lags behind by
+/// more than the retained changes sees that [`Self::first_change_index`] moved past its index and
+/// must resynchronize from the map.
+pub struct ChangelogBTreeMap<K, V, M, LogMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    LogMemory: Memory,
+{
+    map: StableBTreeMap<K, V, M>,
+    changelog: StableRotatingLog<ChangeEvent<K, V>, LogMemory>,
+}
+
+impl<K, V, M, LogMemory> ChangelogBTreeMap<K, V, M, LogMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    LogMemory: Memory,
+{
+    /// Creates the map, or loads it and its changelog from the memories.
+    ///
+    /// See [`StableRotatingLog::new`] for the memories of the changelog and `max_segment_len`.
+    pub fn new(
+        map_memory: M,
+        changelog_memories: [(LogMemory, LogMemory); 2],
+        changelog_state_memory: LogMemory,
+        max_segment_len: u64,
+    ) -> Result<Self> {
+        Ok(Self {
+            map: StableBTreeMap::new(map_memory),
+            changelog: StableRotatingLog::new(
+                changelog_memories,
+                changelog_state_memory,
+                max_segment_len,
+            )?,
+        })
+    }
+
+    /// Returns the map, for the read operations which are not in the [`BTreeMapStructure`] trait.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.map
+    }
+
+    /// Index of the oldest retained change
+    pub fn first_change_index(&self) -> u64 {
+        self.changelog.first_index()
+    }
+
+    /// Index of the next change
+    pub fn next_change_index(&self) -> u64 {
+        self.changelog.next_index()
+    }
+
+    /// Returns at most `max_count` retained changes with their indices, starting from `from`.
+    pub fn changes(&self, from: u64, max_count: u64) -> Vec<(u64, ChangeEvent<K, V>)> {
+        let start = from.max(self.changelog.first_index());
+        let end = start.saturating_add(max_count);
+        (start..end)
+            .zip(self.changelog.get_range(start..end))
+            .collect()
+    }
+
+    fn record(&mut self, event: ChangeEvent<K, V>) {
+        self.changelog
+            .append(event)
+            .expect("failed to append to the changelog");
+    }
+}
+
+impl<K, V, M, LogMemory> BTreeMapStructure<K, V> for ChangelogBTreeMap<K, V, M, LogMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable + Clone,
+    M: Memory,
+    LogMemory: Memory,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let previous = self.map.insert(key.clone(), value.clone());
+        self.record(match previous {
+            Some(_) => ChangeEvent::Update { key, value },
+            None => ChangeEvent::Insert { key, value },
+        });
+        previous
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let removed = self.map.remove(key)?;
+        self.record(ChangeEvent::Remove { key: key.clone() });
+        Some(removed)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.map.first_key_value()
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.map.last_key_value()
+    }
+
+    fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.record(ChangeEvent::Clear);
+    }
+}
+
+impl<K, V, M, LogMemory> IterableSortedMapStructure<K, V> for ChangelogBTreeMap<K, V, M, LogMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    LogMemory: Memory,
+{
+    type Iterator<'a> = btreemap::Iter<'a, K, V, M> where Self: 'a;
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        self.map.iter()
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        self.map.range(key_range)
+    }
+
+    fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
+        IterableSortedMapStructure::iter_upper_bound(&self.map, bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    fn new_map(
+        max_segment_len: u64,
+    ) -> ChangelogBTreeMap<u32, StringValue, VectorMemory, VectorMemory> {
+        ChangelogBTreeMap::new(
+            VectorMemory::default(),
+            [
+                (VectorMemory::default(), VectorMemory::default()),
+                (VectorMemory::default(), VectorMemory::default()),
+            ],
+            VectorMemory::default(),
+            max_segment_len,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn should_record_changes() {
+        let mut map = new_map(100);
+        map.insert(1, str_val(10));
+        map.insert(1, str_val(20));
+        map.insert(2, str_val(30));
+        map.remove(&1);
+        map.remove(&3);
+        map.clear();
+
+        assert_eq!(
+            map.changes(0, 10),
+            vec![
+                (
+                    0,
+                    ChangeEvent::Insert {
+                        key: 1,
+                        value: str_val(10)
+                    }
+                ),
+                (
+                    1,
+                    ChangeEvent::Update {
+                        key: 1,
+                        value: str_val(20)
+                    }
+                ),
+                (
+                    2,
+                    ChangeEvent::Insert {
+                        key: 2,
+                        value: str_val(30)
+                    }
+                ),
+                (3, ChangeEvent::Remove { key: 1 }),
+                (4, ChangeEvent::Clear),
+            ]
+        );
+        assert_eq!(map.changes(3, 1), vec![(3, ChangeEvent::Remove { key: 1 })]);
+        assert_eq!(map.next_change_index(), 5);
+    }
+
+    #[test]
+    fn should_drop_oldest_changes() {
+        let mut map = new_map(2);
+        for key in 0..5 {
+            map.insert(key, str_val(1));
+        }
+
+        assert_eq!(map.first_change_index(), 2);
+        let changes = map.changes(0, 10);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].0, 2);
+        assert_eq!(map.len(), 5);
+    }
+}
@@ -0,0 +1,217 @@
+use std::borrow::Cow;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::{Error, Result};
+
+const CRC32_POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// CRC-32 (IEEE) of the bytes
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (CRC32_POLYNOMIAL & mask);
+        }
+    }
+    !crc
+}
+
+/// Serialized value preceded by its CRC-32
+struct ChecksummedBytes(Vec<u8>);
+
+impl ChecksummedBytes {
+    fn new<V: Storable>(value: &V) -> Self {
+        let value = value.to_bytes();
+        let mut buf = Vec::with_capacity(4 + value.len());
+        buf.extend_from_slice(&crc32(&value).to_le_bytes());
+        buf.extend_from_slice(&value);
+        Self(buf)
+    }
+
+    /// Returns the serialized value if it matches the checksum.
+    fn value_bytes(&self) -> Option<&[u8]> {
+        let checksum = u32::from_le_bytes(self.0.get(..4)?.try_into().ok()?);
+        let value = &self.0[4..];
+        (crc32(value) == checksum).then_some(value)
+    }
+}
+
+impl Storable for ChecksummedBytes {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// What [`ChecksummedBTreeMap`] does when a value doesn't match its checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    /// Return [`Error::ChecksumMismatch`]
+    Error,
+    /// Panic, reverting the changes of the current message
+    Panic,
+}
+
+/// Key-value map in stable memory storing a CRC-32 with every value, verified before the value
+/// is decoded.
+///
+/// This detects the values corrupted e.g. by a bug in the layout of the memories, instead of
+/// decoding garbage. [`Self::verify_all`] checks the whole map, e.g. in `post_upgrade`.
+pub struct ChecksummedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    inner: StableBTreeMap<K, ChecksummedBytes, M>,
+    policy: CorruptionPolicy,
+    _value: std::marker::PhantomData<V>,
+}
+
+impl<K, V, M> ChecksummedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Create new instance of the map with the given corruption policy.
+    pub fn new(memory: M, policy: CorruptionPolicy) -> Self {
+        Self {
+            inner: StableBTreeMap::new(memory),
+            policy,
+            _value: Default::default(),
+        }
+    }
+
+    /// Return value associated with `key` from stable memory.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.inner
+            .get(key)
+            .map(|bytes| self.decode(&bytes))
+            .transpose()
+    }
+
+    /// Add or replace value associated with `key` in stable memory, and returns the previous
+    /// value.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>> {
+        self.inner
+            .insert(key, ChecksummedBytes::new(&value))
+            .map(|bytes| self.decode(&bytes))
+            .transpose()
+    }
+
+    /// Remove value associated with `key` from stable memory.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>> {
+        self.inner
+            .remove(key)
+            .map(|bytes| self.decode(&bytes))
+            .transpose()
+    }
+
+    /// True if contains the key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    /// Count of items in the map.
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Is the map empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Remove all entries from the map.
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    /// Corruption policy of the map
+    pub fn policy(&self) -> CorruptionPolicy {
+        self.policy
+    }
+
+    /// Returns the keys whose values don't match their checksum, regardless of the policy.
+    pub fn verify_all(&self) -> Vec<K> {
+        self.inner
+            .iter()
+            .filter(|(_, bytes)| bytes.value_bytes().is_none())
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    fn decode(&self, bytes: &ChecksummedBytes) -> Result<V> {
+        match (bytes.value_bytes(), self.policy) {
+            (Some(value), _) => Ok(V::from_bytes(Cow::Borrowed(value))),
+            (None, CorruptionPolicy::Error) => Err(Error::ChecksumMismatch),
+            (None, CorruptionPolicy::Panic) => panic!("{}", Error::ChecksumMismatch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    fn corrupt(map: &mut ChecksummedBTreeMap<u32, StringValue, VectorMemory>, key: u32) {
+        let mut bytes = map.inner.get(&key).unwrap();
+        *bytes.0.last_mut().unwrap() ^= 1;
+        map.inner.insert(key, bytes);
+    }
+
+    #[test]
+    fn should_store_values_with_checksums() {
+        let mut map = ChecksummedBTreeMap::new(VectorMemory::default(), CorruptionPolicy::Error);
+        assert_eq!(map.insert(1u32, str_val(100)).unwrap(), None);
+        assert_eq!(map.insert(1, str_val(200)).unwrap(), Some(str_val(100)));
+        map.insert(2, str_val(300)).unwrap();
+
+        assert_eq!(map.get(&1).unwrap(), Some(str_val(200)));
+        assert_eq!(map.get(&3).unwrap(), None);
+        assert_eq!(map.remove(&2).unwrap(), Some(str_val(300)));
+        assert!(map.verify_all().is_empty());
+    }
+
+    #[test]
+    fn should_detect_corrupted_values() {
+        let mut map = ChecksummedBTreeMap::new(VectorMemory::default(), CorruptionPolicy::Error);
+        for key in 1u32..=3 {
+            map.insert(key, str_val(100)).unwrap();
+        }
+        corrupt(&mut map, 2);
+
+        assert!(matches!(map.get(&2), Err(Error::ChecksumMismatch)));
+        assert_eq!(map.get(&1).unwrap(), Some(str_val(100)));
+        assert_eq!(map.verify_all(), vec![2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "checksum")]
+    fn should_panic_on_corrupted_value() {
+        let mut map = ChecksummedBTreeMap::new(VectorMemory::default(), CorruptionPolicy::Panic);
+        map.insert(1u32, str_val(100)).unwrap();
+        corrupt(&mut map, 1);
+
+        let _ = map.get(&1);
+    }
+
+    #[test]
+    fn should_compute_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
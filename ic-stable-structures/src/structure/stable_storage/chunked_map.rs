@@ -0,0 +1,229 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, IterableSortedMapStructure, StableBTreeMap};
+
+/// Stores key-value data in stable memory, splitting every value into chunks of at most
+/// `CHUNK_SIZE` bytes.
+///
+/// Values of several KB stored in a `StableBTreeMap` need large nodes or overflow pages.
+/// This map stores each chunk as a separate bounded entry, so the node size depends on
+/// `CHUNK_SIZE` only and can be tuned for the size of the values.
+pub struct StableChunkedMap<K, V, M, const CHUNK_SIZE: u32>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    chunks: StableBTreeMap<(K, u32), Chunk<CHUNK_SIZE>, M>,
+    _value: PhantomData<V>,
+}
+
+impl<K, V, M, const CHUNK_SIZE: u32> StableChunkedMap<K, V, M, CHUNK_SIZE>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Create new instance of the map.
+    pub fn new(memory: M) -> Self {
+        assert!(CHUNK_SIZE > 0, "chunk size must be greater than zero");
+        Self {
+            chunks: StableBTreeMap::new(memory),
+            _value: PhantomData,
+        }
+    }
+
+    /// Return value associated with `key` from stable memory.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut chunks = self.value_chunks(key).peekable();
+        chunks.peek()?;
+
+        let bytes: Vec<u8> = chunks.flat_map(|(_, chunk)| chunk.0).collect();
+        Some(V::from_bytes(Cow::Owned(bytes)))
+    }
+
+    /// Add or replace value associated with `key` in stable memory.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let previous = self.remove(&key);
+
+        let bytes = value.to_bytes();
+        // Empty values are stored as a single empty chunk
+        let chunks: Vec<&[u8]> = match bytes.is_empty() {
+            true => vec![&[]],
+            false => bytes.chunks(CHUNK_SIZE as usize).collect(),
+        };
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.chunks
+                .insert((key.clone(), index as u32), Chunk(chunk.to_vec()));
+        }
+
+        previous
+    }
+
+    /// Remove value associated with `key` from stable memory.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.get(key)?;
+        let indices: Vec<_> = self.value_chunks(key).map(|(index, _)| index).collect();
+        for index in indices {
+            self.chunks.remove(&(key.clone(), index));
+        }
+        Some(value)
+    }
+
+    /// Returns the size in bytes of the serialized value associated with `key`.
+    pub fn value_size(&self, key: &K) -> Option<u64> {
+        let ((last_key, index), chunk) = self
+            .chunks
+            .iter_upper_bound(&(key.clone(), u32::MAX))
+            .next()?;
+        (&last_key == key).then(|| u64::from(index) * u64::from(CHUNK_SIZE) + chunk.0.len() as u64)
+    }
+
+    /// Reads at most `len` bytes of the serialized value associated with `key`, starting at
+    /// `offset`, e.g. to stream a large value in several responses.
+    ///
+    /// Only the chunks of the range are read, so the value is never loaded in full. Returns an
+    /// empty vector if `offset` is past the end of the value, and `None` if there is no value.
+    pub fn read_value_range(&self, key: &K, offset: u64, len: u64) -> Option<Vec<u8>> {
+        if !self.contains_key(key) {
+            return None;
+        }
+
+        let chunk_size = u64::from(CHUNK_SIZE);
+        let first_chunk = u32::try_from(offset / chunk_size).unwrap_or(u32::MAX);
+        let mut skip = (offset % chunk_size) as usize;
+        let mut bytes = Vec::new();
+        for (_, chunk) in self
+            .chunks
+            .range((key.clone(), first_chunk)..=(key.clone(), u32::MAX))
+        {
+            if bytes.len() as u64 >= len {
+                break;
+            }
+            let available = chunk.0.get(skip..).unwrap_or_default();
+            let missing = (len - bytes.len() as u64).min(available.len() as u64) as usize;
+            bytes.extend_from_slice(&available[..missing]);
+            skip = 0;
+        }
+
+        Some(bytes)
+    }
+
+    /// True if contains the key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.chunks.contains_key(&(key.clone(), 0))
+    }
+
+    /// Is the map empty.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Remove all entries from the map.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Number of chunks stored in the map.
+    pub fn chunks_len(&self) -> u64 {
+        self.chunks.len()
+    }
+
+    fn value_chunks(&self, key: &K) -> impl Iterator<Item = (u32, Chunk<CHUNK_SIZE>)> + '_ {
+        self.chunks
+            .range((key.clone(), 0)..=(key.clone(), u32::MAX))
+            .map(|((_, index), chunk)| (index, chunk))
+    }
+}
+
+/// A part of a value stored in a `StableChunkedMap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chunk<const SIZE: u32>(pub(crate) Vec<u8>);
+
+impl<const SIZE: u32> Storable for Chunk<SIZE> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: SIZE,
+        is_fixed_size: false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    #[test]
+    fn chunked_map_works() {
+        let mut map = StableChunkedMap::<u64, StringValue, _, 16>::new(VectorMemory::default());
+        assert!(map.is_empty());
+
+        assert_eq!(map.insert(1, str_val(40)), None);
+        assert_eq!(map.insert(2, str_val(16)), None);
+        assert_eq!(map.insert(3, str_val(0)), None);
+        assert_eq!(map.chunks_len(), 5);
+
+        assert_eq!(map.get(&1), Some(str_val(40)));
+        assert_eq!(map.get(&2), Some(str_val(16)));
+        assert_eq!(map.get(&3), Some(str_val(0)));
+        assert_eq!(map.get(&4), None);
+        assert!(map.contains_key(&3));
+
+        assert_eq!(map.insert(1, str_val(10)), Some(str_val(40)));
+        assert_eq!(map.get(&1), Some(str_val(10)));
+        assert_eq!(map.chunks_len(), 3);
+
+        assert_eq!(map.remove(&2), Some(str_val(16)));
+        assert_eq!(map.remove(&2), None);
+        assert!(!map.contains_key(&2));
+
+        map.clear();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn should_read_value_ranges() {
+        let mut map = StableChunkedMap::<u64, Vec<u8>, _, 16>::new(VectorMemory::default());
+        let value: Vec<u8> = (0..40).collect();
+        map.insert(1, value.clone());
+        map.insert(2, vec![]);
+        map.insert(3, vec![1; 16]);
+
+        assert_eq!(map.value_size(&1), Some(40));
+        assert_eq!(map.value_size(&2), Some(0));
+        assert_eq!(map.value_size(&3), Some(16));
+        assert_eq!(map.value_size(&4), None);
+
+        assert_eq!(map.read_value_range(&1, 0, 10), Some(value[..10].to_vec()));
+        assert_eq!(
+            map.read_value_range(&1, 14, 20),
+            Some(value[14..34].to_vec())
+        );
+        assert_eq!(
+            map.read_value_range(&1, 32, 100),
+            Some(value[32..].to_vec())
+        );
+        assert_eq!(map.read_value_range(&1, 40, 10), Some(vec![]));
+        assert_eq!(map.read_value_range(&1, 100, 10), Some(vec![]));
+        assert_eq!(map.read_value_range(&2, 0, 10), Some(vec![]));
+        assert_eq!(map.read_value_range(&4, 0, 10), None);
+
+        let streamed: Vec<u8> = (0..3)
+            .flat_map(|part| map.read_value_range(&1, part * 16, 16).unwrap())
+            .collect();
+        assert_eq!(streamed, value);
+    }
+}
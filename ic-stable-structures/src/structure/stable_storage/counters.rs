@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+
+use dfinity_stable_structures::Memory;
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::{Error, Result};
+
+/// Named counters in stable memory, e.g. for metrics or sequence numbers.
+///
+/// A counter which was never updated is zero, and a counter set to zero is not stored.
+pub struct StableCounters<M: Memory> {
+    counters: StableBTreeMap<String, u128, M>,
+}
+
+impl<M: Memory> StableCounters<M> {
+    /// Create new instance of the counters.
+    pub fn new(memory: M) -> Self {
+        Self {
+            counters: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Returns the value of the counter.
+    pub fn get(&self, name: &str) -> u128 {
+        self.counters.get(&name.to_string()).unwrap_or_default()
+    }
+
+    /// Adds `delta` to the counter and returns the new value.
+    ///
+    /// Fails without changing the counter if the value overflows.
+    pub fn inc(&mut self, name: &str, delta: u128) -> Result<u128> {
+        let value = self
+            .get(name)
+            .checked_add(delta)
+            .ok_or_else(|| Error::CounterOverflow(name.to_string()))?;
+        self.set(name, value);
+        Ok(value)
+    }
+
+    /// Subtracts `delta` from the counter and returns the new value.
+    ///
+    /// Fails without changing the counter if the value would be negative.
+    pub fn dec(&mut self, name: &str, delta: u128) -> Result<u128> {
+        let value = self
+            .get(name)
+            .checked_sub(delta)
+            .ok_or_else(|| Error::CounterUnderflow(name.to_string()))?;
+        self.set(name, value);
+        Ok(value)
+    }
+
+    /// Sets the value of the counter.
+    pub fn set(&mut self, name: &str, value: u128) {
+        if value == 0 {
+            self.counters.remove(&name.to_string());
+        } else {
+            self.counters.insert(name.to_string(), value);
+        }
+    }
+
+    /// Returns the non-zero counters.
+    pub fn snapshot_all(&self) -> BTreeMap<String, u128> {
+        self.counters.iter().collect()
+    }
+
+    /// Resets all the counters to zero.
+    pub fn clear(&mut self) {
+        self.counters.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_update_counters() {
+        let memory = VectorMemory::default();
+        let mut counters = StableCounters::new(memory.clone());
+        assert_eq!(counters.get("requests"), 0);
+
+        assert_eq!(counters.inc("requests", 1).unwrap(), 1);
+        assert_eq!(counters.inc("requests", 2).unwrap(), 3);
+        assert_eq!(counters.inc("next_id", 1).unwrap(), 1);
+        assert_eq!(counters.dec("requests", 1).unwrap(), 2);
+
+        let counters = StableCounters::new(memory);
+        assert_eq!(
+            counters.snapshot_all(),
+            BTreeMap::from([("next_id".to_string(), 1), ("requests".to_string(), 2)])
+        );
+    }
+
+    #[test]
+    fn should_reject_overflow_and_underflow() {
+        let mut counters = StableCounters::new(VectorMemory::default());
+        counters.set("balance", u128::MAX);
+
+        assert!(matches!(
+            counters.inc("balance", 1),
+            Err(Error::CounterOverflow(name)) if name == "balance"
+        ));
+        assert_eq!(counters.get("balance"), u128::MAX);
+
+        assert!(matches!(
+            counters.dec("missing", 1),
+            Err(Error::CounterUnderflow(_))
+        ));
+        assert_eq!(counters.dec("balance", u128::MAX).unwrap(), 0);
+        assert!(counters.snapshot_all().is_empty());
+    }
+}
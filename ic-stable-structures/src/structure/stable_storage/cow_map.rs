@@ -0,0 +1,257 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use dfinity_stable_structures::storable::Bound as StorableBound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, CellStructure, StableBTreeMap, StableCell};
+use crate::{Error, Result};
+
+const ABSENT_TAG: u8 = 0;
+const PRESENT_TAG: u8 = 1;
+
+/// Value of a key when the scan was opened, `None` if the key was absent
+struct Preimage<V>(Option<V>);
+
+impl<V: Storable> Storable for Preimage<V> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        match &self.0 {
+            None => vec![ABSENT_TAG].into(),
+            Some(value) => {
+                let value = value.to_bytes();
+                let mut buf = Vec::with_capacity(1 + value.len());
+                buf.push(PRESENT_TAG);
+                buf.extend_from_slice(&value);
+                buf.into()
+            }
+        }
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes[0] {
+            ABSENT_TAG => Self(None),
+            _ => Self(Some(V::from_bytes(bytes[1..].to_vec().into()))),
+        }
+    }
+
+    const BOUND: StorableBound = match V::BOUND {
+        StorableBound::Bounded { max_size, .. } => StorableBound::Bounded {
+            max_size: max_size + 1,
+            is_fixed_size: false,
+        },
+        StorableBound::Unbounded => StorableBound::Unbounded,
+    };
+}
+
+/// [`StableBTreeMap`] whose entries can be scanned as they were when the scan was opened, while the
+/// map is updated.
+///
+/// A long scan, e.g. computing a total over several messages from a task of the scheduler, would
+/// otherwise skip the keys inserted behind its cursor and see the updates of the keys ahead of it.
+/// While a scan is open, the first update of every key copies its previous value to a separate
+/// map, which [`Self::scan_from`] reads instead of the current value. The copies are dropped by
+/// [`Self::close_scan`], so the extra memory is proportional to the keys updated during the scan.
+pub struct CowBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    map: StableBTreeMap<K, V, M>,
+    preimages: StableBTreeMap<K, Preimage<V>, M>,
+    /// 1 if a scan is open
+    scan_open: StableCell<u8, M>,
+}
+
+impl<K, V, M> CowBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Creates the map, or loads it and its open scan from the memories.
+    pub fn new(map_memory: M, preimages_memory: M, state_memory: M) -> Result<Self> {
+        Ok(Self {
+            map: StableBTreeMap::new(map_memory),
+            preimages: StableBTreeMap::new(preimages_memory),
+            scan_open: StableCell::new(state_memory, 0)?,
+        })
+    }
+
+    /// Returns the map, for the read operations which are not in the [`BTreeMapStructure`] trait.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.map
+    }
+
+    /// True if a scan is open.
+    pub fn is_scan_open(&self) -> bool {
+        *self.scan_open.get() == 1
+    }
+
+    /// Opens a scan of the current entries. Only one scan can be open at a time.
+    pub fn open_scan(&mut self) -> Result<()> {
+        if self.is_scan_open() {
+            return Err(Error::ScanInProgress);
+        }
+        self.scan_open.set(1)
+    }
+
+    /// Closes the scan and drops the previous values of the keys updated since it was opened.
+    pub fn close_scan(&mut self) -> Result<()> {
+        self.preimages.clear();
+        self.scan_open.set(0)
+    }
+
+    /// Returns an iterator over the entries as they were when the scan was opened, starting after
+    /// the key `after`, or from the first key if `None`, in key order.
+    ///
+    /// Iterates over the current entries if no scan is open.
+    pub fn scan_from(&self, after: Option<&K>) -> impl Iterator<Item = (K, V)> + '_ {
+        let start = match after {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
+        let mut current = self.map.range((start.clone(), Bound::Unbounded)).peekable();
+        let mut preimages = self.preimages.range((start, Bound::Unbounded)).peekable();
+
+        std::iter::from_fn(move || loop {
+            let order = match (current.peek(), preimages.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((key, _)), Some((preimage_key, _))) => key.cmp(preimage_key),
+            };
+            if order != Ordering::Greater {
+                let entry = current.next();
+                if order == Ordering::Less {
+                    return entry;
+                }
+            }
+            if let Some((key, Preimage(Some(value)))) = preimages.next() {
+                return Some((key, value));
+            }
+        })
+    }
+
+    /// Saves the current value of the key if it is the first update of the key during the scan.
+    fn save_preimage(&mut self, key: &K) {
+        if self.is_scan_open() && !self.preimages.contains_key(key) {
+            self.preimages
+                .insert(key.clone(), Preimage(self.map.get(key)));
+        }
+    }
+}
+
+impl<K, V, M> BTreeMapStructure<K, V> for CowBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.save_preimage(&key);
+        self.map.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.save_preimage(key);
+        self.map.remove(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.map.first_key_value()
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.map.last_key_value()
+    }
+
+    fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Removes all the entries. If a scan is open, the entries are copied first, so the clear
+    /// reads the whole map.
+    fn clear(&mut self) {
+        if self.is_scan_open() {
+            let keys: Vec<_> = self.map.keys().collect();
+            for key in keys {
+                self.save_preimage(&key);
+            }
+        }
+        self.map.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn new_map() -> CowBTreeMap<u32, u64, VectorMemory> {
+        CowBTreeMap::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn should_scan_entries_of_open_scan() {
+        let mut map = new_map();
+        for key in 0..6 {
+            map.insert(key, u64::from(key) * 10);
+        }
+        map.open_scan().unwrap();
+        assert!(matches!(map.open_scan(), Err(Error::ScanInProgress)));
+
+        let first_chunk: Vec<_> = map.scan_from(None).take(2).collect();
+        map.insert(0, 1);
+        map.insert(3, 31);
+        map.insert(3, 32);
+        map.remove(&4);
+        map.insert(7, 70);
+        let second_chunk: Vec<_> = map.scan_from(Some(&1)).collect();
+
+        assert_eq!(first_chunk, vec![(0, 0), (1, 10)]);
+        assert_eq!(second_chunk, vec![(2, 20), (3, 30), (4, 40), (5, 50)]);
+        assert_eq!(map.get(&3), Some(32));
+
+        map.close_scan().unwrap();
+        assert_eq!(
+            map.scan_from(None).collect::<Vec<_>>(),
+            vec![(0, 1), (1, 10), (2, 20), (3, 32), (5, 50), (7, 70)]
+        );
+    }
+
+    #[test]
+    fn should_scan_cleared_map() {
+        let mut map = new_map();
+        map.insert(1, 10);
+        map.insert(2, 20);
+        map.open_scan().unwrap();
+        map.clear();
+        map.insert(3, 30);
+
+        assert_eq!(
+            map.scan_from(None).collect::<Vec<_>>(),
+            vec![(1, 10), (2, 20)]
+        );
+        assert_eq!(map.len(), 1);
+    }
+}
@@ -0,0 +1,206 @@
+use std::ops::RangeBounds;
+
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, MultimapStructure, StableBTreeMap, StableMultimap};
+use crate::{Bounded, Error, Result};
+
+/// Directed graph in stable memory, with attributes on the nodes and on the edges.
+///
+/// The edges are stored twice, by source and by target node, so both the successors and the
+/// predecessors of a node are read without scanning the graph. The edges of a node are ordered by
+/// the other node, so [`Self::edges_range`] reads e.g. the edges to a range of timestamps or
+/// versions.
+pub struct StableGraph<N, NA, EA, M>
+where
+    N: Storable + Ord + Clone + Bounded,
+    NA: Storable,
+    EA: Storable,
+    M: Memory,
+{
+    nodes: StableBTreeMap<N, NA, M>,
+    /// Attributes of the edges by source and target node
+    outgoing: StableMultimap<N, N, EA, M>,
+    /// Edges by target and source node
+    incoming: StableMultimap<N, N, (), M>,
+}
+
+impl<N, NA, EA, M> StableGraph<N, NA, EA, M>
+where
+    N: Storable + Ord + Clone + Bounded,
+    NA: Storable,
+    EA: Storable,
+    M: Memory,
+{
+    /// Create new instance of the graph.
+    pub fn new(nodes_memory: M, outgoing_memory: M, incoming_memory: M) -> Self {
+        Self {
+            nodes: StableBTreeMap::new(nodes_memory),
+            outgoing: StableMultimap::new(outgoing_memory),
+            incoming: StableMultimap::new(incoming_memory),
+        }
+    }
+
+    /// Add or replace the attributes of the node, and returns the previous ones.
+    pub fn add_node(&mut self, node: N, attributes: NA) -> Option<NA> {
+        self.nodes.insert(node, attributes)
+    }
+
+    /// Returns the attributes of the node.
+    pub fn node(&self, node: &N) -> Option<NA> {
+        self.nodes.get(node)
+    }
+
+    /// True if the node is in the graph.
+    pub fn contains_node(&self, node: &N) -> bool {
+        self.nodes.contains_key(node)
+    }
+
+    /// Removes the node with its edges, and returns its attributes.
+    pub fn remove_node(&mut self, node: &N) -> Option<NA> {
+        let attributes = self.nodes.remove(node)?;
+
+        let successors: Vec<_> = self.outgoing.range(node).map(|(to, _)| to).collect();
+        for to in successors {
+            self.incoming.remove(&to, node);
+        }
+        let predecessors: Vec<_> = self.incoming.range(node).map(|(from, _)| from).collect();
+        for from in predecessors {
+            self.outgoing.remove(&from, node);
+        }
+        self.outgoing.remove_partial(node);
+        self.incoming.remove_partial(node);
+
+        Some(attributes)
+    }
+
+    /// Add or replace the attributes of the edge, and returns the previous ones.
+    ///
+    /// Fails if one of the nodes is not in the graph.
+    pub fn add_edge(&mut self, from: &N, to: &N, attributes: EA) -> Result<Option<EA>> {
+        if !self.nodes.contains_key(from) || !self.nodes.contains_key(to) {
+            return Err(Error::NodeNotFound);
+        }
+
+        self.incoming.insert(to, from, ());
+        Ok(self.outgoing.insert(from, to, attributes))
+    }
+
+    /// Returns the attributes of the edge.
+    pub fn edge(&self, from: &N, to: &N) -> Option<EA> {
+        self.outgoing.get(from, to)
+    }
+
+    /// Removes the edge, and returns its attributes.
+    pub fn remove_edge(&mut self, from: &N, to: &N) -> Option<EA> {
+        self.incoming.remove(to, from);
+        self.outgoing.remove(from, to)
+    }
+
+    /// Returns an iterator over the targets of the edges from the node, with the attributes of
+    /// the edges, ordered by target.
+    pub fn successors(&self, node: &N) -> impl Iterator<Item = (N, EA)> + '_ {
+        self.outgoing.range(node)
+    }
+
+    /// Returns an iterator over the sources of the edges to the node, ordered by source.
+    pub fn predecessors(&self, node: &N) -> impl Iterator<Item = N> + '_ {
+        self.incoming.range(node).map(|(from, ())| from)
+    }
+
+    /// Returns an iterator over the edges from the node to the targets in `targets`, ordered by
+    /// target.
+    pub fn edges_range(
+        &self,
+        from: &N,
+        targets: impl RangeBounds<N>,
+    ) -> impl Iterator<Item = (N, EA)> + '_ {
+        self.outgoing.range_by_second_key(from, targets)
+    }
+
+    /// Number of nodes
+    pub fn node_count(&self) -> u64 {
+        self.nodes.len()
+    }
+
+    /// Number of edges
+    pub fn edge_count(&self) -> u64 {
+        self.outgoing.len()
+    }
+
+    /// Removes all the nodes and edges.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.outgoing.clear();
+        self.incoming.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn new_graph() -> StableGraph<u32, u64, u64, VectorMemory> {
+        StableGraph::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+    }
+
+    #[test]
+    fn should_add_nodes_and_edges() {
+        let mut graph = new_graph();
+        for node in 1..=4 {
+            graph.add_node(node, node as u64 * 10);
+        }
+        graph.add_edge(&1, &2, 12).unwrap();
+        graph.add_edge(&1, &3, 13).unwrap();
+        graph.add_edge(&1, &4, 14).unwrap();
+        graph.add_edge(&2, &3, 23).unwrap();
+        assert_eq!(graph.add_edge(&1, &2, 120).unwrap(), Some(12));
+
+        assert!(matches!(
+            graph.add_edge(&1, &5, 15),
+            Err(Error::NodeNotFound)
+        ));
+        assert_eq!(
+            graph.successors(&1).collect::<Vec<_>>(),
+            vec![(2, 120), (3, 13), (4, 14)]
+        );
+        assert_eq!(graph.predecessors(&3).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(
+            graph.edges_range(&1, 3..).collect::<Vec<_>>(),
+            vec![(3, 13), (4, 14)]
+        );
+        assert_eq!(graph.edge(&2, &3), Some(23));
+        assert_eq!(graph.edge(&3, &2), None);
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.edge_count(), 4);
+    }
+
+    #[test]
+    fn should_remove_edges_of_removed_node() {
+        let mut graph = new_graph();
+        for node in 1..=3 {
+            graph.add_node(node, 0);
+        }
+        graph.add_edge(&1, &2, 12).unwrap();
+        graph.add_edge(&2, &3, 23).unwrap();
+        graph.add_edge(&3, &1, 31).unwrap();
+
+        assert_eq!(graph.remove_edge(&3, &1), Some(31));
+        assert_eq!(graph.predecessors(&1).count(), 0);
+
+        assert_eq!(graph.remove_node(&2), Some(0));
+        assert_eq!(graph.edge_count(), 0);
+        assert_eq!(graph.successors(&1).count(), 0);
+        assert_eq!(graph.predecessors(&3).count(), 0);
+        assert!(!graph.contains_node(&2));
+
+        graph.clear();
+        assert_eq!(graph.node_count(), 0);
+    }
+}
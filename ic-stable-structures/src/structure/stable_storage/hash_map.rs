@@ -0,0 +1,195 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hash of the serialized key, which is stable across compiler versions
+fn key_hash(key: &[u8]) -> u64 {
+    key.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// A key with its value
+struct HashEntry<K, V> {
+    key: K,
+    value: V,
+}
+
+impl<K: Storable, V: Storable> Storable for HashEntry<K, V> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let key = self.key.to_bytes();
+        let value = self.value.to_bytes();
+        let mut buf = Vec::with_capacity(size_of::<u32>() + key.len() + value.len());
+        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&key);
+        buf.extend_from_slice(&value);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let key_len =
+            u32::from_le_bytes(bytes[..4].try_into().expect("key length: expected 4 bytes"))
+                as usize;
+        Self {
+            key: K::from_bytes(bytes[4..4 + key_len].to_vec().into()),
+            value: V::from_bytes(bytes[4 + key_len..].to_vec().into()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Key-value map in stable memory indexed by the hash of the keys.
+///
+/// The entries are stored in a `StableBTreeMap` by the 64-bit hash of their key, so a lookup
+/// compares fixed-size hashes instead of the keys, which is cheaper for large keys like hashes and
+/// principals. The keys are unbounded, and the entries are not ordered by key, so the map has no
+/// range queries.
+pub struct StableHashMap<K, V, M>
+where
+    K: Storable + Eq,
+    V: Storable,
+    M: Memory,
+{
+    /// Entries by hash of their key, and index among the keys with the same hash
+    entries: StableBTreeMap<(u64, u32), HashEntry<K, V>, M>,
+}
+
+impl<K, V, M> StableHashMap<K, V, M>
+where
+    K: Storable + Eq,
+    V: Storable,
+    M: Memory,
+{
+    /// Create new instance of the map.
+    pub fn new(memory: M) -> Self {
+        Self {
+            entries: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Return value associated with `key` from stable memory.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.find(key).map(|(_, entry)| entry.value)
+    }
+
+    /// Add or replace value associated with `key` in stable memory.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = key_hash(&key.to_bytes());
+        let mut next_index = 0;
+        for ((_, index), entry) in self.entries.range((hash, 0)..=(hash, u32::MAX)) {
+            if entry.key == key {
+                return self
+                    .entries
+                    .insert((hash, index), HashEntry { key, value })
+                    .map(|entry| entry.value);
+            }
+            next_index = index + 1;
+        }
+
+        self.entries
+            .insert((hash, next_index), HashEntry { key, value });
+        None
+    }
+
+    /// Remove value associated with `key` from stable memory.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (position, _) = self.find(key)?;
+        self.entries.remove(&position).map(|entry| entry.value)
+    }
+
+    /// True if contains the key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Count of items in the map.
+    pub fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    /// Is the map empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove all entries from the map.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns an iterator over the entries, in the order of the hashes of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.entries
+            .iter()
+            .map(|(_, entry)| (entry.key, entry.value))
+    }
+
+    fn find(&self, key: &K) -> Option<((u64, u32), HashEntry<K, V>)> {
+        let hash = key_hash(&key.to_bytes());
+        self.entries
+            .range((hash, 0)..=(hash, u32::MAX))
+            .find(|(_, entry)| &entry.key == key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    #[test]
+    fn should_insert_get_and_remove() {
+        let mut map = StableHashMap::<StringValue, u64, _>::new(VectorMemory::default());
+        assert_eq!(map.insert(str_val(1000), 1), None);
+        assert_eq!(map.insert(str_val(2000), 2), None);
+        assert_eq!(map.insert(str_val(1000), 3), Some(1));
+
+        assert_eq!(map.get(&str_val(1000)), Some(3));
+        assert_eq!(map.get(&str_val(2000)), Some(2));
+        assert_eq!(map.get(&str_val(3000)), None);
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.remove(&str_val(1000)), Some(3));
+        assert_eq!(map.remove(&str_val(1000)), None);
+        assert!(!map.contains_key(&str_val(1000)));
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(str_val(2000), 2)]);
+
+        map.clear();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn should_store_keys_with_colliding_hashes() {
+        let mut map = StableHashMap::<StringValue, u64, _>::new(VectorMemory::default());
+        map.insert(str_val(1), 1);
+
+        // Stores a key under the hash of another key, as if their hashes collided.
+        let hash = key_hash(&str_val(1).to_bytes());
+        map.entries.insert(
+            (hash, 1),
+            HashEntry {
+                key: str_val(2),
+                value: 2,
+            },
+        );
+
+        assert_eq!(map.get(&str_val(1)), Some(1));
+        assert_eq!(map.remove(&str_val(1)), Some(1));
+        map.insert(str_val(3), 3);
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map.entries.get(&(hash, 1)).map(|entry| entry.value),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::RangeBounds;
+
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+
+use crate::structure::{
+    BTreeMapStructure, CellStructure, IterableSortedMapStructure, LogStructure, StableBTreeMap,
+    StableCell, StableLog,
+};
+use crate::{DatasetHash, Result};
+
+/// [`StableBTreeMap`] maintaining the [`DatasetHash`] of its entries, stored in its own memory.
+pub struct HashedBTreeMap<K, V, M, HashMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    HashMemory: Memory,
+{
+    map: StableBTreeMap<K, V, M>,
+    hash: StableCell<DatasetHash, HashMemory>,
+}
+
+impl<K, V, M, HashMemory> HashedBTreeMap<K, V, M, HashMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    HashMemory: Memory,
+{
+    /// Creates the map, or loads it and its hash from the memories.
+    pub fn new(map_memory: M, hash_memory: HashMemory) -> Result<Self> {
+        Ok(Self {
+            map: StableBTreeMap::new(map_memory),
+            hash: StableCell::new(hash_memory, DatasetHash::default())?,
+        })
+    }
+
+    /// Hash of the entries of the map
+    pub fn dataset_hash(&self) -> DatasetHash {
+        *self.hash.get()
+    }
+
+    /// Computes the hash from all the entries, e.g. for a map which was filled before its hash was
+    /// maintained.
+    pub fn rebuild_hash(&mut self) -> Result<()> {
+        let mut hash = DatasetHash::default();
+        for (key, value) in self.map.iter() {
+            hash.add_entry(&[&key.to_bytes(), &value.to_bytes()]);
+        }
+        self.hash.set(hash)
+    }
+
+    /// Returns the map, for the read operations which are not in the [`BTreeMapStructure`] trait.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.map
+    }
+
+    fn update_hash(&mut self, update: impl FnOnce(&mut DatasetHash)) {
+        let mut hash = *self.hash.get();
+        update(&mut hash);
+        self.hash
+            .set(hash)
+            .expect("the dataset hash has a fixed size");
+    }
+}
+
+impl<K, V, M, HashMemory> BTreeMapStructure<K, V> for HashedBTreeMap<K, V, M, HashMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    HashMemory: Memory,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let key_bytes = key.to_bytes().into_owned();
+        let value_bytes = value.to_bytes().into_owned();
+        let previous = self.map.insert(key, value);
+        self.update_hash(|hash| {
+            if let Some(previous) = &previous {
+                hash.remove_entry(&[&key_bytes, &previous.to_bytes()]);
+            }
+            hash.add_entry(&[&key_bytes, &value_bytes]);
+        });
+        previous
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let removed = self.map.remove(key)?;
+        self.update_hash(|hash| hash.remove_entry(&[&key.to_bytes(), &removed.to_bytes()]));
+        Some(removed)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.map.first_key_value()
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.map.last_key_value()
+    }
+
+    fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.update_hash(|hash| *hash = DatasetHash::default());
+    }
+}
+
+impl<K, V, M, HashMemory> IterableSortedMapStructure<K, V> for HashedBTreeMap<K, V, M, HashMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    HashMemory: Memory,
+{
+    type Iterator<'a> = btreemap::Iter<'a, K, V, M> where Self: 'a;
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        self.map.iter()
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        self.map.range(key_range)
+    }
+
+    fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
+        IterableSortedMapStructure::iter_upper_bound(&self.map, bound)
+    }
+}
+
+/// [`StableLog`] maintaining the [`DatasetHash`] of its entries, stored in its own memory.
+pub struct HashedLog<T: Storable, M: Memory, HashMemory: Memory> {
+    log: StableLog<T, M>,
+    hash: StableCell<DatasetHash, HashMemory>,
+}
+
+impl<T: Storable, M: Memory, HashMemory: Memory> HashedLog<T, M, HashMemory> {
+    /// Creates the log, or loads it and its hash from the memories.
+    pub fn new(index_memory: M, data_memory: M, hash_memory: HashMemory) -> Result<Self> {
+        Ok(Self {
+            log: StableLog::new(index_memory, data_memory)?,
+            hash: StableCell::new(hash_memory, DatasetHash::default())?,
+        })
+    }
+
+    /// Hash of the entries of the log
+    pub fn dataset_hash(&self) -> DatasetHash {
+        *self.hash.get()
+    }
+
+    /// Computes the hash from all the entries, e.g. for a log which was filled before its hash was
+    /// maintained.
+    pub fn rebuild_hash(&mut self) -> Result<()> {
+        let mut hash = DatasetHash::default();
+        for index in 0..self.log.len() {
+            if let Some(value) = self.log.get(index) {
+                hash.add_entry(&[&index.to_le_bytes(), &value.to_bytes()]);
+            }
+        }
+        self.hash.set(hash)
+    }
+}
+
+impl<T: Storable, M: Memory, HashMemory: Memory> LogStructure<T> for HashedLog<T, M, HashMemory> {
+    fn get(&self, index: u64) -> Option<T> {
+        self.log.get(index)
+    }
+
+    fn append(&mut self, value: T) -> Result<u64> {
+        let value_bytes = value.to_bytes().into_owned();
+        let index = self.log.append(value)?;
+
+        let mut hash = *self.hash.get();
+        hash.add_entry(&[&index.to_le_bytes(), &value_bytes]);
+        self.hash.set(hash)?;
+        Ok(index)
+    }
+
+    fn len(&self) -> u64 {
+        self.log.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.log.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.log.clear();
+        self.hash
+            .set(DatasetHash::default())
+            .expect("the dataset hash has a fixed size");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+    use crate::Export;
+
+    #[test]
+    fn should_maintain_map_hash() {
+        let mut map = HashedBTreeMap::<u64, StringValue, _, _>::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap();
+        map.insert(1, str_val(1));
+        map.insert(2, str_val(2));
+        map.insert(1, str_val(3));
+        map.insert(3, str_val(3));
+        map.remove(&3);
+        map.remove(&4);
+
+        let mut replica = StableBTreeMap::<u64, StringValue, _>::new(VectorMemory::default());
+        replica.insert(2, str_val(2));
+        replica.insert(1, str_val(3));
+        let replica_hash = DatasetHash::of_snapshot(&replica.export()).unwrap();
+        assert_eq!(map.dataset_hash(), replica_hash);
+        assert_eq!(map.dataset_hash().entries, 2);
+
+        let hash = map.dataset_hash();
+        map.rebuild_hash().unwrap();
+        assert_eq!(map.dataset_hash(), hash);
+
+        map.clear();
+        assert_eq!(map.dataset_hash(), DatasetHash::default());
+    }
+
+    #[test]
+    fn should_maintain_log_hash() {
+        let mut log = HashedLog::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap();
+        log.append(str_val(1)).unwrap();
+        log.append(str_val(1)).unwrap();
+        assert_ne!(log.dataset_hash(), DatasetHash::default());
+
+        let mut replica = StableLog::new(VectorMemory::default(), VectorMemory::default()).unwrap();
+        replica.append(str_val(1)).unwrap();
+        replica.append(str_val(1)).unwrap();
+        assert_eq!(
+            log.dataset_hash(),
+            DatasetHash::of_snapshot(&replica.export()).unwrap()
+        );
+
+        let hash = log.dataset_hash();
+        log.rebuild_hash().unwrap();
+        assert_eq!(log.dataset_hash(), hash);
+    }
+}
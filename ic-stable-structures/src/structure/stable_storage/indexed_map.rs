@@ -0,0 +1,462 @@
+use std::ops::{Bound, RangeBounds};
+
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::Bounded;
+
+/// Derives the secondary key of a value
+type KeyExtractor<V, SK> = Box<dyn Fn(&V) -> Option<SK>>;
+
+/// Secondary index of an [`IndexedMap`]: maps the key derived from each value to the primary
+/// keys of the values, stored in its own memory.
+pub struct SecondaryIndex<K, V, SK, M>
+where
+    K: Storable + Ord + Clone + Bounded,
+    SK: Storable + Ord + Clone,
+    M: Memory,
+{
+    key_of: KeyExtractor<V, SK>,
+    entries: StableBTreeMap<(SK, K), (), M>,
+}
+
+impl<K, V, SK, M> SecondaryIndex<K, V, SK, M>
+where
+    K: Storable + Ord + Clone + Bounded,
+    SK: Storable + Ord + Clone,
+    M: Memory,
+{
+    /// Creates the index of the key derived from the values by `key_of`. The values for which
+    /// `key_of` returns `None` are not indexed.
+    pub fn new(memory: M, key_of: impl Fn(&V) -> Option<SK> + 'static) -> Self {
+        Self {
+            key_of: Box::new(key_of),
+            entries: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Primary keys of the values with the secondary key, in ascending order.
+    pub fn keys(&self, secondary_key: &SK) -> impl Iterator<Item = K> + '_ {
+        self.range(secondary_key.clone()..=secondary_key.clone())
+            .map(|(_, key)| key)
+    }
+
+    /// Secondary and primary keys of the values whose secondary key belongs to the range,
+    /// ordered by secondary key, then by primary key.
+    pub fn range(&self, range: impl RangeBounds<SK>) -> SecondaryIndexIter<'_, SK, K, M> {
+        let start = match range.start_bound() {
+            Bound::Included(key) => Bound::Included((key.clone(), K::MIN)),
+            Bound::Excluded(key) => Bound::Excluded((key.clone(), K::MAX)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Included((key.clone(), K::MAX)),
+            Bound::Excluded(key) => Bound::Excluded((key.clone(), K::MIN)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        SecondaryIndexIter(self.entries.range((start, end)))
+    }
+
+    /// Number of indexed values
+    pub fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Iterator over the secondary and primary keys of a [`SecondaryIndex`]
+pub struct SecondaryIndexIter<'a, SK, K, M>(btreemap::Iter<'a, (SK, K), (), M>)
+where
+    SK: Storable + Ord + Clone,
+    K: Storable + Ord + Clone,
+    M: Memory;
+
+impl<'a, SK, K, M> Iterator for SecondaryIndexIter<'a, SK, K, M>
+where
+    SK: Storable + Ord + Clone,
+    K: Storable + Ord + Clone,
+    M: Memory,
+{
+    type Item = (SK, K);
+
+    fn next(&mut self) -> Option<(SK, K)> {
+        self.0.next().map(|(keys, ())| keys)
+    }
+}
+
+/// Indexes maintained by an [`IndexedMap`]: a [`SecondaryIndex`], or a tuple of up to four of
+/// them.
+pub trait Indexes<K, V> {
+    /// Updates the indexes after the value of the key changed from `previous` to `value`.
+    fn on_insert(&mut self, key: &K, previous: Option<&V>, value: &V);
+
+    /// Updates the indexes after the value of the key was removed.
+    fn on_remove(&mut self, key: &K, value: &V);
+
+    /// Removes all the entries of the indexes.
+    fn clear(&mut self);
+}
+
+impl<K, V, SK, M> Indexes<K, V> for SecondaryIndex<K, V, SK, M>
+where
+    K: Storable + Ord + Clone + Bounded,
+    SK: Storable + Ord + Clone,
+    M: Memory,
+{
+    fn on_insert(&mut self, key: &K, previous: Option<&V>, value: &V) {
+        let previous_key = previous.and_then(|previous| (self.key_of)(previous));
+        let new_key = (self.key_of)(value);
+        if previous_key == new_key {
+            return;
+        }
+
+        if let Some(previous_key) = previous_key {
+            self.entries.remove(&(previous_key, key.clone()));
+        }
+        if let Some(new_key) = new_key {
+            self.entries.insert((new_key, key.clone()), ());
+        }
+    }
+
+    fn on_remove(&mut self, key: &K, value: &V) {
+        if let Some(secondary_key) = (self.key_of)(value) {
+            self.entries.remove(&(secondary_key, key.clone()));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+macro_rules! impl_indexes_for_tuple {
+    ($($index:ident : $field:tt),+) => {
+        impl<K, V, $($index),+> Indexes<K, V> for ($($index,)+)
+        where
+            $($index: Indexes<K, V>),+
+        {
+            fn on_insert(&mut self, key: &K, previous: Option<&V>, value: &V) {
+                $(self.$field.on_insert(key, previous, value);)+
+            }
+
+            fn on_remove(&mut self, key: &K, value: &V) {
+                $(self.$field.on_remove(key, value);)+
+            }
+
+            fn clear(&mut self) {
+                $(self.$field.clear();)+
+            }
+        }
+    };
+}
+
+impl_indexes_for_tuple!(A: 0);
+impl_indexes_for_tuple!(A: 0, B: 1);
+impl_indexes_for_tuple!(A: 0, B: 1, C: 2);
+impl_indexes_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+
+/// [`StableBTreeMap`] maintaining secondary indexes of its values on every insert and remove.
+///
+/// ```ignore
+/// let mut users = IndexedMap::new(
+///     memory(1),
+///     (
+///         SecondaryIndex::new(memory(2), |user: &User| Some(user.email.clone())),
+///         SecondaryIndex::new(memory(3), |user: &User| Some(user.age)),
+///     ),
+/// );
+/// users.insert(id, user);
+///
+/// let with_email = users.find(|indexes| &indexes.0, &email).next();
+/// let adults = users.find_range(|indexes| &indexes.1, 18..).collect::<Vec<_>>();
+/// ```
+///
+/// The indexes are stored in their own memories, so they are kept across upgrades like the map.
+/// As for [`StableMultimap`](crate::StableMultimap), the secondary and primary keys must be
+/// bounded.
+/// An index added to an existing map is filled by [`IndexedMap::rebuild_indexes`].
+pub struct IndexedMap<K, V, M, I>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    I: Indexes<K, V>,
+{
+    map: StableBTreeMap<K, V, M>,
+    indexes: I,
+}
+
+impl<K, V, M, I> IndexedMap<K, V, M, I>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    I: Indexes<K, V>,
+{
+    /// Creates the map, or loads it and its indexes from the memories.
+    pub fn new(memory: M, indexes: I) -> Self {
+        Self {
+            map: StableBTreeMap::new(memory),
+            indexes,
+        }
+    }
+
+    /// Returns the indexes, e.g. to read the primary keys without loading the values.
+    pub fn indexes(&self) -> &I {
+        &self.indexes
+    }
+
+    /// Returns the map, for the read operations which are not in the [`BTreeMapStructure`] trait.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.map
+    }
+
+    /// Entries whose secondary key of the index is `secondary_key`, in primary key order.
+    pub fn find<'a, SK, IM>(
+        &'a self,
+        index: impl FnOnce(&'a I) -> &'a SecondaryIndex<K, V, SK, IM>,
+        secondary_key: &SK,
+    ) -> IndexedMapIter<'a, K, V, M, SK, IM>
+    where
+        K: Bounded,
+        SK: Storable + Ord + Clone,
+        IM: Memory,
+    {
+        self.find_range(index, secondary_key.clone()..=secondary_key.clone())
+    }
+
+    /// Entries whose secondary key of the index belongs to the range, ordered by secondary key,
+    /// then by primary key.
+    pub fn find_range<'a, SK, IM>(
+        &'a self,
+        index: impl FnOnce(&'a I) -> &'a SecondaryIndex<K, V, SK, IM>,
+        range: impl RangeBounds<SK>,
+    ) -> IndexedMapIter<'a, K, V, M, SK, IM>
+    where
+        K: Bounded,
+        SK: Storable + Ord + Clone,
+        IM: Memory,
+    {
+        IndexedMapIter {
+            keys: index(&self.indexes).range(range),
+            map: &self.map,
+        }
+    }
+
+    /// Clears the indexes and indexes all the entries again, e.g. after an index was added to an
+    /// existing map.
+    pub fn rebuild_indexes(&mut self) {
+        self.indexes.clear();
+        for (key, value) in self.map.iter() {
+            self.indexes.on_insert(&key, None, &value);
+        }
+    }
+}
+
+impl<K, V, M, I> BTreeMapStructure<K, V> for IndexedMap<K, V, M, I>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    I: Indexes<K, V>,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let previous = self.map.get(&key);
+        self.indexes.on_insert(&key, previous.as_ref(), &value);
+        self.map.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let removed = self.map.remove(key)?;
+        self.indexes.on_remove(key, &removed);
+        Some(removed)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.map.first_key_value()
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.map.last_key_value()
+    }
+
+    fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.indexes.clear();
+    }
+}
+
+/// Iterator over the entries of an [`IndexedMap`] found by a secondary index
+pub struct IndexedMapIter<'a, K, V, M, SK, IM>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    SK: Storable + Ord + Clone,
+    IM: Memory,
+{
+    keys: SecondaryIndexIter<'a, SK, K, IM>,
+    map: &'a StableBTreeMap<K, V, M>,
+}
+
+impl<'a, K, V, M, SK, IM> Iterator for IndexedMapIter<'a, K, V, M, SK, IM>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+    SK: Storable + Ord + Clone,
+    IM: Memory,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        // The index is updated with the map, so the primary keys are always found
+        self.keys
+            .find_map(|(_, key)| self.map.get(&key).map(|value| (key, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use dfinity_stable_structures::storable::Bound as StorableBound;
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct User {
+        age: u32,
+        team: Option<u64>,
+    }
+
+    impl Storable for User {
+        fn to_bytes(&self) -> Cow<[u8]> {
+            let mut buf = self.age.to_le_bytes().to_vec();
+            if let Some(team) = self.team {
+                buf.extend_from_slice(&team.to_le_bytes());
+            }
+            buf.into()
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Self {
+                age: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+                team: (bytes.len() > 4).then(|| u64::from_le_bytes(bytes[4..].try_into().unwrap())),
+            }
+        }
+
+        const BOUND: StorableBound = StorableBound::Unbounded;
+    }
+
+    fn user(age: u32, team: Option<u64>) -> User {
+        User { age, team }
+    }
+
+    type Users = IndexedMap<
+        u64,
+        User,
+        VectorMemory,
+        (
+            SecondaryIndex<u64, User, u64, VectorMemory>,
+            SecondaryIndex<u64, User, u32, VectorMemory>,
+        ),
+    >;
+
+    fn users() -> Users {
+        IndexedMap::new(
+            VectorMemory::default(),
+            (
+                SecondaryIndex::new(VectorMemory::default(), |user: &User| user.team),
+                SecondaryIndex::new(VectorMemory::default(), |user: &User| Some(user.age)),
+            ),
+        )
+    }
+
+    #[test]
+    fn should_maintain_indexes() {
+        let mut users = users();
+        users.insert(1, user(30, Some(7)));
+        users.insert(2, user(17, Some(8)));
+        users.insert(3, user(30, None));
+
+        assert_eq!(
+            users.find(|i| &i.0, &8).collect::<Vec<_>>(),
+            vec![(2, user(17, Some(8)))]
+        );
+        assert_eq!(users.indexes().0.len(), 2);
+        assert_eq!(users.indexes().1.keys(&30).collect::<Vec<_>>(), vec![1, 3]);
+
+        users.insert(2, user(18, Some(7)));
+        assert_eq!(users.find(|i| &i.0, &8).count(), 0);
+        assert_eq!(
+            users
+                .find(|i| &i.0, &7)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            users
+                .find_range(|i| &i.1, 18..)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec![2, 1, 3]
+        );
+        assert_eq!(
+            users
+                .find_range(|i| &i.1, ..30)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+
+        users.remove(&1);
+        assert_eq!(users.indexes().1.keys(&30).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(users.indexes().0.len(), 1);
+
+        users.clear();
+        assert!(users.indexes().0.is_empty());
+        assert!(users.indexes().1.is_empty());
+    }
+
+    #[test]
+    fn should_rebuild_indexes() {
+        let memory = VectorMemory::default();
+        let mut map = StableBTreeMap::<u64, User, _>::new(memory.clone());
+        map.insert(1, user(30, None));
+        map.insert(2, user(17, None));
+
+        let mut users = IndexedMap::new(
+            memory,
+            SecondaryIndex::new(VectorMemory::default(), |user: &User| Some(user.age)),
+        );
+        assert!(users.indexes().is_empty());
+
+        users.rebuild_indexes();
+        assert_eq!(
+            users.find_range(|i| i, 18..).collect::<Vec<_>>(),
+            vec![(1u64, user(30, None))]
+        );
+    }
+}
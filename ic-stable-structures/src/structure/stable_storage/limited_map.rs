@@ -0,0 +1,173 @@
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::stats::WASM_PAGE_SIZE;
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::{Error, Result};
+
+/// Limits of the growth of a structure. `None` means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StructureLimits {
+    /// Largest number of entries
+    pub max_entries: Option<u64>,
+    /// Largest size of the memory of the structure, in bytes
+    pub max_bytes: Option<u64>,
+}
+
+/// [`StableBTreeMap`] refusing the inserts past its limits.
+///
+/// This prevents a single user or endpoint from growing the map until the canister reaches the
+/// stable memory limit, as a full stable memory prevents the upgrades. The memory limit is checked
+/// before every insert of a new key, so an insert can grow the memory a bit past it, by one page
+/// at most. Replacing the value of a key and removing entries are always allowed.
+pub struct LimitedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory + Clone,
+{
+    inner: StableBTreeMap<K, V, M>,
+    memory: M,
+    limits: StructureLimits,
+}
+
+impl<K, V, M> LimitedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory + Clone,
+{
+    /// Create new instance of the map with the limits.
+    pub fn new(memory: M, limits: StructureLimits) -> Self {
+        Self {
+            inner: StableBTreeMap::new(memory.clone()),
+            memory,
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> StructureLimits {
+        self.limits
+    }
+
+    /// Changes the limits. The map keeps its entries if it is already past the new limits.
+    pub fn set_limits(&mut self, limits: StructureLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the inner map for a readonly access, e.g. to iterate over the entries.
+    pub fn inner(&self) -> &StableBTreeMap<K, V, M> {
+        &self.inner
+    }
+
+    /// Size of the memory of the map, in bytes
+    pub fn memory_bytes(&self) -> u64 {
+        self.memory.size() * WASM_PAGE_SIZE
+    }
+
+    /// Return value associated with `key` from stable memory.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.inner.get(key)
+    }
+
+    /// Add or replace value associated with `key` in stable memory.
+    ///
+    /// Fails without changing the map if the key is new and the map reached one of its limits.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>> {
+        if !self.inner.contains_key(&key) {
+            self.check_limits()?;
+        }
+        Ok(self.inner.insert(key, value))
+    }
+
+    /// Remove value associated with `key` from stable memory.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.inner.remove(key)
+    }
+
+    /// True if contains the key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    /// Count of items in the map.
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Is the map empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Remove all entries from the map.
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    fn check_limits(&self) -> Result<()> {
+        if let Some(limit) = self.limits.max_entries {
+            if self.inner.len() >= limit {
+                return Err(Error::EntryLimitExceeded { limit });
+            }
+        }
+        if let Some(limit) = self.limits.max_bytes {
+            if self.memory_bytes() >= limit {
+                return Err(Error::MemoryLimitExceeded { limit });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    #[test]
+    fn should_limit_entries() {
+        let limits = StructureLimits {
+            max_entries: Some(2),
+            max_bytes: None,
+        };
+        let mut map = LimitedBTreeMap::<u32, u32, _>::new(VectorMemory::default(), limits);
+        map.insert(1, 1).unwrap();
+        map.insert(2, 2).unwrap();
+
+        assert!(matches!(
+            map.insert(3, 3),
+            Err(Error::EntryLimitExceeded { limit: 2 })
+        ));
+        assert_eq!(map.insert(2, 20).unwrap(), Some(2));
+        assert_eq!(map.len(), 2);
+
+        map.remove(&1);
+        map.insert(3, 3).unwrap();
+    }
+
+    #[test]
+    fn should_limit_memory() {
+        let limits = StructureLimits {
+            max_entries: None,
+            max_bytes: Some(2 * WASM_PAGE_SIZE),
+        };
+        let mut map = LimitedBTreeMap::<u32, StringValue, _>::new(VectorMemory::default(), limits);
+
+        let mut key = 0;
+        let error = loop {
+            match map.insert(key, str_val(1_000)) {
+                Ok(_) => key += 1,
+                Err(error) => break error,
+            }
+        };
+
+        assert!(matches!(error, Error::MemoryLimitExceeded { .. }));
+        assert!(map.memory_bytes() <= 3 * WASM_PAGE_SIZE);
+        assert_eq!(map.len(), key as u64);
+
+        map.set_limits(StructureLimits::default());
+        map.insert(key, str_val(1_000)).unwrap();
+    }
+}
@@ -1,7 +1,13 @@
+use std::any::type_name;
+
 use dfinity_stable_structures::{log, Memory, Storable};
 
+use crate::stats::storable_size;
 use crate::structure::LogStructure;
-use crate::{Error, Result};
+use crate::{
+    check_field, decode_field, Error, Export, Import, MemoryStats, MemoryUsage, Result,
+    SnapshotHeader, SnapshotWriter, StructureKind,
+};
 
 /// Stores list of immutable values in stable memory.
 /// Provides only `append()` and `get()` operations.
@@ -14,6 +20,15 @@ impl<T: Storable, M: Memory> StableLog<T, M> {
         Ok(Self(Some(log::Log::init(index_memory, data_memory)?)))
     }
 
+    /// Returns the index and data memories of the log, e.g. to release them with
+    /// [`crate::MemoryPool::release`] after clearing the log.
+    pub fn into_memories(mut self) -> (M, M) {
+        self.0
+            .take()
+            .expect("inner log is always present")
+            .into_memories()
+    }
+
     fn get_inner(&self) -> &log::Log<T, M, M> {
         self.0.as_ref().expect("inner log is always present")
     }
@@ -49,3 +64,45 @@ impl<T: Storable, M: Memory> LogStructure<T> for StableLog<T, M> {
         }
     }
 }
+
+impl<T: Storable, M: Memory> MemoryUsage for StableLog<T, M> {
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.len(),
+            bytes: (0..self.len())
+                .filter_map(|index| self.get(index))
+                .map(|value| storable_size(&value))
+                .sum(),
+        }
+    }
+}
+
+impl<T: Storable, M: Memory> Export for StableLog<T, M> {
+    fn export(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(StructureKind::Log, &[type_name::<T>()], self.len());
+        for value in (0..self.len()).filter_map(|index| self.get(index)) {
+            writer.entry(&[&value.to_bytes()]);
+        }
+        writer.finish()
+    }
+}
+
+impl<T: Storable, M: Memory> Import for StableLog<T, M> {
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()> {
+        header.check_kind(StructureKind::Log, 1)
+    }
+
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()> {
+        check_field::<T>(fields[0])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.clear();
+        Ok(())
+    }
+
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()> {
+        self.append(decode_field(fields[0])?)?;
+        Ok(())
+    }
+}
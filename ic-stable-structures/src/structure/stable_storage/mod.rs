@@ -1,11 +1,53 @@
+mod bitmap;
+mod blob_store;
+mod bloom_filter;
 mod btreemap;
+mod btreemap_entry;
 mod cell;
+mod certified_log;
+mod changelog_map;
+mod checksummed_map;
+mod chunked_map;
+mod counters;
+mod cow_map;
+mod graph;
+mod hash_map;
+mod hashed;
+mod indexed_map;
+mod limited_map;
 mod log;
 mod multimap;
+mod prefix_map;
+mod rotating_log;
+mod set;
+mod slot_map;
+mod time_series;
+mod ttl_map;
 mod vec;
 
-pub use btreemap::StableBTreeMap;
-pub use cell::StableCell;
+pub use bitmap::StableBitmap;
+pub use blob_store::StableBlobStore;
+pub use bloom_filter::StableBloomFilter;
+pub use btreemap::{compact_btreemap, StableBTreeMap};
+pub use btreemap_entry::{MapEntry, OccupiedMapEntry, VacantMapEntry};
+pub use cell::{StableCell, WatcherId};
+pub use certified_log::{CertifiedLog, MerkleHash, MerkleWitness};
+pub use changelog_map::{ChangeEvent, ChangelogBTreeMap};
+pub use checksummed_map::{ChecksummedBTreeMap, CorruptionPolicy};
+pub use chunked_map::StableChunkedMap;
+pub use counters::StableCounters;
+pub use cow_map::CowBTreeMap;
+pub use graph::StableGraph;
+pub use hash_map::StableHashMap;
+pub use hashed::{HashedBTreeMap, HashedLog};
+pub use indexed_map::{IndexedMap, IndexedMapIter, Indexes, SecondaryIndex, SecondaryIndexIter};
+pub use limited_map::{LimitedBTreeMap, StructureLimits};
 pub use log::StableLog;
 pub use multimap::{StableMultimap, StableMultimapIter, StableMultimapRangeIter};
-pub use vec::StableVec;
+pub use prefix_map::{StablePrefixMap, StablePrefixMapIter, StablePrefixMapRangeIter};
+pub use rotating_log::StableRotatingLog;
+pub use set::StableSet;
+pub use slot_map::{SlotKey, StableSlotMap};
+pub use time_series::{Rollup, RollupPolicy, StableTimeSeries};
+pub use ttl_map::StableTtlMap;
+pub use vec::{StableVec, StableVecIter};
@@ -1,7 +1,17 @@
+use std::any::type_name;
+use std::ops::{Bound, RangeBounds};
+
 use dfinity_stable_structures::{btreemap, Memory, StableBTreeMap, Storable};
 
+use crate::stats::storable_size;
 use crate::structure::MultimapStructure;
-use crate::Bounded;
+use crate::{
+    check_field, decode_field, Bounded, Export, Import, MemoryStats, MemoryUsage, Result,
+    SnapshotHeader, SnapshotWriter, StructureKind,
+};
+
+/// Bounds of a range of pairs of keys
+type KeyBounds<K1, K2> = (Bound<(K1, K2)>, Bound<(K1, K2)>);
 
 /// `StableMultimap` stores two keys against a single value, making it possible
 /// to fetch all values by the root key, or a single value by specifying both keys.
@@ -28,6 +38,105 @@ where
     pub fn iter_upper_bound(&self, key: &(K1, K2)) -> StableMultimapIter<'_, K1, K2, V, M> {
         StableMultimapIter::new(self.0.iter_upper_bound(key))
     }
+
+    /// Returns an iterator over all the entries, ordered by keys, starting from the given pair of
+    /// keys included.
+    ///
+    /// This permits to scan the whole map in chunks, e.g. across many messages, by resuming the
+    /// iteration from the keys following the last entry of the previous chunk.
+    pub fn iter_from(
+        &self,
+        first_key: &K1,
+        second_key: &K2,
+    ) -> StableMultimapIter<'_, K1, K2, V, M> {
+        StableMultimapIter::new(self.0.range((first_key.clone(), second_key.clone())..))
+    }
+
+    /// Returns an iterator over the entries of `first_key` with the second key in
+    /// `second_key_range`, ordered by the second key.
+    ///
+    /// Unlike filtering [`MultimapStructure::range`], this doesn't read the entries outside of the
+    /// range, e.g. to query the events of a user within a time window.
+    pub fn range_by_second_key(
+        &self,
+        first_key: &K1,
+        second_key_range: impl RangeBounds<K2>,
+    ) -> StableMultimapRangeIter<'_, K1, K2, V, M> {
+        StableMultimapRangeIter::new(
+            self.0
+                .range(Self::second_key_bounds(first_key, second_key_range)),
+        )
+    }
+
+    /// Removes the entries of `first_key` with the second key in `second_key_range`, and returns
+    /// their number, e.g. to prune the events of a user older than a timestamp.
+    pub fn remove_range(&mut self, first_key: &K1, second_key_range: impl RangeBounds<K2>) -> u64 {
+        let (mut start, end) = Self::second_key_bounds(first_key, second_key_range);
+        let mut removed = 0;
+        while let Some((keys, _)) = self.0.range((start, end.clone())).next() {
+            self.0.remove(&keys);
+            removed += 1;
+            start = Bound::Excluded(keys);
+        }
+        removed
+    }
+
+    /// Visits at most `max_entries` entries starting after the pair of keys `after`, or from the
+    /// first entry if `None`, and removes the entries for which `predicate` returns `false`.
+    ///
+    /// Returns the pair of keys to pass to the next call, or `None` when all the entries are
+    /// visited.
+    pub fn retain(
+        &mut self,
+        after: Option<&(K1, K2)>,
+        max_entries: usize,
+        mut predicate: impl FnMut(&K1, &K2, &V) -> bool,
+    ) -> Option<(K1, K2)> {
+        if max_entries == 0 {
+            return after.cloned();
+        }
+
+        let start = match after {
+            Some(keys) => Bound::Excluded(keys.clone()),
+            None => Bound::Unbounded,
+        };
+        let mut next = None;
+        let mut removed = Vec::new();
+        for (visited, (keys, value)) in self
+            .0
+            .range((start, Bound::Unbounded))
+            .take(max_entries)
+            .enumerate()
+        {
+            if visited + 1 == max_entries {
+                next = Some(keys.clone());
+            }
+            if !predicate(&keys.0, &keys.1, &value) {
+                removed.push(keys);
+            }
+        }
+
+        for keys in &removed {
+            self.0.remove(keys);
+        }
+        next
+    }
+
+    /// Bounds of the pairs of keys of `first_key` with the second key in `second_key_range`
+    fn second_key_bounds(
+        first_key: &K1,
+        second_key_range: impl RangeBounds<K2>,
+    ) -> KeyBounds<K1, K2> {
+        let with_first_key = |bound: Bound<&K2>, unbounded: K2| match bound {
+            Bound::Included(key) => Bound::Included((first_key.clone(), key.clone())),
+            Bound::Excluded(key) => Bound::Excluded((first_key.clone(), key.clone())),
+            Bound::Unbounded => Bound::Included((first_key.clone(), unbounded)),
+        };
+        (
+            with_first_key(second_key_range.start_bound(), K2::MIN),
+            with_first_key(second_key_range.end_bound(), K2::MAX),
+        )
+    }
 }
 
 impl<K1, K2, V, M> MultimapStructure<K1, K2, V> for StableMultimap<K1, K2, V, M>
@@ -68,8 +177,8 @@ where
         found
     }
 
-    fn len(&self) -> usize {
-        self.0.len() as usize
+    fn len(&self) -> u64 {
+        self.0.len()
     }
 
     fn is_empty(&self) -> bool {
@@ -185,6 +294,81 @@ where
     }
 }
 
+impl<K1, K2, V, M> MemoryUsage for StableMultimap<K1, K2, V, M>
+where
+    K1: Storable + Ord + Clone,
+    K2: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.0.len(),
+            bytes: self
+                .0
+                .iter()
+                .map(|(keys, value)| storable_size(&keys) + storable_size(&value))
+                .sum(),
+        }
+    }
+}
+
+impl<K1, K2, V, M> Export for StableMultimap<K1, K2, V, M>
+where
+    K1: Storable + Ord + Clone,
+    K2: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    fn export(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(
+            StructureKind::Multimap,
+            &[type_name::<K1>(), type_name::<K2>(), type_name::<V>()],
+            self.0.len(),
+        );
+        for ((first_key, second_key), value) in self.0.iter() {
+            writer.entry(&[
+                &first_key.to_bytes(),
+                &second_key.to_bytes(),
+                &value.to_bytes(),
+            ]);
+        }
+        writer.finish()
+    }
+}
+
+impl<K1, K2, V, M> Import for StableMultimap<K1, K2, V, M>
+where
+    K1: Storable + Ord + Clone,
+    K2: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()> {
+        header.check_kind(StructureKind::Multimap, 3)
+    }
+
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()> {
+        check_field::<K1>(fields[0])?;
+        check_field::<K2>(fields[1])?;
+        check_field::<V>(fields[2])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.clear();
+        Ok(())
+    }
+
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()> {
+        self.insert(
+            &decode_field(fields[0])?,
+            &decode_field(fields[1])?,
+            decode_field(fields[2])?,
+        );
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -309,6 +493,37 @@ mod test {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn iter_all_entries() {
+        let mut mm = StableMultimap::new(VectorMemory::default());
+        for k1 in 0..10u32 {
+            for k2 in 0..10u32 {
+                mm.insert(&k1, &k2, k1 * 100 + k2);
+            }
+        }
+
+        let entries: Vec<_> = mm.iter().collect();
+        assert_eq!(entries.len(), 100);
+        assert_eq!(entries[0], (0, 0, 0));
+        assert_eq!(entries[99], (9, 9, 909));
+
+        // scan in chunks of 30 entries
+        let mut scanned = vec![];
+        let mut next_keys = Some((0, 0));
+        while let Some((k1, k2)) = next_keys {
+            let mut chunk = mm.iter_from(&k1, &k2).take(31).collect::<Vec<_>>();
+            next_keys = match chunk.len() {
+                31 => chunk.pop().map(|(k1, k2, _)| (k1, k2)),
+                _ => None,
+            };
+            scanned.extend(chunk);
+        }
+        assert_eq!(scanned, entries);
+
+        assert_eq!(mm.iter_from(&9, &9).collect::<Vec<_>>(), vec![(9, 9, 909)]);
+        assert_eq!(mm.iter_from(&10, &0).next(), None);
+    }
+
     #[test]
     fn range_iter() {
         let k1 = Array([1u8, 2]);
@@ -318,6 +533,56 @@ mod test {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn range_by_second_key() {
+        let mut mm = StableMultimap::<u32, u64, u32, _>::new(VectorMemory::default());
+        for timestamp in [10, 20, 30, 40] {
+            mm.insert(&1, &timestamp, timestamp as u32);
+            mm.insert(&2, &timestamp, 0);
+        }
+
+        let values = |iter: StableMultimapRangeIter<'_, u32, u64, u32, VectorMemory>| {
+            iter.map(|(_, value)| value).collect::<Vec<_>>()
+        };
+        assert_eq!(values(mm.range_by_second_key(&1, 15..40)), vec![20, 30]);
+        assert_eq!(
+            values(mm.range_by_second_key(&1, 20..=40)),
+            vec![20, 30, 40]
+        );
+        assert_eq!(values(mm.range_by_second_key(&1, ..20)), vec![10]);
+        assert_eq!(values(mm.range_by_second_key(&1, 35..)), vec![40]);
+        assert_eq!(values(mm.range_by_second_key(&1, ..)).len(), 4);
+        assert_eq!(values(mm.range_by_second_key(&3, ..)), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn remove_range_and_retain() {
+        let mut mm = StableMultimap::<u32, u64, u32, _>::new(VectorMemory::default());
+        for timestamp in [10, 20, 30, 40] {
+            mm.insert(&1, &timestamp, timestamp as u32);
+            mm.insert(&2, &timestamp, timestamp as u32);
+        }
+
+        assert_eq!(mm.remove_range(&1, ..30), 2);
+        assert_eq!(
+            mm.range(&1).map(|(key, _)| key).collect::<Vec<_>>(),
+            vec![30, 40]
+        );
+        assert_eq!(mm.range(&2).count(), 4);
+
+        let mut after = None;
+        loop {
+            after = mm.retain(after.as_ref(), 2, |_, timestamp, _| *timestamp != 40);
+            if after.is_none() {
+                break;
+            }
+        }
+        assert_eq!(
+            mm.iter().map(|(k1, k2, _)| (k1, k2)).collect::<Vec<_>>(),
+            vec![(1, 30), (2, 10), (2, 20), (2, 30)]
+        );
+    }
+
     #[test]
     fn iter_upper_bound() {
         let mm = make_map();
@@ -0,0 +1,323 @@
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::Bounded;
+
+/// Iterator over the entries of a prefix id
+type PrefixEntriesIter<'a, S, V, M> = btreemap::Iter<'a, (u64, S), V, M>;
+
+/// Stores key-value data in stable memory, with composite keys made of a long prefix and a suffix,
+/// e.g. `(Principal, timestamp)`.
+///
+/// The dfinity `BTreeMap` stores the full key inside each node. For key designs where many entries
+/// share the same long prefix this wastes stable memory and increases the amount of data read and
+/// written for each node. The node format belongs to the dfinity crate, so the compression is done
+/// by this structure, to use in place of a `StableBTreeMap<(P, S), V>`: it stores every distinct
+/// prefix once and replaces it inside the nodes with an 8 bytes id.
+///
+/// Entries are sorted by key, like in a `StableBTreeMap<(P, S), V>`: the iteration goes through
+/// the prefixes in order, and through the entries of each prefix, so the ids are only assigned in
+/// insertion order and never change.
+pub struct StablePrefixMap<P, S, V, M>
+where
+    P: Storable + Ord + Clone,
+    S: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    prefix_ids: StableBTreeMap<P, u64, M>,
+    prefixes: StableBTreeMap<u64, P, M>,
+    entries: StableBTreeMap<(u64, S), V, M>,
+}
+
+impl<P, S, V, M> StablePrefixMap<P, S, V, M>
+where
+    P: Storable + Ord + Clone,
+    S: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    /// Create new instance of the map.
+    pub fn new(prefix_ids_memory: M, prefixes_memory: M, entries_memory: M) -> Self {
+        Self {
+            prefix_ids: StableBTreeMap::new(prefix_ids_memory),
+            prefixes: StableBTreeMap::new(prefixes_memory),
+            entries: StableBTreeMap::new(entries_memory),
+        }
+    }
+
+    /// Iterate over the entries with the given prefix, sorted by suffix.
+    pub fn range(&self, prefix: &P) -> StablePrefixMapRangeIter<'_, S, V, M> {
+        let inner = self
+            .prefix_ids
+            .get(prefix)
+            .map(|id| self.prefix_entries(id));
+        StablePrefixMapRangeIter { inner }
+    }
+
+    /// Iterate over all the entries.
+    pub fn iter(&self) -> StablePrefixMapIter<'_, P, S, V, M> {
+        StablePrefixMapIter {
+            entries: &self.entries,
+            prefixes: self.prefix_ids.iter(),
+            current: None,
+        }
+    }
+
+    /// Number of distinct prefixes stored in the map.
+    pub fn prefixes_len(&self) -> u64 {
+        self.prefixes.len()
+    }
+
+    fn get_or_insert_prefix_id(&mut self, prefix: &P) -> u64 {
+        if let Some(id) = self.prefix_ids.get(prefix) {
+            return id;
+        }
+
+        let id = self
+            .prefixes
+            .last_key_value()
+            .map(|(id, _)| id + 1)
+            .unwrap_or_default();
+        self.prefix_ids.insert(prefix.clone(), id);
+        self.prefixes.insert(id, prefix.clone());
+        id
+    }
+
+    /// Iterate over the entries of the prefix id, sorted by suffix.
+    fn prefix_entries(&self, id: u64) -> PrefixEntriesIter<'_, S, V, M> {
+        self.entries.range((id, S::MIN)..=(id, S::MAX))
+    }
+
+    /// Remove the prefix if no more entries use it.
+    fn remove_prefix_if_unused(&mut self, prefix: &P, id: u64) {
+        if self.prefix_entries(id).next().is_none() {
+            self.prefix_ids.remove(prefix);
+            self.prefixes.remove(&id);
+        }
+    }
+}
+
+impl<P, S, V, M> BTreeMapStructure<(P, S), V> for StablePrefixMap<P, S, V, M>
+where
+    P: Storable + Ord + Clone,
+    S: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    fn get(&self, (prefix, suffix): &(P, S)) -> Option<V> {
+        let id = self.prefix_ids.get(prefix)?;
+        self.entries.get(&(id, suffix.clone()))
+    }
+
+    fn insert(&mut self, (prefix, suffix): (P, S), value: V) -> Option<V> {
+        let id = self.get_or_insert_prefix_id(&prefix);
+        self.entries.insert((id, suffix), value)
+    }
+
+    fn remove(&mut self, (prefix, suffix): &(P, S)) -> Option<V> {
+        let id = self.prefix_ids.get(prefix)?;
+        let value = self.entries.remove(&(id, suffix.clone()))?;
+        self.remove_prefix_if_unused(prefix, id);
+        Some(value)
+    }
+
+    fn contains_key(&self, key: &(P, S)) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn first_key_value(&self) -> Option<((P, S), V)> {
+        let (prefix, id) = self.prefix_ids.first_key_value()?;
+        let ((_, suffix), value) = self.prefix_entries(id).next()?;
+        Some(((prefix, suffix), value))
+    }
+
+    fn last_key_value(&self) -> Option<((P, S), V)> {
+        let (prefix, id) = self.prefix_ids.last_key_value()?;
+        let ((_, suffix), value) = self.prefix_entries(id).next_back()?;
+        Some(((prefix, suffix), value))
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.prefixes.clear();
+        self.prefix_ids.clear();
+    }
+}
+
+/// Iterator over the entries of a single prefix
+pub struct StablePrefixMapRangeIter<'a, S, V, M>
+where
+    S: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    inner: Option<PrefixEntriesIter<'a, S, V, M>>,
+}
+
+impl<'a, S, V, M> Iterator for StablePrefixMapRangeIter<'a, S, V, M>
+where
+    S: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    type Item = (S, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .as_mut()?
+            .next()
+            .map(|((_, suffix), value)| (suffix, value))
+    }
+}
+
+/// Iterator over all the entries of the map, prefix by prefix
+pub struct StablePrefixMapIter<'a, P, S, V, M>
+where
+    P: Storable + Ord + Clone,
+    S: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    entries: &'a StableBTreeMap<(u64, S), V, M>,
+    prefixes: btreemap::Iter<'a, P, u64, M>,
+    /// The prefix being iterated and its remaining entries
+    current: Option<(P, PrefixEntriesIter<'a, S, V, M>)>,
+}
+
+impl<'a, P, S, V, M> Iterator for StablePrefixMapIter<'a, P, S, V, M>
+where
+    P: Storable + Ord + Clone,
+    S: Storable + Ord + Clone + Bounded,
+    V: Storable,
+    M: Memory,
+{
+    type Item = ((P, S), V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((prefix, entries)) = &mut self.current {
+                if let Some(((_, suffix), value)) = entries.next() {
+                    return Some(((prefix.clone(), suffix), value));
+                }
+            }
+
+            let (prefix, id) = self.prefixes.next()?;
+            let entries = self.entries.range((id, S::MIN)..=(id, S::MAX));
+            self.current = Some((prefix, entries));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::Array;
+
+    type Map = StablePrefixMap<Array<29>, u64, u32, VectorMemory>;
+
+    fn new_map() -> Map {
+        StablePrefixMap::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+    }
+
+    #[test]
+    fn prefix_map_works() {
+        let mut map = new_map();
+        assert!(map.is_empty());
+
+        let alice = Array([1; 29]);
+        let bob = Array([2; 29]);
+
+        assert_eq!(map.insert((bob, 100), 1), None);
+        assert_eq!(map.insert((alice, 200), 2), None);
+        assert_eq!(map.insert((alice, 101), 3), None);
+        assert_eq!(map.insert((alice, 100), 4), None);
+        assert_eq!(map.insert((alice, 100), 5), Some(4));
+
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.prefixes_len(), 2);
+        assert_eq!(map.get(&(alice, 101)), Some(3));
+        assert_eq!(map.get(&(bob, 101)), None);
+        assert!(map.contains_key(&(bob, 100)));
+
+        let alice_entries: Vec<_> = map.range(&alice).collect();
+        assert_eq!(alice_entries, vec![(100, 5), (101, 3), (200, 2)]);
+        assert_eq!(map.range(&Array([3; 29])).next(), None);
+
+        let entries: Vec<_> = map.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ((alice, 100), 5),
+                ((alice, 101), 3),
+                ((alice, 200), 2),
+                ((bob, 100), 1),
+            ]
+        );
+
+        assert_eq!(map.first_key_value(), Some(((alice, 100), 5)));
+        assert_eq!(map.last_key_value(), Some(((bob, 100), 1)));
+    }
+
+    #[test]
+    fn should_remove_unused_prefixes() {
+        let mut map = new_map();
+        let alice = Array([1; 29]);
+        let bob = Array([2; 29]);
+
+        map.insert((alice, 10), 1);
+        map.insert((alice, 20), 2);
+        map.insert((bob, 10), 3);
+
+        assert_eq!(map.remove(&(alice, 10)), Some(1));
+        assert_eq!(map.prefixes_len(), 2);
+        assert_eq!(map.remove(&(alice, 20)), Some(2));
+        assert_eq!(map.prefixes_len(), 1);
+        assert_eq!(map.remove(&(alice, 20)), None);
+
+        // prefix ids are assigned again
+        map.insert((alice, 30), 4);
+        assert_eq!(map.get(&(alice, 30)), Some(4));
+        assert_eq!(map.get(&(bob, 10)), Some(3));
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.prefixes_len(), 0);
+    }
+
+    #[test]
+    fn should_iterate_in_key_order() {
+        let mut map = new_map();
+        // the prefixes are inserted in an order unrelated to the key order
+        for byte in (0..100u8).map(|byte| byte.wrapping_mul(37)) {
+            map.insert((Array([byte; 29]), 2), u32::from(byte));
+            map.insert((Array([byte; 29]), 1), u32::from(byte));
+        }
+
+        let entries: Vec<_> = map.iter().collect();
+        let mut expected = entries.clone();
+        expected.sort();
+        assert_eq!(entries, expected);
+        assert_eq!(entries.len(), 200);
+        assert_eq!(map.prefixes_len(), 100);
+
+        assert_eq!(map.first_key_value(), Some(((Array([0; 29]), 1), 0)));
+        let last = *entries.last().unwrap();
+        assert_eq!(map.last_key_value(), Some(last));
+        assert_eq!(last.0 .1, 2);
+    }
+}
@@ -0,0 +1,249 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::ops::{Bound as RangeBound, RangeBounds};
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{CellStructure, LogStructure, StableCell, StableLog};
+use crate::{MemoryStats, MemoryUsage, Result};
+
+/// Which segment receives the appends, and the index of the oldest retained entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RotationState {
+    active: u8,
+    first_index: u64,
+}
+
+impl Storable for RotationState {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::with_capacity(size_of::<u8>() + size_of::<u64>());
+        buf.push(self.active);
+        buf.extend_from_slice(&self.first_index.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            active: bytes[0],
+            first_index: u64::from_le_bytes(
+                bytes[1..9]
+                    .try_into()
+                    .expect("first_index: expected 8 bytes"),
+            ),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: (size_of::<u8>() + size_of::<u64>()) as u32,
+        is_fixed_size: true,
+    };
+}
+
+/// Append-only log in stable memory which drops its oldest entries as it grows.
+///
+/// The entries are appended to one of two segments. Once the active segment holds
+/// `max_segment_len` entries, the other segment, which holds the oldest entries, is cleared and
+/// becomes the active one. So the log retains between `max_segment_len` and
+/// `2 * max_segment_len` of the latest entries, and the memory of the dropped entries is reused.
+///
+/// The indices of the entries keep increasing across the rotations: [`Self::first_index`] is the
+/// index of the oldest retained entry and [`Self::next_index`] the index of the next append.
+pub struct StableRotatingLog<T: Storable, M: Memory> {
+    segments: [StableLog<T, M>; 2],
+    state: StableCell<RotationState, M>,
+    max_segment_len: u64,
+}
+
+impl<T: Storable, M: Memory> StableRotatingLog<T, M> {
+    /// Creates the log, or loads it from the memories.
+    ///
+    /// Each segment is stored in a pair of index and data memories, and the rotation state in its
+    /// own memory. Panics if `max_segment_len` is zero.
+    pub fn new(
+        segment_memories: [(M, M); 2],
+        state_memory: M,
+        max_segment_len: u64,
+    ) -> Result<Self> {
+        assert!(max_segment_len > 0, "max_segment_len must be positive");
+
+        let [(index_0, data_0), (index_1, data_1)] = segment_memories;
+        Ok(Self {
+            segments: [
+                StableLog::new(index_0, data_0)?,
+                StableLog::new(index_1, data_1)?,
+            ],
+            state: StableCell::new(state_memory, RotationState::default())?,
+            max_segment_len,
+        })
+    }
+
+    /// Index of the oldest retained entry
+    pub fn first_index(&self) -> u64 {
+        self.state.get().first_index
+    }
+
+    /// Index of the next appended entry
+    pub fn next_index(&self) -> u64 {
+        self.first_index() + self.len()
+    }
+
+    /// Number of retained entries
+    pub fn len(&self) -> u64 {
+        self.segments[0].len() + self.segments[1].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the entry at `index`, or `None` if it was dropped or is not appended yet.
+    pub fn get(&self, index: u64) -> Option<T> {
+        let offset = index.checked_sub(self.first_index())?;
+        let oldest = self.oldest_segment();
+        match offset.checked_sub(oldest.len()) {
+            None => oldest.get(offset),
+            Some(offset) => self.active_segment().get(offset),
+        }
+    }
+
+    /// Appends the value, dropping the oldest segment first if the active one is full, and
+    /// returns its index.
+    pub fn append(&mut self, value: T) -> Result<u64> {
+        if self.active_segment().len() >= self.max_segment_len {
+            self.rotate()?;
+        }
+
+        let state = *self.state.get();
+        let offset = self.segments[usize::from(state.active)].append(value)?;
+        Ok(state.first_index + self.oldest_segment().len() + offset)
+    }
+
+    /// Returns the retained entries with the indices in `range`.
+    pub fn get_range(&self, range: impl RangeBounds<u64>) -> Vec<T> {
+        let start = match range.start_bound() {
+            RangeBound::Included(&start) => start,
+            RangeBound::Excluded(&start) => start.saturating_add(1),
+            RangeBound::Unbounded => 0,
+        }
+        .max(self.first_index());
+        let end = match range.end_bound() {
+            RangeBound::Included(&end) => end.saturating_add(1),
+            RangeBound::Excluded(&end) => end,
+            RangeBound::Unbounded => u64::MAX,
+        }
+        .min(self.next_index());
+
+        (start..end).filter_map(|index| self.get(index)).collect()
+    }
+
+    /// Returns the last `count` entries, oldest first.
+    pub fn tail(&self, count: u64) -> Vec<T> {
+        self.get_range(self.next_index().saturating_sub(count)..)
+    }
+
+    /// Drops all the entries. The indices of the next entries keep increasing.
+    pub fn clear(&mut self) -> Result<()> {
+        let next_index = self.next_index();
+        self.segments[0].clear();
+        self.segments[1].clear();
+        self.state.set(RotationState {
+            active: 0,
+            first_index: next_index,
+        })
+    }
+
+    /// Drops the entries of the oldest segment and makes it the active one.
+    fn rotate(&mut self) -> Result<()> {
+        let state = *self.state.get();
+        let oldest = 1 - state.active;
+        let dropped = self.segments[usize::from(oldest)].len();
+        self.segments[usize::from(oldest)].clear();
+        self.state.set(RotationState {
+            active: oldest,
+            first_index: state.first_index + dropped,
+        })
+    }
+
+    fn active_segment(&self) -> &StableLog<T, M> {
+        &self.segments[usize::from(self.state.get().active)]
+    }
+
+    fn oldest_segment(&self) -> &StableLog<T, M> {
+        &self.segments[usize::from(1 - self.state.get().active)]
+    }
+}
+
+impl<T: Storable, M: Memory> MemoryUsage for StableRotatingLog<T, M> {
+    fn memory_stats(&self) -> MemoryStats {
+        let oldest = self.oldest_segment().memory_stats();
+        let active = self.active_segment().memory_stats();
+        MemoryStats {
+            entries: oldest.entries + active.entries,
+            bytes: oldest.bytes + active.bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn memories() -> ([(VectorMemory, VectorMemory); 2], VectorMemory) {
+        (
+            [
+                (VectorMemory::default(), VectorMemory::default()),
+                (VectorMemory::default(), VectorMemory::default()),
+            ],
+            VectorMemory::default(),
+        )
+    }
+
+    #[test]
+    fn should_drop_oldest_segment() {
+        let (segments, state) = memories();
+        let mut log = StableRotatingLog::<u64, _>::new(segments, state, 3).unwrap();
+        for value in 0..7 {
+            assert_eq!(log.append(value * 10).unwrap(), value);
+        }
+
+        assert_eq!(log.first_index(), 3);
+        assert_eq!(log.next_index(), 7);
+        assert_eq!(log.len(), 4);
+        assert_eq!(log.get(2), None);
+        assert_eq!(log.get(3), Some(30));
+        assert_eq!(log.get(6), Some(60));
+        assert_eq!(log.get(7), None);
+        assert_eq!(log.get_range(..), vec![30, 40, 50, 60]);
+        assert_eq!(log.get_range(4..=5), vec![40, 50]);
+        assert_eq!(log.tail(2), vec![50, 60]);
+    }
+
+    #[test]
+    fn should_restore_rotation_state() {
+        let (segments, state) = memories();
+        let mut log = StableRotatingLog::<u64, _>::new(segments.clone(), state.clone(), 2).unwrap();
+        for value in 0..5 {
+            log.append(value).unwrap();
+        }
+
+        let log = StableRotatingLog::<u64, _>::new(segments, state, 2).unwrap();
+        assert_eq!(log.first_index(), 2);
+        assert_eq!(log.get_range(..), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn should_keep_indices_after_clear() {
+        let (segments, state) = memories();
+        let mut log = StableRotatingLog::<u64, _>::new(segments, state, 2).unwrap();
+        log.append(1).unwrap();
+        log.append(2).unwrap();
+        log.clear().unwrap();
+
+        assert!(log.is_empty());
+        assert_eq!(log.append(3).unwrap(), 2);
+        assert_eq!(log.get_range(..), vec![3]);
+    }
+}
@@ -0,0 +1,269 @@
+use std::any::type_name;
+use std::cmp::Ordering;
+use std::ops::RangeBounds;
+
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::{
+    check_field, decode_field, Export, Import, MemoryStats, MemoryUsage, Result, SnapshotHeader,
+    SnapshotWriter, StructureKind,
+};
+
+/// Stores a sorted set of values in stable memory.
+pub struct StableSet<T, M>(StableBTreeMap<T, (), M>)
+where
+    T: Storable + Ord + Clone,
+    M: Memory;
+
+impl<T, M> StableSet<T, M>
+where
+    T: Storable + Ord + Clone,
+    M: Memory,
+{
+    /// Create new instance of the set.
+    pub fn new(memory: M) -> Self {
+        Self(StableBTreeMap::new(memory))
+    }
+
+    /// Add the value to the set.
+    /// Returns false if the value was already present.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.0.insert(value, ()).is_none()
+    }
+
+    /// True if the set contains the value.
+    pub fn contains(&self, value: &T) -> bool {
+        self.0.contains_key(value)
+    }
+
+    /// Remove the value from the set.
+    /// Returns false if the value was not present.
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.0.remove(value).is_some()
+    }
+
+    /// Count of values in the set.
+    pub fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    /// Is the set empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Remove all values from the set.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Returns the smallest value of the set.
+    pub fn first(&self) -> Option<T> {
+        self.0.first_key_value().map(|(value, _)| value)
+    }
+
+    /// Returns the greatest value of the set.
+    pub fn last(&self) -> Option<T> {
+        self.0.last_key_value().map(|(value, _)| value)
+    }
+
+    /// Iterate over the values in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.0.iter().map(|(value, _)| value)
+    }
+
+    /// Iterate over the values which belong to the given range, in ascending order.
+    pub fn range(&self, range: impl RangeBounds<T>) -> impl Iterator<Item = T> + '_ {
+        self.0.range(range).map(|(value, _)| value)
+    }
+
+    /// Add all the values to the set.
+    /// Returns the number of values which were not already present.
+    pub fn insert_all(&mut self, values: impl IntoIterator<Item = T>) -> u64 {
+        values
+            .into_iter()
+            .map(|value| self.insert(value) as u64)
+            .sum()
+    }
+
+    /// Remove all the values from the set.
+    /// Returns the number of values which were present.
+    pub fn remove_all<'a>(&mut self, values: impl IntoIterator<Item = &'a T>) -> u64
+    where
+        T: 'a,
+    {
+        values
+            .into_iter()
+            .map(|value| self.remove(value) as u64)
+            .sum()
+    }
+
+    /// Keep only the values for which the predicate returns true.
+    pub fn retain(&mut self, mut predicate: impl FnMut(&T) -> bool) {
+        let to_remove: Vec<T> = self.iter().filter(|value| !predicate(value)).collect();
+        self.remove_all(&to_remove);
+    }
+
+    /// Iterate over the values which are in `self` or in `other`, in ascending order.
+    pub fn union<'a, M2: Memory>(
+        &'a self,
+        other: &'a StableSet<T, M2>,
+    ) -> impl Iterator<Item = T> + 'a {
+        let mut left = self.iter().peekable();
+        let mut right = other.iter().peekable();
+        std::iter::from_fn(move || match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => match l.cmp(r) {
+                Ordering::Less => left.next(),
+                Ordering::Greater => right.next(),
+                Ordering::Equal => {
+                    right.next();
+                    left.next()
+                }
+            },
+            (Some(_), None) => left.next(),
+            (None, _) => right.next(),
+        })
+    }
+
+    /// Iterate over the values which are both in `self` and in `other`, in ascending order.
+    pub fn intersection<'a, M2: Memory>(
+        &'a self,
+        other: &'a StableSet<T, M2>,
+    ) -> impl Iterator<Item = T> + 'a {
+        self.iter().filter(move |value| other.contains(value))
+    }
+
+    /// Iterate over the values which are in `self` but not in `other`, in ascending order.
+    pub fn difference<'a, M2: Memory>(
+        &'a self,
+        other: &'a StableSet<T, M2>,
+    ) -> impl Iterator<Item = T> + 'a {
+        self.iter().filter(move |value| !other.contains(value))
+    }
+
+    /// True if all the values of `self` are in `other`.
+    pub fn is_subset<M2: Memory>(&self, other: &StableSet<T, M2>) -> bool {
+        self.len() <= other.len() && self.iter().all(|value| other.contains(&value))
+    }
+}
+
+impl<T, M> MemoryUsage for StableSet<T, M>
+where
+    T: Storable + Ord + Clone,
+    M: Memory,
+{
+    fn memory_stats(&self) -> MemoryStats {
+        self.0.memory_stats()
+    }
+}
+
+impl<T, M> Export for StableSet<T, M>
+where
+    T: Storable + Ord + Clone,
+    M: Memory,
+{
+    fn export(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(StructureKind::Set, &[type_name::<T>()], self.0.len());
+        for value in self.iter() {
+            writer.entry(&[&value.to_bytes()]);
+        }
+        writer.finish()
+    }
+}
+
+impl<T, M> Import for StableSet<T, M>
+where
+    T: Storable + Ord + Clone,
+    M: Memory,
+{
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()> {
+        header.check_kind(StructureKind::Set, 1)
+    }
+
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()> {
+        check_field::<T>(fields[0])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.clear();
+        Ok(())
+    }
+
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()> {
+        self.insert(decode_field(fields[0])?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn set(values: impl IntoIterator<Item = u32>) -> StableSet<u32, VectorMemory> {
+        let mut set = StableSet::new(VectorMemory::default());
+        set.insert_all(values);
+        set
+    }
+
+    #[test]
+    fn set_works() {
+        let mut set = set([]);
+        assert!(set.is_empty());
+
+        assert!(set.insert(3));
+        assert!(set.insert(1));
+        assert!(!set.insert(3));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&1));
+        assert!(!set.contains(&2));
+        assert_eq!(set.first(), Some(1));
+        assert_eq!(set.last(), Some(3));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![1, 3]);
+
+        assert!(set.remove(&1));
+        assert!(!set.remove(&1));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![3]);
+
+        set.clear();
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn bulk_operations() {
+        let mut set = set(0..10);
+        assert_eq!(set.insert_all(5..15), 5);
+        assert_eq!(set.remove_all(&[0, 1, 20]), 2);
+        assert_eq!(set.range(12..).collect::<Vec<_>>(), vec![12, 13, 14]);
+
+        set.retain(|value| value % 2 == 0);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![2, 4, 6, 8, 10, 12, 14]);
+    }
+
+    #[test]
+    fn set_operations() {
+        let evens = set((0..10).filter(|v| v % 2 == 0));
+        let small = set(0..5);
+
+        assert_eq!(
+            evens.union(&small).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 6, 8]
+        );
+        assert_eq!(
+            evens.intersection(&small).collect::<Vec<_>>(),
+            vec![0, 2, 4]
+        );
+        assert_eq!(
+            small.intersection(&evens).collect::<Vec<_>>(),
+            vec![0, 2, 4]
+        );
+        assert_eq!(evens.difference(&small).collect::<Vec<_>>(), vec![6, 8]);
+        assert_eq!(small.difference(&evens).collect::<Vec<_>>(), vec![1, 3]);
+
+        assert!(!small.is_subset(&evens));
+        assert!(set([2, 4]).is_subset(&evens));
+        assert!(set([]).is_subset(&evens));
+    }
+}
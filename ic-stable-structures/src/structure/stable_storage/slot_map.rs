@@ -0,0 +1,363 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{CellStructure, StableCell, StableVec, VecStructure};
+use crate::Result;
+
+/// Index of the slots, marking the end of the free list
+const NO_SLOT: u32 = u32::MAX;
+
+const VACANT_TAG: u8 = 0;
+const OCCUPIED_TAG: u8 = 1;
+
+/// Handle of a value in a [`StableSlotMap`].
+///
+/// The generation distinguishes the values stored in the same slot over time, so a handle of a
+/// removed value doesn't give access to the value stored in its slot later.
+#[derive(
+    CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+pub struct SlotKey {
+    pub index: u32,
+    pub generation: u32,
+}
+
+impl SlotKey {
+    /// Packs the handle in an integer, e.g. to return it from a canister method.
+    pub fn as_u64(&self) -> u64 {
+        (u64::from(self.generation) << 32) | u64::from(self.index)
+    }
+
+    /// Unpacks a handle returned by [`Self::as_u64`].
+    pub fn from_u64(key: u64) -> Self {
+        Self {
+            index: key as u32,
+            generation: (key >> 32) as u32,
+        }
+    }
+}
+
+impl Storable for SlotKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        self.as_u64().to_be_bytes().to_vec().into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self::from_u64(u64::from_be_bytes(
+            bytes[..8].try_into().expect("slot key: expected 8 bytes"),
+        ))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: size_of::<u64>() as u32,
+        is_fixed_size: true,
+    };
+}
+
+/// Slot of the map, with the generation of its current or next value
+enum Slot<V> {
+    Vacant { generation: u32, next_free: u32 },
+    Occupied { generation: u32, value: V },
+}
+
+impl<V: Storable> Storable for Slot<V> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::new();
+        match self {
+            Self::Vacant {
+                generation,
+                next_free,
+            } => {
+                buf.extend_from_slice(&generation.to_le_bytes());
+                buf.push(VACANT_TAG);
+                buf.extend_from_slice(&next_free.to_le_bytes());
+            }
+            Self::Occupied { generation, value } => {
+                buf.extend_from_slice(&generation.to_le_bytes());
+                buf.push(OCCUPIED_TAG);
+                buf.extend_from_slice(&value.to_bytes());
+            }
+        }
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let generation =
+            u32::from_le_bytes(bytes[..4].try_into().expect("generation: expected 4 bytes"));
+        match bytes[4] {
+            VACANT_TAG => Self::Vacant {
+                generation,
+                next_free: u32::from_le_bytes(
+                    bytes[5..9].try_into().expect("next_free: expected 4 bytes"),
+                ),
+            },
+            _ => Self::Occupied {
+                generation,
+                value: V::from_bytes(bytes[5..].to_vec().into()),
+            },
+        }
+    }
+
+    const BOUND: Bound = match V::BOUND {
+        Bound::Bounded { max_size, .. } => Bound::Bounded {
+            max_size: max_size + (size_of::<u32>() * 2 + size_of::<u8>()) as u32,
+            is_fixed_size: false,
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    };
+}
+
+/// Number of values and first slot of the free list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SlotMapState {
+    len: u64,
+    free_head: u32,
+}
+
+impl Default for SlotMapState {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            free_head: NO_SLOT,
+        }
+    }
+}
+
+impl Storable for SlotMapState {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::with_capacity(size_of::<u64>() + size_of::<u32>());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&self.free_head.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            len: u64::from_le_bytes(bytes[..8].try_into().expect("len: expected 8 bytes")),
+            free_head: u32::from_le_bytes(
+                bytes[8..12]
+                    .try_into()
+                    .expect("free_head: expected 4 bytes"),
+            ),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: (size_of::<u64>() + size_of::<u32>()) as u32,
+        is_fixed_size: true,
+    };
+}
+
+/// Arena of values in stable memory, which returns a handle for each inserted value.
+///
+/// The values are stored in the slots of a [`StableVec`], so the reads and writes by handle are
+/// `O(1)`, and the slots of the removed values are reused by the next inserts. A handle of a
+/// removed value is rejected even after its slot is reused. The values must be bounded.
+pub struct StableSlotMap<V: Storable, M: Memory> {
+    slots: StableVec<Slot<V>, M>,
+    state: StableCell<SlotMapState, M>,
+}
+
+impl<V: Storable, M: Memory> StableSlotMap<V, M> {
+    /// Creates the map, or loads it from the memories.
+    pub fn new(slots_memory: M, state_memory: M) -> Result<Self> {
+        Ok(Self {
+            slots: StableVec::new(slots_memory)?,
+            state: StableCell::new(state_memory, SlotMapState::default())?,
+        })
+    }
+
+    /// Stores the value in a free slot, and returns its handle.
+    pub fn insert(&mut self, value: V) -> Result<SlotKey> {
+        let mut state = *self.state.get();
+        let key = if state.free_head == NO_SLOT {
+            let index = u32::try_from(self.slots.len())
+                .ok()
+                .filter(|&index| index != NO_SLOT)
+                .expect("the slot map is full");
+            self.slots.push(&Slot::Occupied {
+                generation: 0,
+                value,
+            })?;
+            SlotKey {
+                index,
+                generation: 0,
+            }
+        } else {
+            let index = state.free_head;
+            let Some(Slot::Vacant {
+                generation,
+                next_free,
+            }) = self.slots.get(u64::from(index))
+            else {
+                panic!("the free list contains only vacant slots");
+            };
+            self.slots
+                .set(u64::from(index), &Slot::Occupied { generation, value })?;
+            state.free_head = next_free;
+            SlotKey { index, generation }
+        };
+
+        state.len += 1;
+        self.state.set(state)?;
+        Ok(key)
+    }
+
+    /// Returns the value of the handle, or `None` if it was removed.
+    pub fn get(&self, key: SlotKey) -> Option<V> {
+        match self.slots.get(u64::from(key.index))? {
+            Slot::Occupied { generation, value } if generation == key.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    /// True if the value of the handle is in the map.
+    pub fn contains_key(&self, key: SlotKey) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Replaces the value of the handle, and returns the previous value, or `None` without
+    /// changing the map if the handle was removed.
+    pub fn replace(&mut self, key: SlotKey, value: V) -> Result<Option<V>> {
+        let Some(previous) = self.get(key) else {
+            return Ok(None);
+        };
+        self.slots.set(
+            u64::from(key.index),
+            &Slot::Occupied {
+                generation: key.generation,
+                value,
+            },
+        )?;
+        Ok(Some(previous))
+    }
+
+    /// Removes the value of the handle, and frees its slot.
+    pub fn remove(&mut self, key: SlotKey) -> Result<Option<V>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+
+        let mut state = *self.state.get();
+        self.slots.set(
+            u64::from(key.index),
+            &Slot::Vacant {
+                generation: key.generation.wrapping_add(1),
+                next_free: state.free_head,
+            },
+        )?;
+        state.free_head = key.index;
+        state.len -= 1;
+        self.state.set(state)?;
+        Ok(Some(value))
+    }
+
+    /// Number of values in the map
+    pub fn len(&self) -> u64 {
+        self.state.get().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the values with their handles, in the order of the slots.
+    pub fn iter(&self) -> impl Iterator<Item = (SlotKey, V)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied { generation, value } => Some((
+                    SlotKey {
+                        index: index as u32,
+                        generation,
+                    },
+                    value,
+                )),
+                Slot::Vacant { .. } => None,
+            })
+    }
+
+    /// Removes all the values. The handles given before are not reused.
+    pub fn clear(&mut self) -> Result<()> {
+        let mut free_head = NO_SLOT;
+        for index in (0..self.slots.len()).rev() {
+            let generation = match self.slots.get(index) {
+                Some(Slot::Occupied { generation, .. }) => generation.wrapping_add(1),
+                Some(Slot::Vacant { generation, .. }) => generation,
+                None => unreachable!("the index is lower than the length"),
+            };
+            self.slots.set(
+                index,
+                &Slot::Vacant {
+                    generation,
+                    next_free: free_head,
+                },
+            )?;
+            free_head = index as u32;
+        }
+
+        self.state.set(SlotMapState { len: 0, free_head })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn new_map() -> StableSlotMap<u64, VectorMemory> {
+        StableSlotMap::new(VectorMemory::default(), VectorMemory::default()).unwrap()
+    }
+
+    #[test]
+    fn should_insert_get_and_remove() {
+        let mut map = new_map();
+        let first = map.insert(10).unwrap();
+        let second = map.insert(20).unwrap();
+
+        assert_eq!(map.get(first), Some(10));
+        assert_eq!(map.get(second), Some(20));
+        assert_eq!(map.replace(second, 21).unwrap(), Some(20));
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.remove(first).unwrap(), Some(10));
+        assert_eq!(map.remove(first).unwrap(), None);
+        assert_eq!(map.get(first), None);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(second, 21)]);
+    }
+
+    #[test]
+    fn should_reject_stale_handles_of_reused_slots() {
+        let mut map = new_map();
+        let stale = map.insert(10).unwrap();
+        map.remove(stale).unwrap();
+
+        let reused = map.insert(20).unwrap();
+        assert_eq!(reused.index, stale.index);
+        assert_ne!(reused, stale);
+        assert_eq!(map.get(stale), None);
+        assert_eq!(map.replace(stale, 30).unwrap(), None);
+        assert_eq!(map.get(reused), Some(20));
+        assert_eq!(SlotKey::from_u64(reused.as_u64()), reused);
+    }
+
+    #[test]
+    fn should_reuse_slots_after_clear() {
+        let mut map = new_map();
+        let keys: Vec<_> = (0..3).map(|value| map.insert(value).unwrap()).collect();
+        map.clear().unwrap();
+
+        assert!(map.is_empty());
+        assert!(keys.iter().all(|key| !map.contains_key(*key)));
+        let key = map.insert(5).unwrap();
+        assert_eq!(key.index, 0);
+        assert_eq!(map.get(key), Some(5));
+        assert_eq!(map.slots.len(), 3);
+    }
+}
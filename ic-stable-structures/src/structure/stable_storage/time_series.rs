@@ -0,0 +1,297 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
+
+use dfinity_stable_structures::storable::Bound as StorableBound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+
+/// Downsampling of a [`StableTimeSeries`] in buckets of `interval`, kept for `retention`.
+/// Both durations use the unit of the timestamps, e.g. nanoseconds for `ic::time()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollupPolicy {
+    pub interval: u64,
+    pub retention: u64,
+}
+
+/// Aggregate of the points of a bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rollup {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Rollup {
+    fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Average of the points of the bucket
+    pub fn average(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+impl Storable for Rollup {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = Vec::with_capacity(size_of::<u64>() * 4);
+        buf.extend_from_slice(&self.count.to_le_bytes());
+        buf.extend_from_slice(&self.sum.to_le_bytes());
+        buf.extend_from_slice(&self.min.to_le_bytes());
+        buf.extend_from_slice(&self.max.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let field = |index: usize| -> [u8; 8] {
+            bytes[index * 8..(index + 1) * 8]
+                .try_into()
+                .expect("rollup: expected 8 bytes")
+        };
+        Self {
+            count: u64::from_le_bytes(field(0)),
+            sum: f64::from_le_bytes(field(1)),
+            min: f64::from_le_bytes(field(2)),
+            max: f64::from_le_bytes(field(3)),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Bounded {
+        max_size: (size_of::<u64>() * 4) as u32,
+        is_fixed_size: true,
+    };
+}
+
+struct RollupTier<M: Memory> {
+    policy: RollupPolicy,
+    /// Rollups by start of their bucket
+    buckets: StableBTreeMap<u64, Rollup, M>,
+}
+
+/// Series of timestamped values in stable memory, with rollups at coarser intervals.
+///
+/// The raw points are kept for `raw_retention` and the rollups of every tier for the retention of
+/// their policy, e.g. the raw points for 7 days and hourly rollups for 90 days. The rollups are
+/// updated by every append, so they are complete even after the raw points are pruned. The
+/// outdated points and rollups are removed by [`StableTimeSeries::prune`], which should be called
+/// periodically, e.g. by a timer or a task of the scheduler.
+///
+/// Several points may have the same timestamp, they are read in the order of their appends.
+pub struct StableTimeSeries<M: Memory> {
+    /// Values by timestamp and index among the points with the same timestamp
+    raw: StableBTreeMap<(u64, u32), f64, M>,
+    raw_retention: u64,
+    tiers: Vec<RollupTier<M>>,
+}
+
+impl<M: Memory> StableTimeSeries<M> {
+    /// Create new instance of the series, or restore it from the memories. Every rollup policy
+    /// has its memory, and the policies must be passed in the same order on every call.
+    pub fn new(raw_memory: M, raw_retention: u64, rollups: Vec<(RollupPolicy, M)>) -> Self {
+        Self {
+            raw: StableBTreeMap::new(raw_memory),
+            raw_retention,
+            tiers: rollups
+                .into_iter()
+                .map(|(policy, memory)| {
+                    assert!(policy.interval > 0, "rollup interval must be positive");
+                    RollupTier {
+                        policy,
+                        buckets: StableBTreeMap::new(memory),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Appends the value at the timestamp, and adds it to the rollups of its buckets.
+    pub fn append(&mut self, timestamp: u64, value: f64) {
+        let index = self
+            .raw
+            .range((timestamp, 0)..=(timestamp, u32::MAX))
+            .count() as u32;
+        self.raw.insert((timestamp, index), value);
+
+        for tier in &mut self.tiers {
+            let start = timestamp - timestamp % tier.policy.interval;
+            let rollup = match tier.buckets.get(&start) {
+                Some(mut rollup) => {
+                    rollup.add(value);
+                    rollup
+                }
+                None => Rollup::new(value),
+            };
+            tier.buckets.insert(start, rollup);
+        }
+    }
+
+    /// Returns an iterator over the raw points with the timestamps in the range, ordered by
+    /// timestamp.
+    pub fn range(
+        &self,
+        timestamps: impl RangeBounds<u64>,
+    ) -> impl Iterator<Item = (u64, f64)> + '_ {
+        let start = match timestamps.start_bound() {
+            Bound::Included(start) => Bound::Included((*start, 0)),
+            Bound::Excluded(start) => Bound::Excluded((*start, u32::MAX)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match timestamps.end_bound() {
+            Bound::Included(end) => Bound::Included((*end, u32::MAX)),
+            Bound::Excluded(end) => Bound::Excluded((*end, 0)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.raw
+            .range((start, end))
+            .map(|((timestamp, _), value)| (timestamp, value))
+    }
+
+    /// Returns an iterator over the rollups of the tier with the bucket starts in the range,
+    /// ordered by bucket start. The tiers are numbered in the order of the policies.
+    pub fn rollups(
+        &self,
+        tier: usize,
+        starts: impl RangeBounds<u64>,
+    ) -> impl Iterator<Item = (u64, Rollup)> + '_ {
+        self.tiers[tier].buckets.range(starts)
+    }
+
+    /// Remove at most `limit` raw points and rollups outdated at `now`, the oldest first.
+    /// Returns the number of removed entries, if it is `limit` more entries may be outdated.
+    pub fn prune(&mut self, now: u64, limit: usize) -> usize {
+        let raw_cutoff = now.saturating_sub(self.raw_retention);
+        let outdated: Vec<_> = self
+            .raw
+            .iter()
+            .map(|(key, _)| key)
+            .take_while(|(timestamp, _)| *timestamp < raw_cutoff)
+            .take(limit)
+            .collect();
+        for key in &outdated {
+            self.raw.remove(key);
+        }
+
+        let mut removed = outdated.len();
+        for tier in &mut self.tiers {
+            let cutoff = now.saturating_sub(tier.policy.retention);
+            let outdated: Vec<_> = tier
+                .buckets
+                .iter()
+                .map(|(start, _)| start)
+                .take_while(|start| start.saturating_add(tier.policy.interval) <= cutoff)
+                .take(limit - removed)
+                .collect();
+            for start in &outdated {
+                tier.buckets.remove(start);
+            }
+            removed += outdated.len();
+        }
+        removed
+    }
+
+    /// Number of raw points
+    pub fn len(&self) -> u64 {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Remove all the points and rollups.
+    pub fn clear(&mut self) {
+        self.raw.clear();
+        for tier in &mut self.tiers {
+            tier.buckets.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    const HOUR: u64 = 3_600;
+    const DAY: u64 = 24 * HOUR;
+
+    fn new_series() -> StableTimeSeries<VectorMemory> {
+        let hourly = RollupPolicy {
+            interval: HOUR,
+            retention: 90 * DAY,
+        };
+        StableTimeSeries::new(
+            VectorMemory::default(),
+            7 * DAY,
+            vec![(hourly, VectorMemory::default())],
+        )
+    }
+
+    #[test]
+    fn should_read_points_and_rollups() {
+        let mut series = new_series();
+        series.append(10, 1.0);
+        series.append(10, 3.0);
+        series.append(HOUR + 5, 10.0);
+        series.append(HOUR + 20, 20.0);
+
+        assert_eq!(
+            series.range(..=10).collect::<Vec<_>>(),
+            vec![(10, 1.0), (10, 3.0)]
+        );
+        assert_eq!(
+            series.range(11..).collect::<Vec<_>>(),
+            vec![(HOUR + 5, 10.0), (HOUR + 20, 20.0)]
+        );
+
+        let rollups: Vec<_> = series.rollups(0, ..).collect();
+        assert_eq!(rollups.len(), 2);
+        assert_eq!(rollups[0].0, 0);
+        assert_eq!(rollups[0].1.average(), 2.0);
+        assert_eq!(
+            rollups[1],
+            (
+                HOUR,
+                Rollup {
+                    count: 2,
+                    sum: 30.0,
+                    min: 10.0,
+                    max: 20.0
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn should_prune_outdated_points() {
+        let mut series = new_series();
+        for day in 0..10 {
+            series.append(day * DAY, day as f64);
+        }
+
+        assert_eq!(series.prune(10 * DAY, 2), 2);
+        assert_eq!(series.prune(10 * DAY, 100), 1);
+        assert_eq!(series.range(..).next(), Some((3 * DAY, 3.0)));
+        assert_eq!(series.rollups(0, ..).count(), 10);
+
+        assert_eq!(series.prune(100 * DAY, 100), 17);
+        assert!(series.is_empty());
+        assert_eq!(series.rollups(0, ..).count(), 0);
+    }
+}
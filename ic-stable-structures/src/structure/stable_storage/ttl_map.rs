@@ -0,0 +1,299 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{BTreeMapStructure, StableBTreeMap};
+use crate::{MemoryStats, MemoryUsage};
+
+/// A value with its expiration timestamp
+struct TtlEntry<V> {
+    expires_at: u64,
+    value: V,
+}
+
+impl<V: Storable> Storable for TtlEntry<V> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let value = self.value.to_bytes();
+        let mut buf = Vec::with_capacity(size_of::<u64>() + value.len());
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        buf.extend_from_slice(&value);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            expires_at: u64::from_le_bytes(
+                bytes[..8].try_into().expect("expires_at: expected 8 bytes"),
+            ),
+            value: V::from_bytes(bytes[8..].to_vec().into()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Key of the expiration index, sorted by expiration timestamp first
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpiryKey<K> {
+    expires_at: u64,
+    key: K,
+}
+
+impl<K: Storable> Storable for ExpiryKey<K> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let key = self.key.to_bytes();
+        let mut buf = Vec::with_capacity(size_of::<u64>() + key.len());
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        buf.extend_from_slice(&key);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            expires_at: u64::from_le_bytes(
+                bytes[..8].try_into().expect("expires_at: expected 8 bytes"),
+            ),
+            key: K::from_bytes(bytes[8..].to_vec().into()),
+        }
+    }
+
+    const BOUND: Bound = match K::BOUND {
+        Bound::Bounded {
+            max_size,
+            is_fixed_size,
+        } => Bound::Bounded {
+            max_size: max_size + size_of::<u64>() as u32,
+            is_fixed_size,
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    };
+}
+
+/// Key-value map in stable memory where every entry expires at a given timestamp.
+///
+/// The timestamps are provided by the caller, e.g. `ic::time()`. The expired entries are ignored
+/// by the reads and are removed by [`StableTtlMap::purge_expired`], which should be called
+/// periodically, e.g. by a timer or a task of the scheduler. The entries are indexed by expiration
+/// timestamp, so a purge reads only the expired entries.
+pub struct StableTtlMap<K, V, EntriesMemory, ExpiryMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    EntriesMemory: Memory,
+    ExpiryMemory: Memory,
+{
+    entries: StableBTreeMap<K, TtlEntry<V>, EntriesMemory>,
+    expiry_index: StableBTreeMap<ExpiryKey<K>, (), ExpiryMemory>,
+}
+
+impl<K, V, EntriesMemory, ExpiryMemory> StableTtlMap<K, V, EntriesMemory, ExpiryMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    EntriesMemory: Memory,
+    ExpiryMemory: Memory,
+{
+    /// Create new instance of the map, or restore it from the memories.
+    pub fn new(entries_memory: EntriesMemory, expiry_memory: ExpiryMemory) -> Self {
+        Self {
+            entries: StableBTreeMap::new(entries_memory),
+            expiry_index: StableBTreeMap::new(expiry_memory),
+        }
+    }
+
+    /// Add or replace the value of the key, expiring at the given timestamp.
+    /// Returns the previous value, even if it is expired.
+    pub fn insert(&mut self, key: K, value: V, expires_at: u64) -> Option<V> {
+        self.expiry_index.insert(
+            ExpiryKey {
+                expires_at,
+                key: key.clone(),
+            },
+            (),
+        );
+        let previous = self
+            .entries
+            .insert(key.clone(), TtlEntry { expires_at, value })?;
+        if previous.expires_at != expires_at {
+            self.expiry_index.remove(&ExpiryKey {
+                expires_at: previous.expires_at,
+                key,
+            });
+        }
+        Some(previous.value)
+    }
+
+    /// Returns the value of the key if it is not expired at `now`.
+    pub fn get(&self, key: &K, now: u64) -> Option<V> {
+        self.entries
+            .get(key)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.value)
+    }
+
+    /// Returns the expiration timestamp of the key if it is not expired at `now`.
+    pub fn expires_at(&self, key: &K, now: u64) -> Option<u64> {
+        self.entries
+            .get(key)
+            .map(|entry| entry.expires_at)
+            .filter(|expires_at| *expires_at > now)
+    }
+
+    /// True if the map contains the key and it is not expired at `now`.
+    pub fn contains_key(&self, key: &K, now: u64) -> bool {
+        self.expires_at(key, now).is_some()
+    }
+
+    /// Change the expiration timestamp of the key, e.g. to extend a session.
+    /// Returns false if the key is not in the map or is expired at `now`.
+    pub fn set_expiration(&mut self, key: &K, expires_at: u64, now: u64) -> bool {
+        match self.remove(key, now) {
+            Some(value) => {
+                self.insert(key.clone(), value, expires_at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the key and returns its value if it is not expired at `now`.
+    pub fn remove(&mut self, key: &K, now: u64) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.expiry_index.remove(&ExpiryKey {
+            expires_at: entry.expires_at,
+            key: key.clone(),
+        });
+        (entry.expires_at > now).then_some(entry.value)
+    }
+
+    /// Remove at most `limit` entries expired at `now`, the oldest first.
+    /// Returns the number of removed entries, if it is `limit` more entries may be expired.
+    pub fn purge_expired(&mut self, now: u64, limit: usize) -> usize {
+        let expired: Vec<ExpiryKey<K>> = self
+            .expiry_index
+            .iter()
+            .map(|(expiry, _)| expiry)
+            .take_while(|expiry| expiry.expires_at <= now)
+            .take(limit)
+            .collect();
+
+        for expiry in &expired {
+            self.expiry_index.remove(expiry);
+            self.entries.remove(&expiry.key);
+        }
+        expired.len()
+    }
+
+    /// Iterate over the entries which are not expired at `now`, in key order.
+    pub fn iter(&self, now: u64) -> impl Iterator<Item = (K, V)> + '_ {
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| entry.expires_at > now)
+            .map(|(key, entry)| (key, entry.value))
+    }
+
+    /// Count of the entries, including the expired entries which are not purged yet.
+    pub fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    /// Is the map empty, including the expired entries which are not purged yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove all entries from the map.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.expiry_index.clear();
+    }
+}
+
+impl<K, V, EntriesMemory, ExpiryMemory> MemoryUsage
+    for StableTtlMap<K, V, EntriesMemory, ExpiryMemory>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    EntriesMemory: Memory,
+    ExpiryMemory: Memory,
+{
+    /// The entries include the expired entries which are not purged yet.
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.entries.len(),
+            bytes: self.entries.memory_stats().bytes + self.expiry_index.memory_stats().bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::test_utils::{str_val, StringValue};
+
+    fn map() -> StableTtlMap<u64, StringValue, VectorMemory, VectorMemory> {
+        StableTtlMap::new(VectorMemory::default(), VectorMemory::default())
+    }
+
+    #[test]
+    fn should_expire_entries_on_read() {
+        let mut map = map();
+        assert_eq!(map.insert(1, str_val(10), 100), None);
+        map.insert(2, str_val(20), 200);
+
+        assert_eq!(map.get(&1, 99), Some(str_val(10)));
+        assert_eq!(map.get(&1, 100), None);
+        assert!(map.contains_key(&2, 150));
+        assert_eq!(map.expires_at(&2, 150), Some(200));
+        assert_eq!(map.iter(150).collect::<Vec<_>>(), vec![(2, str_val(20))]);
+
+        // expired entries are kept until they are purged
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.remove(&1, 150), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn should_replace_and_extend_entries() {
+        let mut map = map();
+        map.insert(1, str_val(10), 100);
+        assert_eq!(map.insert(1, str_val(11), 300), Some(str_val(10)));
+        assert_eq!(map.get(&1, 200), Some(str_val(11)));
+
+        assert!(map.set_expiration(&1, 400, 200));
+        assert!(!map.set_expiration(&2, 400, 200));
+        assert_eq!(map.purge_expired(350, 10), 0);
+        assert_eq!(map.get(&1, 350), Some(str_val(11)));
+
+        assert_eq!(map.purge_expired(400, 10), 1);
+        assert!(map.is_empty());
+        assert!(map.expiry_index.is_empty());
+    }
+
+    #[test]
+    fn should_purge_expired_entries_in_batches() {
+        let mut map = map();
+        for key in 0..10 {
+            map.insert(key, str_val(1), 100 + key);
+        }
+
+        assert_eq!(map.purge_expired(99, 3), 0);
+        assert_eq!(map.purge_expired(106, 3), 3);
+        assert_eq!(map.purge_expired(106, 3), 3);
+        assert_eq!(map.purge_expired(106, 3), 1);
+        assert_eq!(map.purge_expired(106, 3), 0);
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map.iter(106).map(|(key, _)| key).collect::<Vec<_>>(),
+            vec![7, 8, 9]
+        );
+
+        map.clear();
+        assert!(map.is_empty());
+    }
+}
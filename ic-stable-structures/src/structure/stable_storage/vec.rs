@@ -1,7 +1,14 @@
+use std::any::type_name;
+use std::ops::{Bound, Range, RangeBounds};
+
 use dfinity_stable_structures::{vec, Memory, Storable};
 
+use crate::stats::storable_size;
 use crate::structure::VecStructure;
-use crate::Result;
+use crate::{
+    check_field, decode_field, Export, Import, MemoryStats, MemoryUsage, Result, SnapshotHeader,
+    SnapshotWriter, StructureKind,
+};
 
 pub struct StableVec<T: Storable, M: Memory>(Option<vec::Vec<T, M>>);
 
@@ -18,6 +25,53 @@ impl<T: Storable, M: Memory> StableVec<T, M> {
         self.get_inner().iter()
     }
 
+    /// Returns an iterator over the elements in the given range of indices.
+    /// The range is truncated to the length of the vector.
+    pub fn iter_range(&self, range: impl RangeBounds<u64>) -> StableVecIter<'_, T, M> {
+        let len = self.len();
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.saturating_add(1),
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => len,
+        };
+
+        StableVecIter {
+            vec: self.get_inner(),
+            range: start.min(len)..end.min(len),
+        }
+    }
+
+    /// Reads the elements in the given range of indices at once.
+    /// The range is truncated to the length of the vector.
+    pub fn read_range(&self, range: impl RangeBounds<u64>) -> Vec<T> {
+        self.iter_range(range).collect()
+    }
+
+    /// Returns an iterator over the elements of the vector in chunks of `chunk_size` elements.
+    /// The last chunk can be shorter.
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn chunks(&self, chunk_size: u64) -> impl Iterator<Item = Vec<T>> + '_ {
+        assert!(chunk_size > 0, "chunk size must be greater than 0");
+        (0..self.len())
+            .step_by(chunk_size.try_into().unwrap_or(usize::MAX))
+            .map(move |start| self.read_range(start..start.saturating_add(chunk_size)))
+    }
+
+    /// Returns the memory of the vector, e.g. to release it with [`crate::MemoryPool::release`]
+    /// after clearing the vector.
+    pub fn into_memory(mut self) -> M {
+        self.0
+            .take()
+            .expect("vector is always initialized")
+            .into_memory()
+    }
+
     fn mut_inner(&mut self) -> &mut vec::Vec<T, M> {
         self.0.as_mut().expect("vector is always initialized")
     }
@@ -62,6 +116,74 @@ impl<T: Storable, M: Memory> VecStructure<T> for StableVec<T, M> {
     }
 }
 
+/// Iterator over a range of elements of a [`StableVec`]
+pub struct StableVecIter<'a, T: Storable, M: Memory> {
+    vec: &'a vec::Vec<T, M>,
+    range: Range<u64>,
+}
+
+impl<T: Storable, M: Memory> Iterator for StableVecIter<'_, T, M> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let index = self.range.next()?;
+        self.vec.get(index)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<T> {
+        let index = self.range.nth(n)?;
+        self.vec.get(index)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<T: Storable, M: Memory> DoubleEndedIterator for StableVecIter<'_, T, M> {
+    fn next_back(&mut self) -> Option<T> {
+        let index = self.range.next_back()?;
+        self.vec.get(index)
+    }
+}
+
+impl<T: Storable, M: Memory> MemoryUsage for StableVec<T, M> {
+    fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.len(),
+            bytes: self.iter().map(|item| storable_size(&item)).sum(),
+        }
+    }
+}
+
+impl<T: Storable, M: Memory> Export for StableVec<T, M> {
+    fn export(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(StructureKind::Vec, &[type_name::<T>()], self.len());
+        for item in self.iter() {
+            writer.entry(&[&item.to_bytes()]);
+        }
+        writer.finish()
+    }
+}
+
+impl<T: Storable, M: Memory> Import for StableVec<T, M> {
+    fn check_snapshot(&self, header: &SnapshotHeader) -> Result<()> {
+        header.check_kind(StructureKind::Vec, 1)
+    }
+
+    fn check_entry(&self, fields: &[&[u8]]) -> Result<()> {
+        check_field::<T>(fields[0])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.clear()
+    }
+
+    fn import_entry(&mut self, fields: &[&[u8]]) -> Result<()> {
+        self.push(&decode_field(fields[0])?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -126,6 +248,31 @@ mod tests {
         assert_eq!(None, vec.iter().next());
     }
 
+    #[test]
+    fn vec_range_reads() {
+        let mut vec = StableVec::<u64, _>::new(VectorMemory::default()).unwrap();
+        for i in 0..10 {
+            vec.push(&i).unwrap();
+        }
+
+        assert_eq!(vec.iter_range(..).count(), 10);
+        assert_eq!(vec.read_range(2..5), vec![2, 3, 4]);
+        assert_eq!(vec.read_range(8..=20), vec![8, 9]);
+        assert_eq!(vec.read_range(12..), Vec::<u64>::new());
+        assert_eq!(
+            vec.iter_range(3..).rev().take(2).collect::<Vec<_>>(),
+            vec![9, 8]
+        );
+        assert_eq!(vec.iter_range(..6).nth(4), Some(4));
+        assert_eq!(vec.iter_range(..6).size_hint(), (6, Some(6)));
+
+        let chunks: Vec<_> = vec.chunks(4).collect();
+        assert_eq!(chunks, vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+
+        vec.clear().unwrap();
+        assert_eq!(vec.chunks(4).count(), 0);
+    }
+
     #[should_panic]
     #[test]
     fn vec_unbounded_items() {
@@ -36,6 +36,15 @@ impl Storable for BoundedTransaction {
     };
 }
 
+/// Instructions executed by the dummy canister for `size` operations on a structure
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct BenchmarkResult {
+    pub structure: String,
+    pub operation: String,
+    pub size: u64,
+    pub instructions: u64,
+}
+
 #[derive(Debug, Default, Clone, Copy, CandidType, Deserialize)]
 pub struct UnboundedTransaction {
     pub from: u8,
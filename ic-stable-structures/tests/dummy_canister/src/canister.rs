@@ -100,6 +100,17 @@ impl DummyCanister {
         Service::push_tx_to_ring_buffer(transaction)
     }
 
+    #[update]
+    pub async fn seed_btreemap_from_csv(&self, csv: String) -> u64 {
+        Service::seed_btreemap_from_csv(csv)
+    }
+
+    /// Measures the instructions of the operations of the structures on `size` entries.
+    #[update]
+    pub async fn run_benchmarks(&self, size: u64) -> Vec<BenchmarkResult> {
+        Service::run_benchmarks(size)
+    }
+
     pub fn idl() -> Idl {
         generate_idl!()
     }
@@ -14,6 +14,13 @@ const TX_MULTIMAP_MEMORY_ID: MemoryId = MemoryId::new(7);
 const TX_RING_BUFFER_INDICES_MEMORY_ID: MemoryId = MemoryId::new(8);
 const TX_RING_BUFFER_VEC_MEMORY_ID: MemoryId = MemoryId::new(9);
 const TX_CACHED_BTREEMAP_MEMORY_ID: MemoryId = MemoryId::new(10);
+const BENCH_BTREEMAP_MEMORY_ID: MemoryId = MemoryId::new(11);
+const BENCH_UNBOUNDEDMAP_MEMORY_ID: MemoryId = MemoryId::new(12);
+const BENCH_MULTIMAP_MEMORY_ID: MemoryId = MemoryId::new(13);
+const BENCH_VEC_MEMORY_ID: MemoryId = MemoryId::new(14);
+const BENCH_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(15);
+const BENCH_LOG_MEMORY_ID: MemoryId = MemoryId::new(16);
+const BENCH_CACHED_BTREEMAP_MEMORY_ID: MemoryId = MemoryId::new(17);
 
 thread_local! {
     static MEMORY_MANAGER: IcMemoryManager<DefaultMemoryImpl> = IcMemoryManager::init(DefaultMemoryImpl::default());
@@ -199,7 +206,7 @@ impl Service {
 
     pub fn insert_tx_to_multimap(transaction: BoundedTransaction) -> u64 {
         TX_MULTIMAP.with(|storage| {
-            let new_key = storage.borrow().len() as u64;
+            let new_key = storage.borrow().len();
             storage
                 .borrow_mut()
                 .insert(&new_key, &(new_key + 1), transaction);
@@ -221,4 +228,134 @@ impl Service {
             storage.borrow().len()
         })
     }
+
+    /// Inserts the transactions of a CSV fixture with the columns `key,from,to,value`.
+    pub fn seed_btreemap_from_csv(csv: String) -> u64 {
+        TX_BTREEMAP.with(|storage| {
+            seed_map_from_csv(&mut *storage.borrow_mut(), &csv, |record| {
+                let transaction = BoundedTransaction {
+                    from: record.parse("from")?,
+                    to: record.parse("to")?,
+                    value: record.parse("value")?,
+                };
+                Ok((record.parse("key")?, transaction))
+            })
+            .expect("invalid fixture")
+        })
+    }
+
+    pub fn run_benchmarks(size: u64) -> Vec<BenchmarkResult> {
+        let memory = |id| MEMORY_MANAGER.with(|mm| mm.get(id));
+        let tx = BoundedTransaction {
+            from: 1,
+            to: 2,
+            value: 3,
+        };
+        let unbounded_tx = UnboundedTransaction {
+            from: 1,
+            to: 2,
+            value: 3,
+        };
+        let mut results = vec![];
+
+        let mut map = StableBTreeMap::new(memory(BENCH_BTREEMAP_MEMORY_ID));
+        measure(&mut results, "btreemap", "insert", size, || {
+            (0..size).for_each(|key| {
+                map.insert(key, tx);
+            })
+        });
+        measure(&mut results, "btreemap", "get", size, || {
+            (0..size).for_each(|key| {
+                map.get(&key);
+            })
+        });
+        map.clear();
+
+        let mut map = StableBTreeMap::new(memory(BENCH_UNBOUNDEDMAP_MEMORY_ID));
+        measure(&mut results, "unboundedmap", "insert", size, || {
+            (0..size).for_each(|key| {
+                map.insert(key, unbounded_tx);
+            })
+        });
+        measure(&mut results, "unboundedmap", "get", size, || {
+            (0..size).for_each(|key| {
+                map.get(&key);
+            })
+        });
+        map.clear();
+
+        let mut map = StableMultimap::new(memory(BENCH_MULTIMAP_MEMORY_ID));
+        measure(&mut results, "multimap", "insert", size, || {
+            (0..size).for_each(|key| {
+                map.insert(&(key % 10), &key, tx);
+            })
+        });
+        measure(&mut results, "multimap", "get", size, || {
+            (0..size).for_each(|key| {
+                map.get(&(key % 10), &key);
+            })
+        });
+        map.clear();
+
+        let mut vec = StableVec::new(memory(BENCH_VEC_MEMORY_ID)).expect("failed to create vec");
+        measure(&mut results, "vec", "push", size, || {
+            (0..size).for_each(|_| vec.push(&tx).expect("failed to push to vec"))
+        });
+        measure(&mut results, "vec", "get", size, || {
+            (0..size).for_each(|index| {
+                vec.get(index);
+            })
+        });
+        vec.clear().expect("failed to clear vec");
+
+        let mut log = StableLog::new(
+            memory(BENCH_LOG_INDEX_MEMORY_ID),
+            memory(BENCH_LOG_MEMORY_ID),
+        )
+        .expect("failed to create log");
+        measure(&mut results, "log", "append", size, || {
+            (0..size).for_each(|_| {
+                log.append(tx).expect("failed to append to log");
+            })
+        });
+        measure(&mut results, "log", "get", size, || {
+            (0..size).for_each(|index| {
+                log.get(index);
+            })
+        });
+        log.clear();
+
+        let mut map = CachedStableBTreeMap::new(memory(BENCH_CACHED_BTREEMAP_MEMORY_ID), 100);
+        measure(&mut results, "cached_btreemap", "insert", size, || {
+            (0..size).for_each(|key| {
+                map.insert(key, tx);
+            })
+        });
+        measure(&mut results, "cached_btreemap", "get", size, || {
+            (0..size).for_each(|key| {
+                map.get(&(key % 100));
+            })
+        });
+        map.clear();
+
+        results
+    }
+}
+
+/// Runs `f` and records the instructions it executed.
+fn measure(
+    results: &mut Vec<BenchmarkResult>,
+    structure: &str,
+    operation: &str,
+    size: u64,
+    f: impl FnOnce(),
+) {
+    let start = ic_cdk::api::performance_counter(0);
+    f();
+    results.push(BenchmarkResult {
+        structure: structure.to_string(),
+        operation: operation.to_string(),
+        size,
+        instructions: ic_cdk::api::performance_counter(0) - start,
+    });
 }
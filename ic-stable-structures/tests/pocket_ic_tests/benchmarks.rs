@@ -0,0 +1,24 @@
+use super::with_pocket_ic_context;
+
+/// Prints the instructions executed by the operations of the structures in the dummy canister
+/// as CSV, to compare the structures and to catch performance regressions.
+///
+/// Run with `cargo test --features pocket-ic -- --ignored --nocapture benchmark`.
+#[test]
+#[ignore = "benchmark"]
+fn benchmark_stable_structures_instructions() {
+    with_pocket_ic_context(|ctx| {
+        println!("structure,operation,size,instructions");
+        for size in [100, 1_000, 5_000] {
+            for result in ctx.run_benchmarks(size)? {
+                println!(
+                    "{},{},{},{}",
+                    result.structure, result.operation, result.size, result.instructions
+                );
+            }
+        }
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -37,3 +37,21 @@ fn should_persist_btreemap_tx_after_upgrade() {
     })
     .unwrap();
 }
+
+#[test]
+fn should_seed_btreemap_from_csv_fixture() {
+    with_pocket_ic_context(|ctx| {
+        let mut csv = "key,from,to,value\n".to_string();
+        for key in 1..=1_000u64 {
+            csv.push_str(&format!("{key},{},{},{}\n", key % 7, key % 11, key % 256));
+        }
+
+        assert_eq!(ctx.seed_btreemap_from_csv(&csv)?, 1_000);
+        let transaction = ctx.get_tx_from_btreemap(300)?.unwrap();
+        assert_eq!(transaction.from, 300 % 7);
+        assert_eq!(transaction.value, 300 % 256);
+
+        Ok(())
+    })
+    .unwrap();
+}
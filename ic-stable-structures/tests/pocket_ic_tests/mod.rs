@@ -5,6 +5,7 @@ use ic_exports::ic_kit::mock_principals::alice;
 use ic_exports::pocket_ic::{self, PocketIc, WasmResult};
 use wasm_utils::get_dummy_canister_bytecode;
 
+mod benchmarks;
 mod btreemap;
 mod cached_btreemap;
 mod cell;
@@ -201,6 +202,20 @@ impl PocketIcTestContext {
 
         Ok(res)
     }
+
+    pub fn seed_btreemap_from_csv(&self, csv: &str) -> Result<u64> {
+        let args = Encode!(&csv).unwrap();
+        let res = self.update_call_as(alice(), self.dummy_canister, "seed_btreemap_from_csv", args);
+
+        Ok(res)
+    }
+
+    pub fn run_benchmarks(&self, size: u64) -> Result<Vec<BenchmarkResult>> {
+        let args = Encode!(&size).unwrap();
+        let res = self.update_call_as(alice(), self.dummy_canister, "run_benchmarks", args);
+
+        Ok(res)
+    }
 }
 
 pub fn with_pocket_ic_context<F>(f: F) -> Result<()>
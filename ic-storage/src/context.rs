@@ -0,0 +1,237 @@
+//! Registry of the canister components, e.g. payments, scheduler, metrics or access control.
+//!
+//! The components are registered once, usually in the `init` and `post_upgrade` methods, and are
+//! resolved by type in the endpoints. The [`components!`](crate::components) macro generates
+//! accessors for them, so the endpoints don't depend on how the components are created:
+//!
+//! ```
+//! # ic_exports::ic_kit::MockContext::new().inject();
+//! use ic_storage::context;
+//!
+//! #[derive(Default)]
+//! struct Metrics {
+//!     calls: u64,
+//! }
+//!
+//! struct Config {
+//!     owner: String,
+//! }
+//!
+//! ic_storage::components! {
+//!     pub fn metrics() -> Metrics;
+//!     pub fn config() -> Config;
+//! }
+//!
+//! // in `init`
+//! context::register(Metrics::default()).unwrap();
+//! context::register(Config { owner: "alice".into() }).unwrap();
+//!
+//! // in an endpoint
+//! metrics().borrow_mut().calls += 1;
+//! assert_eq!(metrics().borrow().calls, 1);
+//! assert_eq!(config().borrow().owner, "alice");
+//! ```
+//!
+//! As for [`IcStorage`](crate::IcStorage), the components are stored per canister id on the
+//! architectures other than `wasm32`, so several canisters can be simulated in the same test.
+//!
+//! The registry lives in the heap memory: the components must be registered again after an
+//! upgrade, and their state must be saved and restored as any other heap state.
+
+use std::any::{type_name, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::{Error, Result};
+
+/// Set of components, at most one per type.
+#[derive(Default)]
+pub struct Components {
+    components: HashMap<TypeId, Rc<dyn Any>>,
+}
+
+impl Components {
+    /// Add the component. Fails if a component of the same type is already registered.
+    pub fn register<T: 'static>(&mut self, component: T) -> Result<Rc<RefCell<T>>> {
+        if self.contains::<T>() {
+            return Err(Error::ComponentAlreadyRegistered(type_name::<T>()));
+        }
+
+        Ok(self.replace(component))
+    }
+
+    /// Add the component, replacing the component of the same type if any.
+    ///
+    /// The references to the replaced component, resolved before, keep pointing to it.
+    pub fn replace<T: 'static>(&mut self, component: T) -> Rc<RefCell<T>> {
+        let component = Rc::new(RefCell::new(component));
+        self.components.insert(TypeId::of::<T>(), component.clone());
+        component
+    }
+
+    /// Returns the component of the given type.
+    pub fn resolve<T: 'static>(&self) -> Result<Rc<RefCell<T>>> {
+        self.components
+            .get(&TypeId::of::<T>())
+            .and_then(|component| component.clone().downcast::<RefCell<T>>().ok())
+            .ok_or(Error::ComponentNotRegistered(type_name::<T>()))
+    }
+
+    /// True if a component of the given type is registered.
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.components.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Remove the component of the given type and returns whether it was registered.
+    pub fn remove<T: 'static>(&mut self) -> bool {
+        self.components.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Number of registered components
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    /// True if no component is registered
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn with_components<R>(f: impl FnOnce(&mut Components) -> R) -> R {
+    thread_local! {
+        static COMPONENTS: RefCell<Components> = RefCell::new(Components::default());
+    }
+
+    COMPONENTS.with(|components| f(&mut components.borrow_mut()))
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn with_components<R>(f: impl FnOnce(&mut Components) -> R) -> R {
+    use ic_exports::candid::Principal;
+
+    thread_local! {
+        static COMPONENTS: RefCell<HashMap<Principal, Components>> = RefCell::new(HashMap::default());
+    }
+
+    let id = ic_exports::ic_kit::ic::id();
+    COMPONENTS.with(|components| f(components.borrow_mut().entry(id).or_default()))
+}
+
+/// Register the component of the canister. Fails if a component of the same type is already
+/// registered.
+pub fn register<T: 'static>(component: T) -> Result<Rc<RefCell<T>>> {
+    with_components(|components| components.register(component))
+}
+
+/// Register the component of the canister, replacing the component of the same type if any.
+pub fn replace<T: 'static>(component: T) -> Rc<RefCell<T>> {
+    with_components(|components| components.replace(component))
+}
+
+/// Returns the component of the canister with the given type.
+pub fn resolve<T: 'static>() -> Result<Rc<RefCell<T>>> {
+    with_components(|components| components.resolve())
+}
+
+/// True if the canister has a component of the given type.
+pub fn contains<T: 'static>() -> bool {
+    with_components(|components| components.contains::<T>())
+}
+
+/// Remove the component of the canister with the given type and returns whether it was registered.
+pub fn remove<T: 'static>() -> bool {
+    with_components(|components| components.remove::<T>())
+}
+
+/// Generates accessors for the components of the canister.
+///
+/// Every accessor returns the registered component of the given type, and panics if it is not
+/// registered, which is a bug of the canister initialization.
+#[macro_export]
+macro_rules! components {
+    ($($vis:vis fn $name:ident() -> $component:ty;)*) => {
+        $(
+            $vis fn $name() -> ::std::rc::Rc<::std::cell::RefCell<$component>> {
+                match $crate::context::resolve::<$component>() {
+                    Ok(component) => component,
+                    Err(e) => panic!("{e}"),
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_exports::candid::Principal;
+    use ic_exports::ic_kit::MockContext;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Eq)]
+    struct Counter(u64);
+
+    components! {
+        fn counter() -> Counter;
+    }
+
+    #[test]
+    fn should_register_and_resolve_components() {
+        let mut components = Components::default();
+        assert!(components.resolve::<Counter>().is_err());
+
+        components.register(Counter(1)).unwrap();
+        assert!(matches!(
+            components.register(Counter(2)),
+            Err(Error::ComponentAlreadyRegistered(_))
+        ));
+        components.resolve::<Counter>().unwrap().borrow_mut().0 += 1;
+        assert_eq!(
+            *components.resolve::<Counter>().unwrap().borrow(),
+            Counter(2)
+        );
+
+        components.replace(Counter(10));
+        components.register(String::from("config")).unwrap();
+        assert_eq!(components.len(), 2);
+        assert_eq!(
+            *components.resolve::<Counter>().unwrap().borrow(),
+            Counter(10)
+        );
+
+        assert!(components.remove::<Counter>());
+        assert!(!components.contains::<Counter>());
+        assert!(!components.remove::<Counter>());
+    }
+
+    #[test]
+    fn should_isolate_components_of_canisters() {
+        let context = MockContext::new()
+            .with_id(Principal::from_slice(&[1; 29]))
+            .inject();
+        register(Counter(1)).unwrap();
+        counter().borrow_mut().0 += 1;
+        assert_eq!(*counter().borrow(), Counter(2));
+
+        context.update_id(Principal::from_slice(&[2; 29]));
+        assert!(!contains::<Counter>());
+        assert!(matches!(
+            resolve::<Counter>(),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+
+        context.update_id(Principal::from_slice(&[1; 29]));
+        assert_eq!(*counter().borrow(), Counter(2));
+        assert!(remove::<Counter>());
+    }
+
+    #[test]
+    #[should_panic(expected = "is not registered")]
+    fn should_panic_on_missing_component() {
+        MockContext::new().inject();
+        counter();
+    }
+}
@@ -21,6 +21,12 @@ pub enum Error {
 
     #[error("existing version is newer")]
     ExistingVersionIsNewer,
+
+    #[error("component {0} is already registered")]
+    ComponentAlreadyRegistered(&'static str),
+
+    #[error("component {0} is not registered")]
+    ComponentNotRegistered(&'static str),
 }
 
 // Required because `StableMemoryError` doesn't implement Debug
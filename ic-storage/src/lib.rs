@@ -87,6 +87,7 @@ use std::rc::Rc;
 
 pub use ic_storage_derive::IcStorage;
 
+pub mod context;
 pub mod error;
 pub mod stable;
 pub use error::{Error, Result};
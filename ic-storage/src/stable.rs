@@ -175,7 +175,7 @@ impl Versioned for () {
 
 fn read_version() -> Result<u32> {
     let mut version = [0u8; VERSION_SIZE];
-    if ((stable_size() << 16) as usize) < version.len() {
+    if ((stable_size() as u64) << 16) < version.len() as u64 {
         return Err(Error::InsufficientSpace);
     }
 
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use ic_stable_structures::{BTreeMapStructure, IterableSortedMapStructure};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::retry::RetryStrategy;
+use crate::scheduler::{IdleCallback, Scheduler, TaskCompletionCallback};
+use crate::task::{InnerScheduledTask, Task};
+
+pub(crate) const DEFAULT_RUNNING_TASK_TIMEOUT_SECS: u64 = 120;
+
+/// Configures and creates a [`Scheduler`].
+pub struct SchedulerBuilder<T: 'static + Task, P> {
+    pub(crate) pending_tasks: P,
+    pub(crate) running_task_timeout_secs: u64,
+    pub(crate) visibility_timeout_secs: Option<u64>,
+    pub(crate) retry_strategy: RetryStrategy,
+    pub(crate) max_concurrent_tasks: Option<usize>,
+    pub(crate) max_tasks_per_run: Option<usize>,
+    pub(crate) lane_weights: HashMap<String, u32>,
+    pub(crate) observers: Vec<TaskCompletionCallback<T>>,
+    pub(crate) on_idle: Option<IdleCallback>,
+}
+
+impl<
+        T: 'static + Task + Serialize + DeserializeOwned + Clone,
+        P: 'static
+            + IterableSortedMapStructure<u32, InnerScheduledTask<T>>
+            + BTreeMapStructure<u32, InnerScheduledTask<T>>,
+    > SchedulerBuilder<T, P>
+{
+    /// Create a new builder. The pending tasks are stored in the given map.
+    pub fn new(pending_tasks: P) -> Self {
+        Self {
+            pending_tasks,
+            running_task_timeout_secs: DEFAULT_RUNNING_TASK_TIMEOUT_SECS,
+            visibility_timeout_secs: None,
+            retry_strategy: RetryStrategy::default(),
+            max_concurrent_tasks: None,
+            max_tasks_per_run: None,
+            lane_weights: HashMap::new(),
+            observers: vec![],
+            on_idle: None,
+        }
+    }
+
+    /// Set the timeout of a running task. If a task is running for more time the timeout, it will be
+    /// considered as stuck or panicked.
+    /// The default value is 120 seconds.
+    pub fn with_running_task_timeout(mut self, timeout_secs: u64) -> Self {
+        self.running_task_timeout_secs = timeout_secs;
+        self
+    }
+
+    /// Set the lease of the scheduled and running tasks, replacing the running task timeout.
+    /// A task whose lease expires is considered lost, e.g. because the canister trapped mid-await
+    /// or was upgraded while the task was in flight, and becomes visible again to the next runs.
+    /// The lost execution counts as a failure, so the task is executed again only if its retry
+    /// strategy allows it, otherwise it is removed with the TimeoutOrPanic status.
+    /// If the lost execution completes after all, its result is discarded.
+    /// The default is no visibility timeout: the tasks running for more than the running task
+    /// timeout are removed with the TimeoutOrPanic status.
+    pub fn with_visibility_timeout(mut self, timeout_secs: u64) -> Self {
+        self.visibility_timeout_secs = Some(timeout_secs);
+        self
+    }
+
+    /// Set the retry strategy of the tasks whose options do not define one.
+    /// The default is RetryStrategy::default().
+    pub fn with_retry_strategy(mut self, retry_strategy: RetryStrategy) -> Self {
+        self.retry_strategy = retry_strategy;
+        self
+    }
+
+    /// Set the max number of tasks that can be scheduled or running at the same time.
+    /// The default is no limit.
+    pub fn with_max_concurrent_tasks(mut self, max_concurrent_tasks: usize) -> Self {
+        self.max_concurrent_tasks = Some(max_concurrent_tasks);
+        self
+    }
+
+    /// Set the max number of tasks launched by a single `Scheduler::run` call.
+    /// The default is no limit.
+    pub fn with_max_tasks_per_run(mut self, max_tasks_per_run: usize) -> Self {
+        self.max_tasks_per_run = Some(max_tasks_per_run);
+        self
+    }
+
+    /// Set the weight of a task lane, see [`TaskOptions::with_lane`](crate::task::TaskOptions::with_lane).
+    /// At each run, the ready tasks are launched with a weighted round robin between the lanes:
+    /// a lane with weight 3 gets three tasks launched for each task of a lane with weight 1.
+    /// This matters when the number of tasks per run or of concurrent tasks is limited.
+    /// The default weight is 1, a weight of 0 is handled as 1.
+    pub fn with_lane_weight(mut self, lane: impl Into<String>, weight: u32) -> Self {
+        self.lane_weights.insert(lane.into(), weight);
+        self
+    }
+
+    /// Add a callback to be called when a task execution completes.
+    /// Observers are called in the order they are added.
+    pub fn with_observer<F: 'static + Send + Fn(InnerScheduledTask<T>)>(mut self, cb: F) -> Self {
+        self.observers.push(Box::new(cb));
+        self
+    }
+
+    /// Set a callback to be called when `Scheduler::run` finds no task to launch and no task in flight,
+    /// e.g. to trigger compaction, flush metrics or enqueue periodic work.
+    pub fn with_on_idle<F: 'static + Send + Fn()>(mut self, cb: F) -> Self {
+        self.on_idle = Some(Box::new(cb));
+        self
+    }
+
+    /// Create the scheduler.
+    pub fn build(self) -> Scheduler<T, P> {
+        Scheduler::from_builder(self)
+    }
+}
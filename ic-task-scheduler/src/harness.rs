@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+
+use futures::executor::{LocalPool, LocalSpawner};
+use futures::task::LocalSpawnExt;
+use ic_stable_structures::{BTreeMapStructure, IterableSortedMapStructure};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::scheduler::Scheduler;
+use crate::task::{InnerScheduledTask, Task};
+use crate::SchedulerError;
+
+struct HarnessState {
+    now_timestamp_secs: u64,
+    spawner: LocalSpawner,
+}
+
+thread_local! {
+    static HARNESS: RefCell<Option<HarnessState>> = const { RefCell::new(None) };
+}
+
+/// A test runtime to step the scheduler deterministically, without PocketIC.
+///
+/// While the harness is alive, the schedulers of the current thread spawn their tasks on a
+/// local executor owned by the harness, and read the time from the harness clock.
+/// Spawned tasks are executed only when [`SchedulerTestHarness::run_until_stalled`] is called.
+///
+/// ```ignore
+/// let harness = SchedulerTestHarness::new(0);
+/// scheduler.append_task(task.into());
+///
+/// assert_eq!(harness.step(&scheduler).unwrap(), 1);
+/// harness.advance_time(60);
+/// harness.step(&scheduler).unwrap();
+/// ```
+///
+/// Tasks must not await futures driven by another runtime, e.g. tokio timers, or they never complete.
+pub struct SchedulerTestHarness {
+    pool: RefCell<LocalPool>,
+    // The harness is bound to the thread it was created on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl SchedulerTestHarness {
+    /// Activate the harness on the current thread, with the clock set to the given timestamp.
+    ///
+    /// Panics if another harness is active on the current thread.
+    pub fn new(now_timestamp_secs: u64) -> Self {
+        let pool = LocalPool::new();
+        let spawner = pool.spawner();
+        HARNESS.with(|harness| {
+            let mut harness = harness.borrow_mut();
+            assert!(
+                harness.is_none(),
+                "a SchedulerTestHarness is already active on this thread"
+            );
+            *harness = Some(HarnessState {
+                now_timestamp_secs,
+                spawner,
+            });
+        });
+
+        Self {
+            pool: RefCell::new(pool),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns the timestamp of the harness clock.
+    pub fn now_secs(&self) -> u64 {
+        with_state(|state| state.now_timestamp_secs)
+    }
+
+    /// Move the harness clock forward.
+    pub fn advance_time(&self, secs: u64) {
+        with_state(|state| state.now_timestamp_secs += secs);
+    }
+
+    /// Set the harness clock.
+    pub fn set_time(&self, timestamp_secs: u64) {
+        with_state(|state| state.now_timestamp_secs = timestamp_secs);
+    }
+
+    /// Execute the spawned tasks, including the ones spawned meanwhile, until none of them
+    /// can make progress.
+    pub fn run_until_stalled(&self) {
+        self.pool.borrow_mut().run_until_stalled();
+    }
+
+    /// Run the scheduler and execute the launched tasks until they complete or stall.
+    /// Returns the number of launched tasks.
+    pub fn step<T, P>(&self, scheduler: &Scheduler<T, P>) -> Result<usize, SchedulerError>
+    where
+        T: 'static + Task + Serialize + DeserializeOwned + Clone,
+        P: 'static
+            + IterableSortedMapStructure<u32, InnerScheduledTask<T>>
+            + BTreeMapStructure<u32, InnerScheduledTask<T>>,
+    {
+        let launched = scheduler.run()?;
+        self.run_until_stalled();
+        Ok(launched)
+    }
+}
+
+impl Drop for SchedulerTestHarness {
+    fn drop(&mut self) {
+        HARNESS.with(|harness| harness.borrow_mut().take());
+    }
+}
+
+fn with_state<R>(f: impl FnOnce(&mut HarnessState) -> R) -> R {
+    HARNESS.with(|harness| {
+        f(harness
+            .borrow_mut()
+            .as_mut()
+            .expect("no SchedulerTestHarness is active on this thread"))
+    })
+}
+
+/// Returns the time of the active harness, if any.
+pub(crate) fn now_secs() -> Option<u64> {
+    HARNESS.with(|harness| {
+        harness
+            .borrow()
+            .as_ref()
+            .map(|state| state.now_timestamp_secs)
+    })
+}
+
+/// Spawns the future on the active harness.
+/// Returns the future back if no harness is active.
+pub(crate) fn spawn<F: 'static + Future<Output = ()>>(future: F) -> Result<(), F> {
+    HARNESS.with(|harness| match harness.borrow().as_ref() {
+        Some(state) => {
+            state
+                .spawner
+                .spawn_local(future)
+                .expect("the harness executor is alive");
+            Ok(())
+        }
+        None => Err(future),
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::pin::Pin;
+    use std::sync::Arc;
+
+    use ic_stable_structures::{StableBTreeMap, VectorMemory};
+    use parking_lot::Mutex;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::scheduler::TaskScheduler;
+    use crate::task::{ScheduledTask, TaskOptions};
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    enum ChainTask {
+        Step { remaining: u32 },
+    }
+
+    impl Task for ChainTask {
+        fn execute(
+            &self,
+            task_scheduler: Box<dyn 'static + TaskScheduler<Self>>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), SchedulerError>>>> {
+            let ChainTask::Step { remaining } = *self;
+            Box::pin(async move {
+                if remaining > 0 {
+                    task_scheduler.append_task(
+                        ChainTask::Step {
+                            remaining: remaining - 1,
+                        }
+                        .into(),
+                    );
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn test_step_scheduler_deterministically() {
+        let harness = SchedulerTestHarness::new(1_000);
+        let map = StableBTreeMap::new(VectorMemory::default());
+        let scheduler = Scheduler::new(map);
+
+        scheduler.append_task(ScheduledTask::with_options(
+            ChainTask::Step { remaining: 2 },
+            TaskOptions::new().with_execute_after_timestamp_in_secs(1_060),
+        ));
+
+        // not ready yet
+        assert_eq!(harness.step(&scheduler).unwrap(), 0);
+
+        harness.advance_time(60);
+        assert_eq!(harness.now_secs(), 1_060);
+        assert_eq!(harness.step(&scheduler).unwrap(), 1);
+
+        // the first task completed and appended the next one
+        let task = scheduler.get_task(1).unwrap();
+        assert_eq!(task.status().timestamp_secs(), 1_060);
+
+        assert_eq!(harness.step(&scheduler).unwrap(), 1);
+        assert_eq!(harness.step(&scheduler).unwrap(), 1);
+        assert_eq!(harness.step(&scheduler).unwrap(), 0);
+        assert!(scheduler.get_task(3).is_none());
+    }
+
+    #[test]
+    fn test_trace_id_propagated_to_follow_up_tasks() {
+        let harness = SchedulerTestHarness::new(1_000);
+        let completed = Arc::new(Mutex::new(vec![]));
+        let completed_t = completed.clone();
+        let scheduler = Scheduler::builder(StableBTreeMap::new(VectorMemory::default()))
+            .with_observer(move |task| completed_t.lock().push((task.id(), task.trace_id())))
+            .build();
+
+        scheduler.append_task(ChainTask::Step { remaining: 2 }.into());
+        scheduler.append_task(ScheduledTask::with_options(
+            ChainTask::Step { remaining: 0 },
+            TaskOptions::new().with_trace_id(42),
+        ));
+        while harness.step(&scheduler).unwrap() > 0 {}
+
+        let completed = completed.lock().clone();
+        assert_eq!(completed.len(), 4);
+        let root_trace_id = completed[0].1.unwrap();
+        assert_ne!(root_trace_id, 42);
+        assert_eq!(
+            completed,
+            vec![
+                (0, Some(root_trace_id)),
+                (1, Some(42)),
+                (2, Some(root_trace_id)),
+                (3, Some(root_trace_id)),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_single_harness_per_thread() {
+        let _harness = SchedulerTestHarness::new(0);
+        let _other = SchedulerTestHarness::new(0);
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Weight of the lanes without a configured weight, including the default lane.
+pub(crate) const DEFAULT_LANE_WEIGHT: u32 = 1;
+
+/// Orders the task keys with a weighted round robin between their lanes.
+///
+/// In each round, a lane with weight `w` contributes its next `w` tasks. The FIFO order is
+/// kept within each lane, and lanes take turns in order of their first task.
+pub(crate) fn interleave_by_weight(
+    tasks: Vec<(u32, Option<String>)>,
+    weights: &HashMap<String, u32>,
+) -> Vec<u32> {
+    let total = tasks.len();
+    let mut lane_positions = HashMap::new();
+    let mut lanes: Vec<(u32, VecDeque<u32>)> = Vec::new();
+
+    for (task_key, lane) in tasks {
+        let position = *lane_positions.entry(lane).or_insert_with_key(|lane| {
+            let weight = lane
+                .as_ref()
+                .and_then(|lane| weights.get(lane).copied())
+                .unwrap_or(DEFAULT_LANE_WEIGHT)
+                .max(1);
+            lanes.push((weight, VecDeque::new()));
+            lanes.len() - 1
+        });
+        lanes[position].1.push_back(task_key);
+    }
+
+    let mut ordered = Vec::with_capacity(total);
+    while ordered.len() < total {
+        for (weight, keys) in lanes.iter_mut() {
+            let count = (*weight as usize).min(keys.len());
+            ordered.extend(keys.drain(..count));
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn lane(name: &str) -> Option<String> {
+        Some(name.to_string())
+    }
+
+    #[test]
+    fn test_fifo_without_lanes() {
+        let tasks = (0..5).map(|key| (key, None)).collect();
+        assert_eq!(
+            interleave_by_weight(tasks, &HashMap::new()),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_interleave_by_weight() {
+        let mut tasks: Vec<_> = (0..6).map(|key| (key, lane("bulk"))).collect();
+        tasks.extend((6..10).map(|key| (key, lane("user"))));
+        tasks.push((10, None));
+
+        let weights = HashMap::from([("bulk".to_string(), 1), ("user".to_string(), 3)]);
+        assert_eq!(
+            interleave_by_weight(tasks, &weights),
+            vec![0, 6, 7, 8, 10, 1, 9, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_zero_weight_is_not_starved() {
+        let tasks = vec![(0, lane("a")), (1, lane("a")), (2, lane("b"))];
+        let weights = HashMap::from([("a".to_string(), 0)]);
+        assert_eq!(interleave_by_weight(tasks, &weights), vec![0, 2, 1]);
+    }
+}
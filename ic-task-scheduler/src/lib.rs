@@ -1,5 +1,11 @@
+pub mod builder;
 mod error;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod harness;
+mod lane;
+pub mod reservation;
 pub mod retry;
+pub mod saga;
 pub mod scheduler;
 pub mod task;
 mod time;
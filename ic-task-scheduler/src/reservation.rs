@@ -0,0 +1,271 @@
+use std::sync::Arc;
+
+use candid::CandidType;
+use ic_stable_structures::{BTreeMapStructure, Bound, IterableSortedMapStructure, Storable};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::time::time_secs;
+
+type ReleaseCallback<R> = Arc<dyn Fn(Reservation<R>)>;
+
+/// A resource held until it is committed or released, e.g. a balance hold or a slot.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Reservation<R> {
+    pub id: u64,
+    pub resource: R,
+    pub expires_at_secs: u64,
+}
+
+impl<R: Serialize + DeserializeOwned> Storable for Reservation<R> {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        bincode::serialize(self)
+            .expect("failed to serialize Reservation")
+            .into()
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("failed to deserialize Reservation")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Keeps track of the reserved resources, to release them if an async operation fails halfway.
+///
+/// A resource is reserved before an `await` and the returned [`ReservationGuard`] is committed
+/// once the operation succeeds. If the guard is dropped without being committed, e.g. because the
+/// code after the `await` traps and the future is dropped by the cleanup callback, the release
+/// callback is invoked. Reservations left behind when the guard could not be dropped, or held for
+/// more than their TTL, are released by [`Reservations::sweep`], that should be called periodically,
+/// e.g. by a timer or by a task of the scheduler:
+///
+/// ```ignore
+/// let reservations = Reservations::new(map, |reservation| release_hold(reservation.resource));
+/// let sweeper = reservations.clone();
+/// ic_cdk_timers::set_timer_interval(Duration::from_secs(60), move || {
+///     sweeper.sweep();
+/// });
+///
+/// let guard = reservations.reserve(Hold { account, amount }, 300);
+/// transfer(account, amount).await?;
+/// guard.commit();
+/// ```
+///
+/// The reservations must be stored in stable memory, so they survive the rollback of a trapped message.
+pub struct Reservations<R, M>
+where
+    M: BTreeMapStructure<u64, Reservation<R>> + IterableSortedMapStructure<u64, Reservation<R>>,
+{
+    reservations: Arc<Mutex<M>>,
+    on_release: ReleaseCallback<R>,
+}
+
+impl<R, M> Clone for Reservations<R, M>
+where
+    M: BTreeMapStructure<u64, Reservation<R>> + IterableSortedMapStructure<u64, Reservation<R>>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            reservations: self.reservations.clone(),
+            on_release: self.on_release.clone(),
+        }
+    }
+}
+
+impl<R, M> Reservations<R, M>
+where
+    M: BTreeMapStructure<u64, Reservation<R>> + IterableSortedMapStructure<u64, Reservation<R>>,
+{
+    /// Create a new registry. `on_release` is called with every reservation which is released
+    /// instead of being committed.
+    pub fn new(reservations: M, on_release: impl Fn(Reservation<R>) + 'static) -> Self {
+        Self {
+            reservations: Arc::new(Mutex::new(reservations)),
+            on_release: Arc::new(on_release),
+        }
+    }
+
+    /// Reserve the resource for `ttl_secs` seconds.
+    pub fn reserve(&self, resource: R, ttl_secs: u64) -> ReservationGuard<R, M> {
+        let mut lock = self.reservations.lock();
+        let id = lock
+            .last_key_value()
+            .map(|(id, _)| id + 1)
+            .unwrap_or_default();
+        lock.insert(
+            id,
+            Reservation {
+                id,
+                resource,
+                expires_at_secs: time_secs().saturating_add(ttl_secs),
+            },
+        );
+
+        ReservationGuard {
+            id,
+            reservations: Some(self.clone()),
+        }
+    }
+
+    /// Returns the pending reservation with the given id.
+    pub fn get(&self, id: u64) -> Option<Reservation<R>> {
+        self.reservations.lock().get(&id)
+    }
+
+    /// Returns the number of pending reservations.
+    pub fn len(&self) -> u64 {
+        self.reservations.lock().len()
+    }
+
+    /// Returns true if there are no pending reservations.
+    pub fn is_empty(&self) -> bool {
+        self.reservations.lock().is_empty()
+    }
+
+    /// Release the reservations whose TTL has expired.
+    /// Returns the number of released reservations.
+    pub fn sweep(&self) -> usize {
+        let now_secs = time_secs();
+        let expired = {
+            let mut lock = self.reservations.lock();
+            let expired_ids: Vec<u64> = lock
+                .iter()
+                .filter(|(_, reservation)| reservation.expires_at_secs <= now_secs)
+                .map(|(id, _)| id)
+                .collect();
+
+            expired_ids
+                .into_iter()
+                .filter_map(|id| lock.remove(&id))
+                .collect::<Vec<_>>()
+        };
+
+        let released = expired.len();
+        for reservation in expired {
+            (self.on_release)(reservation);
+        }
+        released
+    }
+
+    fn take(&self, id: u64) -> Option<Reservation<R>> {
+        self.reservations.lock().remove(&id)
+    }
+}
+
+/// Releases the reservation when dropped, unless it is committed.
+pub struct ReservationGuard<R, M>
+where
+    M: BTreeMapStructure<u64, Reservation<R>> + IterableSortedMapStructure<u64, Reservation<R>>,
+{
+    id: u64,
+    reservations: Option<Reservations<R, M>>,
+}
+
+impl<R, M> ReservationGuard<R, M>
+where
+    M: BTreeMapStructure<u64, Reservation<R>> + IterableSortedMapStructure<u64, Reservation<R>>,
+{
+    /// Returns the id of the reservation.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Consume the reservation without releasing it.
+    /// Returns None if the reservation has already been released by a sweep.
+    pub fn commit(mut self) -> Option<R> {
+        let reservations = self.reservations.take()?;
+        reservations
+            .take(self.id)
+            .map(|reservation| reservation.resource)
+    }
+
+    /// Release the reservation now.
+    pub fn release(self) {
+        // released on drop
+    }
+}
+
+impl<R, M> Drop for ReservationGuard<R, M>
+where
+    M: BTreeMapStructure<u64, Reservation<R>> + IterableSortedMapStructure<u64, Reservation<R>>,
+{
+    fn drop(&mut self) {
+        if let Some(reservations) = self.reservations.take() {
+            if let Some(reservation) = reservations.take(self.id) {
+                (reservations.on_release)(reservation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use ic_stable_structures::{StableBTreeMap, VectorMemory};
+
+    use super::*;
+
+    type TestReservations = Reservations<u32, StableBTreeMap<u64, Reservation<u32>, VectorMemory>>;
+
+    fn reservations() -> (TestReservations, Rc<RefCell<Vec<u32>>>) {
+        let released = Rc::new(RefCell::new(vec![]));
+        let released_t = released.clone();
+        let reservations = Reservations::new(
+            StableBTreeMap::new(VectorMemory::default()),
+            move |reservation: Reservation<u32>| released_t.borrow_mut().push(reservation.resource),
+        );
+        (reservations, released)
+    }
+
+    #[test]
+    fn test_commit_reservation() {
+        let (reservations, released) = reservations();
+
+        let guard = reservations.reserve(10, 60);
+        assert_eq!(reservations.get(guard.id()).unwrap().resource, 10);
+        assert_eq!(guard.commit(), Some(10));
+
+        assert!(reservations.is_empty());
+        assert!(released.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_release_on_drop() {
+        let (reservations, released) = reservations();
+
+        {
+            let _first = reservations.reserve(1, 60);
+            let second = reservations.reserve(2, 60);
+            assert_eq!(reservations.len(), 2);
+            second.release();
+            assert_eq!(*released.borrow(), vec![2]);
+        }
+
+        assert!(reservations.is_empty());
+        assert_eq!(*released.borrow(), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_sweep_expired_reservations() {
+        let (reservations, released) = reservations();
+
+        let expired = reservations.reserve(1, 0);
+        let pending = reservations.reserve(2, 3600);
+        // simulate a trap: the guard is never dropped
+        std::mem::forget(reservations.reserve(3, 0));
+
+        assert_eq!(reservations.sweep(), 2);
+        assert_eq!(*released.borrow(), vec![1, 3]);
+
+        // already released by the sweep
+        assert_eq!(expired.commit(), None);
+        assert_eq!(pending.commit(), Some(2));
+        assert_eq!(*released.borrow(), vec![1, 3]);
+        assert_eq!(reservations.sweep(), 0);
+    }
+}
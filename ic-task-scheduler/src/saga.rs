@@ -0,0 +1,151 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::task::{ScheduledTask, Task, TaskOptions};
+
+/// A single step of a [`Saga`]: the action to execute and, optionally, the
+/// compensating action that reverts its effects if one of the following steps fails.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SagaStep<T> {
+    pub(crate) action: T,
+    pub(crate) options: TaskOptions,
+    pub(crate) compensation: Option<T>,
+    pub(crate) compensation_options: TaskOptions,
+}
+
+impl<T> SagaStep<T> {
+    /// Creates a step without compensation
+    pub fn new(action: T) -> Self {
+        Self {
+            action,
+            options: Default::default(),
+            compensation: None,
+            compensation_options: Default::default(),
+        }
+    }
+
+    /// Set the task to be executed if a following step of the saga fails.
+    pub fn with_compensation(mut self, compensation: T) -> Self {
+        self.compensation = Some(compensation);
+        self
+    }
+
+    /// Set the scheduling options of the step action.
+    pub fn with_options(mut self, options: TaskOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set the scheduling options of the compensation task.
+    pub fn with_compensation_options(mut self, options: TaskOptions) -> Self {
+        self.compensation_options = options;
+        self
+    }
+}
+
+impl<T> From<T> for SagaStep<T> {
+    fn from(action: T) -> Self {
+        Self::new(action)
+    }
+}
+
+impl<T> From<(T, T)> for SagaStep<T> {
+    fn from((action, compensation): (T, T)) -> Self {
+        Self::new(action).with_compensation(compensation)
+    }
+}
+
+/// A saga is a chain of tasks executed one after the other.
+///
+/// Each step can record a compensation task. If the step N fails and no more retries are
+/// allowed, the scheduler enqueues the compensations of the steps N-1..1, in this order.
+/// The compensations are executed one after the other as well.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Saga<T> {
+    steps: Vec<SagaStep<T>>,
+}
+
+impl<T> Default for Saga<T> {
+    fn default() -> Self {
+        Self { steps: vec![] }
+    }
+}
+
+impl<T: Task> Saga<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a step to the saga.
+    pub fn with_step(mut self, step: impl Into<SagaStep<T>>) -> Self {
+        self.steps.push(step.into());
+        self
+    }
+
+    /// Returns the number of steps of the saga
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Returns whether the saga has no steps
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Returns the task that executes the first step of the saga, if any.
+    pub(crate) fn into_scheduled_task(self) -> Option<ScheduledTask<T>> {
+        SagaState::start(self.steps, vec![])
+    }
+}
+
+/// The execution state of a saga.
+/// It is carried by the task that executes the current step.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SagaState<T> {
+    /// The compensation of the step currently executed
+    compensation: Option<SagaStep<T>>,
+    /// The compensations of the completed steps, in execution order
+    completed_compensations: Vec<SagaStep<T>>,
+    /// The steps to be executed after the current one
+    pending_steps: Vec<SagaStep<T>>,
+}
+
+impl<T: Task> SagaState<T> {
+    fn start(
+        mut steps: Vec<SagaStep<T>>,
+        completed_compensations: Vec<SagaStep<T>>,
+    ) -> Option<ScheduledTask<T>> {
+        if steps.is_empty() {
+            return None;
+        }
+
+        let step = steps.remove(0);
+        let compensation = step.compensation.map(|compensation| {
+            SagaStep::new(compensation).with_options(step.compensation_options)
+        });
+        let state = Self {
+            compensation,
+            completed_compensations,
+            pending_steps: steps,
+        };
+
+        Some(ScheduledTask::with_options(step.action, step.options).with_saga(state))
+    }
+}
+
+impl<T: Task + Clone> SagaState<T> {
+    /// Returns the task that executes the next step of the saga, if any.
+    /// To be called when the current step completes successfully.
+    pub(crate) fn next_step_task(&self) -> Option<ScheduledTask<T>> {
+        let mut completed_compensations = self.completed_compensations.clone();
+        completed_compensations.extend(self.compensation.clone());
+        Self::start(self.pending_steps.clone(), completed_compensations)
+    }
+
+    /// Returns the task that executes the compensations of the completed steps, if any.
+    /// To be called when the current step fails.
+    pub(crate) fn compensation_task(&self) -> Option<ScheduledTask<T>> {
+        let compensations = self.completed_compensations.iter().rev().cloned().collect();
+        Self::start(compensations, vec![])
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
@@ -7,13 +9,16 @@ use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use crate::builder::SchedulerBuilder;
+use crate::lane::interleave_by_weight;
+use crate::retry::RetryStrategy;
+use crate::saga::Saga;
 use crate::task::{InnerScheduledTask, ScheduledTask, Task, TaskStatus};
-use crate::time::time_secs;
+use crate::time::{jitter_secs, time_secs};
 use crate::SchedulerError;
 
-type TaskCompletionCallback<T> = Box<dyn 'static + Fn(InnerScheduledTask<T>) + Send>;
-
-const DEFAULT_RUNNING_TASK_TIMEOUT_SECS: u64 = 120;
+pub(crate) type TaskCompletionCallback<T> = Box<dyn 'static + Fn(InnerScheduledTask<T>) + Send>;
+pub(crate) type IdleCallback = Box<dyn 'static + Fn() + Send>;
 
 /// A scheduler is responsible for executing tasks.
 pub struct Scheduler<
@@ -25,7 +30,15 @@ pub struct Scheduler<
     pending_tasks: Arc<Mutex<P>>,
     phantom: std::marker::PhantomData<T>,
     on_completion_callback: Arc<Option<TaskCompletionCallback<T>>>,
+    on_idle_callback: Rc<Option<IdleCallback>>,
     running_task_timeout_secs: AtomicU64,
+    visibility_timeout_secs: Option<u64>,
+    retry_strategy: RetryStrategy,
+    max_concurrent_tasks: Option<usize>,
+    max_tasks_per_run: Option<usize>,
+    lane_weights: HashMap<String, u32>,
+    /// The id of the task this scheduler has been handed to, if any.
+    running_task_id: Option<u32>,
 }
 
 impl<
@@ -35,13 +48,40 @@ impl<
             + BTreeMapStructure<u32, InnerScheduledTask<T>>,
     > Scheduler<T, P>
 {
-    /// Create a new scheduler.
+    /// Create a new scheduler with the default configuration.
     pub fn new(pending_tasks: P) -> Self {
+        Self::builder(pending_tasks).build()
+    }
+
+    /// Returns a builder to configure a new scheduler.
+    pub fn builder(pending_tasks: P) -> SchedulerBuilder<T, P> {
+        SchedulerBuilder::new(pending_tasks)
+    }
+
+    pub(crate) fn from_builder(builder: SchedulerBuilder<T, P>) -> Self {
+        let mut observers = builder.observers;
+        let on_completion_callback: Option<TaskCompletionCallback<T>> = match observers.len() {
+            0 => None,
+            1 => observers.pop(),
+            _ => Some(Box::new(move |task: InnerScheduledTask<T>| {
+                for observer in &observers {
+                    observer(task.clone());
+                }
+            })),
+        };
+
         Self {
-            pending_tasks: Arc::new(Mutex::new(pending_tasks)),
+            pending_tasks: Arc::new(Mutex::new(builder.pending_tasks)),
             phantom: std::marker::PhantomData,
-            on_completion_callback: Arc::new(None),
-            running_task_timeout_secs: AtomicU64::new(DEFAULT_RUNNING_TASK_TIMEOUT_SECS),
+            on_completion_callback: Arc::new(on_completion_callback),
+            on_idle_callback: Rc::new(builder.on_idle),
+            running_task_timeout_secs: AtomicU64::new(builder.running_task_timeout_secs),
+            visibility_timeout_secs: builder.visibility_timeout_secs,
+            retry_strategy: builder.retry_strategy,
+            max_concurrent_tasks: builder.max_concurrent_tasks,
+            max_tasks_per_run: builder.max_tasks_per_run,
+            lane_weights: builder.lane_weights,
+            running_task_id: None,
         }
     }
 
@@ -59,6 +99,11 @@ impl<
         self.on_completion_callback = Arc::new(Some(Box::new(cb)));
     }
 
+    /// Set a callback to be called when `run` finds no task to launch and no task in flight.
+    pub fn on_idle_callback<F: 'static + Send + Fn()>(&mut self, cb: F) {
+        self.on_idle_callback = Rc::new(Some(Box::new(cb)));
+    }
+
     /// Execute all pending tasks.
     /// Each task is executed asynchronously in a dedicated ic_cdk::spawn call.
     /// This function does not wait for the tasks to complete.
@@ -71,7 +116,10 @@ impl<
         debug!("Scheduler - Running tasks");
         let mut to_be_scheduled_tasks = Vec::new();
         let mut out_of_time_tasks = Vec::new();
-        let running_task_timeout_secs = self.running_task_timeout_secs.load(Ordering::Relaxed);
+        let mut in_flight_tasks = 0;
+        let running_task_timeout_secs = self
+            .visibility_timeout_secs
+            .unwrap_or_else(|| self.running_task_timeout_secs.load(Ordering::Relaxed));
 
         {
             let lock = self.pending_tasks.lock();
@@ -80,7 +128,7 @@ impl<
                     TaskStatus::Waiting { .. } => {
                         if task.options.execute_after_timestamp_in_secs <= now_timestamp_secs {
                             debug!("Scheduler - Task {} scheduled to be processed", task_key);
-                            to_be_scheduled_tasks.push(task_key);
+                            to_be_scheduled_tasks.push((task_key, task.options.lane));
                         }
                     }
                     TaskStatus::Running { timestamp_secs }
@@ -91,6 +139,8 @@ impl<
                         );
                         if timestamp_secs + running_task_timeout_secs < now_timestamp_secs {
                             out_of_time_tasks.push(task_key);
+                        } else {
+                            in_flight_tasks += 1;
                         }
                     }
                     TaskStatus::Completed { .. }
@@ -100,17 +150,49 @@ impl<
             }
         }
 
+        // Interleave the lanes before applying the limits, so that no lane is starved
+        let mut to_be_scheduled_tasks =
+            interleave_by_weight(to_be_scheduled_tasks, &self.lane_weights);
+
+        // Apply the concurrency limit and the budget of the run
+        let available_slots = self
+            .max_concurrent_tasks
+            .map(|max_concurrent_tasks| max_concurrent_tasks.saturating_sub(in_flight_tasks));
+        if let Some(limit) = [available_slots, self.max_tasks_per_run]
+            .into_iter()
+            .flatten()
+            .min()
+        {
+            to_be_scheduled_tasks.truncate(limit);
+        }
+
         // Process the tasks that are ready to be scheduled
         for task_key in to_be_scheduled_tasks.iter() {
             self.process_pending_task(*task_key, now_timestamp_secs);
         }
 
-        // Remove the tasks that are out of time
+        // Make the lost tasks visible again and remove the tasks that are out of time
+        let mut compensation_tasks = Vec::new();
+        let mut recovered_tasks = 0;
         {
             let mut lock = self.pending_tasks.lock();
             for task_key in out_of_time_tasks.into_iter() {
+                if self.visibility_timeout_secs.is_some()
+                    && self.make_visible_again(&mut *lock, task_key, now_timestamp_secs)
+                {
+                    recovered_tasks += 1;
+                    continue;
+                }
                 if let Some(mut task) = lock.remove(&task_key) {
                     task.status = TaskStatus::timeout_or_panic(now_timestamp_secs);
+                    compensation_tasks.extend(
+                        task.saga
+                            .as_ref()
+                            .and_then(|saga| saga.compensation_task())
+                            .map(|compensation| {
+                                compensation.with_trace_id_or(task.options.trace_id)
+                            }),
+                    );
                     if let Some(cb) = &*self.on_completion_callback {
                         cb(task);
                     }
@@ -118,9 +200,101 @@ impl<
             }
         }
 
+        let is_idle = to_be_scheduled_tasks.is_empty()
+            && in_flight_tasks == 0
+            && recovered_tasks == 0
+            && compensation_tasks.is_empty();
+
+        // Enqueue the compensations of the sagas whose step is out of time
+        self.append_tasks(compensation_tasks);
+
+        if is_idle {
+            if let Some(cb) = &*self.on_idle_callback {
+                debug!("Scheduler - No tasks to run, calling the idle callback");
+                cb();
+            }
+        }
+
         Ok(to_be_scheduled_tasks.len())
     }
 
+    /// Set a lost task as waiting, counting the lost execution as a failure.
+    /// Returns false if the retry strategy of the task allows no more executions.
+    fn make_visible_again(
+        &self,
+        pending_tasks: &mut P,
+        task_key: u32,
+        now_timestamp_secs: u64,
+    ) -> bool {
+        let Some(mut task) = pending_tasks.get(&task_key) else {
+            return false;
+        };
+
+        task.options.failures += 1;
+        let (should_retry, retry_delay) = task
+            .options
+            .retry_strategy
+            .as_ref()
+            .unwrap_or(&self.retry_strategy)
+            .should_retry(task.options.failures);
+        if !should_retry {
+            return false;
+        }
+
+        warn!(
+            "Scheduler - Task {} of trace {:?} lease expired, it could be lost. Status changed: {:?} -> Waiting",
+            task_key, task.options.trace_id, task.status
+        );
+        task.options.execute_after_timestamp_in_secs = now_timestamp_secs
+            + (retry_delay as u64)
+            + jitter_secs(task.options.jitter_secs, task_key, now_timestamp_secs);
+        task.status = TaskStatus::waiting(now_timestamp_secs);
+        pending_tasks.insert(task_key, task);
+        true
+    }
+
+    /// Returns true if the task is still running since the given timestamp, i.e. its lease has
+    /// not expired in the meantime.
+    fn holds_lease(&self, task_key: u32, running_timestamp_secs: u64) -> bool {
+        matches!(
+            self.pending_tasks.lock().get(&task_key).map(|task| task.status),
+            Some(TaskStatus::Running { timestamp_secs }) if timestamp_secs == running_timestamp_secs
+        )
+    }
+
+    /// Returns a copy of the scheduler to be handed to the task with the given id.
+    fn for_running_task(&self, task_id: u32) -> Self {
+        let mut scheduler = self.clone();
+        scheduler.running_task_id = Some(task_id);
+        scheduler
+    }
+
+    /// Enqueue the next task of the saga the completed task belongs to.
+    /// If the task failed, the compensations of the previous steps are enqueued instead.
+    fn continue_saga(&self, task: &InnerScheduledTask<T>) {
+        let Some(saga) = &task.saga else {
+            return;
+        };
+
+        let next_task = match task.status {
+            TaskStatus::Completed { .. } => saga.next_step_task(),
+            TaskStatus::Failed { .. } | TaskStatus::TimeoutOrPanic { .. } => {
+                debug!(
+                    "Scheduler - Task {} of a saga failed. Scheduling the compensations of the previous steps",
+                    task.id
+                );
+                saga.compensation_task()
+            }
+            TaskStatus::Waiting { .. }
+            | TaskStatus::Scheduled { .. }
+            | TaskStatus::Running { .. } => None,
+        };
+
+        if let Some(next_task) = next_task {
+            self.append_task(next_task.with_trace_id_or(task.options.trace_id));
+        }
+    }
+
     fn process_pending_task(&self, task_key: u32, now_timestamp_secs: u64) {
         let task_scheduler = self.clone();
 
@@ -147,8 +321,8 @@ impl<
             if let Some(mut task) = task {
                 if let TaskStatus::Scheduled { .. } = task.status {
                     debug!(
-                        "Scheduler - Task {} status changed: Scheduled -> Running",
-                        task_key
+                        "Scheduler - Task {} of trace {:?} status changed: Scheduled -> Running",
+                        task_key, task.options.trace_id
                     );
                     task.status = TaskStatus::running(now_timestamp_secs);
                     task_scheduler
@@ -156,35 +330,59 @@ impl<
                         .lock()
                         .insert(task_key, task.clone());
 
-                    let completed_task = match task
+                    let result = task
                         .task
-                        .execute(Box::new(task_scheduler.clone()))
-                        .await
-                    {
+                        .execute(Box::new(task_scheduler.for_running_task(task_key)))
+                        .await;
+                    if !task_scheduler.holds_lease(task_key, now_timestamp_secs) {
+                        warn!("Scheduler - Task {} of trace {:?} completed after its lease expired. The result is discarded", task_key, task.options.trace_id);
+                        return;
+                    }
+
+                    let completed_task = match result {
                         Ok(()) => {
-                            debug!("Scheduler - Task {} execution succeeded. Status changed: Running -> Completed", task_key);
                             let mut lock = task_scheduler.pending_tasks.lock();
                             let mut task = lock.remove(&task_key).unwrap();
-                            task.status = TaskStatus::completed(now_timestamp_secs);
-                            Some(task)
+                            if task.checkpoint.is_some() {
+                                debug!("Scheduler - Task {} of trace {:?} execution succeeded with a checkpoint. Execution will be resumed. Status changed: Running -> Waiting", task_key, task.options.trace_id);
+                                task.options.failures = 0;
+                                task.status = TaskStatus::waiting(now_timestamp_secs);
+                                lock.insert(task_key, task);
+                                None
+                            } else {
+                                debug!("Scheduler - Task {} of trace {:?} execution succeeded. Status changed: Running -> Completed", task_key, task.options.trace_id);
+                                task.status = TaskStatus::completed(now_timestamp_secs);
+                                Some(task)
+                            }
                         }
                         Err(err) => {
                             let mut lock = task_scheduler.pending_tasks.lock();
                             task.options.failures += 1;
+                            // Keep the checkpoint saved during the execution
+                            task.checkpoint = lock
+                                .get(&task_key)
+                                .and_then(|stored_task| stored_task.checkpoint);
                             let (should_retry, retry_delay) = task
                                 .options
                                 .retry_strategy
+                                .as_ref()
+                                .unwrap_or(&task_scheduler.retry_strategy)
                                 .should_retry(task.options.failures);
 
                             if should_retry {
-                                debug!("Scheduler - Task {} execution failed. Execution will be retried. Status changed: Running -> Waiting", task_key);
-                                task.options.execute_after_timestamp_in_secs =
-                                    now_timestamp_secs + (retry_delay as u64);
+                                debug!("Scheduler - Task {} of trace {:?} execution failed. Execution will be retried. Status changed: Running -> Waiting", task_key, task.options.trace_id);
+                                task.options.execute_after_timestamp_in_secs = now_timestamp_secs
+                                    + (retry_delay as u64)
+                                    + jitter_secs(
+                                        task.options.jitter_secs,
+                                        task_key,
+                                        now_timestamp_secs,
+                                    );
                                 task.status = TaskStatus::waiting(now_timestamp_secs);
                                 lock.insert(task_key, task);
                                 None
                             } else {
-                                debug!("Scheduler - Task {} execution failed. Status changed: Running -> Failed", task_key);
+                                debug!("Scheduler - Task {} of trace {:?} execution failed. Status changed: Running -> Failed", task_key, task.options.trace_id);
                                 let mut task = lock.remove(&task_key).unwrap();
                                 task.status = TaskStatus::failed(now_timestamp_secs, err);
                                 Some(task)
@@ -193,6 +391,7 @@ impl<
                     };
 
                     if let Some(task) = completed_task {
+                        task_scheduler.continue_saga(&task);
                         if let Some(cb) = &*task_scheduler.on_completion_callback {
                             cb(task);
                         }
@@ -207,18 +406,101 @@ impl<
     // This makes impossible to test concurrent behavior.
     #[cfg(test)]
     fn spawn<F: 'static + std::future::Future<Output = ()>>(future: F) {
-        tokio::task::spawn_local(future);
+        if let Err(future) = crate::harness::spawn(future) {
+            tokio::task::spawn_local(future);
+        }
     }
 
     #[cfg(not(test))]
     #[inline(always)]
     fn spawn<F: 'static + std::future::Future<Output = ()>>(future: F) {
-        ic_cdk_timers::set_timer(std::time::Duration::from_millis(0), || {
+        #[cfg(feature = "test-harness")]
+        let Err(future) = crate::harness::spawn(future) else {
+            return;
+        };
+
+        #[cfg(feature = "chaos")]
+        let delay = ic_kit::chaos::task_delay();
+        #[cfg(not(feature = "chaos"))]
+        let delay = std::time::Duration::from_millis(0);
+
+        ic_cdk_timers::set_timer(delay, || {
             ic_kit::ic::spawn(future);
         });
     }
 }
 
+impl<
+        T: 'static + Task + Serialize + DeserializeOwned,
+        P: 'static
+            + IterableSortedMapStructure<u32, InnerScheduledTask<T>>
+            + BTreeMapStructure<u32, InnerScheduledTask<T>>,
+    > Scheduler<T, P>
+{
+    /// Append many tasks to the scheduler and return their keys, in the same order.
+    ///
+    /// The tasks are written under a single lock acquisition with consecutive keys,
+    /// which is much faster than appending them one by one.
+    pub fn add_tasks<I>(&self, tasks: I) -> Vec<u32>
+    where
+        I: IntoIterator,
+        I::Item: Into<ScheduledTask<T>>,
+    {
+        let mut tasks = tasks.into_iter().peekable();
+        if tasks.peek().is_none() {
+            return vec![];
+        }
+
+        let time_secs = time_secs();
+        let mut lock = self.pending_tasks.lock();
+        let first_key = lock
+            .last_key_value()
+            .map(|(val, _)| val + 1)
+            .unwrap_or_default();
+        // The tasks appended by a running task belong to its trace
+        let parent_trace_id = self
+            .running_task_id
+            .and_then(|task_id| lock.get(&task_id))
+            .and_then(|task| task.options.trace_id);
+
+        tasks
+            .zip(first_key..)
+            .map(|(task, key)| {
+                let mut task: ScheduledTask<T> = task.into();
+                task.options.trace_id = task
+                    .options
+                    .trace_id
+                    .or(parent_trace_id)
+                    .or_else(|| Some(new_trace_id(key, time_secs)));
+                if task.options.jitter_secs > 0 {
+                    task.options.execute_after_timestamp_in_secs =
+                        task.options.execute_after_timestamp_in_secs.max(time_secs)
+                            + jitter_secs(task.options.jitter_secs, key, time_secs);
+                }
+
+                lock.insert(
+                    key,
+                    InnerScheduledTask::with_status(key, task, TaskStatus::waiting(time_secs)),
+                );
+                key
+            })
+            .collect()
+    }
+
+    /// Set the checkpoint of the running task. Does nothing if the scheduler is not handed to a task.
+    fn update_running_task_checkpoint(&self, checkpoint: Option<Vec<u8>>) {
+        let Some(task_id) = self.running_task_id else {
+            return;
+        };
+
+        let mut lock = self.pending_tasks.lock();
+        if let Some(mut task) = lock.get(&task_id) {
+            task.checkpoint = checkpoint;
+            lock.insert(task_id, task);
+        }
+    }
+}
+
 pub trait TaskScheduler<T: 'static + Task> {
     /// Append a task to the scheduler and return the key of the task.
     fn append_task(&self, task: ScheduledTask<T>) -> u32;
@@ -226,6 +508,31 @@ pub trait TaskScheduler<T: 'static + Task> {
     fn append_tasks(&self, tasks: Vec<ScheduledTask<T>>) -> Vec<u32>;
     /// Get a task by its key.
     fn get_task(&self, task_id: u32) -> Option<InnerScheduledTask<T>>;
+    /// Append a saga to the scheduler and return the key of the task that executes its first step.
+    /// Returns None if the saga has no steps.
+    fn append_saga(&self, saga: Saga<T>) -> Option<u32>;
+    /// Persist in stable memory the progress cursor of the running task.
+    ///
+    /// The cursor is available to the next executions of the task through [`TaskScheduler::checkpoint`],
+    /// this includes the retries after a failure.
+    /// If the task completes successfully while a checkpoint is saved, it is scheduled again to resume
+    /// from it. The task is completed only when it succeeds after calling [`TaskScheduler::clear_checkpoint`].
+    ///
+    /// This permits to split a long running task, e.g. an iteration over millions of map entries,
+    /// across many executions.
+    ///
+    /// The default implementation doesn't persist the cursor.
+    fn save_checkpoint(&self, _cursor: Vec<u8>) {}
+    /// Returns the progress cursor saved by the running task, if any.
+    fn checkpoint(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Remove the progress cursor of the running task.
+    fn clear_checkpoint(&self) {}
+    /// Returns the trace id of the running task, to correlate the logs of a multi-step job.
+    fn trace_id(&self) -> Option<u64> {
+        None
+    }
 }
 
 impl<
@@ -240,9 +547,16 @@ impl<
             pending_tasks: self.pending_tasks.clone(),
             phantom: self.phantom,
             on_completion_callback: self.on_completion_callback.clone(),
+            on_idle_callback: self.on_idle_callback.clone(),
             running_task_timeout_secs: AtomicU64::new(
                 self.running_task_timeout_secs.load(Ordering::Relaxed),
             ),
+            visibility_timeout_secs: self.visibility_timeout_secs,
+            retry_strategy: self.retry_strategy.clone(),
+            max_concurrent_tasks: self.max_concurrent_tasks,
+            max_tasks_per_run: self.max_tasks_per_run,
+            lane_weights: self.lane_weights.clone(),
+            running_task_id: self.running_task_id,
         }
     }
 }
@@ -255,58 +569,50 @@ impl<
     > TaskScheduler<T> for Scheduler<T, P>
 {
     fn append_task(&self, task: ScheduledTask<T>) -> u32 {
-        let time_secs = time_secs();
-        let mut lock = self.pending_tasks.lock();
-        let key = lock
-            .last_key_value()
-            .map(|(val, _)| val + 1)
-            .unwrap_or_default();
-        lock.insert(
-            key,
-            InnerScheduledTask::with_status(
-                key,
-                task,
-                TaskStatus::Waiting {
-                    timestamp_secs: time_secs,
-                },
-            ),
-        );
-        key
+        self.add_tasks([task])[0]
     }
 
     fn append_tasks(&self, tasks: Vec<ScheduledTask<T>>) -> Vec<u32> {
-        if tasks.is_empty() {
-            return vec![];
-        };
-
-        let time_secs = time_secs();
-        let mut lock = self.pending_tasks.lock();
-        let mut key = lock
-            .last_key_value()
-            .map(|(val, _)| val + 1)
-            .unwrap_or_default();
-
-        let mut keys = Vec::with_capacity(tasks.len());
-        for task in tasks {
-            lock.insert(
-                key,
-                InnerScheduledTask::with_status(
-                    key,
-                    task,
-                    TaskStatus::Waiting {
-                        timestamp_secs: time_secs,
-                    },
-                ),
-            );
-            keys.push(key);
-            key += 1;
-        }
-        keys
+        self.add_tasks(tasks)
     }
 
     fn get_task(&self, task_id: u32) -> Option<InnerScheduledTask<T>> {
         self.pending_tasks.lock().get(&task_id)
     }
+
+    fn append_saga(&self, saga: Saga<T>) -> Option<u32> {
+        saga.into_scheduled_task()
+            .map(|task| self.append_task(task))
+    }
+
+    fn save_checkpoint(&self, cursor: Vec<u8>) {
+        self.update_running_task_checkpoint(Some(cursor));
+    }
+
+    fn checkpoint(&self) -> Option<Vec<u8>> {
+        let task_id = self.running_task_id?;
+        self.pending_tasks
+            .lock()
+            .get(&task_id)
+            .and_then(|task| task.checkpoint)
+    }
+
+    fn clear_checkpoint(&self) {
+        self.update_running_task_checkpoint(None);
+    }
+
+    fn trace_id(&self) -> Option<u64> {
+        let task_id = self.running_task_id?;
+        self.pending_tasks
+            .lock()
+            .get(&task_id)
+            .and_then(|task| task.options.trace_id)
+    }
+}
+
+/// Returns the id of a new trace, started by the task with the given key.
+fn new_trace_id(task_key: u32, time_secs: u64) -> u64 {
+    (time_secs << 32) | task_key as u64
 }
 
 #[cfg(test)]
@@ -959,4 +1265,684 @@ mod test {
             assert_eq!(called.load(std::sync::atomic::Ordering::SeqCst), 1);
         }
     }
+
+    mod test_saga {
+
+        use std::collections::HashMap;
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::time::Duration;
+
+        use ic_stable_structures::{StableBTreeMap, VectorMemory};
+        use rand::random;
+        use serde::{Deserialize, Serialize};
+
+        use super::*;
+        use crate::saga::SagaStep;
+        use crate::task::TaskOptions;
+
+        type SagaScheduler =
+            Scheduler<SagaTask, StableBTreeMap<u32, InnerScheduledTask<SagaTask>, VectorMemory>>;
+
+        thread_local! {
+            static STATE: Mutex<HashMap<u32, Vec<String>>> = Mutex::new(HashMap::new());
+        }
+
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+        pub enum SagaTask {
+            Step { id: u32, step: u32, fail: bool },
+            Compensate { id: u32, step: u32 },
+        }
+
+        impl Task for SagaTask {
+            fn execute(
+                &self,
+                _task_scheduler: Box<dyn 'static + TaskScheduler<Self>>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), SchedulerError>>>> {
+                let task = self.clone();
+                Box::pin(async move {
+                    let (id, msg, result) = match task {
+                        SagaTask::Step { id, step, fail } if fail => (
+                            id,
+                            format!("Step {} - Failure", step),
+                            Err(SchedulerError::TaskExecutionFailed("".into())),
+                        ),
+                        SagaTask::Step { id, step, .. } => (id, format!("Step {}", step), Ok(())),
+                        SagaTask::Compensate { id, step } => {
+                            (id, format!("Compensate {}", step), Ok(()))
+                        }
+                    };
+                    STATE.with(|state| {
+                        state.lock().entry(id).or_default().push(msg);
+                    });
+                    result
+                })
+            }
+        }
+
+        fn saga(id: u32, failing_step: u32) -> Saga<SagaTask> {
+            (1..=3).fold(Saga::new(), |saga, step| {
+                saga.with_step((
+                    SagaTask::Step {
+                        id,
+                        step,
+                        fail: step == failing_step,
+                    },
+                    SagaTask::Compensate { id, step },
+                ))
+            })
+        }
+
+        async fn run_until_empty(scheduler: &SagaScheduler) {
+            for _ in 0..10 {
+                scheduler.run().unwrap();
+                tokio::time::sleep(Duration::from_millis(25)).await;
+            }
+            assert!(scheduler.pending_tasks.lock().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_saga_executes_all_steps() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::new(map);
+                    let id = random();
+
+                    assert!(scheduler.append_saga(saga(id, 0)).is_some());
+                    run_until_empty(&scheduler).await;
+
+                    STATE.with(|state| {
+                        let state = state.lock();
+                        assert_eq!(
+                            state.get(&id).cloned().unwrap_or_default(),
+                            vec!["Step 1", "Step 2", "Step 3"]
+                        );
+                    });
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_saga_runs_compensations_in_reverse_order_on_failure() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::new(map);
+                    let id = random();
+
+                    scheduler.append_saga(saga(id, 3));
+                    run_until_empty(&scheduler).await;
+
+                    STATE.with(|state| {
+                        let state = state.lock();
+                        assert_eq!(
+                            state.get(&id).cloned().unwrap_or_default(),
+                            vec![
+                                "Step 1",
+                                "Step 2",
+                                "Step 3 - Failure",
+                                "Compensate 2",
+                                "Compensate 1"
+                            ]
+                        );
+                    });
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_saga_compensates_only_after_retries() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::new(map);
+                    let id = random();
+
+                    let saga = Saga::new()
+                        .with_step((
+                            SagaTask::Step {
+                                id,
+                                step: 1,
+                                fail: false,
+                            },
+                            SagaTask::Compensate { id, step: 1 },
+                        ))
+                        .with_step(
+                            SagaStep::new(SagaTask::Step {
+                                id,
+                                step: 2,
+                                fail: true,
+                            })
+                            .with_options(
+                                TaskOptions::new()
+                                    .with_max_retries_policy(1)
+                                    .with_fixed_backoff_policy(0),
+                            ),
+                        );
+
+                    scheduler.append_saga(saga);
+                    run_until_empty(&scheduler).await;
+
+                    STATE.with(|state| {
+                        let state = state.lock();
+                        assert_eq!(
+                            state.get(&id).cloned().unwrap_or_default(),
+                            vec![
+                                "Step 1",
+                                "Step 2 - Failure",
+                                "Step 2 - Failure",
+                                "Compensate 1"
+                            ]
+                        );
+                    });
+                })
+                .await;
+        }
+
+        #[test]
+        fn test_empty_saga_is_not_scheduled() {
+            let map = StableBTreeMap::new(VectorMemory::default());
+            let scheduler = Scheduler::<SagaTask, _>::new(map);
+
+            assert_eq!(scheduler.append_saga(Saga::new()), None);
+            assert!(scheduler.pending_tasks.lock().is_empty());
+        }
+    }
+
+    mod test_checkpoint {
+
+        use std::collections::HashMap;
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::time::Duration;
+
+        use ic_stable_structures::{StableBTreeMap, VectorMemory};
+        use rand::random;
+        use serde::{Deserialize, Serialize};
+
+        use super::*;
+        use crate::task::TaskOptions;
+
+        thread_local! {
+            static STATE: Mutex<HashMap<u32, Vec<u32>>> = Mutex::new(HashMap::new());
+        }
+
+        /// Processes the numbers in `0..items` in chunks of `chunk_size` elements.
+        /// If `fail_after_checkpoint` is true, the first execution fails after saving the checkpoint.
+        #[derive(Serialize, Deserialize, Debug, Clone)]
+        pub struct ChunkedTask {
+            id: u32,
+            items: u32,
+            chunk_size: u32,
+            fail_after_checkpoint: bool,
+        }
+
+        impl Task for ChunkedTask {
+            fn execute(
+                &self,
+                task_scheduler: Box<dyn 'static + TaskScheduler<Self>>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), SchedulerError>>>> {
+                let task = self.clone();
+                Box::pin(async move {
+                    let start = task_scheduler
+                        .checkpoint()
+                        .map(|cursor| u32::from_le_bytes(cursor.try_into().unwrap()))
+                        .unwrap_or_default();
+                    let end = (start + task.chunk_size).min(task.items);
+
+                    STATE.with(|state| {
+                        state.lock().entry(task.id).or_default().extend(start..end);
+                    });
+
+                    if end == task.items {
+                        task_scheduler.clear_checkpoint();
+                        return Ok(());
+                    }
+
+                    task_scheduler.save_checkpoint(end.to_le_bytes().to_vec());
+                    if task.fail_after_checkpoint && start == 0 {
+                        return Err(SchedulerError::TaskExecutionFailed("".into()));
+                    }
+                    Ok(())
+                })
+            }
+        }
+
+        #[tokio::test]
+        async fn test_task_resumes_from_checkpoint() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::new(map);
+                    let id = random();
+
+                    scheduler.append_task(
+                        ChunkedTask {
+                            id,
+                            items: 10,
+                            chunk_size: 3,
+                            fail_after_checkpoint: false,
+                        }
+                        .into(),
+                    );
+
+                    for i in 1..=3u32 {
+                        scheduler.run().unwrap();
+                        tokio::time::sleep(Duration::from_millis(25)).await;
+
+                        let pending_tasks = scheduler.pending_tasks.lock();
+                        let task = pending_tasks.get(&0).unwrap();
+                        assert_eq!(task.checkpoint(), Some(&(i * 3).to_le_bytes()[..]));
+                        assert!(matches!(task.status, TaskStatus::Waiting { .. }));
+                    }
+
+                    scheduler.run().unwrap();
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+
+                    assert!(scheduler.pending_tasks.lock().is_empty());
+                    STATE.with(|state| {
+                        let state = state.lock();
+                        assert_eq!(
+                            state.get(&id).cloned().unwrap_or_default(),
+                            (0..10).collect::<Vec<_>>()
+                        );
+                    });
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_retry_resumes_from_checkpoint() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::new(map);
+                    let id = random();
+
+                    scheduler.append_task(
+                        (
+                            ChunkedTask {
+                                id,
+                                items: 4,
+                                chunk_size: 2,
+                                fail_after_checkpoint: true,
+                            },
+                            TaskOptions::new()
+                                .with_max_retries_policy(1)
+                                .with_fixed_backoff_policy(0),
+                        )
+                            .into(),
+                    );
+
+                    scheduler.run().unwrap();
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                    {
+                        let pending_tasks = scheduler.pending_tasks.lock();
+                        let task = pending_tasks.get(&0).unwrap();
+                        assert_eq!(task.options.failures, 1);
+                        assert_eq!(task.checkpoint(), Some(&2u32.to_le_bytes()[..]));
+                    }
+
+                    scheduler.run().unwrap();
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+
+                    assert!(scheduler.pending_tasks.lock().is_empty());
+                    STATE.with(|state| {
+                        let state = state.lock();
+                        assert_eq!(
+                            state.get(&id).cloned().unwrap_or_default(),
+                            vec![0, 1, 2, 3]
+                        );
+                    });
+                })
+                .await;
+        }
+
+        #[test]
+        fn test_checkpoint_requires_running_task() {
+            let map = StableBTreeMap::new(VectorMemory::default());
+            let scheduler = Scheduler::new(map);
+            scheduler.append_task(
+                ChunkedTask {
+                    id: 0,
+                    items: 1,
+                    chunk_size: 1,
+                    fail_after_checkpoint: false,
+                }
+                .into(),
+            );
+
+            scheduler.save_checkpoint(vec![1]);
+            assert_eq!(scheduler.checkpoint(), None);
+            assert_eq!(
+                scheduler.pending_tasks.lock().get(&0).unwrap().checkpoint(),
+                None
+            );
+        }
+    }
+
+    mod test_builder {
+
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::sync::atomic::AtomicU32;
+        use std::time::Duration;
+
+        use ic_stable_structures::{StableBTreeMap, VectorMemory};
+        use serde::{Deserialize, Serialize};
+
+        use super::*;
+        use crate::retry::{BackoffPolicy, RetryPolicy};
+        use crate::task::TaskOptions;
+
+        #[derive(Serialize, Deserialize, Debug, Clone)]
+        pub enum SimpleTask {
+            Sleep { millis: u64 },
+            Fail,
+        }
+
+        impl Task for SimpleTask {
+            fn execute(
+                &self,
+                _task_scheduler: Box<dyn 'static + TaskScheduler<Self>>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), SchedulerError>>>> {
+                match self {
+                    SimpleTask::Sleep { millis } => {
+                        let millis = *millis;
+                        Box::pin(async move {
+                            tokio::time::sleep(Duration::from_millis(millis)).await;
+                            Ok(())
+                        })
+                    }
+                    SimpleTask::Fail => {
+                        Box::pin(async move { Err(SchedulerError::TaskExecutionFailed("".into())) })
+                    }
+                }
+            }
+        }
+
+        #[tokio::test]
+        async fn test_max_tasks_per_run() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::builder(map).with_max_tasks_per_run(2).build();
+                    for _ in 0..5 {
+                        scheduler.append_task(SimpleTask::Sleep { millis: 0 }.into());
+                    }
+
+                    for expected in [2, 2, 1, 0] {
+                        assert_eq!(expected, scheduler.run().unwrap());
+                        tokio::time::sleep(Duration::from_millis(25)).await;
+                    }
+                    assert!(scheduler.pending_tasks.lock().is_empty());
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_max_concurrent_tasks() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::builder(map).with_max_concurrent_tasks(2).build();
+                    for _ in 0..3 {
+                        scheduler.append_task(SimpleTask::Sleep { millis: 100 }.into());
+                    }
+
+                    assert_eq!(2, scheduler.run().unwrap());
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                    // The first two tasks are still running
+                    assert_eq!(0, scheduler.run().unwrap());
+
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    assert_eq!(1, scheduler.run().unwrap());
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    assert!(scheduler.pending_tasks.lock().is_empty());
+                })
+                .await;
+        }
+
+        #[test]
+        fn test_add_tasks() {
+            let map = StableBTreeMap::new(VectorMemory::default());
+            let scheduler = Scheduler::builder(map).build();
+
+            assert!(scheduler.add_tasks(Vec::<SimpleTask>::new()).is_empty());
+            assert_eq!(scheduler.append_task(SimpleTask::Fail.into()), 0);
+
+            let keys = scheduler.add_tasks((0..1000).map(|millis| SimpleTask::Sleep { millis }));
+            assert_eq!(keys, (1..=1000).collect::<Vec<_>>());
+            assert_eq!(scheduler.pending_tasks.lock().len(), 1001);
+
+            let task = scheduler.get_task(1000).unwrap();
+            assert!(matches!(task.task, SimpleTask::Sleep { millis: 999 }));
+            assert!(matches!(task.status, TaskStatus::Waiting { .. }));
+
+            let keys = scheduler.add_tasks([(
+                SimpleTask::Fail,
+                TaskOptions::new().with_execute_after_timestamp_in_secs(10),
+            )]);
+            assert_eq!(keys, vec![1001]);
+        }
+
+        #[test]
+        fn test_jitter_tasks() {
+            let map = StableBTreeMap::new(VectorMemory::default());
+            let scheduler = Scheduler::builder(map).build();
+            let now = time_secs();
+
+            let keys = scheduler.add_tasks(
+                (0..100).map(|_| (SimpleTask::Fail, TaskOptions::new().with_jitter_secs(60))),
+            );
+
+            let delays: Vec<_> = keys
+                .iter()
+                .map(|key| {
+                    scheduler
+                        .get_task(*key)
+                        .unwrap()
+                        .options
+                        .execute_after_timestamp_in_secs
+                })
+                .collect();
+            assert!(delays
+                .iter()
+                .all(|timestamp| (now..=time_secs() + 60).contains(timestamp)));
+            assert!(delays.iter().any(|timestamp| *timestamp != delays[0]));
+
+            let key = scheduler.append_task(
+                (
+                    SimpleTask::Fail,
+                    TaskOptions::new()
+                        .with_execute_after_timestamp_in_secs(now + 1_000)
+                        .with_jitter_secs(5),
+                )
+                    .into(),
+            );
+            let task = scheduler.get_task(key).unwrap();
+            assert!(
+                (now + 1_000..=now + 1_005).contains(&task.options.execute_after_timestamp_in_secs)
+            );
+        }
+
+        #[tokio::test]
+        async fn test_lane_weights() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::builder(map)
+                        .with_max_tasks_per_run(4)
+                        .with_lane_weight("user", 3)
+                        .build();
+                    for _ in 0..6 {
+                        scheduler.append_task(
+                            (
+                                SimpleTask::Sleep { millis: 0 },
+                                TaskOptions::new().with_lane("bulk"),
+                            )
+                                .into(),
+                        );
+                    }
+                    for _ in 0..3 {
+                        scheduler.append_task(
+                            (
+                                SimpleTask::Sleep { millis: 0 },
+                                TaskOptions::new().with_lane("user"),
+                            )
+                                .into(),
+                        );
+                    }
+
+                    assert_eq!(4, scheduler.run().unwrap());
+                    let scheduled_tasks: Vec<_> = scheduler
+                        .pending_tasks
+                        .lock()
+                        .iter()
+                        .filter(|(_, task)| matches!(task.status, TaskStatus::Scheduled { .. }))
+                        .map(|(key, _)| key)
+                        .collect();
+                    assert_eq!(scheduled_tasks, vec![0, 6, 7, 8]);
+
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                    assert_eq!(4, scheduler.run().unwrap());
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                    assert_eq!(1, scheduler.run().unwrap());
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_on_idle() {
+            let local = tokio::task::LocalSet::new();
+            let idle_calls = Arc::new(AtomicU32::new(0));
+            let idle_calls_t = idle_calls.clone();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::builder(map)
+                        .with_on_idle(move || {
+                            idle_calls_t.fetch_add(1, Ordering::SeqCst);
+                        })
+                        .build();
+
+                    assert_eq!(0, scheduler.run().unwrap());
+                    assert_eq!(idle_calls.load(Ordering::SeqCst), 1);
+
+                    scheduler.append_task(SimpleTask::Sleep { millis: 50 }.into());
+                    assert_eq!(1, scheduler.run().unwrap());
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    // The task is still running
+                    assert_eq!(0, scheduler.run().unwrap());
+                    assert_eq!(idle_calls.load(Ordering::SeqCst), 1);
+
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    assert_eq!(0, scheduler.run().unwrap());
+                    assert_eq!(idle_calls.load(Ordering::SeqCst), 2);
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_visibility_timeout() {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::builder(map)
+                        .with_visibility_timeout(10)
+                        .with_retry_strategy(RetryStrategy {
+                            retry_policy: RetryPolicy::MaxRetries { retries: 1 },
+                            backoff_policy: BackoffPolicy::None,
+                        })
+                        .build();
+                    let now = time_secs();
+                    scheduler.append_task(SimpleTask::Sleep { millis: 100 }.into());
+
+                    assert_eq!(1, scheduler.run_with_timestamp(now).unwrap());
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                    assert!(matches!(
+                        scheduler.get_task(0).unwrap().status,
+                        TaskStatus::Running { .. }
+                    ));
+
+                    // The lease expires while the task is running
+                    assert_eq!(0, scheduler.run_with_timestamp(now + 20).unwrap());
+                    let task = scheduler.get_task(0).unwrap();
+                    assert!(matches!(task.status, TaskStatus::Waiting { .. }));
+                    assert_eq!(task.options.failures, 1);
+
+                    // The result of the lost execution is discarded
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    assert!(matches!(
+                        scheduler.get_task(0).unwrap().status,
+                        TaskStatus::Waiting { .. }
+                    ));
+
+                    assert_eq!(1, scheduler.run_with_timestamp(now + 20).unwrap());
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    assert!(scheduler.pending_tasks.lock().is_empty());
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_scheduler_retry_strategy_and_observers() {
+            let local = tokio::task::LocalSet::new();
+            let first_observer_calls = Arc::new(AtomicU32::new(0));
+            let second_observer_calls = Arc::new(AtomicU32::new(0));
+            let first_observer_calls_t = first_observer_calls.clone();
+            let second_observer_calls_t = second_observer_calls.clone();
+            local
+                .run_until(async move {
+                    let map = StableBTreeMap::new(VectorMemory::default());
+                    let scheduler = Scheduler::builder(map)
+                        .with_retry_strategy(RetryStrategy {
+                            retry_policy: RetryPolicy::MaxRetries { retries: 2 },
+                            backoff_policy: BackoffPolicy::None,
+                        })
+                        .with_observer(move |task| {
+                            assert!(matches!(task.status, TaskStatus::Failed { .. }));
+                            first_observer_calls_t.fetch_add(1, Ordering::SeqCst);
+                        })
+                        .with_observer(move |_| {
+                            second_observer_calls_t.fetch_add(1, Ordering::SeqCst);
+                        })
+                        .build();
+
+                    // uses the retry strategy of the scheduler
+                    scheduler.append_task(SimpleTask::Fail.into());
+                    // overrides the retry strategy of the scheduler
+                    scheduler.append_task(
+                        (
+                            SimpleTask::Fail,
+                            TaskOptions::new().with_retry_policy(RetryPolicy::None),
+                        )
+                            .into(),
+                    );
+
+                    for _ in 0..3 {
+                        scheduler.run().unwrap();
+                        tokio::time::sleep(Duration::from_millis(25)).await;
+                        if scheduler.pending_tasks.lock().len() == 1 {
+                            assert!(scheduler.get_task(0).is_some());
+                        }
+                    }
+                    assert!(scheduler.pending_tasks.lock().is_empty());
+                })
+                .await;
+
+            assert_eq!(first_observer_calls.load(Ordering::SeqCst), 2);
+            assert_eq!(second_observer_calls.load(Ordering::SeqCst), 2);
+        }
+    }
 }
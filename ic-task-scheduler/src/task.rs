@@ -7,9 +7,16 @@ use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use crate::retry::{BackoffPolicy, RetryPolicy, RetryStrategy};
+use crate::saga::SagaState;
 use crate::scheduler::TaskScheduler;
 use crate::SchedulerError;
 
+/// Header of the versioned encoding of the stored tasks. The unversioned encoding of the first
+/// releases starts with the id of the task, which is never `u32::MAX`.
+const TASK_ENCODING_HEADER: [u8; 4] = [0xFF; 4];
+/// Version of the encoding of the stored tasks, to be increased when a field is added
+const TASK_ENCODING_VERSION: u8 = 1;
+
 /// A sync task is a unit of work that can be executed by the scheduler.
 pub trait Task {
     /// Execute the task and return the next task to execute.
@@ -24,6 +31,7 @@ pub trait Task {
 pub struct ScheduledTask<T: Task> {
     pub(crate) task: T,
     pub(crate) options: TaskOptions,
+    pub(crate) saga: Option<SagaState<T>>,
 }
 
 impl<T: Task> ScheduledTask<T> {
@@ -31,11 +39,28 @@ impl<T: Task> ScheduledTask<T> {
         Self {
             task,
             options: Default::default(),
+            saga: None,
         }
     }
 
     pub fn with_options(task: T, options: TaskOptions) -> Self {
-        Self { task, options }
+        Self {
+            task,
+            options,
+            saga: None,
+        }
+    }
+
+    /// Set the trace id of the task, unless it has one already
+    pub(crate) fn with_trace_id_or(mut self, trace_id: Option<u64>) -> Self {
+        self.options.trace_id = self.options.trace_id.or(trace_id);
+        self
+    }
+
+    /// Set the state of the saga the task belongs to
+    pub(crate) fn with_saga(mut self, saga: SagaState<T>) -> Self {
+        self.saga = Some(saga);
+        self
     }
 }
 
@@ -57,6 +82,8 @@ pub struct InnerScheduledTask<T: Task> {
     pub(crate) task: T,
     pub(crate) options: TaskOptions,
     pub(crate) status: TaskStatus,
+    pub(crate) saga: Option<SagaState<T>>,
+    pub(crate) checkpoint: Option<Vec<u8>>,
 }
 
 impl<T: Task> InnerScheduledTask<T> {
@@ -67,6 +94,8 @@ impl<T: Task> InnerScheduledTask<T> {
             task: task.task,
             options: task.options,
             status,
+            saga: task.saga,
+            checkpoint: None,
         }
     }
 
@@ -89,22 +118,81 @@ impl<T: Task> InnerScheduledTask<T> {
     pub fn id(&self) -> u32 {
         self.id
     }
+
+    /// Returns the state of the saga the task belongs to, if any
+    pub fn saga(&self) -> Option<&SagaState<T>> {
+        self.saga.as_ref()
+    }
+
+    /// Returns the trace id of the task. The tasks appended to a scheduler always have one.
+    pub fn trace_id(&self) -> Option<u64> {
+        self.options.trace_id
+    }
+
+    /// Returns the progress cursor saved by the task, if any
+    pub fn checkpoint(&self) -> Option<&[u8]> {
+        self.checkpoint.as_deref()
+    }
 }
 
 impl<T: 'static + Task + Serialize + DeserializeOwned> Storable for InnerScheduledTask<T> {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        bincode::serialize(self)
-            .expect("failed to serialize ScheduledTask")
-            .into()
+        let mut bytes = TASK_ENCODING_HEADER.to_vec();
+        bytes.push(TASK_ENCODING_VERSION);
+        bincode::serialize_into(&mut bytes, self).expect("failed to serialize ScheduledTask");
+        bytes.into()
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        bincode::deserialize(&bytes).expect("failed to deserialize ScheduledTask")
+        match bytes.strip_prefix(&TASK_ENCODING_HEADER[..]) {
+            Some([TASK_ENCODING_VERSION, encoded @ ..]) => {
+                bincode::deserialize(encoded).expect("failed to deserialize ScheduledTask")
+            }
+            Some([version, ..]) => panic!("unknown task encoding version {version}"),
+            Some([]) => panic!("missing task encoding version"),
+            None => bincode::deserialize::<LegacyInnerScheduledTask<T>>(&bytes)
+                .expect("failed to deserialize ScheduledTask")
+                .into(),
+        }
     }
 
     const BOUND: Bound = Bound::Unbounded;
 }
 
+/// Unversioned encoding of the tasks stored by the first releases
+#[derive(Deserialize)]
+struct LegacyInnerScheduledTask<T> {
+    id: u32,
+    task: T,
+    options: LegacyTaskOptions,
+    status: TaskStatus,
+}
+
+#[derive(Deserialize)]
+struct LegacyTaskOptions {
+    failures: u32,
+    execute_after_timestamp_in_secs: u64,
+    retry_strategy: RetryStrategy,
+}
+
+impl<T: Task> From<LegacyInnerScheduledTask<T>> for InnerScheduledTask<T> {
+    fn from(task: LegacyInnerScheduledTask<T>) -> Self {
+        Self {
+            id: task.id,
+            task: task.task,
+            options: TaskOptions {
+                failures: task.options.failures,
+                execute_after_timestamp_in_secs: task.options.execute_after_timestamp_in_secs,
+                retry_strategy: Some(task.options.retry_strategy),
+                ..Default::default()
+            },
+            status: task.status,
+            saga: None,
+            checkpoint: None,
+        }
+    }
+}
+
 /// The status of a task in the scheduler
 #[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum TaskStatus {
@@ -177,7 +265,14 @@ impl TaskStatus {
 pub struct TaskOptions {
     pub(crate) failures: u32,
     pub(crate) execute_after_timestamp_in_secs: u64,
-    pub(crate) retry_strategy: RetryStrategy,
+    /// The retry strategy of the task. If not set, the retry strategy of the scheduler is used.
+    pub(crate) retry_strategy: Option<RetryStrategy>,
+    /// The lane of the task, used to interleave the execution of different kinds of tasks.
+    pub(crate) lane: Option<String>,
+    /// The max random delay added to the execution timestamp when the task is appended.
+    pub(crate) jitter_secs: u32,
+    /// The id shared by the tasks of the same job, set when the task is appended.
+    pub(crate) trace_id: Option<u64>,
 }
 
 impl TaskOptions {
@@ -187,25 +282,65 @@ impl TaskOptions {
 
     /// Set the retry policy for a failed task to RetryPolicy::MaxRetries.
     pub fn with_max_retries_policy(mut self, retries: u32) -> Self {
-        self.retry_strategy.retry_policy = RetryPolicy::MaxRetries { retries };
+        self.retry_strategy_mut().retry_policy = RetryPolicy::MaxRetries { retries };
         self
     }
 
     /// Set the retry policy for a failed task. Default is RetryPolicy::None.
     pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
-        self.retry_strategy.retry_policy = retry_policy;
+        self.retry_strategy_mut().retry_policy = retry_policy;
         self
     }
 
     /// Set the backoff policy for a failed task to BackoffPolicy::Fixed.
     pub fn with_fixed_backoff_policy(mut self, secs: u32) -> Self {
-        self.retry_strategy.backoff_policy = BackoffPolicy::Fixed { secs };
+        self.retry_strategy_mut().backoff_policy = BackoffPolicy::Fixed { secs };
         self
     }
 
     /// Set the backoff policy for a failed task. Default is BackoffPolicy::Fixed{ secs: 2 }.
     pub fn with_backoff_policy(mut self, backoff_policy: BackoffPolicy) -> Self {
-        self.retry_strategy.backoff_policy = backoff_policy;
+        self.retry_strategy_mut().backoff_policy = backoff_policy;
+        self
+    }
+
+    /// Set the retry strategy for a failed task.
+    /// If neither the strategy nor any of its policies is set, the retry strategy of the scheduler is used.
+    pub fn with_retry_strategy(mut self, retry_strategy: RetryStrategy) -> Self {
+        self.retry_strategy = Some(retry_strategy);
+        self
+    }
+
+    fn retry_strategy_mut(&mut self) -> &mut RetryStrategy {
+        self.retry_strategy.get_or_insert_with(Default::default)
+    }
+
+    /// Set the lane of the task.
+    /// The scheduler interleaves the tasks of different lanes proportionally to the lane weights,
+    /// see [`SchedulerBuilder::with_lane_weight`](crate::builder::SchedulerBuilder::with_lane_weight).
+    /// Tasks without a lane belong to a default lane with weight 1.
+    pub fn with_lane(mut self, lane: impl Into<String>) -> Self {
+        self.lane = Some(lane.into());
+        self
+    }
+
+    /// Delay the execution of the task by a random amount of seconds, up to `jitter_secs`.
+    ///
+    /// The delay depends on the canister id, so canisters running the same code don't fire the
+    /// task at the same second. It is drawn again for every occurrence of the task: when it is
+    /// appended to the scheduler, e.g. by a recurring task appending itself again with the same
+    /// options, and when it is retried after a failure. Default is 0.
+    pub fn with_jitter_secs(mut self, jitter_secs: u32) -> Self {
+        self.jitter_secs = jitter_secs;
+        self
+    }
+
+    /// Set the trace id of the task, e.g. to continue a trace started by another canister.
+    ///
+    /// If not set, the tasks appended by a running task, and the next steps of a saga, inherit the
+    /// trace id of that task. Other tasks start a new trace.
+    pub fn with_trace_id(mut self, trace_id: u64) -> Self {
+        self.trace_id = Some(trace_id);
         self
     }
 
@@ -236,6 +371,55 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_decode_legacy_task() {
+        // A task encoded by the first releases, without the version header
+        let bytes: Vec<u8> = [
+            &7u32.to_le_bytes()[..], // id
+            &1u32.to_le_bytes(),     // failures
+            &100u64.to_le_bytes(),   // execute_after_timestamp_in_secs
+            &1u32.to_le_bytes(),     // RetryPolicy::MaxRetries
+            &3u32.to_le_bytes(),     // retries
+            &1u32.to_le_bytes(),     // BackoffPolicy::Fixed
+            &2u32.to_le_bytes(),     // secs
+            &0u32.to_le_bytes(),     // TaskStatus::Waiting
+            &50u64.to_le_bytes(),    // timestamp_secs
+        ]
+        .concat();
+
+        let task = InnerScheduledTask::<TestTask>::from_bytes(bytes.into());
+
+        assert_eq!(
+            task,
+            InnerScheduledTask {
+                id: 7,
+                task: TestTask {},
+                options: TaskOptions {
+                    failures: 1,
+                    ..TaskOptions::new()
+                        .with_execute_after_timestamp_in_secs(100)
+                        .with_max_retries_policy(3)
+                        .with_fixed_backoff_policy(2)
+                },
+                status: TaskStatus::Waiting { timestamp_secs: 50 },
+                saga: None,
+                checkpoint: None,
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown task encoding version 2")]
+    fn test_decode_unknown_task_encoding_version() {
+        let bytes = [
+            &TASK_ENCODING_HEADER[..],
+            &[TASK_ENCODING_VERSION + 1],
+            &[0; 32],
+        ]
+        .concat();
+        InnerScheduledTask::<TestTask>::from_bytes(bytes.into());
+    }
+
     #[test]
     fn test_storable_task() {
         {
@@ -246,6 +430,8 @@ mod test {
                     .with_max_retries_policy(3)
                     .with_fixed_backoff_policy(2),
                 status: TaskStatus::Waiting { timestamp_secs: 0 },
+                saga: None,
+                checkpoint: None,
             };
 
             let serialized = task.to_bytes();
@@ -262,6 +448,8 @@ mod test {
                     .with_retry_policy(RetryPolicy::None)
                     .with_backoff_policy(BackoffPolicy::None),
                 status: TaskStatus::Waiting { timestamp_secs: 0 },
+                saga: None,
+                checkpoint: None,
             };
 
             let serialized = task.to_bytes();
@@ -283,6 +471,8 @@ mod test {
                 status: TaskStatus::Completed {
                     timestamp_secs: 1230,
                 },
+                saga: None,
+                checkpoint: None,
             };
 
             let serialized = task.to_bytes();
@@ -303,6 +493,8 @@ mod test {
                 status: TaskStatus::Running {
                     timestamp_secs: 21230,
                 },
+                saga: None,
+                checkpoint: Some(vec![1, 2, 3]),
             };
 
             let serialized = task.to_bytes();
@@ -1,8 +1,15 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
 /// returns the timestamp in seconds
 #[inline]
 pub fn time_secs() -> u64 {
     #[cfg(not(target_family = "wasm"))]
     {
+        #[cfg(any(test, feature = "test-harness"))]
+        if let Some(now_secs) = crate::harness::now_secs() {
+            return now_secs;
+        }
+
         std::time::SystemTime::now()
             .duration_since(std::time::SystemTime::UNIX_EPOCH)
             .expect("get current timestamp error")
@@ -16,3 +23,35 @@ pub fn time_secs() -> u64 {
         ic_kit::ic::time() / E_9
     }
 }
+
+/// Returns a pseudo random delay in `[0, max_secs]` for the task with the given key,
+/// seeded by the canister id so that different canisters get different delays.
+pub(crate) fn jitter_secs(max_secs: u32, task_key: u32, time_secs: u64) -> u64 {
+    if max_secs == 0 {
+        return 0;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    #[cfg(target_family = "wasm")]
+    ic_kit::ic::id().hash(&mut hasher);
+    task_key.hash(&mut hasher);
+    time_secs.hash(&mut hasher);
+
+    hasher.finish() % (max_secs as u64 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_secs() {
+        assert_eq!(jitter_secs(0, 1, 100), 0);
+
+        let delays: Vec<_> = (0..100).map(|key| jitter_secs(10, key, 100)).collect();
+        assert!(delays.iter().all(|delay| *delay <= 10));
+        // the delays are spread over the interval
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert_eq!(jitter_secs(10, 5, 100), delays[5]);
+    }
+}
@@ -23,12 +23,10 @@ thread_local! {
     static SCHEDULER: RefCell<PanickingScheduler> = {
         let map: Storage = Storage::new(MEMORY_MANAGER.with(|mm| mm.get(SCHEDULER_STORAGE_MEMORY_ID)));
 
-        let mut scheduler = PanickingScheduler::new(
-            map,
-        );
-
-        scheduler.set_running_task_timeout(30);
-        scheduler.on_completion_callback(save_state_cb);
+        let scheduler = PanickingScheduler::builder(map)
+            .with_running_task_timeout(30)
+            .with_observer(save_state_cb)
+            .build();
 
         RefCell::new(scheduler)
     };
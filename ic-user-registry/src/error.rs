@@ -0,0 +1,11 @@
+use candid::{CandidType, Deserialize};
+use thiserror::Error;
+
+#[derive(CandidType, Debug, Error, Deserialize, PartialEq, Eq, Clone)]
+pub enum RegistryError {
+    #[error("the value {value} of the field {field} is already taken")]
+    AlreadyTaken { field: String, value: String },
+}
+
+/// Result type for the user registry
+pub type Result<T> = std::result::Result<T, RegistryError>;
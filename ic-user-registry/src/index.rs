@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+
+use candid::Principal;
+use ic_stable_structures::stable_structures::storable::Bound;
+use ic_stable_structures::{Bounded, Storable};
+
+/// Key of an index entry: the indexed field, its value and the user the profile belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct IndexKey {
+    pub field: String,
+    pub value: String,
+    pub user: Principal,
+}
+
+impl IndexKey {
+    pub fn new(field: &str, value: &str, user: Principal) -> Self {
+        Self {
+            field: field.to_string(),
+            value: value.to_string(),
+            user,
+        }
+    }
+
+    /// Returns the range of the keys of all the users with the given field value
+    pub fn range(field: &str, value: &str) -> std::ops::RangeInclusive<Self> {
+        Self::new(field, value, Principal::MIN)..=Self::new(field, value, Principal::MAX)
+    }
+}
+
+impl Storable for IndexKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let field = self.field.as_bytes();
+        let value = self.value.as_bytes();
+        let user = self.user.as_slice();
+
+        let mut buf = Vec::with_capacity(8 + field.len() + value.len() + user.len());
+        buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        buf.extend_from_slice(field);
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(value);
+        buf.extend_from_slice(user);
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (field, rest) = split_string(&bytes);
+        let (value, user) = split_string(rest);
+        Self {
+            field,
+            value,
+            user: Principal::from_slice(user),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Reads a length prefixed string and returns it with the remaining bytes
+fn split_string(bytes: &[u8]) -> (String, &[u8]) {
+    let len = u32::from_le_bytes(bytes[..4].try_into().expect("expected 4 bytes")) as usize;
+    let string = String::from_utf8(bytes[4..4 + len].to_vec()).expect("invalid utf8 string");
+    (string, &bytes[4 + len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_key_roundtrip() {
+        let key = IndexKey::new("username", "alice", Principal::from_slice(&[1, 2, 3]));
+        assert_eq!(IndexKey::from_bytes(key.to_bytes()), key);
+
+        let key = IndexKey::new("", "", Principal::anonymous());
+        assert_eq!(IndexKey::from_bytes(key.to_bytes()), key);
+    }
+}
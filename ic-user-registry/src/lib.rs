@@ -0,0 +1,12 @@
+//! A registry of user profiles stored in stable memory.
+//!
+//! Profiles are indexed by the principal of their user. Chosen fields of the profiles (e.g. the
+//! username) can be declared unique across the registry, and other fields can be indexed for
+//! secondary lookups.
+
+mod error;
+mod index;
+mod registry;
+
+pub use error::{RegistryError, Result};
+pub use registry::{Page, Profile, UserRegistry};
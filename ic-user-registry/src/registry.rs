@@ -0,0 +1,315 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::stable_structures::Memory;
+use ic_stable_structures::{BTreeMapStructure, PrincipalKey, StableBTreeMap, Storable};
+
+use crate::index::IndexKey;
+use crate::{RegistryError, Result};
+
+/// A profile record stored in the registry.
+pub trait Profile: Storable + Clone {
+    /// Returns the name and the value of the fields that must be unique across the registry,
+    /// e.g. `vec![("username", self.username.clone())]`.
+    fn unique_fields(&self) -> Vec<(&'static str, String)>;
+
+    /// Returns the name and the value of the fields indexed for secondary lookups.
+    /// Field names must be different from the names of the unique fields.
+    fn indexed_fields(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+}
+
+/// A page of results.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The cursor to pass to get the next page, if there are more results
+    pub next_cursor: Option<Principal>,
+}
+
+/// Maps the principals of the users to their profiles.
+pub struct UserRegistry<P: Profile, M: Memory> {
+    profiles: StableBTreeMap<PrincipalKey, P, M>,
+    index: StableBTreeMap<IndexKey, (), M>,
+}
+
+impl<P: Profile, M: Memory> UserRegistry<P, M> {
+    /// Creates a new registry
+    pub fn new(profiles_memory: M, index_memory: M) -> Self {
+        Self {
+            profiles: StableBTreeMap::new(profiles_memory),
+            index: StableBTreeMap::new(index_memory),
+        }
+    }
+
+    /// Inserts or replaces the profile of a user and returns the previous one.
+    ///
+    /// Fails if the value of a unique field is already taken by another user.
+    pub fn insert(&mut self, user: Principal, profile: P) -> Result<Option<P>> {
+        for (field, value) in profile.unique_fields() {
+            if matches!(self.find_user(field, &value), Some(owner) if owner != user) {
+                return Err(RegistryError::AlreadyTaken {
+                    field: field.to_string(),
+                    value,
+                });
+            }
+        }
+
+        let previous = self.remove(user);
+        for (field, value) in Self::index_fields(&profile) {
+            self.index.insert(IndexKey::new(field, &value, user), ());
+        }
+        self.profiles.insert(PrincipalKey(user), profile);
+
+        Ok(previous)
+    }
+
+    /// Returns the profile of a user.
+    pub fn get(&self, user: Principal) -> Option<P> {
+        self.profiles.get(&PrincipalKey(user))
+    }
+
+    /// Removes the profile of a user and returns it.
+    pub fn remove(&mut self, user: Principal) -> Option<P> {
+        let profile = self.profiles.remove(&PrincipalKey(user))?;
+        for (field, value) in Self::index_fields(&profile) {
+            self.index.remove(&IndexKey::new(field, &value, user));
+        }
+        Some(profile)
+    }
+
+    /// Returns the user whose profile has the given value of a unique field, with the profile.
+    pub fn find_unique(&self, field: &str, value: &str) -> Option<(Principal, P)> {
+        let user = self.find_user(field, value)?;
+        self.get(user).map(|profile| (user, profile))
+    }
+
+    /// Returns a page of the users whose profile has the given value of an indexed field.
+    ///
+    /// The results are sorted by principal and start after the `cursor`, if given.
+    pub fn find(
+        &self,
+        field: &str,
+        value: &str,
+        cursor: Option<Principal>,
+        limit: usize,
+    ) -> Page<(Principal, P)> {
+        let range = IndexKey::range(field, value);
+        let start = match cursor {
+            Some(cursor) => IndexKey::new(field, value, cursor),
+            None => range.start().clone(),
+        };
+
+        let users = self
+            .index
+            .range(start..=range.end().clone())
+            .map(|(key, _)| key.user)
+            .filter(|user| Some(*user) != cursor);
+
+        self.page(users, limit)
+    }
+
+    /// Returns a page of all the users with their profiles.
+    ///
+    /// The results are sorted by principal and start after the `cursor`, if given.
+    pub fn list(&self, cursor: Option<Principal>, limit: usize) -> Page<(Principal, P)> {
+        let users = match cursor {
+            Some(cursor) => self.profiles.range(PrincipalKey(cursor)..),
+            None => self.profiles.iter(),
+        }
+        .map(|(user, _)| user.0)
+        .filter(|user| Some(*user) != cursor);
+
+        self.page(users, limit)
+    }
+
+    /// Number of registered users.
+    pub fn len(&self) -> u64 {
+        self.profiles.len()
+    }
+
+    /// Returns whether there are no registered users.
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+
+    fn find_user(&self, field: &str, value: &str) -> Option<Principal> {
+        self.index
+            .range(IndexKey::range(field, value))
+            .next()
+            .map(|(key, _)| key.user)
+    }
+
+    fn index_fields(profile: &P) -> impl Iterator<Item = (&'static str, String)> {
+        profile
+            .unique_fields()
+            .into_iter()
+            .chain(profile.indexed_fields())
+    }
+
+    fn page(&self, users: impl Iterator<Item = Principal>, limit: usize) -> Page<(Principal, P)> {
+        let mut users = users.peekable();
+        let items: Vec<_> = users
+            .by_ref()
+            .take(limit)
+            .filter_map(|user| self.get(user).map(|profile| (user, profile)))
+            .collect();
+
+        let next_cursor = match users.peek() {
+            Some(_) => items.last().map(|(user, _)| *user),
+            None => None,
+        };
+
+        Page { items, next_cursor }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use candid::{Decode, Encode};
+    use ic_stable_structures::stable_structures::storable::Bound;
+    use ic_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct TestProfile {
+        username: String,
+        country: String,
+    }
+
+    impl TestProfile {
+        fn new(username: &str, country: &str) -> Self {
+            Self {
+                username: username.to_string(),
+                country: country.to_string(),
+            }
+        }
+    }
+
+    impl Storable for TestProfile {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(Encode!(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            Decode!(&bytes, Self).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    impl Profile for TestProfile {
+        fn unique_fields(&self) -> Vec<(&'static str, String)> {
+            vec![("username", self.username.clone())]
+        }
+
+        fn indexed_fields(&self) -> Vec<(&'static str, String)> {
+            vec![("country", self.country.clone())]
+        }
+    }
+
+    fn user(id: u8) -> Principal {
+        Principal::from_slice(&[id; 29])
+    }
+
+    fn registry() -> UserRegistry<TestProfile, VectorMemory> {
+        UserRegistry::new(VectorMemory::default(), VectorMemory::default())
+    }
+
+    #[test]
+    fn should_insert_and_get_profiles() {
+        let mut registry = registry();
+        assert!(registry.is_empty());
+
+        let profile = TestProfile::new("alice", "it");
+        assert_eq!(registry.insert(user(1), profile.clone()), Ok(None));
+        assert_eq!(registry.get(user(1)), Some(profile.clone()));
+        assert_eq!(registry.len(), 1);
+
+        let updated = TestProfile::new("alice_2", "it");
+        assert_eq!(registry.insert(user(1), updated.clone()), Ok(Some(profile)));
+        assert_eq!(registry.find_unique("username", "alice"), None);
+        assert_eq!(
+            registry.find_unique("username", "alice_2"),
+            Some((user(1), updated.clone()))
+        );
+
+        assert_eq!(registry.remove(user(1)), Some(updated));
+        assert_eq!(registry.find_unique("username", "alice_2"), None);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn should_enforce_unique_fields() {
+        let mut registry = registry();
+        registry
+            .insert(user(1), TestProfile::new("alice", "it"))
+            .unwrap();
+
+        assert_eq!(
+            registry.insert(user(2), TestProfile::new("alice", "fr")),
+            Err(RegistryError::AlreadyTaken {
+                field: "username".to_string(),
+                value: "alice".to_string()
+            })
+        );
+        assert_eq!(registry.get(user(2)), None);
+
+        // the owner of the value can keep it
+        assert!(registry
+            .insert(user(1), TestProfile::new("alice", "fr"))
+            .is_ok());
+    }
+
+    #[test]
+    fn should_paginate_secondary_index_lookups() {
+        let mut registry = registry();
+        for id in 1..=5 {
+            let country = if id % 2 == 0 { "fr" } else { "it" };
+            registry
+                .insert(user(id), TestProfile::new(&format!("user_{id}"), country))
+                .unwrap();
+        }
+
+        let page = registry.find("country", "it", None, 2);
+        assert_eq!(
+            page.items.iter().map(|(user, _)| *user).collect::<Vec<_>>(),
+            vec![user(1), user(3)]
+        );
+        assert_eq!(page.next_cursor, Some(user(3)));
+
+        let page = registry.find("country", "it", page.next_cursor, 2);
+        assert_eq!(
+            page.items.iter().map(|(user, _)| *user).collect::<Vec<_>>(),
+            vec![user(5)]
+        );
+        assert_eq!(page.next_cursor, None);
+
+        assert!(registry.find("country", "de", None, 2).items.is_empty());
+    }
+
+    #[test]
+    fn should_list_all_users() {
+        let mut registry = registry();
+        for id in 1..=5 {
+            registry
+                .insert(user(id), TestProfile::new(&format!("user_{id}"), "it"))
+                .unwrap();
+        }
+
+        let mut users = vec![];
+        let mut cursor = None;
+        loop {
+            let page = registry.list(cursor, 2);
+            users.extend(page.items.into_iter().map(|(user, _)| user));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(users, (1..=5).map(user).collect::<Vec<_>>());
+    }
+}